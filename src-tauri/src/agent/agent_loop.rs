@@ -1,27 +1,44 @@
+use crate::agent::approval::{denial_message, disabled_message};
+use crate::agent::context::{
+    estimate_messages_tokens, render_transcript, summary_message, summary_split_point,
+    truncate_old_tool_results, ContextBudget, SUMMARY_SYSTEM_PROMPT,
+};
+use crate::agent::message_builder::{ApiContent, ApiMessage, ClaudeApiRequest};
+use crate::agent::usage::{estimate_cost, TokenUsage};
 use crate::agent::{
-    AgentConfig, AgentContent, AgentEvent, AgentMessage, ContentBlock, MessageBuilder,
-    PlanStepInfo, ToolExecutor, ToolUse,
+    AgentConfig, AgentContent, AgentEvent, AgentMessage, ApprovalDecision, ApprovalManager,
+    ContentBlock, MessageBuilder, PlanStepInfo, ToolExecutor, ToolPermission, ToolResult, ToolUse,
 };
-use crate::llm_client::{ApiFormat, ProviderConfig};
 use crate::mcp::MCPManager;
+use crate::providers::retry::retry_delay;
+use crate::providers::{self, ProviderEndpoint, ProviderResponse, RequestError, StreamEvent};
+use crate::tools::bash::truncate_output;
+use crate::tools::sandbox::Sandbox;
+use futures::StreamExt;
 use regex::Regex;
 use reqwest::Client;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 #[allow(dead_code)]
 pub struct AgentLoop {
     client: Client,
-    api_key: String,
-    base_url: String,
     config: AgentConfig,
     model: String,
     max_tokens: u32,
     temperature: Option<f32>,
     tool_executor: ToolExecutor,
     message_builder: MessageBuilder,
-    /// Provider configuration for determining API format
-    provider_config: ProviderConfig,
+    /// Primary provider followed by the fallback chain
+    endpoints: Vec<ProviderEndpoint>,
+    /// Endpoint currently in use; moves down the chain when a provider stays unavailable
+    active_endpoint: AtomicUsize,
+    /// Stops the loop, the in-flight request and any running tool when cancelled
+    cancel_token: CancellationToken,
+    /// Resolves tool calls whose permission policy is "ask"
+    approval_manager: Option<Arc<ApprovalManager>>,
 }
 
 impl AgentLoop {
@@ -52,6 +69,7 @@ impl AgentLoop {
         conversation_id: Option<String>,
     ) -> Self {
         let mut tool_executor = ToolExecutor::new(config.project_path.clone())
+            .with_path_resolver(config.path_resolver())
             .with_mcp_manager(mcp_manager.clone());
         
         // Add backup manager if provided
@@ -70,31 +88,52 @@ impl AgentLoop {
         ).with_mcp_manager(mcp_manager);
 
         // Infer config from provider_id or model
-        let mut provider_config = if let Some(pid) = provider_id {
-            ProviderConfig::from_preset(pid)
-        } else {
-            ProviderConfig::from_model(&model)
-        };
-
-        // Use custom base_url
-        if !base_url.is_empty() {
-            provider_config.base_url = base_url.clone();
-        }
+        let endpoint = ProviderEndpoint::new(provider_id, &base_url, api_key, model.clone());
 
         Self {
             client: Client::new(),
-            api_key,
-            base_url: provider_config.base_url.clone(),
             config,
             model,
             max_tokens,
             temperature,
             tool_executor,
             message_builder,
-            provider_config,
+            endpoints: vec![endpoint],
+            active_endpoint: AtomicUsize::new(0),
+            cancel_token: CancellationToken::new(),
+            approval_manager: None,
         }
     }
 
+    /// Attach a cancellation token shared with the caller (e.g. the running task registry)
+    pub fn with_cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.tool_executor = self.tool_executor.with_cancel_token(cancel_token.clone());
+        self.cancel_token = cancel_token;
+        self
+    }
+
+    pub fn with_approval_manager(mut self, approval_manager: Arc<ApprovalManager>) -> Self {
+        self.approval_manager = Some(approval_manager);
+        self
+    }
+
+    /// Run bash commands in a sandbox container instead of on the host
+    pub fn with_sandbox(mut self, sandbox: Arc<Sandbox>) -> Self {
+        self.tool_executor = self.tool_executor.with_sandbox(sandbox);
+        self
+    }
+
+    /// Providers to fall back to, in order, when the primary one stays unavailable
+    pub fn with_fallbacks(mut self, fallbacks: Vec<ProviderEndpoint>) -> Self {
+        self.endpoints.extend(fallbacks);
+        self
+    }
+
+    /// The endpoint requests are currently sent to
+    fn endpoint(&self) -> &ProviderEndpoint {
+        &self.endpoints[self.active_endpoint.load(Ordering::Relaxed)]
+    }
+
     pub async fn run(
         &self,
         initial_message: String,
@@ -110,15 +149,52 @@ impl AgentLoop {
 
     /// Run agent with existing conversation history
     pub async fn run_with_history(
+        &self,
+        messages: Vec<AgentMessage>,
+        event_tx: mpsc::Sender<AgentEvent>,
+    ) -> Result<Vec<AgentMessage>, String> {
+        self.run_turns(messages, 0, event_tx).await
+    }
+
+    /// Continue a run from a checkpoint taken after `turn` turns. Tool calls
+    /// of the last assistant message that never produced results run first.
+    pub async fn resume(
         &self,
         mut messages: Vec<AgentMessage>,
+        turn: u32,
         event_tx: mpsc::Sender<AgentEvent>,
     ) -> Result<Vec<AgentMessage>, String> {
-        let mut turn = 0;
+        let pending = pending_tool_uses(&messages);
+        if pending.is_empty() {
+            return self.run_turns(messages, turn, event_tx).await;
+        }
+
+        // The pending calls belong to the turn after the checkpoint
+        let turn = turn + 1;
+        let tool_results = self.execute_tools(&pending, &event_tx).await;
+        self.complete_turn(turn, &mut messages, tool_results, &event_tx).await;
+
+        if self.cancel_token.is_cancelled() {
+            let _ = event_tx.send(AgentEvent::Cancelled { turn }).await;
+            return Ok(messages);
+        }
+        self.run_turns(messages, turn, event_tx).await
+    }
 
+    async fn run_turns(
+        &self,
+        mut messages: Vec<AgentMessage>,
+        mut turn: u32,
+        event_tx: mpsc::Sender<AgentEvent>,
+    ) -> Result<Vec<AgentMessage>, String> {
         loop {
             turn += 1;
 
+            if self.cancel_token.is_cancelled() {
+                let _ = event_tx.send(AgentEvent::Cancelled { turn }).await;
+                break;
+            }
+
             if turn > self.config.max_turns {
                 let _ = event_tx
                     .send(AgentEvent::Error {
@@ -128,13 +204,26 @@ impl AgentLoop {
                 break;
             }
 
+            // Keep the history within the model's context window
+            self.compact_history(turn, &mut messages, &event_tx).await;
+
             // Build and send request
             let request = self.message_builder.build_request(&messages).await;
 
-            let response = self.send_request(&request, &event_tx).await?;
-
-            // Parse response
-            let (text_content, tool_uses) = self.parse_response(&response)?;
+            // Dropping the request future aborts the in-flight provider stream
+            let response = tokio::select! {
+                response = self.send_request(&request, &event_tx) => response?,
+                _ = self.cancel_token.cancelled() => {
+                    let _ = event_tx.send(AgentEvent::Cancelled { turn }).await;
+                    break;
+                }
+            };
+            self.emit_usage(turn, &response.usage, &event_tx).await;
+            let ProviderResponse {
+                text: text_content,
+                tool_uses,
+                ..
+            } = response;
 
             // Parse and emit plan if present
             if let Some(plan_steps) = self.parse_plan(&text_content) {
@@ -187,789 +276,415 @@ impl AgentLoop {
                 break;
             }
 
-            // Execute tools
-            let mut tool_results = Vec::new();
+            // The tool calls are known before any of them runs
+            let _ = event_tx
+                .send(AgentEvent::Checkpoint {
+                    turn: turn - 1,
+                    messages: messages.clone(),
+                })
+                .await;
 
-            for tool_use in &tool_uses {
-                // Emit tool start
-                let _ = event_tx
-                    .send(AgentEvent::ToolStart {
-                        tool: tool_use.name.clone(),
-                        input: tool_use.input.clone(),
-                    })
-                    .await;
-
-                // Execute tool
-                let result = self.tool_executor.execute(tool_use).await;
+            let tool_results = self.execute_tools(&tool_uses, &event_tx).await;
+            self.complete_turn(turn, &mut messages, tool_results, &event_tx).await;
 
-                // Emit tool end
-                let _ = event_tx
-                    .send(AgentEvent::ToolEnd {
-                        tool: tool_use.name.clone(),
-                        result: result.content.clone(),
-                        success: result.is_error.is_none(),
-                    })
-                    .await;
-
-                tool_results.push(result);
+            if self.cancel_token.is_cancelled() {
+                let _ = event_tx.send(AgentEvent::Cancelled { turn }).await;
+                break;
             }
-
-            // Add tool results as user message
-            messages.push(AgentMessage {
-                role: "user".to_string(),
-                content: AgentContent::ToolResults(tool_results.clone()),
-            });
-
-            // Emit tool results for DB saving
-            let _ = event_tx.send(AgentEvent::ToolResults { results: tool_results }).await;
-
-            // Emit turn complete
-            let _ = event_tx.send(AgentEvent::TurnComplete { turn }).await;
         }
 
         Ok(messages)
     }
 
-    async fn send_request(
-        &self,
-        request: &crate::agent::message_builder::ClaudeApiRequest,
-        event_tx: &mpsc::Sender<AgentEvent>,
-    ) -> Result<serde_json::Value, String> {
-        match self.provider_config.api_format {
-            ApiFormat::Anthropic => self.send_anthropic_request(request, event_tx).await,
-            ApiFormat::OpenAI | ApiFormat::OpenAICompatible => {
-                self.send_openai_request(request, event_tx).await
+    /// Execute a turn's tool calls. Consecutive read-only calls run
+    /// concurrently; everything else runs one at a time in the order requested.
+    async fn execute_tools(&self, tool_uses: &[ToolUse], event_tx: &mpsc::Sender<AgentEvent>) -> Vec<ToolResult> {
+        let mut tool_results = Vec::new();
+        let mut pending = tool_uses.iter().peekable();
+
+        while let Some(tool_use) = pending.next() {
+            // Every tool_use needs a matching result, even once cancelled
+            if self.cancel_token.is_cancelled() {
+                tool_results.push(ToolResult::error(
+                    tool_use.id.clone(),
+                    "Cancelled by user".to_string(),
+                ));
+                continue;
             }
-            ApiFormat::Google => self.send_google_request(request, event_tx).await,
-            _ => Err(format!("Unsupported API format: {:?}", self.provider_config.api_format)),
-        }
-    }
-
-    /// Send Anthropic format request
-    async fn send_anthropic_request(
-        &self,
-        request: &crate::agent::message_builder::ClaudeApiRequest,
-        event_tx: &mpsc::Sender<AgentEvent>,
-    ) -> Result<serde_json::Value, String> {
-        let url = format!("{}/v1/messages", self.base_url.trim_end_matches('/'));
-
-        let mut req = self.client.post(&url)
-            .header("Content-Type", "application/json")
-            .header("anthropic-version", "2023-06-01");
 
-        // Add authentication
-        if !self.api_key.is_empty() {
-            req = req.header("x-api-key", &self.api_key);
-        }
-
-        let response = req
-            .json(request)
-            .send()
-            .await
-            .map_err(|e| format!("HTTP error: {}", e))?;
+            if !self.runs_concurrently(tool_use).await {
+                tool_results.push(self.run_tool(tool_use, event_tx).await);
+                continue;
+            }
 
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("API error: {}", error_text));
+            let mut batch = vec![tool_use];
+            while let Some(next) = pending.peek() {
+                if !self.runs_concurrently(next).await {
+                    break;
+                }
+                batch.push(pending.next().unwrap());
+            }
+            tool_results.extend(self.run_tool_batch(&batch, event_tx).await);
         }
 
-        self.handle_stream_response(response, event_tx).await
+        tool_results
     }
 
-    /// Send OpenAI compatible format request
-    async fn send_openai_request(
+    /// Add the tool results to the history and report the finished turn
+    async fn complete_turn(
         &self,
-        request: &crate::agent::message_builder::ClaudeApiRequest,
+        turn: u32,
+        messages: &mut Vec<AgentMessage>,
+        tool_results: Vec<ToolResult>,
         event_tx: &mpsc::Sender<AgentEvent>,
-    ) -> Result<serde_json::Value, String> {
-        let base = self.base_url.trim_end_matches('/');
-        let url = if base.ends_with("/v1") {
-            format!("{}/chat/completions", base)
-        } else {
-            format!("{}/v1/chat/completions", base)
-        };
-
-        // Convert request format to OpenAI format
-        let openai_request = self.convert_to_openai_format(request);
-
-        let mut req = self.client.post(&url)
-            .header("Content-Type", "application/json");
-
-        // Add authentication (if needed)
-        if !self.api_key.is_empty() {
-            req = req.header("Authorization", format!("Bearer {}", self.api_key));
-        }
-
-        let response = req
-            .json(&openai_request)
-            .send()
-            .await
-            .map_err(|e| format!("HTTP error: {}", e))?;
+    ) {
+        // Add tool results as user message
+        messages.push(AgentMessage {
+            role: "user".to_string(),
+            content: AgentContent::ToolResults(tool_results.clone()),
+        });
 
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("API error: {}", error_text));
-        }
+        // Emit tool results for DB saving
+        let _ = event_tx.send(AgentEvent::ToolResults { results: tool_results }).await;
 
-        self.handle_openai_stream_response(response, event_tx).await
+        // Emit turn complete
+        let _ = event_tx.send(AgentEvent::TurnComplete { turn }).await;
+        let _ = event_tx
+            .send(AgentEvent::Checkpoint {
+                turn,
+                messages: messages.clone(),
+            })
+            .await;
     }
 
-    /// Convert Claude request format to OpenAI format
-    fn convert_to_openai_format(
-        &self,
-        request: &crate::agent::message_builder::ClaudeApiRequest,
-    ) -> serde_json::Value {
-        use crate::agent::message_builder::ApiContent;
-
-        // Build messages, including system prompt
-        let mut messages: Vec<serde_json::Value> = Vec::new();
-
-        // Add system message
-        if !request.system.is_empty() {
-            messages.push(serde_json::json!({
-                "role": "system",
-                "content": request.system
-            }));
+    /// Compact the history when it no longer fits the context budget: first
+    /// shorten old tool results, then replace earlier turns with a summary
+    async fn compact_history(&self, turn: u32, messages: &mut Vec<AgentMessage>, event_tx: &mpsc::Sender<AgentEvent>) {
+        let fixed_tokens = self.message_builder.fixed_tokens().await;
+        let budget = ContextBudget::new(&self.endpoint().model, self.max_tokens, fixed_tokens);
+        if !budget.is_exceeded(messages) {
+            return;
         }
 
-        // Convert conversation messages
-        for msg in &request.messages {
-            let role = &msg.role;
+        let tokens_before = estimate_messages_tokens(messages);
+        let truncated_results = truncate_old_tool_results(messages);
 
-            match &msg.content {
-                ApiContent::Text(text) => {
-                    messages.push(serde_json::json!({
-                        "role": role,
-                        "content": text
-                    }));
-                }
-                ApiContent::Blocks(blocks) => {
-                    // Handle content blocks (text, tool_use, tool_result)
-                    let mut text_parts: Vec<String> = Vec::new();
-                    let mut tool_calls: Vec<serde_json::Value> = Vec::new();
-
-                    for block in blocks {
-                        let block_type = block.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                        match block_type {
-                            "text" => {
-                                if let Some(text) = block.get("text").and_then(|v| v.as_str()) {
-                                    text_parts.push(text.to_string());
-                                }
-                            }
-                            "tool_use" => {
-                                tool_calls.push(serde_json::json!({
-                                    "id": block.get("id"),
-                                    "type": "function",
-                                    "function": {
-                                        "name": block.get("name"),
-                                        "arguments": serde_json::to_string(block.get("input").unwrap_or(&serde_json::json!({}))).unwrap_or_default()
-                                    }
-                                }));
-                            }
-                            "tool_result" => {
-                                // OpenAI uses tool role to represent tool results
-                                messages.push(serde_json::json!({
-                                    "role": "tool",
-                                    "tool_call_id": block.get("tool_use_id"),
-                                    "content": block.get("content")
-                                }));
-                            }
-                            _ => {}
-                        }
-                    }
+        let mut summary = None;
+        let mut summary_error = None;
+        let mut kept_messages = Vec::new();
 
-                    // If there's text content
-                    if !text_parts.is_empty() {
-                        let mut msg_obj = serde_json::json!({
-                            "role": role,
-                            "content": text_parts.join("\n")
-                        });
-
-                        // If there are tool_calls
-                        if !tool_calls.is_empty() {
-                            msg_obj["tool_calls"] = serde_json::json!(tool_calls);
-                        }
-
-                        messages.push(msg_obj);
-                    } else if !tool_calls.is_empty() {
-                        // Only tool_calls, no text
-                        messages.push(serde_json::json!({
-                            "role": role,
-                            "content": serde_json::Value::Null,
-                            "tool_calls": tool_calls
-                        }));
+        if budget.is_exceeded(messages) {
+            if let Some(split) = summary_split_point(messages, budget.threshold() / 2) {
+                let result = tokio::select! {
+                    result = self.summarize(turn, &messages[..split], &budget, event_tx) => result,
+                    _ = self.cancel_token.cancelled() => return,
+                };
+
+                match result {
+                    Ok(text) => {
+                        kept_messages = messages.split_off(split);
+                        *messages = vec![summary_message(&text)];
+                        messages.extend(kept_messages.iter().cloned());
+                        summary = Some(text);
                     }
+                    Err(e) => summary_error = Some(format!("History summarization failed: {}", e)),
                 }
             }
         }
 
-        // Convert tools definition
-        let tools: Vec<serde_json::Value> = request.tools.iter().map(|tool| {
-            serde_json::json!({
-                "type": "function",
-                "function": {
-                    "name": tool.name,
-                    "description": tool.description,
-                    "parameters": tool.input_schema
-                }
+        let _ = event_tx
+            .send(AgentEvent::Compacted {
+                tokens_before,
+                tokens_after: estimate_messages_tokens(messages),
+                truncated_results,
+                summary,
+                summary_error,
+                kept_messages,
             })
-        }).collect();
-
-        let mut openai_request = serde_json::json!({
-            "model": request.model,
-            "stream": request.stream,
-            "messages": messages
-        });
-
-        // Use correct max tokens parameter based on provider and model
-        // max_completion_tokens is ONLY for official OpenAI new models (gpt-4o, gpt-4-turbo, o1, o3, gpt-5)
-        // All OpenAI-compatible services (Ollama, vLLM, LM Studio, etc.) use max_tokens
-        let model_lower = request.model.to_lowercase();
-        let is_official_openai_new_model = matches!(self.provider_config.api_format, ApiFormat::OpenAI)
-            && (model_lower.contains("gpt-4o") 
-                || model_lower.contains("gpt-4-turbo") 
-                || model_lower.starts_with("o1") 
-                || model_lower.starts_with("o3") 
-                || model_lower.starts_with("gpt-5"));
-
-        if is_official_openai_new_model {
-            openai_request["max_completion_tokens"] = serde_json::json!(request.max_tokens);
-        } else {
-            openai_request["max_tokens"] = serde_json::json!(request.max_tokens);
-        }
+            .await;
+    }
 
-        // Only add temperature for non-reasoning models (o1, o3, gpt-5 don't support custom temperature)
-        let is_reasoning = model_lower.starts_with("o1") || model_lower.starts_with("o3") || model_lower.starts_with("gpt-5")
-            || model_lower.contains("-o1") || model_lower.contains("-o3")
-            || model_lower.contains("o1-") || model_lower.contains("o3-");
+    /// Ask the model to summarize part of the history
+    async fn summarize(
+        &self,
+        turn: u32,
+        messages: &[AgentMessage],
+        budget: &ContextBudget,
+        event_tx: &mpsc::Sender<AgentEvent>,
+    ) -> Result<String, String> {
+        let transcript = truncate_output(&render_transcript(messages), budget.max_transcript_chars());
+        let request = ClaudeApiRequest {
+            model: self.model.clone(),
+            max_tokens: self.max_tokens.min(4096),
+            system: SUMMARY_SYSTEM_PROMPT.to_string(),
+            messages: vec![ApiMessage {
+                role: "user".to_string(),
+                content: ApiContent::Text(transcript),
+            }],
+            tools: Vec::new(),
+            temperature: self.temperature,
+            stream: true,
+        };
 
-        if !is_reasoning {
-            if let Some(temp) = request.temperature {
-                openai_request["temperature"] = serde_json::json!(temp);
+        // The summary's text isn't streamed to the user, but retries and
+        // failovers are, so a slow compaction doesn't look stalled
+        let (tx, mut rx) = mpsc::channel(16);
+        let request_summary = async {
+            let response = self.send_request(&request, &tx).await;
+            drop(tx);
+            response
+        };
+        let forward_status = async {
+            while let Some(event) = rx.recv().await {
+                if matches!(event, AgentEvent::Retrying { .. } | AgentEvent::Failover { .. }) {
+                    let _ = event_tx.send(event).await;
+                }
             }
+        };
+        let (response, ()) = tokio::join!(request_summary, forward_status);
+        let response = response?;
+        self.emit_usage(turn, &response.usage, event_tx).await;
+        if response.text.trim().is_empty() {
+            return Err("Model returned an empty summary".to_string());
         }
+        Ok(response.text)
+    }
 
-        if !tools.is_empty() {
-            openai_request["tools"] = serde_json::json!(tools);
-            openai_request["tool_choice"] = serde_json::json!("auto");
+    /// Report the tokens used by a request and their estimated cost
+    async fn emit_usage(&self, turn: u32, usage: &TokenUsage, event_tx: &mpsc::Sender<AgentEvent>) {
+        if usage.is_empty() {
+            return;
         }
 
-        openai_request
+        let model = &self.endpoint().model;
+        let _ = event_tx
+            .send(AgentEvent::Usage {
+                turn,
+                model: model.clone(),
+                usage: *usage,
+                cost: estimate_cost(model, usage, &self.config.model_prices),
+            })
+            .await;
     }
 
-    /// Send Google Gemini format request
-    async fn send_google_request(
-        &self,
-        request: &crate::agent::message_builder::ClaudeApiRequest,
-        event_tx: &mpsc::Sender<AgentEvent>,
-    ) -> Result<serde_json::Value, String> {
-        let base = self.base_url.trim_end_matches('/');
-        let url = format!("{}/v1beta/models/{}:streamGenerateContent?alt=sse", base, request.model);
-
-        // Convert request format to Google format
-        let google_request = self.convert_to_google_format(request);
-
-        let response = self.client.post(&url)
-            .header("Content-Type", "application/json")
-            .header("x-goog-api-key", &self.api_key)
-            .json(&google_request)
-            .send()
-            .await
-            .map_err(|e| format!("HTTP error: {}", e))?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("API error: {}", error_text));
-        }
+    /// Whether a tool call can run alongside other calls of the same turn
+    async fn runs_concurrently(&self, tool_use: &ToolUse) -> bool {
+        self.config.max_parallel_tools > 1
+            && self.config.permission_for(&tool_use.name) == ToolPermission::Allow
+            && self.tool_executor.is_read_only(&tool_use.name).await
+    }
 
-        self.handle_google_stream_response(response, event_tx).await
+    /// Run a single tool call, emitting its start and end events
+    async fn run_tool(&self, tool_use: &ToolUse, event_tx: &mpsc::Sender<AgentEvent>) -> ToolResult {
+        self.emit_tool_start(tool_use, event_tx).await;
+        self.finish_tool(tool_use, event_tx).await
     }
 
-    /// Convert Claude request format to Google Gemini format
-    fn convert_to_google_format(
+    /// Run read-only tool calls concurrently, returning results in the original order
+    async fn run_tool_batch(
         &self,
-        request: &crate::agent::message_builder::ClaudeApiRequest,
-    ) -> serde_json::Value {
-        use crate::agent::message_builder::ApiContent;
-
-        // Build a map of tool_use_id -> thought_signature for looking up when building functionResponse
-        let mut thought_signatures: std::collections::HashMap<String, String> = std::collections::HashMap::new();
-        for msg in &request.messages {
-            if let ApiContent::Blocks(blocks) = &msg.content {
-                for block in blocks {
-                    if block.get("type").and_then(|v| v.as_str()) == Some("tool_use") {
-                        if let (Some(id), Some(sig)) = (
-                            block.get("id").and_then(|v| v.as_str()),
-                            block.get("thought_signature").and_then(|v| v.as_str()),
-                        ) {
-                            thought_signatures.insert(id.to_string(), sig.to_string());
-                        }
-                    }
-                }
-            }
+        batch: &[&ToolUse],
+        event_tx: &mpsc::Sender<AgentEvent>,
+    ) -> Vec<ToolResult> {
+        // Start events keep the requested order; end events arrive as calls finish
+        for tool_use in batch {
+            self.emit_tool_start(tool_use, event_tx).await;
         }
 
-        // Build contents array
-        let mut contents: Vec<serde_json::Value> = Vec::new();
+        let calls: Vec<_> = batch
+            .iter()
+            .map(|tool_use| self.finish_tool(tool_use, event_tx))
+            .collect();
 
-        // Convert messages to Google format
-        for msg in &request.messages {
-            // Google uses "user" and "model" instead of "user" and "assistant"
-            let role = if msg.role == "assistant" { "model" } else { &msg.role };
+        futures::stream::iter(calls)
+            .buffered(self.config.max_parallel_tools)
+            .collect()
+            .await
+    }
 
-            let parts = match &msg.content {
-                ApiContent::Text(text) => {
-                    vec![serde_json::json!({"text": text})]
-                }
-                ApiContent::Blocks(blocks) => {
-                    let mut parts_list = Vec::new();
-                    for block in blocks {
-                        let block_type = block.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                        match block_type {
-                            "text" => {
-                                if let Some(text) = block.get("text").and_then(|v| v.as_str()) {
-                                    parts_list.push(serde_json::json!({"text": text}));
-                                }
-                            }
-                            "tool_use" => {
-                                // Convert to functionCall format with thoughtSignature if present
-                                let mut fc_part = serde_json::json!({
-                                    "functionCall": {
-                                        "name": block.get("name"),
-                                        "args": block.get("input")
-                                    }
-                                });
-                                // Include thoughtSignature if present (for Gemini 3)
-                                if let Some(sig) = block.get("thought_signature").and_then(|v| v.as_str()) {
-                                    fc_part["thoughtSignature"] = serde_json::json!(sig);
-                                }
-                                parts_list.push(fc_part);
-                            }
-                            "tool_result" => {
-                                // Convert to functionResponse format with thoughtSignature
-                                let tool_use_id = block.get("tool_use_id").and_then(|v| v.as_str()).unwrap_or("unknown");
-
-                                let mut fr_part = serde_json::json!({
-                                    "functionResponse": {
-                                        "name": tool_use_id,
-                                        "response": {
-                                            "content": block.get("content")
-                                        }
-                                    }
-                                });
-
-                                // Include thoughtSignature from matching tool_use (required for Gemini 3)
-                                if let Some(sig) = thought_signatures.get(tool_use_id) {
-                                    fr_part["thoughtSignature"] = serde_json::json!(sig);
-                                }
-
-                                parts_list.push(fr_part);
-                            }
-                            _ => {}
-                        }
-                    }
-                    parts_list
-                }
-            };
+    /// Execute a tool whose start event was already emitted
+    async fn finish_tool(&self, tool_use: &ToolUse, event_tx: &mpsc::Sender<AgentEvent>) -> ToolResult {
+        let result = self.execute_tool(tool_use, event_tx).await;
+        self.emit_tool_end(tool_use, &result, event_tx).await;
+        result
+    }
 
-            if !parts.is_empty() {
-                contents.push(serde_json::json!({
-                    "role": role,
-                    "parts": parts
-                }));
-            }
+    /// Execute a tool unless the permission policy blocks it
+    async fn execute_tool(&self, tool_use: &ToolUse, event_tx: &mpsc::Sender<AgentEvent>) -> ToolResult {
+        match self.check_permission(tool_use, event_tx).await {
+            Some(denied) => denied,
+            None => tokio::select! {
+                result = self.tool_executor.execute_with_output(tool_use, Some(event_tx.clone())) => result,
+                _ = self.cancel_token.cancelled() => ToolResult::error(
+                    tool_use.id.clone(),
+                    "Cancelled by user".to_string(),
+                ),
+            },
         }
+    }
 
-        // Convert tools to Google functionDeclarations format
-        let function_declarations: Vec<serde_json::Value> = request.tools.iter().map(|tool| {
-            serde_json::json!({
-                "name": tool.name,
-                "description": tool.description,
-                "parameters": tool.input_schema
+    async fn emit_tool_start(&self, tool_use: &ToolUse, event_tx: &mpsc::Sender<AgentEvent>) {
+        let _ = event_tx
+            .send(AgentEvent::ToolStart {
+                tool_use_id: tool_use.id.clone(),
+                tool: tool_use.name.clone(),
+                input: tool_use.input.clone(),
             })
-        }).collect();
-
-        let mut google_request = serde_json::json!({
-            "contents": contents,
-            "generationConfig": {
-                "maxOutputTokens": request.max_tokens
-            }
-        });
-
-        // Add system instruction if present
-        if !request.system.is_empty() {
-            google_request["systemInstruction"] = serde_json::json!({
-                "parts": [{"text": request.system}]
-            });
-        }
-
-        // Add tools if present
-        if !function_declarations.is_empty() {
-            google_request["tools"] = serde_json::json!([{
-                "functionDeclarations": function_declarations
-            }]);
-        }
-
-        google_request
+            .await;
     }
 
-    /// Handle Google Gemini streaming response
-    async fn handle_google_stream_response(
+    async fn emit_tool_end(
         &self,
-        response: reqwest::Response,
+        tool_use: &ToolUse,
+        result: &ToolResult,
         event_tx: &mpsc::Sender<AgentEvent>,
-    ) -> Result<serde_json::Value, String> {
-        use futures::StreamExt;
-
-        let mut stream = response.bytes_stream();
-        let mut buffer = String::new();
-        let mut accumulated_text = String::new();
-        let mut tool_calls: Vec<serde_json::Value> = Vec::new();
-
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
-            buffer.push_str(&String::from_utf8_lossy(&chunk));
-
-            while let Some(pos) = buffer.find('\n') {
-                let line = buffer[..pos].trim().to_string();
-                buffer = buffer[pos + 1..].to_string();
-
-                if line.is_empty() {
-                    continue;
-                }
+    ) {
+        let _ = event_tx
+            .send(AgentEvent::ToolEnd {
+                tool_use_id: tool_use.id.clone(),
+                tool: tool_use.name.clone(),
+                result: result.content.clone(),
+                success: result.is_error.is_none(),
+            })
+            .await;
+    }
 
-                // Parse SSE data: prefix
-                let json_str = if let Some(data) = line.strip_prefix("data: ") {
-                    data
-                } else {
-                    continue;
+    /// Apply the tool permission policy. Returns an error result if the call must not run.
+    async fn check_permission(
+        &self,
+        tool_use: &ToolUse,
+        event_tx: &mpsc::Sender<AgentEvent>,
+    ) -> Option<ToolResult> {
+        match self.config.permission_for(&tool_use.name) {
+            ToolPermission::Allow => None,
+            ToolPermission::Deny => Some(ToolResult::error(
+                tool_use.id.clone(),
+                disabled_message(&tool_use.name),
+            )),
+            ToolPermission::Ask => {
+                let Some(approval_manager) = &self.approval_manager else {
+                    return Some(ToolResult::error(
+                        tool_use.id.clone(),
+                        format!("The '{}' tool requires user approval, which is not available here", tool_use.name),
+                    ));
                 };
 
-                if let Ok(event) = serde_json::from_str::<serde_json::Value>(json_str) {
-                    // Extract text and function calls from candidates
-                    if let Some(candidates) = event.get("candidates").and_then(|v| v.as_array()) {
-                        for candidate in candidates {
-                            if let Some(parts) = candidate.get("content")
-                                .and_then(|c| c.get("parts"))
-                                .and_then(|p| p.as_array())
-                            {
-                                for part in parts {
-                                    // Handle text
-                                    if let Some(text) = part.get("text").and_then(|v| v.as_str()) {
-                                        if !text.is_empty() {
-                                            accumulated_text.push_str(text);
-                                            let _ = event_tx.send(AgentEvent::Text {
-                                                content: accumulated_text.clone(),
-                                            }).await;
-                                        }
-                                    }
-                                    // Handle function calls (with thoughtSignature for Gemini 3)
-                                    if let Some(fc) = part.get("functionCall") {
-                                        let name = fc.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                        let args = fc.get("args").cloned().unwrap_or(serde_json::json!({}));
-                                        let id = format!("fc_{}", uuid::Uuid::new_v4());
-
-                                        // Capture thoughtSignature from the same part (required for Gemini 3)
-                                        let thought_sig = part.get("thoughtSignature").and_then(|v| v.as_str());
-
-                                        let mut tool_use = serde_json::json!({
-                                            "type": "tool_use",
-                                            "id": id,
-                                            "name": name,
-                                            "input": args
-                                        });
-
-                                        // Store thoughtSignature with tool_use for later use in functionResponse
-                                        if let Some(sig) = thought_sig {
-                                            tool_use["thought_signature"] = serde_json::json!(sig);
-                                        }
-
-                                        tool_calls.push(tool_use);
-                                    }
-                                }
-                            }
-                        }
-                    }
+                let (request_id, rx) = approval_manager.register();
+                let _ = event_tx
+                    .send(AgentEvent::ApprovalRequired {
+                        request_id: request_id.clone(),
+                        tool: tool_use.name.clone(),
+                        input: tool_use.input.clone(),
+                    })
+                    .await;
+
+                let decision = approval_manager.wait(&request_id, rx, &self.cancel_token).await;
+                if let ApprovalDecision::Approved = decision {
+                    return None;
                 }
+                denial_message(&tool_use.name, &decision)
+                    .map(|message| ToolResult::error(tool_use.id.clone(), message))
             }
         }
-
-        // Build Claude format response
-        let mut content = Vec::new();
-        if !accumulated_text.is_empty() {
-            content.push(serde_json::json!({
-                "type": "text",
-                "text": accumulated_text
-            }));
-        }
-        content.extend(tool_calls);
-
-        Ok(serde_json::json!({
-            "content": content
-        }))
     }
 
-    /// Handle OpenAI streaming response
-    async fn handle_openai_stream_response(
+    /// Send a request to the active provider, retrying transient failures and
+    /// moving down the fallback chain when a provider stays unavailable.
+    /// Once a fallback is used, the rest of the run stays on it.
+    async fn send_request(
         &self,
-        response: reqwest::Response,
+        request: &ClaudeApiRequest,
         event_tx: &mpsc::Sender<AgentEvent>,
-    ) -> Result<serde_json::Value, String> {
-        use futures::StreamExt;
-
-        let mut stream = response.bytes_stream();
-        let mut buffer = String::new();
-        let mut accumulated_text = String::new();
-        let mut tool_calls: Vec<serde_json::Value> = Vec::new();
-        let mut current_tool_calls: std::collections::HashMap<i64, (String, String, String)> = std::collections::HashMap::new();
-
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
-            buffer.push_str(&String::from_utf8_lossy(&chunk));
-
-            while let Some(pos) = buffer.find('\n') {
-                let line = buffer[..pos].to_string();
-                buffer = buffer[pos + 1..].to_string();
-
-                if let Some(data) = line.strip_prefix("data: ") {
-                    if data.trim() == "[DONE]" {
-                        continue;
-                    }
+    ) -> Result<ProviderResponse, String> {
+        let mut index = self.active_endpoint.load(Ordering::Relaxed);
 
-                    if let Ok(event) = serde_json::from_str::<serde_json::Value>(data) {
-                        if let Some(choices) = event.get("choices").and_then(|v| v.as_array()) {
-                            for choice in choices {
-                                if let Some(delta) = choice.get("delta") {
-                                    // Handle text content
-                                    if let Some(content) = delta.get("content").and_then(|v| v.as_str()) {
-                                        accumulated_text.push_str(content);
-                                        let _ = event_tx.send(AgentEvent::Text {
-                                            content: accumulated_text.clone(),
-                                        }).await;
-                                    }
-
-                                    // Handle tool_calls
-                                    if let Some(tcs) = delta.get("tool_calls").and_then(|v| v.as_array()) {
-                                        for tc in tcs {
-                                            let index = tc.get("index").and_then(|v| v.as_i64()).unwrap_or(0);
-
-                                            let entry = current_tool_calls.entry(index).or_insert_with(|| {
-                                                (String::new(), String::new(), String::new())
-                                            });
-
-                                            if let Some(id) = tc.get("id").and_then(|v| v.as_str()) {
-                                                entry.0 = id.to_string();
-                                            }
-                                            if let Some(func) = tc.get("function") {
-                                                if let Some(name) = func.get("name").and_then(|v| v.as_str()) {
-                                                    entry.1 = name.to_string();
-                                                }
-                                                if let Some(args) = func.get("arguments").and_then(|v| v.as_str()) {
-                                                    entry.2.push_str(args);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-
-                                // Check if finished
-                                if choice.get("finish_reason").and_then(|v| v.as_str()).is_some() {
-                                    // When finished, convert collected tool_calls to Claude format
-                                    for (id, name, args) in current_tool_calls.values() {
-                                        if !id.is_empty() && !name.is_empty() {
-                                            let input: serde_json::Value = serde_json::from_str(args)
-                                                .unwrap_or(serde_json::json!({}));
-                                            tool_calls.push(serde_json::json!({
-                                                "type": "tool_use",
-                                                "id": id,
-                                                "name": name,
-                                                "input": input
-                                            }));
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        loop {
+            let endpoint = &self.endpoints[index];
+            let error = match self.send_with_retry(endpoint, request, event_tx).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
 
-        // Build Claude format response
-        let mut content = Vec::new();
-        if !accumulated_text.is_empty() {
-            content.push(serde_json::json!({
-                "type": "text",
-                "text": accumulated_text
-            }));
-        }
-        content.extend(tool_calls);
+            let Some(next) = self.endpoints.get(index + 1).filter(|_| error.is_retryable()) else {
+                return Err(error.into());
+            };
 
-        Ok(serde_json::json!({
-            "content": content
-        }))
+            let _ = event_tx
+                .send(AgentEvent::Failover {
+                    from_provider: endpoint.config.id.clone(),
+                    to_provider: next.config.id.clone(),
+                    model: next.model.clone(),
+                    error: error.to_string(),
+                })
+                .await;
+
+            index += 1;
+            self.active_endpoint.store(index, Ordering::Relaxed);
+        }
     }
 
-    async fn handle_stream_response(
+    /// Send a request to one endpoint, retrying transient failures with backoff
+    async fn send_with_retry(
         &self,
-        response: reqwest::Response,
+        endpoint: &ProviderEndpoint,
+        request: &ClaudeApiRequest,
         event_tx: &mpsc::Sender<AgentEvent>,
-    ) -> Result<serde_json::Value, String> {
-        use futures::StreamExt;
-
-        let mut stream = response.bytes_stream();
-        let mut buffer = String::new();
-        let mut full_response: Option<serde_json::Value> = None;
-        let mut accumulated_text = String::new();
-        let mut tool_uses: Vec<serde_json::Value> = Vec::new();
-        let mut current_tool_input = String::new();
-        let mut current_tool_id = String::new();
-        let mut current_tool_name = String::new();
-
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| format!("Stream error: {}", e))?;
-            buffer.push_str(&String::from_utf8_lossy(&chunk));
-
-            while let Some(pos) = buffer.find('\n') {
-                let line = buffer[..pos].to_string();
-                buffer = buffer[pos + 1..].to_string();
-
-                if let Some(data) = line.strip_prefix("data: ") {
-                    if data == "[DONE]" {
-                        continue;
-                    }
+    ) -> Result<ProviderResponse, RequestError> {
+        // Fallback providers may serve a different model
+        let mut request = request.clone();
+        request.model = endpoint.model.clone();
 
-                    if let Ok(event) = serde_json::from_str::<serde_json::Value>(data) {
-                        let event_type = event.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                        match event_type {
-                            "content_block_start" => {
-                                if let Some(block) = event.get("content_block") {
-                                    if block.get("type").and_then(|v| v.as_str()) == Some("tool_use") {
-                                        current_tool_id = block
-                                            .get("id")
-                                            .and_then(|v| v.as_str())
-                                            .unwrap_or("")
-                                            .to_string();
-                                        current_tool_name = block
-                                            .get("name")
-                                            .and_then(|v| v.as_str())
-                                            .unwrap_or("")
-                                            .to_string();
-                                        current_tool_input.clear();
-                                    }
-                                }
-                            }
-                            "content_block_delta" => {
-                                if let Some(delta) = event.get("delta") {
-                                    let delta_type = delta.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                                    if delta_type == "text_delta" {
-                                        if let Some(text) = delta.get("text").and_then(|v| v.as_str()) {
-                                            accumulated_text.push_str(text);
-                                            // Emit streaming text
-                                            let _ = event_tx
-                                                .send(AgentEvent::Text {
-                                                    content: accumulated_text.clone(),
-                                                })
-                                                .await;
-                                        }
-                                    } else if delta_type == "input_json_delta" {
-                                        if let Some(partial) = delta.get("partial_json").and_then(|v| v.as_str()) {
-                                            current_tool_input.push_str(partial);
-                                        }
-                                    }
-                                }
-                            }
-                            "content_block_stop" => {
-                                if !current_tool_id.is_empty() {
-                                    let input: serde_json::Value = serde_json::from_str(&current_tool_input)
-                                        .unwrap_or(serde_json::json!({}));
-
-                                    tool_uses.push(serde_json::json!({
-                                        "type": "tool_use",
-                                        "id": current_tool_id,
-                                        "name": current_tool_name,
-                                        "input": input
-                                    }));
-
-                                    current_tool_id.clear();
-                                    current_tool_name.clear();
-                                    current_tool_input.clear();
-                                }
-                            }
-                            "message_stop" => {
-                                // Build final response
-                                let mut content = Vec::new();
-                                if !accumulated_text.is_empty() {
-                                    content.push(serde_json::json!({
-                                        "type": "text",
-                                        "text": accumulated_text
-                                    }));
-                                }
-                                content.extend(tool_uses.clone());
-
-                                full_response = Some(serde_json::json!({
-                                    "content": content
-                                }));
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+        let mut attempt = 0;
+        loop {
+            let error = match self.send_to_endpoint(endpoint, &request, event_tx).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            let RequestError::Retryable { message, retry_after } = error else {
+                return Err(error);
+            };
+            if attempt >= self.config.max_retries {
+                return Err(RequestError::Retryable { message, retry_after });
             }
-        }
 
-        full_response.ok_or_else(|| "No response received".to_string())
+            attempt += 1;
+            let delay = retry_delay(attempt, retry_after);
+            let _ = event_tx
+                .send(AgentEvent::Retrying {
+                    provider: endpoint.config.id.clone(),
+                    attempt,
+                    max_retries: self.config.max_retries,
+                    delay_ms: delay.as_millis() as u64,
+                    error: message,
+                })
+                .await;
+            tokio::time::sleep(delay).await;
+        }
     }
 
-    fn parse_response(
+    /// Stream a request to one endpoint, emitting the text as it arrives
+    async fn send_to_endpoint(
         &self,
-        response: &serde_json::Value,
-    ) -> Result<(String, Vec<ToolUse>), String> {
-        let content = response
-            .get("content")
-            .and_then(|v| v.as_array())
-            .ok_or("Invalid response: missing content")?;
-
-        let mut text_parts = Vec::new();
-        let mut tool_uses = Vec::new();
-
-        for block in content {
-            let block_type = block.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-            match block_type {
-                "text" => {
-                    if let Some(text) = block.get("text").and_then(|v| v.as_str()) {
-                        text_parts.push(text.to_string());
-                    }
-                }
-                "tool_use" => {
-                    let id = block
-                        .get("id")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string();
-                    let name = block
-                        .get("name")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string();
-                    let input = block.get("input").cloned().unwrap_or(serde_json::json!({}));
-                    let thought_signature = block
-                        .get("thought_signature")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string());
-
-                    tool_uses.push(ToolUse { id, name, input, thought_signature });
-                }
-                _ => {}
+        endpoint: &ProviderEndpoint,
+        request: &ClaudeApiRequest,
+        event_tx: &mpsc::Sender<AgentEvent>,
+    ) -> Result<ProviderResponse, RequestError> {
+        let mut stream = providers::stream_chat(&self.client, endpoint, request).await?;
+        let mut response = ProviderResponse::default();
+
+        while let Some(event) = stream.next().await? {
+            response.apply(&event);
+            if let StreamEvent::Text(_) = event {
+                let _ = event_tx
+                    .send(AgentEvent::Text {
+                        content: response.text.clone(),
+                    })
+                    .await;
             }
         }
 
-        Ok((text_parts.join(""), tool_uses))
+        Ok(response)
     }
 
     /// Parse plan from text content
@@ -1029,8 +744,31 @@ impl AgentLoop {
     }
 }
 
+/// Tool calls of a trailing assistant message, which have no results yet
+pub fn pending_tool_uses(messages: &[AgentMessage]) -> Vec<ToolUse> {
+    let Some(AgentMessage { role, content: AgentContent::Blocks(blocks) }) = messages.last() else {
+        return Vec::new();
+    };
+    if role != "assistant" {
+        return Vec::new();
+    }
+
+    blocks
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::ToolUse { id, name, input, thought_signature } => Some(ToolUse {
+                id: id.clone(),
+                name: name.clone(),
+                input: input.clone(),
+                thought_signature: thought_signature.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
 // Make ClaudeApiRequest cloneable for non-stream fallback
-impl Clone for crate::agent::message_builder::ClaudeApiRequest {
+impl Clone for ClaudeApiRequest {
     fn clone(&self) -> Self {
         Self {
             model: self.model.clone(),
@@ -1043,3 +781,33 @@ impl Clone for crate::agent::message_builder::ClaudeApiRequest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_tool_uses_only_from_trailing_assistant() {
+        let call = AgentMessage {
+            role: "assistant".to_string(),
+            content: AgentContent::Blocks(vec![
+                ContentBlock::Text { text: "Checking".to_string() },
+                ContentBlock::ToolUse {
+                    id: "t1".to_string(),
+                    name: "bash".to_string(),
+                    input: serde_json::json!({ "command": "ls" }),
+                    thought_signature: None,
+                },
+            ]),
+        };
+        let results = AgentMessage {
+            role: "user".to_string(),
+            content: AgentContent::ToolResults(vec![ToolResult::success("t1".to_string(), "a.txt".to_string())]),
+        };
+
+        let pending = pending_tool_uses(std::slice::from_ref(&call));
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, "t1");
+        assert!(pending_tool_uses(&[call, results]).is_empty());
+    }
+}
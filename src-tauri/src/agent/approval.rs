@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+
+/// A tool call left unanswered this long is denied, so a run never waits forever
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Per-tool permission policy, ordered from least to most restrictive
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolPermission {
+    /// Run the tool without asking
+    #[default]
+    Allow,
+    /// Pause the agent until the user approves or denies the call
+    Ask,
+    /// Never run the tool
+    Deny,
+}
+
+/// User decision for a pending tool call
+#[derive(Debug, Clone)]
+pub enum ApprovalDecision {
+    Approved,
+    Denied { reason: Option<String> },
+}
+
+/// Tracks tool calls that are waiting for the user's approval.
+/// The agent registers a request and awaits the receiver; the
+/// approve/deny commands resolve it by request ID.
+pub struct ApprovalManager {
+    pending: Mutex<HashMap<String, oneshot::Sender<ApprovalDecision>>>,
+}
+
+impl ApprovalManager {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a new pending approval. Returns the request ID and the receiver to await.
+    pub fn register(&self) -> (String, oneshot::Receiver<ApprovalDecision>) {
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(request_id.clone(), tx);
+        (request_id, rx)
+    }
+
+    /// Resolve a pending approval with the user's decision
+    pub fn resolve(&self, request_id: &str, decision: ApprovalDecision) -> Result<(), String> {
+        let sender = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(request_id)
+            .ok_or_else(|| format!("No pending approval with id {}", request_id))?;
+
+        sender
+            .send(decision)
+            .map_err(|_| "The agent is no longer waiting for this approval".to_string())
+    }
+
+    /// Wait for the decision on a registered request. A dropped request,
+    /// a cancelled run or no answer within the timeout counts as a denial.
+    pub async fn wait(
+        &self,
+        request_id: &str,
+        rx: oneshot::Receiver<ApprovalDecision>,
+        cancel: &CancellationToken,
+    ) -> ApprovalDecision {
+        let decision = tokio::select! {
+            decision = rx => decision.unwrap_or(ApprovalDecision::Denied {
+                reason: Some("Approval request was dropped".to_string()),
+            }),
+            _ = cancel.cancelled() => ApprovalDecision::Denied {
+                reason: Some("Cancelled by user".to_string()),
+            },
+            _ = tokio::time::sleep(APPROVAL_TIMEOUT) => ApprovalDecision::Denied {
+                reason: Some(format!("No answer within {} minutes", APPROVAL_TIMEOUT.as_secs() / 60)),
+            },
+        };
+
+        self.pending.lock().unwrap().remove(request_id);
+        decision
+    }
+}
+
+impl Default for ApprovalManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error message returned to the model for a tool whose policy is "deny"
+pub fn disabled_message(tool_name: &str) -> String {
+    format!(
+        "The '{}' tool is disabled by the tool permission settings",
+        tool_name
+    )
+}
+
+/// Error message returned to the model when the user did not approve a tool call
+pub fn denial_message(tool_name: &str, decision: &ApprovalDecision) -> Option<String> {
+    match decision {
+        ApprovalDecision::Approved => None,
+        ApprovalDecision::Denied { reason: Some(reason) } if !reason.is_empty() => Some(format!(
+            "The user denied the '{}' tool call: {}",
+            tool_name, reason
+        )),
+        ApprovalDecision::Denied { .. } => {
+            Some(format!("The user denied the '{}' tool call", tool_name))
+        }
+    }
+}
@@ -1,7 +1,8 @@
+use crate::agent::file_tracker::FileTracker;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const MAX_FILE_SIZE_BYTES: u64 = 100 * 1024 * 1024; // 100MB
@@ -27,51 +28,60 @@ pub enum UndoAction {
         original_path: String, // Where it was
         timestamp: u64,
     },
+    /// Restore every file changed by one patch
+    PatchRestore {
+        files: Vec<PatchedFile>,
+        timestamp: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PatchedFile {
+    pub target_path: String,
+    /// `None` when the patch created the file, so undo removes it
+    pub backup_path: Option<String>,
 }
 
 pub struct BackupManager {
     /// Maps conversation_id to a list of undo actions (stack)
     history: Mutex<std::collections::HashMap<String, Vec<UndoAction>>>,
+    /// Versions of the files each conversation's agent has seen
+    trackers: Mutex<std::collections::HashMap<String, Arc<FileTracker>>>,
 }
 
 impl BackupManager {
     pub fn new() -> Self {
         Self {
             history: Mutex::new(std::collections::HashMap::new()),
+            trackers: Mutex::new(std::collections::HashMap::new()),
         }
     }
 
-    /// Create a backup for writing/editing a file. Returns path to backup if successful.
-    pub fn create_backup(&self, conversation_id: &str, file_path: &Path) -> Result<Option<String>, String> {
-        if !file_path.exists() {
-            return Ok(None); // Nothing to backup if creating new file
-        }
+    /// File versions for a conversation, kept across its agent runs
+    pub fn file_tracker(&self, conversation_id: &str) -> Arc<FileTracker> {
+        self.trackers
+            .lock()
+            .unwrap()
+            .entry(conversation_id.to_string())
+            .or_default()
+            .clone()
+    }
 
+    /// Create a backup for writing/editing a file, stored under its content
+    /// version as returned by `FileTracker::check_unchanged`. Returns path to backup if successful.
+    pub fn create_backup(&self, conversation_id: &str, file_path: &Path, version: &str) -> Result<Option<String>, String> {
         let metadata = fs::metadata(file_path).map_err(|e| e.to_string())?;
         if metadata.len() > MAX_FILE_SIZE_BYTES {
             println!("Skipping backup for {}: file size {} exceeds limit", file_path.display(), metadata.len());
             return Ok(None);
         }
 
-        // Setup backup directory: .kuse/backups/<conversation_id>/<timestamp>/
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
-        
-        let project_root = std::env::current_dir().map_err(|e| e.to_string())?;
-        let backup_dir = project_root
-            .join(".kuse")
-            .join("backups")
-            .join(conversation_id)
-            .join(timestamp.to_string());
-
-        fs::create_dir_all(&backup_dir).map_err(|e| e.to_string())?;
-
-        let file_name = file_path.file_name().ok_or("Invalid file name")?;
-        let backup_path = backup_dir.join(file_name);
 
-        fs::copy(file_path, &backup_path).map_err(|e| e.to_string())?;
+        let backup_path = Self::store(conversation_id, file_path, version)?;
 
         let action = UndoAction::ContentRestore {
             target_path: file_path.to_string_lossy().to_string(),
@@ -84,6 +94,56 @@ impl BackupManager {
         Ok(Some(backup_path.to_string_lossy().to_string()))
     }
 
+    /// Back up the files a patch is about to change, each given with its
+    /// current version (`None` for files the patch creates). The returned
+    /// action is registered with `register_patch` once the patch has applied.
+    pub fn backup_patch(&self, conversation_id: &str, files: &[(&Path, Option<String>)]) -> Result<UndoAction, String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut patched = Vec::new();
+        for (file_path, version) in files {
+            let backup_path = match version {
+                Some(version) => Some(Self::store(conversation_id, file_path, version)?.to_string_lossy().to_string()),
+                None => None,
+            };
+
+            patched.push(PatchedFile {
+                target_path: file_path.to_string_lossy().to_string(),
+                backup_path,
+            });
+        }
+
+        Ok(UndoAction::PatchRestore {
+            files: patched,
+            timestamp,
+        })
+    }
+
+    /// Copy a file to .kuse/backups/<conversation_id>/<version>. Identical
+    /// content is stored once.
+    fn store(conversation_id: &str, file_path: &Path, version: &str) -> Result<PathBuf, String> {
+        let project_root = std::env::current_dir().map_err(|e| e.to_string())?;
+        let backup_dir = project_root
+            .join(".kuse")
+            .join("backups")
+            .join(conversation_id);
+        fs::create_dir_all(&backup_dir).map_err(|e| e.to_string())?;
+
+        let backup_path = backup_dir.join(version);
+        if !backup_path.exists() {
+            fs::copy(file_path, &backup_path).map_err(|e| e.to_string())?;
+        }
+        Ok(backup_path)
+    }
+
+    /// Register a patch backed up with `backup_patch` as a single undoable action
+    pub fn register_patch(&self, conversation_id: &str, action: UndoAction) {
+        self.push_history(conversation_id, action);
+    }
+
     /// Register a move operation (for Smart Undo)
     pub fn register_move(&self, conversation_id: &str, from: &Path, to: &Path) {
         let timestamp = SystemTime::now()
@@ -180,6 +240,30 @@ impl BackupManager {
                 fs::rename(&from, &to).map_err(|e| format!("Failed to restore from trash: {}", e))?;
                 Ok(format!("Restored {} from trash", original_path))
             }
+            UndoAction::PatchRestore { files, .. } => {
+                if let Some(missing) = files.iter().filter_map(|f| f.backup_path.as_ref()).find(|b| !Path::new(b).exists()) {
+                    return Err(format!("Backup file missing: {}", missing));
+                }
+
+                for file in &files {
+                    let target = PathBuf::from(&file.target_path);
+                    match &file.backup_path {
+                        Some(backup) => {
+                            if let Some(parent) = target.parent() {
+                                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                            }
+                            fs::copy(backup, &target)
+                                .map_err(|e| format!("Failed to restore {}: {}", file.target_path, e))?;
+                        }
+                        None if target.exists() => {
+                            fs::remove_file(&target)
+                                .map_err(|e| format!("Failed to remove {}: {}", file.target_path, e))?;
+                        }
+                        None => {}
+                    }
+                }
+                Ok(format!("Restored {} file(s) changed by a patch", files.len()))
+            }
         }
     }
 
@@ -189,5 +273,6 @@ impl BackupManager {
         // A full cleanup would require deleting the .kuse/backups/<id> folder.
         let mut history = self.history.lock().unwrap();
         history.remove(conversation_id);
+        self.trackers.lock().unwrap().remove(conversation_id);
     }
 }
@@ -0,0 +1,305 @@
+use crate::agent::{AgentContent, AgentMessage, ContentBlock};
+use crate::tools::bash::truncate_output;
+use serde::{Deserialize, Serialize};
+
+/// Rough characters-per-token ratio used for estimates
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Fraction of the input budget at which compaction kicks in
+const COMPACTION_THRESHOLD: f64 = 0.8;
+
+/// Tool results in the most recent messages are never truncated
+const KEEP_RECENT_MESSAGES: usize = 4;
+
+/// Older tool results are cut down to this many characters
+const TRUNCATED_RESULT_CHARS: usize = 2000;
+
+/// Rough token cost of an image attached to a tool result
+const IMAGE_TOKENS: usize = 1600;
+
+/// Tool results are cut to this length in the transcript sent for summarization
+const TRANSCRIPT_RESULT_CHARS: usize = 1000;
+
+/// Estimate the number of tokens in a string
+pub fn estimate_text_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Estimate the number of tokens a message takes in the request
+pub fn estimate_tokens(message: &AgentMessage) -> usize {
+    // Small per-message overhead for role and structure
+    let overhead = 4;
+    let content = match &message.content {
+        AgentContent::Text(text) => estimate_text_tokens(text),
+        AgentContent::Blocks(blocks) => blocks
+            .iter()
+            .map(|block| match block {
+                ContentBlock::Text { text } => estimate_text_tokens(text),
+                ContentBlock::ToolUse { name, input, .. } => {
+                    estimate_text_tokens(name) + estimate_text_tokens(&input.to_string())
+                }
+            })
+            .sum(),
+        AgentContent::ToolResults(results) => results
+            .iter()
+            .map(|r| estimate_text_tokens(&r.content) + r.images.len() * IMAGE_TOKENS + 8)
+            .sum(),
+    };
+    overhead + content
+}
+
+/// Estimate the tokens of a whole history
+pub fn estimate_messages_tokens(messages: &[AgentMessage]) -> usize {
+    messages.iter().map(estimate_tokens).sum()
+}
+
+/// Context window size (in tokens) for a model
+pub fn context_window(model: &str) -> usize {
+    let model = model.to_lowercase();
+
+    if model.contains("claude") {
+        200_000
+    } else if model.contains("gemini") || model.contains("gpt-4.1") {
+        1_000_000
+    } else if model.contains("gpt-5") {
+        400_000
+    } else if model.starts_with("o1") || model.starts_with("o3") || model.starts_with("o4") {
+        200_000
+    } else if model.contains("gpt-4o") || model.contains("gpt-4-turbo") {
+        128_000
+    } else if model.contains("gpt-4") {
+        8_192
+    } else if model.contains("gpt-3.5") {
+        16_385
+    } else if model.contains("deepseek") {
+        64_000
+    } else {
+        // Conservative default for unknown / local models (qwen, llama, mistral, ...)
+        32_768
+    }
+}
+
+/// Token budget for the input messages of a request
+#[derive(Debug, Clone, Copy)]
+pub struct ContextBudget {
+    /// Tokens available for messages after the system prompt, tools and output
+    pub available: usize,
+}
+
+impl ContextBudget {
+    pub fn new(model: &str, max_output_tokens: u32, fixed_tokens: usize) -> Self {
+        let available = context_window(model)
+            .saturating_sub(max_output_tokens as usize)
+            .saturating_sub(fixed_tokens);
+        Self { available }
+    }
+
+    /// Token count above which the history should be compacted
+    pub fn threshold(&self) -> usize {
+        (self.available as f64 * COMPACTION_THRESHOLD) as usize
+    }
+
+    pub fn is_exceeded(&self, messages: &[AgentMessage]) -> bool {
+        estimate_messages_tokens(messages) > self.threshold()
+    }
+
+    /// Maximum transcript length (in characters) for a summarization request
+    pub fn max_transcript_chars(&self) -> usize {
+        self.threshold() * CHARS_PER_TOKEN
+    }
+}
+
+/// First compaction stage: shorten tool results outside the most recent messages.
+/// Returns the number of results that were truncated.
+pub fn truncate_old_tool_results(messages: &mut [AgentMessage]) -> usize {
+    let recent_start = messages.len().saturating_sub(KEEP_RECENT_MESSAGES);
+    let mut truncated = 0;
+
+    for message in &mut messages[..recent_start] {
+        if let AgentContent::ToolResults(results) = &mut message.content {
+            for result in results.iter_mut() {
+                let long = result.content.chars().count() > TRUNCATED_RESULT_CHARS;
+                if long {
+                    result.content = truncate_output(&result.content, TRUNCATED_RESULT_CHARS);
+                }
+                // The text still describes dropped images
+                if long || !result.images.is_empty() {
+                    result.images.clear();
+                    truncated += 1;
+                }
+            }
+        }
+    }
+
+    truncated
+}
+
+/// Pick where to split the history for summarization. Messages before the
+/// returned index are summarized; the rest are kept verbatim. The kept part
+/// fits in `keep_tokens` and never starts with tool results, whose matching
+/// tool calls would otherwise be summarized away.
+pub fn summary_split_point(messages: &[AgentMessage], keep_tokens: usize) -> Option<usize> {
+    let mut split = messages.len();
+    let mut kept_tokens = 0;
+
+    while split > 0 {
+        let tokens = estimate_tokens(&messages[split - 1]);
+        // Always keep the latest message, even if it is large
+        if split < messages.len() && kept_tokens + tokens > keep_tokens {
+            break;
+        }
+        kept_tokens += tokens;
+        split -= 1;
+    }
+
+    // Don't start the kept part with tool results
+    while split < messages.len()
+        && matches!(messages[split].content, AgentContent::ToolResults(_))
+    {
+        split += 1;
+    }
+
+    (split > 0 && split < messages.len()).then_some(split)
+}
+
+/// Render messages as a plain-text transcript for the summarization request
+pub fn render_transcript(messages: &[AgentMessage]) -> String {
+    let mut transcript = String::new();
+
+    for message in messages {
+        match &message.content {
+            AgentContent::Text(text) => {
+                transcript.push_str(&format!("[{}]\n{}\n\n", message.role, text));
+            }
+            AgentContent::Blocks(blocks) => {
+                transcript.push_str(&format!("[{}]\n", message.role));
+                for block in blocks {
+                    match block {
+                        ContentBlock::Text { text } => {
+                            transcript.push_str(text);
+                            transcript.push('\n');
+                        }
+                        ContentBlock::ToolUse { name, input, .. } => {
+                            transcript.push_str(&format!("(called tool {} with {})\n", name, input));
+                        }
+                    }
+                }
+                transcript.push('\n');
+            }
+            AgentContent::ToolResults(results) => {
+                for result in results {
+                    let label = if result.is_error.is_some() { "tool error" } else { "tool result" };
+                    transcript.push_str(&format!(
+                        "[{}]\n{}\n\n",
+                        label,
+                        truncate_output(&result.content, TRANSCRIPT_RESULT_CHARS)
+                    ));
+                }
+            }
+        }
+    }
+
+    transcript
+}
+
+/// System prompt for the summarization request
+pub const SUMMARY_SYSTEM_PROMPT: &str = "You compress the history of an agent session so the work can continue with a smaller context. \
+Write a concise summary of the transcript you are given. Keep the user's original request and any later instructions, \
+decisions that were made, files that were read or changed (with paths), commands run and their important results, \
+errors encountered, and what remains to be done. Do not invent details. Reply with the summary only.";
+
+/// Build the message that replaces the summarized part of the history
+pub fn summary_message(summary: &str) -> AgentMessage {
+    AgentMessage {
+        role: "user".to_string(),
+        content: AgentContent::Text(format!(
+            "[Summary of the earlier part of this session, condensed to save context]\n\n{}",
+            summary
+        )),
+    }
+}
+
+/// Persisted form of a compaction, stored as a task message with role "compaction".
+/// When loading a task, everything before it is replaced by the summary and
+/// the messages that were kept verbatim.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactionRecord {
+    pub summary: String,
+    pub kept: Vec<AgentMessage>,
+}
+
+impl CompactionRecord {
+    pub fn into_messages(self) -> Vec<AgentMessage> {
+        let mut messages = vec![summary_message(&self.summary)];
+        messages.extend(self.kept);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::ToolResult;
+
+    fn text(role: &str, text: &str) -> AgentMessage {
+        AgentMessage {
+            role: role.to_string(),
+            content: AgentContent::Text(text.to_string()),
+        }
+    }
+
+    fn tool_call(id: &str) -> AgentMessage {
+        AgentMessage {
+            role: "assistant".to_string(),
+            content: AgentContent::Blocks(vec![ContentBlock::ToolUse {
+                id: id.to_string(),
+                name: "read_file".to_string(),
+                input: serde_json::json!({ "path": "a.txt" }),
+                thought_signature: None,
+            }]),
+        }
+    }
+
+    fn tool_result(id: &str, content: &str) -> AgentMessage {
+        AgentMessage {
+            role: "user".to_string(),
+            content: AgentContent::ToolResults(vec![ToolResult::success(id.to_string(), content.to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_truncate_old_tool_results_keeps_recent() {
+        let big = "x".repeat(10_000);
+        let mut messages = vec![
+            text("user", "task"),
+            tool_call("1"),
+            tool_result("1", &big),
+            tool_call("2"),
+            tool_result("2", &big),
+            text("assistant", "done"),
+            text("user", "next"),
+        ];
+
+        assert_eq!(truncate_old_tool_results(&mut messages), 1);
+
+        let AgentContent::ToolResults(old) = &messages[2].content else { panic!() };
+        let AgentContent::ToolResults(recent) = &messages[4].content else { panic!() };
+        assert!(old[0].content.len() < big.len());
+        assert_eq!(recent[0].content.len(), big.len());
+    }
+
+    #[test]
+    fn test_summary_split_skips_tool_results() {
+        let messages = vec![
+            text("user", &"a".repeat(400)),
+            tool_call("1"),
+            tool_result("1", &"b".repeat(400)),
+            text("assistant", "short"),
+        ];
+
+        // Budget only fits the last message and the tool result
+        let split = summary_split_point(&messages, 120).unwrap();
+        assert!(!matches!(messages[split].content, AgentContent::ToolResults(_)));
+        assert_eq!(split, 3);
+    }
+}
@@ -0,0 +1,147 @@
+//! Versions of the files the agent has read or written. Writes to a file that
+//! changed on disk since are refused, so edits made meanwhile (say, in the
+//! user's editor) are not silently overwritten.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Larger files are identified by size and modification time instead of a
+/// hash, so reading a window of a huge file stays cheap
+const MAX_HASHED_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Version of a file's content: the SHA-256 of its bytes as hex, or `None`
+/// when the file doesn't exist
+pub fn file_version(path: &Path) -> Result<Option<String>, String> {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    if metadata.len() > MAX_HASHED_BYTES {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        return Ok(Some(format!("{}-{}", metadata.len(), modified)));
+    }
+
+    let mut hasher = Sha256::new();
+    File::open(path)
+        .and_then(|mut file| io::copy(&mut file, &mut hasher))
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+#[derive(Default)]
+pub struct FileTracker {
+    /// Version of each file as the agent last saw it
+    versions: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl FileTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember the current version of a file after reading or writing it
+    pub fn record(&self, path: &Path) {
+        let version = file_version(path);
+        let mut versions = self.versions.lock().unwrap();
+        match version {
+            Ok(Some(version)) => {
+                versions.insert(path.to_path_buf(), version);
+            }
+            _ => {
+                versions.remove(path);
+            }
+        }
+    }
+
+    pub fn forget(&self, path: &Path) {
+        self.versions.lock().unwrap().remove(path);
+    }
+
+    /// Carry a file's version over to where it was moved
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let mut versions = self.versions.lock().unwrap();
+        versions.remove(to);
+        if let Some(version) = versions.remove(from) {
+            versions.insert(to.to_path_buf(), version);
+        }
+    }
+
+    /// Check that a file is as the agent last saw it before changing it, and
+    /// return its current version. Files the agent hasn't seen pass.
+    pub fn check_unchanged(&self, path: &Path) -> Result<Option<String>, String> {
+        let current = file_version(path)?;
+        let versions = self.versions.lock().unwrap();
+        let Some(known) = versions.get(path) else {
+            return Ok(current);
+        };
+
+        match &current {
+            Some(version) if version == known => Ok(current),
+            Some(_) => Err(format!(
+                "{} has changed on disk since it was last read. Read it again with read_file and redo the change, so edits made in the meantime are not overwritten.",
+                path.display()
+            )),
+            None => Err(format!(
+                "{} was deleted since it was last read. Check whether it should still exist before writing it.",
+                path.display()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_unseen_and_new_files_pass() {
+        let dir = TempDir::new("tracker");
+        let path = dir.join("notes.md");
+        let tracker = FileTracker::new();
+
+        assert_eq!(tracker.check_unchanged(&path).unwrap(), None);
+        fs::write(&path, "draft").unwrap();
+        assert!(tracker.check_unchanged(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_recorded_file_passes_until_edited() {
+        let dir = TempDir::new("tracker");
+        let path = dir.write("notes.md", "draft");
+        let tracker = FileTracker::new();
+
+        tracker.record(&path);
+        assert_eq!(tracker.check_unchanged(&path).unwrap(), file_version(&path).unwrap());
+
+        fs::write(&path, "edited by the user").unwrap();
+        assert!(tracker.check_unchanged(&path).unwrap_err().contains("changed on disk"));
+
+        tracker.record(&path);
+        assert!(tracker.check_unchanged(&path).is_ok());
+    }
+
+    #[test]
+    fn test_deleted_file_is_reported() {
+        let dir = TempDir::new("tracker");
+        let path = dir.write("notes.md", "draft");
+        let tracker = FileTracker::new();
+
+        tracker.record(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(tracker.check_unchanged(&path).unwrap_err().contains("was deleted"));
+    }
+}
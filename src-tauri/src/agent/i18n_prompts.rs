@@ -13,7 +13,7 @@ You have access to a set of valid tools that allow you to interact with the file
 
 <available_tools>
 You can ONLY use these tools:
-- `list_dir`, `read_file`, `write_file`, `edit_file`, `glob`, `grep`, `bash`, `move_file`, `delete_file`.
+- `list_dir`, `read_file`, `write_file`, `edit_file`, `apply_patch`, `glob`, `grep`, `bash`, `move_file`, `delete_file`.
 </available_tools>
 
 <plan_format>
@@ -66,7 +66,7 @@ fn get_zh_tw_prompt() -> String {
 
 <available_tools>
 你只能使用以下指定工具：
-- `list_dir`, `read_file`, `write_file`, `edit_file`, `glob`, `grep`, `bash`, `move_file`, `delete_file`。
+- `list_dir`, `read_file`, `write_file`, `edit_file`, `apply_patch`, `glob`, `grep`, `bash`, `move_file`, `delete_file`。
 </available_tools>
 
 <plan_format>
@@ -1,4 +1,6 @@
-use crate::agent::{AgentConfig, AgentContent, AgentMessage, ToolDefinition};
+use crate::agent::context::estimate_text_tokens;
+use crate::agent::tool_executor::mcp_tool_name;
+use crate::agent::{AgentConfig, AgentContent, AgentMessage, ToolDefinition, ToolResult};
 use crate::mcp::{MCPManager, MCPTool};
 use crate::tools;
 use serde::{Deserialize, Serialize};
@@ -85,27 +87,39 @@ impl MessageBuilder {
         }
     }
 
+    /// Estimated tokens used by the system prompt and tool definitions of a request
+    pub async fn fixed_tokens(&self) -> usize {
+        let request = self.build_request(&[]).await;
+        let tools = serde_json::to_string(&request.tools).unwrap_or_default();
+        estimate_text_tokens(&request.system) + estimate_text_tokens(&tools)
+    }
+
     async fn get_mcp_tools(&self, mcp_manager: &MCPManager) -> Vec<ToolDefinition> {
         let server_statuses = mcp_manager.get_server_statuses().await;
         let mut mcp_tools = Vec::new();
 
+        let mut has_resources = false;
+
         for status in server_statuses {
             if matches!(status.status, crate::mcp::types::ConnectionStatus::Connected) {
                 for tool in status.tools {
                     mcp_tools.push(Self::convert_mcp_tool_to_definition(&status.id, &status.name, &tool));
                 }
+                has_resources |= !status.resources.is_empty() || !status.resource_templates.is_empty();
             }
         }
 
+        // Resources are read through generic tools rather than one per resource
+        if has_resources {
+            mcp_tools.extend(tools::mcp_resources::get_resource_tools());
+        }
+
         mcp_tools
     }
 
     fn convert_mcp_tool_to_definition(server_id: &str, server_name: &str, mcp_tool: &MCPTool) -> ToolDefinition {
-        let safe_server_id = server_id.replace("-", "_").replace(":", "_");
-        let safe_tool_name = mcp_tool.name.replace("-", "_").replace(":", "_");
-
         ToolDefinition {
-            name: format!("mcp_{}_{}", safe_server_id, safe_tool_name),
+            name: mcp_tool_name(server_id, &mcp_tool.name),
             description: format!("{} (MCP tool from server '{}')", mcp_tool.description, server_name),
             input_schema: mcp_tool.input_schema.clone(),
         }
@@ -127,7 +141,7 @@ impl MessageBuilder {
                     AgentContent::ToolResults(results) => {
                         let json_results: Vec<serde_json::Value> = results
                             .iter()
-                            .map(|r| serde_json::to_value(r).unwrap_or_default())
+                            .map(tool_result_block)
                             .collect();
                         ApiContent::Blocks(json_results)
                     }
@@ -141,3 +155,27 @@ impl MessageBuilder {
             .collect()
     }
 }
+
+/// A tool result in Anthropic's format. Images turn the content into a list
+/// of text and image blocks.
+fn tool_result_block(result: &ToolResult) -> serde_json::Value {
+    let mut value = serde_json::to_value(result).unwrap_or_default();
+    if let Some(object) = value.as_object_mut() {
+        object.remove("images");
+        if !result.images.is_empty() {
+            let mut content = vec![serde_json::json!({ "type": "text", "text": result.content })];
+            content.extend(result.images.iter().map(|image| {
+                serde_json::json!({
+                    "type": "image",
+                    "source": {
+                        "type": "base64",
+                        "media_type": image.media_type,
+                        "data": image.data,
+                    }
+                })
+            }));
+            object.insert("content".to_string(), serde_json::Value::Array(content));
+        }
+    }
+    value
+}
@@ -1,11 +1,17 @@
 pub mod agent_loop;
+pub mod approval;
 pub mod backup;
+pub mod context;
+pub mod file_tracker;
 pub mod message_builder;
 pub mod tool_executor;
 pub mod types;
+pub mod usage;
 pub mod i18n_prompts;
 
-pub use agent_loop::AgentLoop;
+pub use agent_loop::{pending_tool_uses, AgentLoop};
+pub use approval::{ApprovalDecision, ApprovalManager, ToolPermission};
 pub use message_builder::MessageBuilder;
 pub use tool_executor::ToolExecutor;
 pub use types::*;
+pub use usage::{ModelPrice, TokenUsage};
@@ -1,24 +1,44 @@
-use crate::agent::{ToolResult, ToolUse};
+use crate::agent::{AgentEvent, ToolResult, ToolUse};
 use crate::agent::backup::BackupManager;
-use crate::mcp::{MCPManager, MCPToolCall};
+use crate::agent::file_tracker::FileTracker;
+use crate::mcp::{MCPManager, MCPProgress, MCPToolCall, ProgressCallback};
 use crate::tools;
+use crate::tools::paths::PathResolver;
+use crate::tools::sandbox::Sandbox;
+use crate::tools::shell_session::ShellSessions;
 use std::sync::Arc;
 use std::path::Path;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 pub struct ToolExecutor {
     project_path: Option<String>,
+    paths: PathResolver,
+    /// Persistent shell sessions and background processes started by the agent
+    shells: ShellSessions,
     mcp_manager: Option<Arc<MCPManager>>,
     backup_manager: Option<Arc<BackupManager>>,
     conversation_id: Option<String>,
+    /// Versions of the files the agent has read or written, checked before writes
+    files: Arc<FileTracker>,
+    /// Container bash commands run in, in sandbox mode
+    sandbox: Option<Arc<Sandbox>>,
+    /// Cancelled when the owning task is stopped; running tools abort on it
+    cancel_token: CancellationToken,
 }
 
 impl ToolExecutor {
     pub fn new(project_path: Option<String>) -> Self {
         Self {
+            paths: PathResolver::new(project_path.as_deref()),
+            shells: ShellSessions::new(project_path.clone()),
             project_path,
             mcp_manager: None,
             backup_manager: None,
             conversation_id: None,
+            files: Arc::new(FileTracker::new()),
+            sandbox: None,
+            cancel_token: CancellationToken::new(),
         }
     }
 
@@ -29,16 +49,71 @@ impl ToolExecutor {
 
     pub fn with_backup_manager(mut self, backup_manager: Arc<BackupManager>) -> Self {
         self.backup_manager = Some(backup_manager);
-        self
+        self.with_conversation_files()
     }
 
     pub fn with_conversation_id(mut self, conversation_id: String) -> Self {
         self.conversation_id = Some(conversation_id);
+        self.with_conversation_files()
+    }
+
+    /// Share file versions with earlier runs in the same conversation
+    fn with_conversation_files(mut self) -> Self {
+        if let (Some(bm), Some(conv_id)) = (&self.backup_manager, &self.conversation_id) {
+            self.files = bm.file_tracker(conv_id);
+        }
+        self
+    }
+
+    pub fn with_path_resolver(mut self, paths: PathResolver) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = cancel_token;
+        self
+    }
+
+    pub fn with_sandbox(mut self, sandbox: Arc<Sandbox>) -> Self {
+        self.sandbox = Some(sandbox);
         self
     }
 
+    /// Whether a tool only reads state, so it can safely run alongside other calls.
+    /// MCP tools count as read-only when the server marks them with `readOnlyHint`.
+    /// `shell_poll` is not: it drains the session's output buffer.
+    pub async fn is_read_only(&self, tool_name: &str) -> bool {
+        match tool_name {
+            "read_file" | "glob" | "grep" | "list_dir" | "docker_list"
+            | "docker_images" | "list_mcp_resources" | "read_mcp_resource" => true,
+            name if name.starts_with("mcp_") => match &self.mcp_manager {
+                Some(mcp_manager) => mcp_manager
+                    .get_all_tools()
+                    .await
+                    .iter()
+                    .any(|tool| tool.read_only && mcp_tool_name(&tool.server_id, &tool.name) == name),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
     pub async fn execute(&self, tool_use: &ToolUse) -> ToolResult {
+        self.execute_with_output(tool_use, None).await
+    }
+
+    /// Execute a tool, streaming incremental output from bash as
+    /// `AgentEvent::ToolOutput` and MCP progress as `AgentEvent::ToolProgress`.
+    /// Streaming is best-effort: updates are dropped rather than blocking the
+    /// tool when the event channel is full.
+    pub async fn execute_with_output(
+        &self,
+        tool_use: &ToolUse,
+        output_tx: Option<mpsc::Sender<AgentEvent>>,
+    ) -> ToolResult {
         let project_path = self.project_path.as_deref();
+        let paths = &self.paths;
 
         // Check if this is an MCP tool (format: mcp_server_id_tool_name)
         if tool_use.name.starts_with("mcp_") {
@@ -49,11 +124,7 @@ impl ToolExecutor {
 
                 // Find the tool that matches the current tool_use name
                 for tool in &all_tools {
-                    let safe_server_id = tool.server_id.replace("-", "_").replace(":", "_");
-                    let safe_tool_name = tool.name.replace("-", "_").replace(":", "_");
-                    let expected_name = format!("mcp_{}_{}", safe_server_id, safe_tool_name);
-
-                    if expected_name == tool_use.name {
+                    if mcp_tool_name(&tool.server_id, &tool.name) == tool_use.name {
                         matching_tool = Some(tool);
                         break;
                     }
@@ -66,7 +137,22 @@ impl ToolExecutor {
                         parameters: tool_use.input.clone(),
                     };
 
-                    let mcp_result = mcp_manager.execute_tool(&mcp_call).await;
+                    let on_progress = output_tx.map(|tx| {
+                        let tool_use_id = tool_use.id.clone();
+                        let tool = tool_use.name.clone();
+                        let callback: ProgressCallback = Arc::new(move |update: &MCPProgress| {
+                            let _ = tx.try_send(AgentEvent::ToolProgress {
+                                tool_use_id: tool_use_id.clone(),
+                                tool: tool.clone(),
+                                progress: update.progress,
+                                total: update.total,
+                                message: update.message.clone(),
+                            });
+                        });
+                        callback
+                    });
+
+                    let mcp_result = mcp_manager.execute_tool_with_progress(&mcp_call, on_progress).await;
 
                     return if mcp_result.success {
                         ToolResult::success(tool_use.id.clone(), mcp_result.result.to_string())
@@ -90,54 +176,135 @@ impl ToolExecutor {
             }
         }
 
+        if matches!(tool_use.name.as_str(), "list_mcp_resources" | "read_mcp_resource") {
+            let result = match &self.mcp_manager {
+                Some(mcp_manager) => tools::mcp_resources::execute(&tool_use.name, &tool_use.input, mcp_manager).await,
+                None => Err("MCP manager not available".to_string()),
+            };
+            return match result {
+                Ok(output) => ToolResult::success(tool_use.id.clone(), output),
+                Err(error) => ToolResult::error(tool_use.id.clone(), error),
+            };
+        }
+
+        if tool_use.name.starts_with("shell_") {
+            // Shell sessions run on the host, which sandbox mode keeps commands off
+            if self.sandbox.is_some() {
+                return ToolResult::error(
+                    tool_use.id.clone(),
+                    "Shell sessions are not available in sandbox mode. Use bash, which runs in the sandbox container and keeps its working directory and exported variables between calls.".to_string(),
+                );
+            }
+            return match self.shells.execute(&tool_use.name, &tool_use.input, &self.cancel_token).await {
+                Ok(output) => ToolResult::success(tool_use.id.clone(), output),
+                Err(error) => ToolResult::error(tool_use.id.clone(), error),
+            };
+        }
+
         // Docker tools have their own result handling
         if tool_use.name.starts_with("docker_") {
-            return tools::docker::execute_docker_tool(tool_use, &self.project_path);
+            return tools::docker::execute_docker_tool(tool_use, &self.project_path, &self.cancel_token).await;
         }
 
         let result = match tool_use.name.as_str() {
-            "read_file" => tools::file_read::execute(&tool_use.input, project_path),
-            "write_file" => {
-                // Create backup before writing
-                if let (Some(bm), Some(conv_id)) = (&self.backup_manager, &self.conversation_id) {
-                    if let Some(path_str) = tool_use.input.get("path").and_then(|v| v.as_str()) {
-                        let file_path = Path::new(path_str);
-                        let _ = bm.create_backup(conv_id, file_path);
+            "read_file" => {
+                // Images come back alongside the text
+                let input = tool_use.input.clone();
+                let paths = paths.clone();
+                let files = self.files.clone();
+                let read = tokio::task::spawn_blocking(move || {
+                    let content = tools::file_read::read(&input, &paths)?;
+                    if let Some(Ok(path)) = input.get("path").and_then(|v| v.as_str()).map(|p| paths.resolve(p)) {
+                        files.record(&path);
+                    }
+                    Ok(content)
+                });
+                match read.await {
+                    Ok(Ok(content)) => {
+                        return ToolResult::success(tool_use.id.clone(), content.text)
+                            .with_images(content.images);
                     }
+                    Ok(Err(error)) => Err(error),
+                    Err(e) => Err(format!("Tool execution failed: {}", e)),
                 }
-                tools::file_write::execute(&tool_use.input, project_path)
             },
-            "edit_file" => {
-                // Create backup before editing
-                if let (Some(bm), Some(conv_id)) = (&self.backup_manager, &self.conversation_id) {
-                    if let Some(path_str) = tool_use.input.get("path").and_then(|v| v.as_str()) {
-                        let file_path = Path::new(path_str);
-                        let _ = bm.create_backup(conv_id, file_path);
+            "write_file" => self.write_checked(tool_use, tools::file_write::execute),
+            "edit_file" => self.write_checked(tool_use, tools::file_edit::execute),
+            "apply_patch" => {
+                let paths = paths.clone();
+                let input = tool_use.input.clone();
+                let backup = self.backup_manager.clone().zip(self.conversation_id.clone());
+                let files = self.files.clone();
+                tokio::task::spawn_blocking(move || {
+                    let changes = tools::apply_patch::plan(&input, &paths)?;
+                    let versions = changes
+                        .iter()
+                        .map(|c| Ok((c.path.as_path(), files.check_unchanged(&c.path)?)))
+                        .collect::<Result<Vec<_>, String>>()?;
+
+                    // One undo entry restores every file the patch touches
+                    let undo = match &backup {
+                        Some((bm, conv_id)) => Some(bm.backup_patch(conv_id, &versions)?),
+                        None => None,
+                    };
+
+                    let output = tools::apply_patch::apply(&changes, &paths)?;
+                    if let (Some((bm, conv_id)), Some(undo)) = (&backup, undo) {
+                        bm.register_patch(conv_id, undo);
                     }
+                    for change in &changes {
+                        files.record(&change.path);
+                    }
+                    Ok(output)
+                })
+                .await
+                .unwrap_or_else(|e| Err(format!("Tool execution failed: {}", e)))
+            },
+            "bash" => {
+                let on_output = output_tx.map(|tx| {
+                    let tool_use_id = tool_use.id.clone();
+                    let tool = tool_use.name.clone();
+                    let callback: tools::bash::OutputCallback = Arc::new(move |stream: &str, content: &str| {
+                        let _ = tx.try_send(AgentEvent::ToolOutput {
+                            tool_use_id: tool_use_id.clone(),
+                            tool: tool.clone(),
+                            stream: stream.to_string(),
+                            content: content.to_string(),
+                        });
+                    });
+                    callback
+                });
+                match &self.sandbox {
+                    Some(sandbox) => sandbox.execute(&tool_use.input, &self.cancel_token, on_output).await,
+                    None => tools::bash::execute(&tool_use.input, project_path, &self.cancel_token, on_output).await,
                 }
-                tools::file_edit::execute(&tool_use.input, project_path)
             },
-            "bash" => tools::bash::execute(&tool_use.input, project_path),
-            "glob" => tools::glob::execute(&tool_use.input, project_path),
-            "grep" => tools::grep::execute(&tool_use.input, project_path),
-            "list_dir" => tools::list_dir::execute(&tool_use.input, project_path),
+            "glob" => run_blocking(tool_use, paths, tools::glob::execute).await,
+            "grep" => run_blocking(tool_use, paths, tools::grep::execute).await,
+            "list_dir" => run_blocking(tool_use, paths, tools::list_dir::execute).await,
             "move_file" => {
-                let result = tools::file_move::execute(&tool_use.input, project_path);
+                let result = tools::file_move::execute(&tool_use.input, paths);
                 // Register move for undo after successful execution
                 if result.is_ok() {
                     if let (Some(bm), Some(conv_id)) = (&self.backup_manager, &self.conversation_id) {
-                        if let (Some(src), Some(dst)) = (
-                            tool_use.input.get("source").and_then(|v| v.as_str()),
-                            tool_use.input.get("destination").and_then(|v| v.as_str())
+                        if let (Some(Ok(src)), Some(Ok(dst))) = (
+                            tool_use.input.get("source").and_then(|v| v.as_str()).map(|p| paths.resolve(p)),
+                            tool_use.input.get("destination").and_then(|v| v.as_str()).map(|p| paths.resolve(p))
                         ) {
-                            bm.register_move(conv_id, Path::new(src), Path::new(dst));
+                            bm.register_move(conv_id, &src, &dst);
                         }
                     }
+                    if let (Some(Ok(src)), Some(Ok(dst))) = (
+                        tool_use.input.get("source").and_then(|v| v.as_str()).map(|p| paths.resolve(p)),
+                        tool_use.input.get("destination").and_then(|v| v.as_str()).map(|p| paths.resolve(p))
+                    ) {
+                        self.files.rename(&src, &dst);
+                    }
                 }
                 result
             },
             "delete_file" => {
-                let result = tools::file_delete::execute(&tool_use.input, project_path);
+                let result = tools::file_delete::execute(&tool_use.input, paths);
                 if let Ok(json_str) = &result {
                     if let Ok(val) = serde_json::from_str::<serde_json::Value>(json_str) {
                          if let (Some(bm), Some(conv_id)) = (&self.backup_manager, &self.conversation_id) {
@@ -148,6 +315,9 @@ impl ToolExecutor {
                                  bm.register_delete(conv_id, Path::new(orig_path), Path::new(trash_path));
                              }
                          }
+                         if let Some(orig_path) = val.get("original_path").and_then(|v| v.as_str()) {
+                             self.files.forget(Path::new(orig_path));
+                         }
                          // Return only the message field to the LLM to keep it clean
                          if let Some(msg) = val.get("message").and_then(|v| v.as_str()) {
                              return ToolResult::success(tool_use.id.clone(), msg.to_string());
@@ -164,4 +334,49 @@ impl ToolExecutor {
             Err(error) => ToolResult::error(tool_use.id.clone(), error),
         }
     }
+
+    /// Run write_file or edit_file, refusing when the file changed on disk
+    /// since the agent last saw it. The previous content is backed up first.
+    fn write_checked(
+        &self,
+        tool_use: &ToolUse,
+        execute: fn(&serde_json::Value, &PathResolver) -> Result<String, String>,
+    ) -> Result<String, String> {
+        let Some(Ok(file_path)) = tool_use.input.get("path").and_then(|v| v.as_str()).map(|p| self.paths.resolve(p)) else {
+            // Let the tool report the bad path
+            return execute(&tool_use.input, &self.paths);
+        };
+
+        let version = self.files.check_unchanged(&file_path)?;
+        if let (Some(bm), Some(conv_id), Some(version)) = (&self.backup_manager, &self.conversation_id, &version) {
+            let _ = bm.create_backup(conv_id, &file_path, version);
+        }
+
+        let result = execute(&tool_use.input, &self.paths);
+        if result.is_ok() {
+            self.files.record(&file_path);
+        }
+        result
+    }
+}
+
+/// Name under which an MCP tool is exposed to the model
+pub(crate) fn mcp_tool_name(server_id: &str, tool_name: &str) -> String {
+    let safe_server_id = server_id.replace("-", "_").replace(":", "_");
+    let safe_tool_name = tool_name.replace("-", "_").replace(":", "_");
+    format!("mcp_{}_{}", safe_server_id, safe_tool_name)
+}
+
+/// Run a synchronous filesystem tool on the blocking pool so concurrent calls
+/// don't stall each other (or the async runtime)
+async fn run_blocking(
+    tool_use: &ToolUse,
+    paths: &PathResolver,
+    execute: fn(&serde_json::Value, &PathResolver) -> Result<String, String>,
+) -> Result<String, String> {
+    let input = tool_use.input.clone();
+    let paths = paths.clone();
+    tokio::task::spawn_blocking(move || execute(&input, &paths))
+        .await
+        .map_err(|e| format!("Tool execution failed: {}", e))?
 }
@@ -1,5 +1,9 @@
 use serde::{Deserialize, Serialize};
-use crate::skills::{get_available_skills, get_skills_directory_path};
+use std::collections::HashMap;
+use crate::agent::approval::ToolPermission;
+use crate::agent::usage::{ModelPrice, TokenUsage};
+use crate::skills::{get_available_skills, get_skills_directory, get_skills_directory_path};
+use crate::tools::paths::PathResolver;
 
 /// Tool definition sent to Claude API
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +36,18 @@ pub struct ToolResult {
     /// Thought signature from Google Gemini 3 (required for function response)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thought_signature: Option<String>,
+    /// Images returned alongside the text, sent to models that accept image input
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ToolImage>,
+}
+
+/// An image returned by a tool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolImage {
+    /// MIME type, e.g. "image/png"
+    pub media_type: String,
+    /// Base64-encoded image bytes
+    pub data: String,
 }
 
 impl ToolResult {
@@ -42,9 +58,15 @@ impl ToolResult {
             content,
             is_error: None,
             thought_signature: None,
+            images: Vec::new(),
         }
     }
 
+    pub fn with_images(mut self, images: Vec<ToolImage>) -> Self {
+        self.images = images;
+        self
+    }
+
     pub fn error(tool_use_id: String, error: String) -> Self {
         Self {
             result_type: "tool_result".to_string(),
@@ -52,6 +74,7 @@ impl ToolResult {
             content: error,
             is_error: Some(true),
             thought_signature: None,
+            images: Vec::new(),
         }
     }
 }
@@ -95,8 +118,70 @@ pub struct AgentConfig {
     pub max_turns: u32,
     pub project_path: Option<String>,
     pub allowed_tools: Vec<String>,
+    /// Permission policy per tool name; tools not listed are allowed
+    #[serde(default)]
+    pub tool_permissions: HashMap<String, ToolPermission>,
+    /// Confine filesystem tools to the project directory (when one is set)
+    #[serde(default = "default_restrict_to_project")]
+    pub restrict_to_project: bool,
+    /// Extra directories the filesystem tools may access besides the project
+    #[serde(default)]
+    pub allowed_paths: Vec<String>,
+    /// Maximum read-only tool calls run at the same time within a turn
+    #[serde(default = "default_max_parallel_tools")]
+    pub max_parallel_tools: usize,
+    /// User price table (USD per million tokens) overriding the built-in one
+    #[serde(default)]
+    pub model_prices: HashMap<String, ModelPrice>,
+    /// Retries of a failed model request before giving up on a provider
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_restrict_to_project() -> bool {
+    true
+}
+
+fn default_max_parallel_tools() -> usize {
+    4
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+impl AgentConfig {
+    /// Build the path resolver used by the filesystem tools.
+    /// The skills directory is always readable so skills can be loaded.
+    pub fn path_resolver(&self) -> PathResolver {
+        self.allowed_paths.iter().fold(
+            PathResolver::new(self.project_path.as_deref())
+                .with_confinement(self.restrict_to_project)
+                .with_allowed_root(get_skills_directory()),
+            |resolver, root| resolver.with_allowed_root(root),
+        )
+    }
+
+    /// Get the permission policy for a tool. The shell tools that run
+    /// commands are also held to the `bash` policy, the stricter one winning.
+    pub fn permission_for(&self, tool_name: &str) -> ToolPermission {
+        let own = self
+            .tool_permissions
+            .get(tool_name)
+            .copied()
+            .unwrap_or_default();
+
+        if SHELL_COMMAND_TOOLS.contains(&tool_name) {
+            own.max(self.permission_for("bash"))
+        } else {
+            own
+        }
+    }
 }
 
+/// Tools that run arbitrary commands on the host, like `bash`
+const SHELL_COMMAND_TOOLS: [&str; 3] = ["shell_exec", "shell_start", "shell_stdin"];
+
 impl Default for AgentConfig {
     fn default() -> Self {
         Self {
@@ -107,16 +192,28 @@ impl Default for AgentConfig {
                 "read_file".to_string(),
                 "write_file".to_string(),
                 "edit_file".to_string(),
+                "apply_patch".to_string(),
                 "bash".to_string(),
                 "glob".to_string(),
                 "grep".to_string(),
                 "list_dir".to_string(),
                 "move_file".to_string(),
                 "delete_file".to_string(),
+                "shell_exec".to_string(),
+                "shell_start".to_string(),
+                "shell_poll".to_string(),
+                "shell_stdin".to_string(),
+                "shell_kill".to_string(),
                 "docker_run".to_string(),
                 "docker_list".to_string(),
                 "docker_images".to_string(),
             ],
+            tool_permissions: HashMap::new(),
+            restrict_to_project: true,
+            allowed_paths: Vec::new(),
+            max_parallel_tools: default_max_parallel_tools(),
+            model_prices: HashMap::new(),
+            max_retries: default_max_retries(),
         }
     }
 }
@@ -168,17 +265,98 @@ pub enum AgentEvent {
     #[serde(rename = "step_done")]
     StepDone { step: i32 },
     #[serde(rename = "tool_start")]
-    ToolStart { tool: String, input: serde_json::Value },
+    ToolStart {
+        tool_use_id: String,
+        tool: String,
+        input: serde_json::Value,
+    },
     #[serde(rename = "tool_end")]
-    ToolEnd { tool: String, result: String, success: bool },
+    ToolEnd {
+        tool_use_id: String,
+        tool: String,
+        result: String,
+        success: bool,
+    },
+    /// Incremental output from a running tool ("stdout" or "stderr")
+    #[serde(rename = "tool_output")]
+    ToolOutput {
+        tool_use_id: String,
+        tool: String,
+        stream: String,
+        content: String,
+    },
+    /// Progress reported by an MCP server for a running tool
+    #[serde(rename = "tool_progress")]
+    ToolProgress {
+        tool_use_id: String,
+        tool: String,
+        progress: f64,
+        total: Option<f64>,
+        message: Option<String>,
+    },
+    #[serde(rename = "approval_required")]
+    ApprovalRequired {
+        request_id: String,
+        tool: String,
+        input: serde_json::Value,
+    },
     #[serde(rename = "turn_complete")]
     TurnComplete { turn: u32 },
     #[serde(rename = "done")]
     Done { total_turns: u32 },
     #[serde(rename = "error")]
     Error { message: String },
+    #[serde(rename = "cancelled")]
+    Cancelled { turn: u32 },
+    /// History was compacted to fit the context window
+    #[serde(rename = "compacted")]
+    Compacted {
+        tokens_before: usize,
+        tokens_after: usize,
+        truncated_results: usize,
+        /// Set when earlier turns were replaced by a model-written summary
+        summary: Option<String>,
+        /// Set when summarizing was needed but failed, leaving the history over budget
+        summary_error: Option<String>,
+        /// Messages kept verbatim after the summary, for persisting the new history
+        #[serde(skip)]
+        kept_messages: Vec<AgentMessage>,
+    },
+    /// Tokens used by one model request, with its estimated cost in USD
+    #[serde(rename = "usage")]
+    Usage {
+        turn: u32,
+        model: String,
+        usage: TokenUsage,
+        cost: Option<f64>,
+    },
+    /// A model request failed transiently and is retried after `delay_ms`
+    #[serde(rename = "retrying")]
+    Retrying {
+        provider: String,
+        attempt: u32,
+        max_retries: u32,
+        delay_ms: u64,
+        error: String,
+    },
+    /// The provider stayed unavailable; requests move to the next fallback
+    #[serde(rename = "failover")]
+    Failover {
+        from_provider: String,
+        to_provider: String,
+        model: String,
+        error: String,
+    },
     #[serde(rename = "tool_results")]
     ToolResults { results: Vec<ToolResult> },
+    /// History after `turn` turns, sent before a turn's tools run and when it
+    /// completes, so an interrupted run can be resumed
+    #[serde(rename = "checkpoint")]
+    Checkpoint {
+        turn: u32,
+        #[serde(skip)]
+        messages: Vec<AgentMessage>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -186,3 +364,25 @@ pub struct PlanStepInfo {
     pub step: i32,
     pub description: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_tools_follow_bash_permission() {
+        let mut config = AgentConfig::default();
+        config.tool_permissions.insert("bash".to_string(), ToolPermission::Deny);
+        assert_eq!(config.permission_for("shell_exec"), ToolPermission::Deny);
+        assert_eq!(config.permission_for("shell_start"), ToolPermission::Deny);
+        assert_eq!(config.permission_for("shell_stdin"), ToolPermission::Deny);
+        assert_eq!(config.permission_for("shell_poll"), ToolPermission::Allow);
+
+        // An explicit allow doesn't override a stricter bash policy
+        config.tool_permissions.insert("bash".to_string(), ToolPermission::Ask);
+        config.tool_permissions.insert("shell_exec".to_string(), ToolPermission::Allow);
+        assert_eq!(config.permission_for("shell_exec"), ToolPermission::Ask);
+        config.tool_permissions.insert("shell_exec".to_string(), ToolPermission::Deny);
+        assert_eq!(config.permission_for("shell_exec"), ToolPermission::Deny);
+    }
+}
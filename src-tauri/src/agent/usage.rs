@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Token counts reported by the provider for one request.
+/// `input_tokens` excludes tokens served from or written to the prompt cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cache_read_tokens: u64,
+    #[serde(default)]
+    pub cache_creation_tokens: u64,
+}
+
+fn count(value: &serde_json::Value, key: &str) -> Option<u64> {
+    value.get(key).and_then(|v| v.as_u64())
+}
+
+impl TokenUsage {
+    /// Apply an Anthropic `usage` object. `message_delta` events repeat the
+    /// cumulative counts, so present fields overwrite earlier values.
+    pub fn update_anthropic(&mut self, usage: &serde_json::Value) {
+        if let Some(n) = count(usage, "input_tokens") {
+            self.input_tokens = n;
+        }
+        if let Some(n) = count(usage, "output_tokens") {
+            self.output_tokens = n;
+        }
+        if let Some(n) = count(usage, "cache_read_input_tokens") {
+            self.cache_read_tokens = n;
+        }
+        if let Some(n) = count(usage, "cache_creation_input_tokens") {
+            self.cache_creation_tokens = n;
+        }
+    }
+
+    /// Parse an OpenAI `usage` object, from Chat Completions
+    /// (`prompt_tokens`) or the Responses API (`input_tokens`)
+    pub fn from_openai(usage: &serde_json::Value) -> Self {
+        let prompt = count(usage, "prompt_tokens")
+            .or_else(|| count(usage, "input_tokens"))
+            .unwrap_or(0);
+        let completion = count(usage, "completion_tokens")
+            .or_else(|| count(usage, "output_tokens"))
+            .unwrap_or(0);
+        let cached = usage
+            .get("prompt_tokens_details")
+            .or_else(|| usage.get("input_tokens_details"))
+            .and_then(|details| count(details, "cached_tokens"))
+            .unwrap_or(0);
+
+        Self {
+            input_tokens: prompt.saturating_sub(cached),
+            output_tokens: completion,
+            cache_read_tokens: cached,
+            cache_creation_tokens: 0,
+        }
+    }
+
+    /// Parse a Google `usageMetadata` object. Thinking tokens are billed as output.
+    pub fn from_google(metadata: &serde_json::Value) -> Self {
+        let prompt = count(metadata, "promptTokenCount").unwrap_or(0);
+        let cached = count(metadata, "cachedContentTokenCount").unwrap_or(0);
+        let output = count(metadata, "candidatesTokenCount").unwrap_or(0)
+            + count(metadata, "thoughtsTokenCount").unwrap_or(0);
+
+        Self {
+            input_tokens: prompt.saturating_sub(cached),
+            output_tokens: output,
+            cache_read_tokens: cached,
+            cache_creation_tokens: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+    #[serde(default)]
+    pub cache_read_per_mtok: f64,
+    #[serde(default)]
+    pub cache_write_per_mtok: f64,
+}
+
+impl ModelPrice {
+    const fn new(input: f64, output: f64, cache_read: f64, cache_write: f64) -> Self {
+        Self {
+            input_per_mtok: input,
+            output_per_mtok: output,
+            cache_read_per_mtok: cache_read,
+            cache_write_per_mtok: cache_write,
+        }
+    }
+
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        (usage.input_tokens as f64 * self.input_per_mtok
+            + usage.output_tokens as f64 * self.output_per_mtok
+            + usage.cache_read_tokens as f64 * self.cache_read_per_mtok
+            + usage.cache_creation_tokens as f64 * self.cache_write_per_mtok)
+            / 1_000_000.0
+    }
+}
+
+/// Built-in list prices, keyed by model name prefix
+const DEFAULT_PRICES: &[(&str, ModelPrice)] = &[
+    ("claude-opus-4", ModelPrice::new(15.0, 75.0, 1.5, 18.75)),
+    ("claude-opus-4-5", ModelPrice::new(5.0, 25.0, 0.5, 6.25)),
+    ("claude-sonnet-4", ModelPrice::new(3.0, 15.0, 0.3, 3.75)),
+    ("claude-3-7-sonnet", ModelPrice::new(3.0, 15.0, 0.3, 3.75)),
+    ("claude-3-5-sonnet", ModelPrice::new(3.0, 15.0, 0.3, 3.75)),
+    ("claude-haiku-4", ModelPrice::new(1.0, 5.0, 0.1, 1.25)),
+    ("claude-3-5-haiku", ModelPrice::new(0.8, 4.0, 0.08, 1.0)),
+    ("gpt-5", ModelPrice::new(1.25, 10.0, 0.125, 0.0)),
+    ("gpt-5-mini", ModelPrice::new(0.25, 2.0, 0.025, 0.0)),
+    ("gpt-5-nano", ModelPrice::new(0.05, 0.4, 0.005, 0.0)),
+    ("gpt-4.1", ModelPrice::new(2.0, 8.0, 0.5, 0.0)),
+    ("gpt-4.1-mini", ModelPrice::new(0.4, 1.6, 0.1, 0.0)),
+    ("gpt-4.1-nano", ModelPrice::new(0.1, 0.4, 0.025, 0.0)),
+    ("gpt-4o", ModelPrice::new(2.5, 10.0, 1.25, 0.0)),
+    ("gpt-4o-mini", ModelPrice::new(0.15, 0.6, 0.075, 0.0)),
+    ("o3", ModelPrice::new(2.0, 8.0, 0.5, 0.0)),
+    ("o4-mini", ModelPrice::new(1.1, 4.4, 0.275, 0.0)),
+    ("gemini-2.5-pro", ModelPrice::new(1.25, 10.0, 0.31, 0.0)),
+    ("gemini-2.5-flash", ModelPrice::new(0.3, 2.5, 0.075, 0.0)),
+    ("deepseek-chat", ModelPrice::new(0.27, 1.1, 0.07, 0.0)),
+    ("deepseek-reasoner", ModelPrice::new(0.55, 2.19, 0.14, 0.0)),
+];
+
+/// Look up the price of a model. Entries from `overrides` (the user's price
+/// table) replace built-in ones with the same key; the longest matching
+/// prefix wins. Router prefixes such as `anthropic/` are ignored.
+pub fn price_for(model: &str, overrides: &HashMap<String, ModelPrice>) -> Option<ModelPrice> {
+    let model = model.to_lowercase();
+    let model = model.rsplit('/').next().unwrap_or(&model);
+
+    let mut table: HashMap<String, ModelPrice> = DEFAULT_PRICES
+        .iter()
+        .map(|(prefix, price)| (prefix.to_string(), *price))
+        .collect();
+    table.extend(overrides.iter().map(|(prefix, price)| (prefix.to_lowercase(), *price)));
+
+    table
+        .into_iter()
+        .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, price)| price)
+}
+
+/// Estimated cost in USD, or None when the model has no known price
+pub fn estimate_cost(model: &str, usage: &TokenUsage, overrides: &HashMap<String, ModelPrice>) -> Option<f64> {
+    price_for(model, overrides).map(|price| price.cost(usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_provider_usage() {
+        let mut anthropic = TokenUsage::default();
+        anthropic.update_anthropic(&serde_json::json!({
+            "input_tokens": 10, "output_tokens": 1,
+            "cache_read_input_tokens": 200, "cache_creation_input_tokens": 50
+        }));
+        anthropic.update_anthropic(&serde_json::json!({ "output_tokens": 42 }));
+        assert_eq!(anthropic, TokenUsage {
+            input_tokens: 10,
+            output_tokens: 42,
+            cache_read_tokens: 200,
+            cache_creation_tokens: 50,
+        });
+
+        let openai = TokenUsage::from_openai(&serde_json::json!({
+            "prompt_tokens": 100, "completion_tokens": 20,
+            "prompt_tokens_details": { "cached_tokens": 60 }
+        }));
+        assert_eq!((openai.input_tokens, openai.cache_read_tokens), (40, 60));
+
+        let google = TokenUsage::from_google(&serde_json::json!({
+            "promptTokenCount": 30, "candidatesTokenCount": 5, "thoughtsTokenCount": 7
+        }));
+        assert_eq!((google.input_tokens, google.output_tokens), (30, 12));
+    }
+
+    #[test]
+    fn test_price_lookup_prefers_longest_prefix() {
+        let usage = TokenUsage { input_tokens: 1_000_000, ..Default::default() };
+        let mut overrides = HashMap::new();
+
+        let cost = |model: &str, overrides: &HashMap<String, ModelPrice>| estimate_cost(model, &usage, overrides);
+
+        assert!((cost("gpt-4o-mini-2024-07-18", &overrides).unwrap() - 0.15).abs() < 1e-9);
+        assert!((cost("openai/gpt-4o", &overrides).unwrap() - 2.5).abs() < 1e-9);
+        assert_eq!(cost("llama3.2", &overrides), None);
+
+        overrides.insert("llama3".to_string(), ModelPrice::new(0.0, 0.0, 0.0, 0.0));
+        assert_eq!(cost("llama3.2", &overrides), Some(0.0));
+    }
+}
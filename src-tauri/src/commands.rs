@@ -1,19 +1,68 @@
-use crate::agent::{AgentConfig, AgentContent, AgentEvent, AgentLoop, AgentMessage, ContentBlock};
+use crate::agent::{
+    pending_tool_uses, AgentConfig, AgentContent, AgentEvent, AgentLoop, AgentMessage, ContentBlock, ToolResult,
+};
+use crate::agent::approval::{denial_message, disabled_message};
+use crate::agent::context::CompactionRecord;
 use crate::agent::backup::BackupManager;
-use crate::claude::{ClaudeClient, Message as ClaudeMessage};
-use crate::database::{Conversation, Database, Message, PlanStep, Settings, Task, TaskMessage};
+use crate::agent::{ApprovalDecision, ApprovalManager, TokenUsage, ToolPermission};
+use crate::agent::usage::estimate_cost;
+use crate::agent::message_builder::{ApiContent, ApiMessage, ClaudeApiRequest};
+use crate::database::{
+    BranchPoint, Conversation, DailyUsage, Database, Message, PlanStep, SearchHit, SecretsStatus, Settings, Task,
+    TaskMessage, UsageTotals,
+};
 use crate::mcp::{MCPManager, MCPServerConfig, MCPServerStatus, MCPToolCall, MCPToolResult};
+use crate::providers::{self, ProviderResponse, RequestError, StreamEvent};
 use crate::skills::{SkillMetadata, get_available_skills};
+use crate::tools::sandbox::{Sandbox, WORKSPACE};
+use crate::transcript::{self, ImportedTranscript};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use tauri::{command, Emitter, State, Window};
-use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 
 pub struct AppState {
     pub db: Arc<Database>,
-    pub claude_client: Mutex<Option<ClaudeClient>>,
     pub mcp_manager: Arc<MCPManager>,
     pub backup_manager: Arc<BackupManager>,
+    pub approval_manager: Arc<ApprovalManager>,
+    /// Cancellation tokens of task agents that are currently running, keyed by task ID
+    pub running_tasks: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl AppState {
+    /// Register a task run so it can be stopped via cancel_task_agent.
+    /// Fails if the task is already running.
+    fn start_task_run(&self, task_id: &str) -> Result<TaskRun<'_>, CommandError> {
+        let mut running = self.running_tasks.lock().unwrap();
+        if running.contains_key(task_id) {
+            return Err(CommandError {
+                message: "Task is already running".to_string(),
+            });
+        }
+
+        let cancel_token = CancellationToken::new();
+        running.insert(task_id.to_string(), cancel_token.clone());
+        Ok(TaskRun {
+            running_tasks: &self.running_tasks,
+            task_id: task_id.to_string(),
+            cancel_token,
+        })
+    }
+}
+
+/// A registered task run, unregistered when dropped
+struct TaskRun<'a> {
+    running_tasks: &'a Mutex<HashMap<String, CancellationToken>>,
+    task_id: String,
+    cancel_token: CancellationToken,
+}
+
+impl Drop for TaskRun<'_> {
+    fn drop(&mut self) {
+        self.running_tasks.lock().unwrap().remove(&self.task_id);
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -29,8 +78,14 @@ impl From<crate::database::DbError> for CommandError {
     }
 }
 
-impl From<crate::claude::ClaudeError> for CommandError {
-    fn from(e: crate::claude::ClaudeError) -> Self {
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError { message }
+    }
+}
+
+impl From<RequestError> for CommandError {
+    fn from(e: RequestError) -> Self {
         CommandError {
             message: e.to_string(),
         }
@@ -57,7 +112,6 @@ pub fn get_platform() -> String {
 #[command]
 pub fn get_settings(state: State<'_, Arc<AppState>>) -> Result<Settings, CommandError> {
     let settings = state.db.get_settings()?;
-    println!("[get_settings] api_key length from db: {}", settings.api_key.len());
     Ok(settings)
 }
 
@@ -68,51 +122,48 @@ pub async fn save_settings(
 ) -> Result<(), CommandError> {
     println!("[save_settings] model: {}", settings.model);
     println!("[save_settings] base_url: {}", settings.base_url);
-    println!("[save_settings] api_key length: {}", settings.api_key.len());
-    // Show first and last 10 chars for debugging
-    if settings.api_key.len() > 20 {
-        println!("[save_settings] api_key preview: {}...{}",
-            &settings.api_key[..10],
-            &settings.api_key[settings.api_key.len()-10..]);
-    }
 
     state.db.save_settings(&settings)?;
 
-    // Update Claude client with new settings
-    let mut client = state.claude_client.lock().await;
-    if !settings.api_key.is_empty() {
-        *client = Some(ClaudeClient::new(
-            settings.api_key.clone(),
-            Some(settings.base_url.clone()),
-        ));
-    } else {
-        *client = None;
-    }
-
     Ok(())
 }
 
+// Secret store commands
 #[command]
-pub async fn test_connection(state: State<'_, Arc<AppState>>) -> Result<String, CommandError> {
-    use crate::llm_client::LLMClient;
+pub fn get_secrets_status(state: State<'_, Arc<AppState>>) -> Result<SecretsStatus, CommandError> {
+    Ok(state.db.secrets_status())
+}
 
+#[command]
+pub async fn unlock_secrets(
+    state: State<'_, Arc<AppState>>,
+    passphrase: String,
+) -> Result<(), CommandError> {
+    state.db.unlock_secrets(&passphrase).map_err(Into::into)
+}
+
+/// Delete the stored secrets and start over with a new key, derived from
+/// `passphrase` when one is given
+#[command]
+pub async fn reset_secrets(
+    state: State<'_, Arc<AppState>>,
+    passphrase: Option<String>,
+) -> Result<(), CommandError> {
+    let passphrase = passphrase.filter(|p| !p.is_empty());
+    state.db.reset_secrets(passphrase.as_deref()).map_err(Into::into)
+}
+
+#[command]
+pub async fn test_connection(state: State<'_, Arc<AppState>>) -> Result<String, CommandError> {
     let settings = state.db.get_settings()?;
 
     // Debug logging
     println!("[test_connection] model: {}", settings.model);
 
-    // Create client instance for connection check
-    let client = LLMClient::new(
-        settings.api_key.clone(),
-        Some(settings.base_url.clone()),
-        None, // provider_id inferred from model or settings
-        Some(&settings.model),
-    );
-
-    match client.check_connection().await {
-        Ok(true) => Ok("success".to_string()),
-        Ok(false) => Ok("Connection failed: Service unreachable".to_string()),
-        Err(e) => Err(CommandError { message: format!("Connection error: {}", e) }),
+    // Listing models checks both reachability and credentials
+    match providers::list_models(&reqwest::Client::new(), &settings.endpoint()).await {
+        Ok(_) => Ok("success".to_string()),
+        Err(e) => Ok(format!("Connection failed: {}", e)),
     }
 }
 
@@ -197,6 +248,25 @@ pub fn get_messages(
     state.db.get_messages(&conversation_id).map_err(Into::into)
 }
 
+#[command]
+pub fn list_branches(
+    state: State<'_, Arc<AppState>>,
+    conversation_id: String,
+) -> Result<Vec<BranchPoint>, CommandError> {
+    state.db.list_branches(&conversation_id).map_err(Into::into)
+}
+
+/// Show the branch containing `message_id` and return its messages
+#[command]
+pub fn switch_branch(
+    state: State<'_, Arc<AppState>>,
+    conversation_id: String,
+    message_id: String,
+) -> Result<Vec<Message>, CommandError> {
+    conversation_message(&state.db, &conversation_id, &message_id, None)?;
+    state.db.switch_branch(&conversation_id, &message_id).map_err(Into::into)
+}
+
 #[command]
 pub fn add_message(
     state: State<'_, Arc<AppState>>,
@@ -225,8 +295,6 @@ pub async fn send_chat_message(
     conversation_id: String,
     content: String,
 ) -> Result<String, CommandError> {
-    use crate::llm_client::{LLMClient, Message as LLMMessage};
-
     let settings = state.db.get_settings()?;
 
     if settings.api_key.is_empty() && !settings.allows_empty_api_key() {
@@ -244,72 +312,12 @@ pub async fn send_chat_message(
     // Get conversation history
     let db_messages = state.db.get_messages(&conversation_id)?;
 
-    // Create channel for streaming
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(100);
-
-    // Spawn task to emit events
-    let window_clone = window.clone();
-    let emit_task = tokio::spawn(async move {
-        while let Some(text) = rx.recv().await {
-            let _ = window_clone.emit("chat-stream", StreamPayload { text, done: false });
-        }
-    });
-
-    // Choose client based on provider
-    let provider = settings.get_provider();
-    let response = match provider.as_str() {
-        "anthropic" => {
-            // Use ClaudeClient for Anthropic
-            let claude_messages: Vec<ClaudeMessage> = db_messages
-                .iter()
-                .map(|m| ClaudeMessage {
-                    role: m.role.clone(),
-                    content: m.content.clone(),
-                })
-                .collect();
-            let client = ClaudeClient::new(settings.api_key, Some(settings.base_url));
-            client
-                .send_message_stream(
-                    claude_messages,
-                    &settings.model,
-                    settings.max_tokens,
-                    Some(settings.temperature),
-                    tx,
-                )
-                .await?
-        }
-        _ => {
-            // Use LLMClient for OpenAI and other providers
-            let llm_messages: Vec<LLMMessage> = db_messages
-                .iter()
-                .map(|m| LLMMessage {
-                    role: m.role.clone(),
-                    content: m.content.clone(),
-                })
-                .collect();
-            let llm_client = LLMClient::new_with_openai_headers(
-                settings.api_key.clone(),
-                Some(settings.base_url.clone()),
-                Some(&provider),
-                Some(&settings.model),
-                settings.openai_organization.clone(),
-                settings.openai_project.clone(),
-            );
-            llm_client
-                .send_message_stream(
-                    llm_messages,
-                    &settings.model,
-                    settings.max_tokens,
-                    Some(settings.temperature),
-                    tx,
-                )
-                .await
-                .map_err(|e| CommandError { message: e.to_string() })?
-        }
-    };
+    let ProviderResponse { text: response, usage, .. } = stream_chat_reply(&settings, &db_messages, |text| {
+        let _ = window.emit("chat-stream", StreamPayload { text: text.to_string(), done: false });
+    })
+    .await?;
 
-    // Wait for emit task to finish
-    let _ = emit_task.await;
+    record_chat_usage(&state.db, &settings, &conversation_id, &usage);
 
     // Emit done event
     let _ = window.emit(
@@ -349,6 +357,12 @@ pub enum ChatEvent {
     ToolStart { tool: String, input: serde_json::Value },
     #[serde(rename = "tool_end")]
     ToolEnd { tool: String, result: String, success: bool },
+    #[serde(rename = "approval_required")]
+    ApprovalRequired {
+        request_id: String,
+        tool: String,
+        input: serde_json::Value,
+    },
     #[serde(rename = "done")]
     Done { final_text: String },
 }
@@ -363,6 +377,51 @@ pub struct AgentRequest {
     pub locale: Option<String>,
 }
 
+/// Describe the connected MCP servers for the system prompt
+fn mcp_system_info(servers: &[MCPServerStatus]) -> String {
+    let mut mcp_info = String::new();
+    if !servers.is_empty() {
+        mcp_info.push_str("\nMCP (Model Context Protocol) Tools:\n");
+        for server in servers {
+            if matches!(server.status, crate::mcp::types::ConnectionStatus::Connected) {
+                mcp_info.push_str(&format!("Server '{}' is connected with tools:\n", server.id));
+                for tool in &server.tools {
+                    mcp_info.push_str(&format!("  - {}: {} (use format: {}:{})\n",
+                        tool.name, tool.description, server.id, tool.name));
+                }
+                let resources = server.resources.len() + server.resource_templates.len();
+                if resources > 0 {
+                    mcp_info.push_str(&format!(
+                        "  It also exposes {} resource(s); use list_mcp_resources and read_mcp_resource to see them.\n",
+                        resources
+                    ));
+                }
+            }
+        }
+    }
+    mcp_info
+}
+
+/// Tell the model where its bash commands run in sandbox mode
+fn sandbox_system_info(image: &str) -> String {
+    format!(
+        "\n\n## Sandbox\nThe bash tool runs commands inside a Docker container (image `{}`), not on the user's machine. \
+        The project is mounted at `{}` and skills at `/skills`, so use those paths in commands. \
+        Files, installed packages, the working directory and exported variables persist between commands until the task ends. \
+        The file tools still take paths on the user's machine.",
+        image,
+        WORKSPACE
+    )
+}
+
+/// Expand a `/prompt` message into the MCP prompt it names, leaving other messages unchanged
+async fn expand_prompt_command(mcp_manager: &MCPManager, message: String) -> Result<String, CommandError> {
+    match mcp_manager.expand_prompt_command(&message).await {
+        Some(expanded) => expanded.map_err(|message| CommandError { message }),
+        None => Ok(message),
+    }
+}
+
 #[command]
 pub async fn run_agent(
     window: Window,
@@ -378,6 +437,9 @@ pub async fn run_agent(
         });
     }
 
+    // A message naming an MCP prompt runs that prompt instead
+    let message = expand_prompt_command(&state.mcp_manager, request.message).await?;
+
     // Build agent config
     let mut config = AgentConfig::default();
     if let Some(prompt) = request.system_prompt {
@@ -387,20 +449,7 @@ pub async fn run_agent(
         config.system_prompt = crate::agent::types::build_system_prompt(request.locale.as_deref());
 
         // Add MCP servers info to default system prompt
-        let mcp_servers = state.mcp_manager.get_server_statuses().await;
-        let mut mcp_info = String::new();
-        if !mcp_servers.is_empty() {
-            mcp_info.push_str("\nMCP (Model Context Protocol) Tools:\n");
-            for server in mcp_servers {
-                if matches!(server.status, crate::mcp::types::ConnectionStatus::Connected) {
-                    mcp_info.push_str(&format!("Server '{}' is connected with tools:\n", server.id));
-                    for tool in server.tools {
-                        mcp_info.push_str(&format!("  - {}: {} (use format: {}:{})\n",
-                            tool.name, tool.description, server.id, tool.name));
-                    }
-                }
-            }
-        }
+        let mcp_info = mcp_system_info(&state.mcp_manager.get_server_statuses().await);
         if !mcp_info.is_empty() {
             config.system_prompt.push_str(&mcp_info);
         }
@@ -409,9 +458,16 @@ pub async fn run_agent(
         config.max_turns = turns;
     }
     config.project_path = request.project_path;
+    config.tool_permissions = settings.tool_permissions.clone();
+    config.restrict_to_project = settings.restrict_to_project;
+    config.allowed_paths = settings.allowed_paths.clone();
+    config.max_parallel_tools = settings.max_parallel_tools;
+    config.model_prices = settings.model_prices.clone();
+    config.max_retries = settings.max_retries;
 
     // Get provider info
     let provider_id = settings.get_provider();
+    let fallbacks = settings.fallback_endpoints();
 
     // Create agent loop with provider
     let agent = AgentLoop::new_with_provider(
@@ -425,21 +481,27 @@ pub async fn run_agent(
         Some(&provider_id),
         Some(state.backup_manager.clone()),
         None,
-    );
+    )
+    .with_approval_manager(state.approval_manager.clone())
+    .with_fallbacks(fallbacks);
 
     // Create channel for events
     let (tx, mut rx) = tokio::sync::mpsc::channel::<AgentEvent>(100);
 
     // Spawn event emitter
     let window_clone = window.clone();
+    let db = state.db.clone();
     let emit_task = tokio::spawn(async move {
         while let Some(event) = rx.recv().await {
+            if let AgentEvent::Usage { model, usage, cost, .. } = &event {
+                let _ = db.add_usage_record(None, None, model, usage, *cost);
+            }
             let _ = window_clone.emit("agent-event", &event);
         }
     });
 
     // Run agent
-    let result = agent.run(request.message, tx).await;
+    let result = agent.run(message, tx).await;
 
     // Wait for emitter to finish
     let _ = emit_task.await;
@@ -450,6 +512,50 @@ pub async fn run_agent(
     }
 }
 
+/// Store the tokens used by a chat request against its conversation
+fn record_chat_usage(db: &Database, settings: &Settings, conversation_id: &str, usage: &TokenUsage) {
+    if usage.is_empty() {
+        return;
+    }
+    let cost = estimate_cost(&settings.model, usage, &settings.model_prices);
+    let _ = db.add_usage_record(None, Some(conversation_id), &settings.model, usage, cost);
+}
+
+/// Stream a plain reply (no tools) to the conversation history, calling
+/// `on_text` with the accumulated text as it arrives
+async fn stream_chat_reply(
+    settings: &Settings,
+    history: &[Message],
+    mut on_text: impl FnMut(&str),
+) -> Result<ProviderResponse, CommandError> {
+    let endpoint = settings.endpoint();
+    let request = ClaudeApiRequest {
+        model: endpoint.model.clone(),
+        max_tokens: settings.max_tokens,
+        system: String::new(),
+        messages: history
+            .iter()
+            .map(|m| ApiMessage {
+                role: m.role.clone(),
+                content: ApiContent::Text(m.content.clone()),
+            })
+            .collect(),
+        tools: Vec::new(),
+        temperature: Some(settings.temperature),
+        stream: true,
+    };
+
+    let stream = providers::stream_chat(&reqwest::Client::new(), &endpoint, &request).await?;
+    let response = stream
+        .collect(|response, event| {
+            if let StreamEvent::Text(_) = event {
+                on_text(&response.text);
+            }
+        })
+        .await?;
+    Ok(response)
+}
+
 // Enhanced chat with tools - integrates agent capabilities into chat
 #[derive(Debug, Deserialize)]
 pub struct EnhancedChatRequest {
@@ -458,6 +564,29 @@ pub struct EnhancedChatRequest {
     pub project_path: Option<String>,
     pub enable_tools: bool,
     pub locale: Option<String>,
+    /// Send `content` in place of this earlier user message, on a new branch
+    pub edit_message_id: Option<String>,
+    /// Reply again in place of this assistant message, on a new branch; `content` is ignored
+    pub regenerate_message_id: Option<String>,
+}
+
+/// Look up a message of `conversation_id`, optionally requiring a role
+fn conversation_message(
+    db: &Database,
+    conversation_id: &str,
+    id: &str,
+    role: Option<&str>,
+) -> Result<Message, CommandError> {
+    match db.get_message(id)? {
+        Some(message)
+            if message.conversation_id == conversation_id && role.is_none_or(|role| message.role == role) =>
+        {
+            Ok(message)
+        }
+        _ => Err(CommandError {
+            message: format!("No {} message {} in this conversation", role.unwrap_or("such"), id),
+        }),
+    }
 }
 
 #[command]
@@ -467,9 +596,8 @@ pub async fn send_chat_with_tools(
     request: EnhancedChatRequest,
 ) -> Result<String, CommandError> {
     use crate::agent::{
-        AgentConfig, AgentContent, AgentMessage, ContentBlock, MessageBuilder, ToolExecutor, ToolUse,
+        AgentConfig, AgentContent, AgentMessage, ContentBlock, MessageBuilder, ToolExecutor,
     };
-    use futures::StreamExt;
 
     let settings = state.db.get_settings()?;
 
@@ -479,122 +607,80 @@ pub async fn send_chat_with_tools(
         });
     }
 
-    // Add user message to database
-    let user_msg_id = uuid::Uuid::new_v4().to_string();
-    state
-        .db
-        .add_message(&user_msg_id, &request.conversation_id, "user", &request.content)?;
-
-    // Get conversation history
-    let db_messages = state.db.get_messages(&request.conversation_id)?;
+    // History the reply answers. A regenerated reply answers the same message
+    // as the old one; that branch becomes active once the new reply is stored.
+    let db_messages = if let Some(id) = &request.regenerate_message_id {
+        let reply = conversation_message(&state.db, &request.conversation_id, id, Some("assistant"))?;
+        let parent_id = reply.parent_id.ok_or_else(|| CommandError {
+            message: "Reply has no message to answer".to_string(),
+        })?;
+        state.db.get_branch(&request.conversation_id, &parent_id)?
+    } else {
+        // Add user message to database, next to the edited one if any
+        let user_msg_id = uuid::Uuid::new_v4().to_string();
+        match &request.edit_message_id {
+            Some(id) => {
+                let edited = conversation_message(&state.db, &request.conversation_id, id, Some("user"))?;
+                state.db.add_message_after(
+                    &user_msg_id,
+                    &request.conversation_id,
+                    "user",
+                    &request.content,
+                    edited.parent_id.as_deref(),
+                )?;
+            }
+            None => {
+                state
+                    .db
+                    .add_message(&user_msg_id, &request.conversation_id, "user", &request.content)?;
+            }
+        }
+        state.db.get_messages(&request.conversation_id)?
+    };
+    let reply_to = db_messages.last().map(|message| message.id.clone());
 
     // If tools are not enabled, fall back to simple chat
     if !request.enable_tools {
-        use crate::llm_client::{LLMClient, Message as LLMMessage};
-
-        let provider = settings.get_provider();
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(100);
-
-        let window_clone = window.clone();
-        let emit_task = tokio::spawn(async move {
-            while let Some(text) = rx.recv().await {
-                let _ = window_clone.emit("chat-event", ChatEvent::Text { content: text });
-            }
-        });
-
-        let response = match provider.as_str() {
-            "anthropic" => {
-                // Use ClaudeClient for Anthropic
-                let claude_messages: Vec<ClaudeMessage> = db_messages
-                    .iter()
-                    .map(|m| ClaudeMessage {
-                        role: m.role.clone(),
-                        content: m.content.clone(),
-                    })
-                    .collect();
-                let client = ClaudeClient::new(settings.api_key.clone(), Some(settings.base_url.clone()));
-                client
-                    .send_message_stream(
-                        claude_messages,
-                        &settings.model,
-                        settings.max_tokens,
-                        Some(settings.temperature),
-                        tx,
-                    )
-                    .await?
-            }
-            _ => {
-                // Use LLMClient for OpenAI and other providers
-                let llm_messages: Vec<LLMMessage> = db_messages
-                    .iter()
-                    .map(|m| LLMMessage {
-                        role: m.role.clone(),
-                        content: m.content.clone(),
-                    })
-                    .collect();
-                let llm_client = LLMClient::new_with_openai_headers(
-                    settings.api_key.clone(),
-                    Some(settings.base_url.clone()),
-                    Some(&provider),
-                    Some(&settings.model),
-                    settings.openai_organization.clone(),
-                    settings.openai_project.clone(),
-                );
-                llm_client
-                    .send_message_stream(
-                        llm_messages,
-                        &settings.model,
-                        settings.max_tokens,
-                        Some(settings.temperature),
-                        tx,
-                    )
-                    .await
-                    .map_err(|e| CommandError { message: e.to_string() })?
-            }
-        };
+        let ProviderResponse { text: response, usage, .. } = stream_chat_reply(&settings, &db_messages, |text| {
+            let _ = window.emit("chat-event", ChatEvent::Text { content: text.to_string() });
+        })
+        .await?;
 
-        let _ = emit_task.await;
+        record_chat_usage(&state.db, &settings, &request.conversation_id, &usage);
         let _ = window.emit("chat-event", ChatEvent::Done { final_text: response.clone() });
 
         // Save assistant response
         let assistant_msg_id = uuid::Uuid::new_v4().to_string();
-        state
-            .db
-            .add_message(&assistant_msg_id, &request.conversation_id, "assistant", &response)?;
+        state.db.add_message_after(
+            &assistant_msg_id,
+            &request.conversation_id,
+            "assistant",
+            &response,
+            reply_to.as_deref(),
+        )?;
 
         return Ok(response);
     }
 
-    // Enhanced chat with tools - use AgentLoop which supports multiple providers
-    use crate::llm_client::ProviderConfig;
-
-    let tool_executor = ToolExecutor::new(request.project_path.clone())
-        .with_mcp_manager(state.mcp_manager.clone())
-        .with_backup_manager(state.backup_manager.clone())
-        .with_conversation_id(request.conversation_id.clone());
-
+    // Enhanced chat with tools
     // Build agent-style config for tools
     let mut config = AgentConfig {
         project_path: request.project_path,
         max_turns: 10, // Limit turns in chat mode
+        tool_permissions: settings.tool_permissions.clone(),
+        restrict_to_project: settings.restrict_to_project,
+        allowed_paths: settings.allowed_paths.clone(),
         ..Default::default()
     };
 
+    let tool_executor = ToolExecutor::new(config.project_path.clone())
+        .with_path_resolver(config.path_resolver())
+        .with_mcp_manager(state.mcp_manager.clone())
+        .with_backup_manager(state.backup_manager.clone())
+        .with_conversation_id(request.conversation_id.clone());
+
     // System prompt for chat with tools - include MCP servers info
-    let mcp_servers = state.mcp_manager.get_server_statuses().await;
-    let mut mcp_info = String::new();
-    if !mcp_servers.is_empty() {
-        mcp_info.push_str("\nMCP (Model Context Protocol) Tools:\n");
-        for server in mcp_servers {
-            if matches!(server.status, crate::mcp::types::ConnectionStatus::Connected) {
-                mcp_info.push_str(&format!("Server '{}' is connected with tools:\n", server.id));
-                for tool in server.tools {
-                    mcp_info.push_str(&format!("  - {}: {} (use format: {}:{})\n",
-                        tool.name, tool.description, server.id, tool.name));
-                }
-            }
-        }
-    }
+    let mcp_info = mcp_system_info(&state.mcp_manager.get_server_statuses().await);
 
 
 
@@ -653,25 +739,7 @@ Be concise and helpful. Explain what you're doing when using tools."#
     let mut turn = 0;
     let max_turns = config.max_turns;
 
-    // Get provider config for determining API format
-    let provider_id = settings.get_provider();
-    let mut provider_config = ProviderConfig::from_preset(&provider_id);
-    if !settings.base_url.is_empty() {
-        provider_config.base_url = settings.base_url.clone();
-    }
-
-    // Determine API format
-    let use_openai_format = matches!(
-        provider_config.api_format,
-        crate::llm_client::ApiFormat::OpenAI | crate::llm_client::ApiFormat::OpenAICompatible
-    );
-    let use_google_format = matches!(
-        provider_config.api_format,
-        crate::llm_client::ApiFormat::Google
-    );
-
-    // For Google: track thoughtSignature per function call across iterations (required for Gemini 3)
-    let mut google_thought_signatures: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+    let endpoint = settings.endpoint();
 
     loop {
         turn += 1;
@@ -682,319 +750,29 @@ Be concise and helpful. Explain what you're doing when using tools."#
         // Build and send request
         let api_request = message_builder.build_request(&agent_messages).await;
 
-        let response = if use_google_format {
-            // Google Gemini format request (pass thought signatures for Gemini 3 function calling)
-            let google_request = convert_to_google_format(&api_request, &settings.model, settings.max_tokens, &google_thought_signatures);
-            let base = provider_config.base_url.trim_end_matches('/');
-            let url = format!("{}/v1beta/models/{}:streamGenerateContent?alt=sse", base, settings.model);
-
-            client.post(&url)
-                .header("Content-Type", "application/json")
-                .header("x-goog-api-key", &settings.api_key)
-                .json(&google_request)
-                .send()
-                .await
-                .map_err(|e| CommandError { message: format!("HTTP error: {}", e) })?
-        } else if use_openai_format {
-            // OpenAI format request
-            let openai_request = convert_to_openai_format(&api_request, &settings.model);
-            let base = provider_config.base_url.trim_end_matches('/');
-            let url = if base.ends_with("/v1") {
-                format!("{}/chat/completions", base)
-            } else {
-                format!("{}/v1/chat/completions", base)
-            };
-
-            let mut req = client.post(&url)
-                .header("Content-Type", "application/json");
-
-            if !settings.api_key.is_empty() {
-                req = req.header("Authorization", format!("Bearer {}", settings.api_key));
-            }
-            // Add optional OpenAI headers
-            if let Some(ref org) = settings.openai_organization {
-                if !org.is_empty() {
-                    req = req.header("OpenAI-Organization", org);
-                }
-            }
-            if let Some(ref proj) = settings.openai_project {
-                if !proj.is_empty() {
-                    req = req.header("OpenAI-Project", proj);
-                }
-            }
-
-            req.json(&openai_request)
-                .send()
-                .await
-                .map_err(|e| CommandError { message: format!("HTTP error: {}", e) })?
-        } else {
-            // Anthropic format request
-            client
-                .post(format!("{}/v1/messages", provider_config.base_url.trim_end_matches('/')))
-                .header("Content-Type", "application/json")
-                .header("x-api-key", &settings.api_key)
-                .header("anthropic-version", "2023-06-01")
-                .json(&api_request)
-                .send()
-                .await
-                .map_err(|e| CommandError { message: format!("HTTP error: {}", e) })?
-        };
-
-        if !response.status().is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(CommandError { message: format!("API error: {}", error_text) });
-        }
-
-        // Handle streaming response based on provider format
-        let mut stream = response.bytes_stream();
-        let mut buffer = String::new();
-        let mut accumulated_text = String::new();
-        let mut tool_uses: Vec<ToolUse> = Vec::new();
-
-        if use_google_format {
-            // Google Gemini streaming format (SSE with alt=sse)
-            while let Some(chunk) = stream.next().await {
-                let chunk = chunk.map_err(|e| CommandError { message: format!("Stream error: {}", e) })?;
-                buffer.push_str(&String::from_utf8_lossy(&chunk));
-
-                while let Some(pos) = buffer.find('\n') {
-                    let line = buffer[..pos].trim().to_string();
-                    buffer = buffer[pos + 1..].to_string();
-
-                    if line.is_empty() {
-                        continue;
-                    }
-
-                    // Parse SSE data: prefix
-                    let json_str = if let Some(data) = line.strip_prefix("data: ") {
-                        data
-                    } else {
-                        continue;
-                    };
-
-                    if let Ok(event) = serde_json::from_str::<serde_json::Value>(json_str) {
-                        // Extract text and function calls from candidates
-                        if let Some(candidates) = event.get("candidates").and_then(|v| v.as_array()) {
-                            for candidate in candidates {
-                                if let Some(parts) = candidate.get("content")
-                                    .and_then(|c| c.get("parts"))
-                                    .and_then(|p| p.as_array())
-                                {
-                                    for part in parts {
-                                        // Handle text
-                                        if let Some(text) = part.get("text").and_then(|v| v.as_str()) {
-                                            if !text.is_empty() {
-                                                accumulated_text.push_str(text);
-                                                let _ = window.emit("chat-event", ChatEvent::Text {
-                                                    content: accumulated_text.clone(),
-                                                });
-                                            }
-                                        }
-                                        // Handle function calls (with thoughtSignature for Gemini 3)
-                                        if let Some(fc) = part.get("functionCall") {
-                                            let name = fc.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                            let args = fc.get("args").cloned().unwrap_or(serde_json::json!({}));
-                                            let id = format!("fc_{}", uuid::Uuid::new_v4());
-
-                                            // Capture thoughtSignature from the same part (required for Gemini 3)
-                                            let thought_signature = part.get("thoughtSignature")
-                                                .and_then(|v| v.as_str())
-                                                .map(|s| s.to_string());
-
-                                            // Also store in map for lookup when building functionResponse
-                                            if let Some(ref sig) = thought_signature {
-                                                google_thought_signatures.insert(id.clone(), sig.clone());
-                                            }
-
-                                            tool_uses.push(ToolUse {
-                                                id: id.clone(),
-                                                name: name.clone(),
-                                                input: args.clone(),
-                                                thought_signature,
-                                            });
-
-                                            let _ = window.emit("chat-event", ChatEvent::ToolStart {
-                                                tool: name,
-                                                input: args,
-                                            });
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+        let stream = providers::stream_chat(&client, &endpoint, &api_request).await?;
+        let ProviderResponse {
+            text: accumulated_text,
+            tool_uses,
+            usage,
+        } = stream
+            .collect(|response, event| match event {
+                StreamEvent::Text(_) => {
+                    let _ = window.emit("chat-event", ChatEvent::Text {
+                        content: response.text.clone(),
+                    });
                 }
-            }
-        } else if use_openai_format {
-            // OpenAI streaming format
-            let mut current_tool_calls: std::collections::HashMap<i64, (String, String, String)> = std::collections::HashMap::new();
-
-            while let Some(chunk) = stream.next().await {
-                let chunk = chunk.map_err(|e| CommandError { message: format!("Stream error: {}", e) })?;
-                buffer.push_str(&String::from_utf8_lossy(&chunk));
-
-                while let Some(pos) = buffer.find('\n') {
-                    let line = buffer[..pos].to_string();
-                    buffer = buffer[pos + 1..].to_string();
-
-                    if let Some(data) = line.strip_prefix("data: ") {
-                        if data.trim() == "[DONE]" {
-                            continue;
-                        }
-
-                        if let Ok(event) = serde_json::from_str::<serde_json::Value>(data) {
-                            if let Some(choices) = event.get("choices").and_then(|v| v.as_array()) {
-                                for choice in choices {
-                                    if let Some(delta) = choice.get("delta") {
-                                        // Handle text content
-                                        if let Some(content) = delta.get("content").and_then(|v| v.as_str()) {
-                                            accumulated_text.push_str(content);
-                                            let _ = window.emit("chat-event", ChatEvent::Text {
-                                                content: accumulated_text.clone(),
-                                            });
-                                        }
-
-                                        // Handle tool_calls
-                                        if let Some(tcs) = delta.get("tool_calls").and_then(|v| v.as_array()) {
-                                            for tc in tcs {
-                                                let index = tc.get("index").and_then(|v| v.as_i64()).unwrap_or(0);
-
-                                                let entry = current_tool_calls.entry(index).or_insert_with(|| {
-                                                    (String::new(), String::new(), String::new())
-                                                });
-
-                                                if let Some(id) = tc.get("id").and_then(|v| v.as_str()) {
-                                                    entry.0 = id.to_string();
-                                                }
-                                                if let Some(func) = tc.get("function") {
-                                                    if let Some(name) = func.get("name").and_then(|v| v.as_str()) {
-                                                        entry.1 = name.to_string();
-                                                    }
-                                                    if let Some(args) = func.get("arguments").and_then(|v| v.as_str()) {
-                                                        entry.2.push_str(args);
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-
-                                    // Check if finished
-                                    if choice.get("finish_reason").and_then(|v| v.as_str()).is_some() {
-                                        // Convert collected tool_calls to ToolUse
-                                        for (id, name, args) in current_tool_calls.values() {
-                                            if !id.is_empty() && !name.is_empty() {
-                                                let input: serde_json::Value = serde_json::from_str(args)
-                                                    .unwrap_or(serde_json::json!({}));
-
-                                                tool_uses.push(ToolUse {
-                                                    id: id.clone(),
-                                                    name: name.clone(),
-                                                    input: input.clone(),
-                                                    thought_signature: None, // OpenAI doesn't use thought signatures
-                                                });
-
-                                                // Emit tool start
-                                                let _ = window.emit("chat-event", ChatEvent::ToolStart {
-                                                    tool: name.clone(),
-                                                    input,
-                                                });
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+                StreamEvent::ToolUse(tool_use) => {
+                    let _ = window.emit("chat-event", ChatEvent::ToolStart {
+                        tool: tool_use.name.clone(),
+                        input: tool_use.input.clone(),
+                    });
                 }
-            }
-        } else {
-            // Anthropic streaming format
-            let mut current_tool_input = String::new();
-            let mut current_tool_id = String::new();
-            let mut current_tool_name = String::new();
-
-            while let Some(chunk) = stream.next().await {
-                let chunk = chunk.map_err(|e| CommandError { message: format!("Stream error: {}", e) })?;
-                buffer.push_str(&String::from_utf8_lossy(&chunk));
-
-                while let Some(pos) = buffer.find('\n') {
-                    let line = buffer[..pos].to_string();
-                    buffer = buffer[pos + 1..].to_string();
-
-                    if let Some(data) = line.strip_prefix("data: ") {
-                        if data == "[DONE]" {
-                            continue;
-                        }
+                StreamEvent::Usage(_) => {}
+            })
+            .await?;
 
-                        if let Ok(event) = serde_json::from_str::<serde_json::Value>(data) {
-                            let event_type = event.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                            match event_type {
-                                "content_block_start" => {
-                                    if let Some(block) = event.get("content_block") {
-                                        if block.get("type").and_then(|v| v.as_str()) == Some("tool_use") {
-                                            current_tool_id = block
-                                                .get("id")
-                                                .and_then(|v| v.as_str())
-                                                .unwrap_or("")
-                                                .to_string();
-                                            current_tool_name = block
-                                                .get("name")
-                                                .and_then(|v| v.as_str())
-                                                .unwrap_or("")
-                                                .to_string();
-                                            current_tool_input.clear();
-                                        }
-                                    }
-                                }
-                                "content_block_delta" => {
-                                    if let Some(delta) = event.get("delta") {
-                                        let delta_type = delta.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                                        if delta_type == "text_delta" {
-                                            if let Some(text) = delta.get("text").and_then(|v| v.as_str()) {
-                                                accumulated_text.push_str(text);
-                                                let _ = window.emit("chat-event", ChatEvent::Text {
-                                                    content: accumulated_text.clone(),
-                                                });
-                                            }
-                                        } else if delta_type == "input_json_delta" {
-                                            if let Some(partial) = delta.get("partial_json").and_then(|v| v.as_str()) {
-                                                current_tool_input.push_str(partial);
-                                            }
-                                        }
-                                    }
-                                }
-                                "content_block_stop" => {
-                                    if !current_tool_id.is_empty() {
-                                        let input: serde_json::Value = serde_json::from_str(&current_tool_input)
-                                            .unwrap_or(serde_json::json!({}));
-
-                                        tool_uses.push(ToolUse {
-                                            id: current_tool_id.clone(),
-                                            name: current_tool_name.clone(),
-                                            input: input.clone(),
-                                            thought_signature: None, // Anthropic doesn't use thought signatures
-                                        });
-
-                                        // Emit tool start
-                                        let _ = window.emit("chat-event", ChatEvent::ToolStart {
-                                            tool: current_tool_name.clone(),
-                                            input,
-                                        });
-
-                                        current_tool_id.clear();
-                                        current_tool_name.clear();
-                                        current_tool_input.clear();
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        record_chat_usage(&state.db, &settings, &request.conversation_id, &usage);
 
         // Update final text
         if !accumulated_text.is_empty() {
@@ -1034,7 +812,30 @@ Be concise and helpful. Explain what you're doing when using tools."#
         let mut tool_results = Vec::new();
 
         for tool_use in &tool_uses {
-            let result = tool_executor.execute(tool_use).await;
+            // Apply the tool permission policy before running anything
+            let denied = match config.permission_for(&tool_use.name) {
+                ToolPermission::Allow => None,
+                ToolPermission::Deny => Some(disabled_message(&tool_use.name)),
+                ToolPermission::Ask => {
+                    let (request_id, rx) = state.approval_manager.register();
+                    let _ = window.emit("chat-event", ChatEvent::ApprovalRequired {
+                        request_id: request_id.clone(),
+                        tool: tool_use.name.clone(),
+                        input: tool_use.input.clone(),
+                    });
+                    // Chat turns can't be cancelled; an unanswered request times out
+                    let decision = state
+                        .approval_manager
+                        .wait(&request_id, rx, &CancellationToken::new())
+                        .await;
+                    denial_message(&tool_use.name, &decision)
+                }
+            };
+
+            let result = match denied {
+                Some(message) => crate::agent::ToolResult::error(tool_use.id.clone(), message),
+                None => tool_executor.execute(tool_use).await,
+            };
 
             // Emit tool end
             let _ = window.emit("chat-event", ChatEvent::ToolEnd {
@@ -1058,12 +859,16 @@ Be concise and helpful. Explain what you're doing when using tools."#
 
     // Save final assistant response to database
     let assistant_msg_id = uuid::Uuid::new_v4().to_string();
-    state
-        .db
-        .add_message(&assistant_msg_id, &request.conversation_id, "assistant", &final_text)?;
+    state.db.add_message_after(
+        &assistant_msg_id,
+        &request.conversation_id,
+        "assistant",
+        &final_text,
+        reply_to.as_deref(),
+    )?;
 
     // Update conversation title if this is the first exchange
-    if db_messages.len() == 1 {
+    if db_messages.len() == 1 && request.regenerate_message_id.is_none() {
         let title = if request.content.len() > 30 {
             format!("{}...", &request.content[..30])
         } else {
@@ -1112,6 +917,36 @@ pub struct TaskAgentRequest {
     pub locale: Option<String>,
 }
 
+/// Rebuild the agent history of a task from its stored messages.
+/// A compaction record replaces everything stored before it.
+fn task_history(messages: &[TaskMessage]) -> Vec<AgentMessage> {
+    let mut history = Vec::new();
+
+    for m in messages {
+        match m.role.as_str() {
+            "compaction" => {
+                if let Ok(record) = serde_json::from_str::<CompactionRecord>(&m.content) {
+                    history = record.into_messages();
+                }
+            }
+            // Error notices are shown in the UI but are not part of the conversation
+            "error" => {}
+            _ => {
+                let content = match serde_json::from_str::<AgentContent>(&m.content) {
+                    Ok(c) => c,
+                    Err(_) => AgentContent::Text(m.content.clone()),
+                };
+                history.push(AgentMessage {
+                    role: m.role.clone(),
+                    content,
+                });
+            }
+        }
+    }
+
+    history
+}
+
 #[command]
 pub async fn run_task_agent(
     window: Window,
@@ -1127,52 +962,182 @@ pub async fn run_task_agent(
         });
     }
 
+    let run = state.start_task_run(&request.task_id)?;
+
     // Load existing conversation history
     let existing_messages = state.db.get_task_messages(&request.task_id)?;
 
+    // A message naming an MCP prompt runs that prompt instead
+    let message = expand_prompt_command(&state.mcp_manager, request.message).await?;
+
     // Save new user message
     let user_msg_id = uuid::Uuid::new_v4().to_string();
-    state.db.add_task_message(&user_msg_id, &request.task_id, "user", &request.message)?;
+    state.db.add_task_message(&user_msg_id, &request.task_id, "user", &message)?;
 
     // Update task status to running
     state.db.update_task_status(&request.task_id, "running")?;
 
-    // Build agent config with MCP servers info
+    // Build conversation history from existing messages, parsing JSON if possible
+    let mut agent_messages = task_history(&existing_messages);
+
+    // Tool calls cut off by an interruption that is not resumed get error results
+    let pending = pending_tool_uses(&agent_messages);
+    if !pending.is_empty() {
+        let results: Vec<ToolResult> = pending
+            .into_iter()
+            .map(|tool_use| ToolResult::error(tool_use.id, "Interrupted before the tool call finished".to_string()))
+            .collect();
+        let content = AgentContent::ToolResults(results);
+        let content_json = serde_json::to_string(&content).unwrap_or_default();
+        state.db.add_task_message(&uuid::Uuid::new_v4().to_string(), &request.task_id, "user", &content_json)?;
+        agent_messages.push(AgentMessage {
+            role: "user".to_string(),
+            content,
+        });
+    }
+
+    // Add the new user message
+    agent_messages.push(AgentMessage {
+        role: "user".to_string(),
+        content: AgentContent::Text(message),
+    });
+
+    // A run interrupted before its first turn resumes from here
+    let messages_json = serde_json::to_string(&agent_messages).unwrap_or_default();
+    state.db.save_task_checkpoint(&request.task_id, 0, &messages_json)?;
+
+    let options = TaskRunOptions {
+        task_id: request.task_id,
+        project_path: request.project_path,
+        max_turns: request.max_turns,
+        locale: request.locale,
+    };
+    execute_task_agent(window, &state, run, settings, options, agent_messages, 0).await
+}
+
+/// Continue an interrupted task from its last checkpoint
+#[command]
+pub async fn resume_task_agent(
+    window: Window,
+    state: State<'_, Arc<AppState>>,
+    task_id: String,
+    max_turns: Option<u32>,
+    locale: Option<String>,
+) -> Result<String, CommandError> {
+    let settings = state.db.get_settings()?;
+
+    if settings.api_key.is_empty() && !settings.allows_empty_api_key() {
+        return Err(CommandError {
+            message: "API key not configured".to_string(),
+        });
+    }
+
+    let run = state.start_task_run(&task_id)?;
+
+    let task = state.db.get_task(&task_id)?.ok_or_else(|| CommandError {
+        message: format!("Task not found: {}", task_id),
+    })?;
+    if task.status != "interrupted" {
+        return Err(CommandError {
+            message: format!("Only interrupted tasks can be resumed (task is {})", task.status),
+        });
+    }
+
+    // Tasks interrupted before checkpoints existed resume from their stored messages
+    let (messages, turn) = match state.db.get_task_checkpoint(&task_id)? {
+        Some(checkpoint) => {
+            let messages = serde_json::from_str::<Vec<AgentMessage>>(&checkpoint.messages)
+                .map_err(|e| format!("Invalid checkpoint: {}", e))?;
+            (messages, checkpoint.turn)
+        }
+        None => (task_history(&state.db.get_task_messages(&task_id)?), 0),
+    };
+
+    state.db.update_task_status(&task_id, "running")?;
+
+    let options = TaskRunOptions {
+        task_id,
+        project_path: task.project_path,
+        max_turns,
+        locale,
+    };
+    execute_task_agent(window, &state, run, settings, options, messages, turn).await
+}
+
+/// Settings of a task run that are not stored with the task
+struct TaskRunOptions {
+    task_id: String,
+    project_path: Option<String>,
+    max_turns: Option<u32>,
+    locale: Option<String>,
+}
+
+/// Run the agent for a task from `messages`, taken after `turn` turns, storing
+/// its messages, plan progress and checkpoints as it goes
+async fn execute_task_agent(
+    window: Window,
+    state: &AppState,
+    run: TaskRun<'_>,
+    settings: Settings,
+    options: TaskRunOptions,
+    agent_messages: Vec<AgentMessage>,
+    turn: u32,
+) -> Result<String, CommandError> {
     // Build agent config with MCP servers info
     let mut config = AgentConfig::default();
     
     // Use localized system prompt
-    config.system_prompt = crate::agent::types::build_system_prompt(request.locale.as_deref());
+    config.system_prompt = crate::agent::types::build_system_prompt(options.locale.as_deref());
 
     // Add MCP servers info to system prompt
-    let mcp_servers = state.mcp_manager.get_server_statuses().await;
-    let mut mcp_info = String::new();
-    if !mcp_servers.is_empty() {
-        mcp_info.push_str("\nMCP (Model Context Protocol) Tools:\n");
-        for server in mcp_servers {
-            if matches!(server.status, crate::mcp::types::ConnectionStatus::Connected) {
-                mcp_info.push_str(&format!("Server '{}' is connected with tools:\n", server.id));
-                for tool in server.tools {
-                    mcp_info.push_str(&format!("  - {}: {} (use format: {}:{})\n",
-                        tool.name, tool.description, server.id, tool.name));
-                }
-            }
-        }
-    }
+    let mcp_info = mcp_system_info(&state.mcp_manager.get_server_statuses().await);
     if !mcp_info.is_empty() {
         config.system_prompt.push_str(&mcp_info);
     }
 
-    if let Some(turns) = request.max_turns {
+    if let Some(turns) = options.max_turns {
         config.max_turns = turns;
     }
-    config.project_path = request.project_path;
+    config.project_path = options.project_path;
+    config.tool_permissions = settings.tool_permissions.clone();
+    config.restrict_to_project = settings.restrict_to_project;
+    config.allowed_paths = settings.allowed_paths.clone();
+    config.max_parallel_tools = settings.max_parallel_tools;
+    config.model_prices = settings.model_prices.clone();
+    config.max_retries = settings.max_retries;
+
+    // Sandbox mode gives the task its own container for bash commands. Starting
+    // it may pull an image, so the run can be cancelled meanwhile.
+    let sandbox = if settings.sandbox_mode {
+        let started = tokio::select! {
+            started = Sandbox::start(&options.task_id, &settings.sandbox_image, config.project_path.as_deref()) => started,
+            _ = run.cancel_token.cancelled() => {
+                let _ = state.db.update_task_status(&options.task_id, "cancelled");
+                return Ok("Task cancelled".to_string());
+            }
+        };
+        match started {
+            Ok(sandbox) => {
+                config.system_prompt.push_str(&sandbox_system_info(&settings.sandbox_image));
+                Some(Arc::new(sandbox))
+            }
+            Err(e) => {
+                let _ = state.db.update_task_status(&options.task_id, "failed");
+                return Err(CommandError { message: e });
+            }
+        }
+    } else {
+        None
+    };
 
     // Get provider info
     let provider_id = settings.get_provider();
+    let fallbacks = settings.fallback_endpoints();
+
+    let cancel_token = run.cancel_token.clone();
 
     // Create agent loop with provider
-    let agent = AgentLoop::new_with_provider(
+    let mut agent = AgentLoop::new_with_provider(
         settings.api_key,
         settings.base_url,
         config,
@@ -1182,35 +1147,20 @@ pub async fn run_task_agent(
         state.mcp_manager.clone(),
         Some(&provider_id),
         Some(state.backup_manager.clone()),
-        Some(request.task_id.clone()),
-    );
-
-    // Build conversation history from existing messages, parsing JSON if possible
-    let mut agent_messages: Vec<AgentMessage> = existing_messages
-        .iter()
-        .map(|m| {
-            let content = match serde_json::from_str::<AgentContent>(&m.content) {
-                Ok(c) => c,
-                Err(_) => AgentContent::Text(m.content.clone()),
-            };
-            AgentMessage {
-                role: m.role.clone(),
-                content,
-            }
-        })
-        .collect();
-
-    // Add the new user message
-    agent_messages.push(AgentMessage {
-        role: "user".to_string(),
-        content: AgentContent::Text(request.message.clone()),
-    });
+        Some(options.task_id.clone()),
+    )
+    .with_cancel_token(cancel_token.clone())
+    .with_approval_manager(state.approval_manager.clone())
+    .with_fallbacks(fallbacks);
+    if let Some(sandbox) = &sandbox {
+        agent = agent.with_sandbox(sandbox.clone());
+    }
 
     // Create channel for events
     let (tx, mut rx) = tokio::sync::mpsc::channel::<AgentEvent>(100);
 
     // Clone state for event handler
-    let task_id = request.task_id.clone();
+    let task_id = options.task_id.clone();
     let db = state.db.clone();
 
     // Track state for saving structured messages and smooth streaming
@@ -1218,14 +1168,17 @@ pub async fn run_task_agent(
     let current_turn_text = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
     let history_text_clone = history_text.clone();
     let current_turn_text_clone = current_turn_text.clone();
+
+    // Tool calls awaiting results were stored with the checkpoint that holds them
+    let pending_saved = !pending_tool_uses(&agent_messages).is_empty();
     
     // Spawn event emitter with task tracking
     let window_clone = window.clone();
     let emit_task = tokio::spawn(async move {
         let mut turn_tool_uses = Vec::new();
+        let mut assistant_saved = pending_saved;
         while let Some(event) = rx.recv().await {
             let mut event_to_emit = event.clone();
-
             // Track plan and step updates in database
             match &event {
                 AgentEvent::Text { content } => {
@@ -1241,10 +1194,10 @@ pub async fn run_task_agent(
                     };
                     event_to_emit = AgentEvent::Text { content: full_streaming_text };
                 }
-                AgentEvent::ToolStart { tool, input } => {
+                AgentEvent::ToolStart { tool_use_id, tool, input } => {
                     // Record tool use for structured saving
                     turn_tool_uses.push(ContentBlock::ToolUse {
-                        id: format!("tc_{}", uuid::Uuid::new_v4()),
+                        id: tool_use_id.clone(),
                         name: tool.clone(),
                         input: input.clone(),
                         thought_signature: None,
@@ -1262,8 +1215,9 @@ pub async fn run_task_agent(
                         history.push_str(&current);
                     }
 
-                    // 2. Save assistant message (structured if it had tool uses) independently in DB
-                    if !current.is_empty() || !turn_tool_uses.is_empty() {
+                    // 2. Save assistant message (structured if it had tool uses) independently in DB,
+                    //    unless it was already stored ahead of its tool results
+                    if !assistant_saved && (!current.is_empty() || !turn_tool_uses.is_empty()) {
                         let content = if turn_tool_uses.is_empty() {
                             AgentContent::Text(current.clone())
                         } else {
@@ -1271,7 +1225,7 @@ pub async fn run_task_agent(
                             if !current.is_empty() {
                                 blocks.push(ContentBlock::Text { text: current.clone() });
                             }
-                            blocks.extend(turn_tool_uses.drain(..));
+                            blocks.append(&mut turn_tool_uses);
                             AgentContent::Blocks(blocks)
                         };
                         
@@ -1280,14 +1234,35 @@ pub async fn run_task_agent(
                     }
                     
                     current.clear();
+                    assistant_saved = false;
 
                     if let AgentEvent::Done { .. } = event {
                         let _ = db.update_task_status(&task_id, "completed");
                     }
                 }
                 AgentEvent::ToolResults { results } => {
-                    // Save tool results as a 'user' message in DB for AI context
-                    let content = AgentContent::ToolResults(results.clone());
+                    // The assistant turn holding the tool calls must be stored before its results
+                    if assistant_saved {
+                        turn_tool_uses.clear();
+                    } else if !turn_tool_uses.is_empty() {
+                        let current = current_turn_text_clone.lock().unwrap();
+                        let mut blocks = Vec::new();
+                        if !current.is_empty() {
+                            blocks.push(ContentBlock::Text { text: current.clone() });
+                        }
+                        blocks.extend(turn_tool_uses.drain(..));
+                        let content_json = serde_json::to_string(&AgentContent::Blocks(blocks)).unwrap_or_default();
+                        let _ = db.add_task_message(&uuid::Uuid::new_v4().to_string(), &task_id, "assistant", &content_json);
+                        assistant_saved = true;
+                    }
+
+                    // Save tool results as a 'user' message in DB for AI context. Images
+                    // stay out of the history; their descriptions remain.
+                    let stored = results
+                        .iter()
+                        .map(|result| ToolResult { images: Vec::new(), ..result.clone() })
+                        .collect();
+                    let content = AgentContent::ToolResults(stored);
                     let content_json = serde_json::to_string(&content).unwrap_or_default();
                     let _ = db.add_task_message(&uuid::Uuid::new_v4().to_string(), &task_id, "user", &content_json);
                 }
@@ -1310,6 +1285,37 @@ pub async fn run_task_agent(
                     let msg_id = uuid::Uuid::new_v4().to_string();
                     let _ = db.add_task_message(&msg_id, &task_id, "error", message);
                 }
+                AgentEvent::Cancelled { .. } => {
+                    let _ = db.update_task_status(&task_id, "cancelled");
+                }
+                AgentEvent::Usage { model, usage, cost, .. } => {
+                    let _ = db.add_usage_record(Some(&task_id), None, model, usage, *cost);
+                }
+                AgentEvent::Compacted { summary: Some(summary), kept_messages, .. } => {
+                    // Persist the compacted history so later runs start from it
+                    let record = CompactionRecord {
+                        summary: summary.clone(),
+                        kept: kept_messages.clone(),
+                    };
+                    if let Ok(content_json) = serde_json::to_string(&record) {
+                        let _ = db.add_task_message(&uuid::Uuid::new_v4().to_string(), &task_id, "compaction", &content_json);
+                    }
+                }
+                AgentEvent::Checkpoint { turn, messages } => {
+                    // Store the assistant turn before its tools run, so a resumed
+                    // run finds the calls it re-issues in the transcript
+                    if !assistant_saved && !pending_tool_uses(messages).is_empty() {
+                        if let Some(last) = messages.last() {
+                            let content_json = serde_json::to_string(&last.content).unwrap_or_default();
+                            let _ = db.add_task_message(&uuid::Uuid::new_v4().to_string(), &task_id, "assistant", &content_json);
+                            assistant_saved = true;
+                        }
+                    }
+                    if let Ok(messages_json) = serde_json::to_string(messages) {
+                        let _ = db.save_task_checkpoint(&task_id, *turn, &messages_json);
+                    }
+                    continue;
+                }
                 _ => {}
             }
 
@@ -1318,24 +1324,52 @@ pub async fn run_task_agent(
         }
     });
 
-    // Run agent with conversation history
-    let result = agent.run_with_history(agent_messages, tx).await;
+    // Run agent from the given history
+    let result = agent.resume(agent_messages, turn, tx).await;
 
     // Wait for emitter to finish
     let _ = emit_task.await;
 
+    if let Some(sandbox) = &sandbox {
+        sandbox.stop().await;
+    }
+
+    drop(run);
+
     // Always ensure task status is updated at the end
+    if cancel_token.is_cancelled() {
+        let _ = state.db.update_task_status(&options.task_id, "cancelled");
+        return Ok("Task cancelled".to_string());
+    }
+
     match result {
         Ok(_messages) => {
             Ok("Task completed successfully".to_string())
         }
         Err(e) => {
-            let _ = state.db.update_task_status(&request.task_id, "failed");
+            let _ = state.db.update_task_status(&options.task_id, "failed");
             Err(CommandError { message: e })
         }
     }
 }
 
+#[command]
+pub async fn cancel_task_agent(
+    state: State<'_, Arc<AppState>>,
+    task_id: String,
+) -> Result<(), CommandError> {
+    let running = state.running_tasks.lock().unwrap();
+    match running.get(&task_id) {
+        Some(token) => {
+            token.cancel();
+            Ok(())
+        }
+        None => Err(CommandError {
+            message: "Task is not running".to_string(),
+        }),
+    }
+}
+
 // Get task messages command
 #[command]
 pub fn get_task_messages(
@@ -1345,6 +1379,70 @@ pub fn get_task_messages(
     state.db.get_task_messages(&task_id).map_err(Into::into)
 }
 
+// Usage commands
+#[command]
+pub fn get_task_usage(
+    state: State<'_, Arc<AppState>>,
+    task_id: String,
+) -> Result<UsageTotals, CommandError> {
+    state.db.get_task_usage(&task_id).map_err(Into::into)
+}
+
+#[command]
+pub fn get_conversation_usage(
+    state: State<'_, Arc<AppState>>,
+    conversation_id: String,
+) -> Result<UsageTotals, CommandError> {
+    state.db.get_conversation_usage(&conversation_id).map_err(Into::into)
+}
+
+#[command]
+pub fn get_usage_by_day(
+    state: State<'_, Arc<AppState>>,
+    days: Option<u32>,
+) -> Result<Vec<DailyUsage>, CommandError> {
+    state.db.get_daily_usage(days.unwrap_or(30)).map_err(Into::into)
+}
+
+// Export/import commands
+#[command]
+pub fn export_conversation(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+    format: String,
+) -> Result<String, CommandError> {
+    let format = transcript::ExportFormat::parse(&format)?;
+    transcript::export_conversation(&state.db, &id, format).map_err(Into::into)
+}
+
+#[command]
+pub fn export_task(
+    state: State<'_, Arc<AppState>>,
+    id: String,
+    format: String,
+) -> Result<String, CommandError> {
+    let format = transcript::ExportFormat::parse(&format)?;
+    transcript::export_task(&state.db, &id, format).map_err(Into::into)
+}
+
+#[command]
+pub fn import_transcript(
+    state: State<'_, Arc<AppState>>,
+    bundle: String,
+) -> Result<ImportedTranscript, CommandError> {
+    transcript::import(&state.db, &bundle).map_err(Into::into)
+}
+
+// Search commands
+#[command]
+pub fn search_history(
+    state: State<'_, Arc<AppState>>,
+    query: String,
+    limit: Option<u32>,
+) -> Result<Vec<SearchHit>, CommandError> {
+    state.db.search_history(&query, limit.unwrap_or(50)).map_err(Into::into)
+}
+
 // Skills commands
 #[command]
 pub fn get_skills_list() -> Vec<SkillMetadata> {
@@ -1434,254 +1532,31 @@ pub async fn execute_mcp_tool(
     Ok(state.mcp_manager.execute_tool(&call).await)
 }
 
-/// Convert Claude API request format to OpenAI format
-fn convert_to_openai_format(
-    request: &crate::agent::message_builder::ClaudeApiRequest,
-    model: &str,
-) -> serde_json::Value {
-    use crate::agent::message_builder::ApiContent;
-
-    // Build messages, including system prompt
-    let mut messages: Vec<serde_json::Value> = Vec::new();
-
-    // Add system message
-    if !request.system.is_empty() {
-        messages.push(serde_json::json!({
-            "role": "system",
-            "content": request.system
-        }));
-    }
-
-    // Convert conversation messages
-    for msg in &request.messages {
-        let role = &msg.role;
-
-        match &msg.content {
-            ApiContent::Text(text) => {
-                messages.push(serde_json::json!({
-                    "role": role,
-                    "content": text
-                }));
-            }
-            ApiContent::Blocks(blocks) => {
-                // Handle content blocks (text, tool_use, tool_result)
-                let mut text_parts: Vec<String> = Vec::new();
-                let mut tool_calls: Vec<serde_json::Value> = Vec::new();
-
-                for block in blocks {
-                    let block_type = block.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                    match block_type {
-                        "text" => {
-                            if let Some(text) = block.get("text").and_then(|v| v.as_str()) {
-                                text_parts.push(text.to_string());
-                            }
-                        }
-                        "tool_use" => {
-                            tool_calls.push(serde_json::json!({
-                                "id": block.get("id"),
-                                "type": "function",
-                                "function": {
-                                    "name": block.get("name"),
-                                    "arguments": serde_json::to_string(block.get("input").unwrap_or(&serde_json::json!({}))).unwrap_or_default()
-                                }
-                            }));
-                        }
-                        "tool_result" => {
-                            // OpenAI uses tool role to represent tool results
-                            messages.push(serde_json::json!({
-                                "role": "tool",
-                                "tool_call_id": block.get("tool_use_id"),
-                                "content": block.get("content")
-                            }));
-                        }
-                        _ => {}
-                    }
-                }
-
-                // If there's text content
-                if !text_parts.is_empty() {
-                    let mut msg_obj = serde_json::json!({
-                        "role": role,
-                        "content": text_parts.join("\n")
-                    });
-
-                    // If there are tool_calls
-                    if !tool_calls.is_empty() {
-                        msg_obj["tool_calls"] = serde_json::json!(tool_calls);
-                    }
-
-                    messages.push(msg_obj);
-                } else if !tool_calls.is_empty() {
-                    // Only tool_calls, no text
-                    messages.push(serde_json::json!({
-                        "role": role,
-                        "content": serde_json::Value::Null,
-                        "tool_calls": tool_calls
-                    }));
-                }
-            }
-        }
-    }
-
-    // Convert tools definition
-    let tools: Vec<serde_json::Value> = request.tools.iter().map(|tool| {
-        serde_json::json!({
-            "type": "function",
-            "function": {
-                "name": tool.name,
-                "description": tool.description,
-                "parameters": tool.input_schema
-            }
-        })
-    }).collect();
-
-    let mut openai_request = serde_json::json!({
-        "model": request.model,
-        "stream": request.stream,
-        "messages": messages
-    });
-
-    // Use correct max tokens parameter based on model
-    let model_lower = model.to_lowercase();
-    let is_legacy = model_lower.contains("gpt-3.5")
-        || (model_lower.contains("gpt-4") && !model_lower.contains("gpt-4o") && !model_lower.contains("gpt-4-turbo"));
-
-    if is_legacy {
-        openai_request["max_tokens"] = serde_json::json!(request.max_tokens);
-    } else {
-        openai_request["max_completion_tokens"] = serde_json::json!(request.max_tokens);
-    }
-
-    // Only add temperature for non-reasoning models (o1, o3, gpt-5 don't support custom temperature)
-    let is_reasoning = model_lower.starts_with("o1") || model_lower.starts_with("o3") || model_lower.starts_with("gpt-5")
-        || model_lower.contains("-o1") || model_lower.contains("-o3")
-        || model_lower.contains("o1-") || model_lower.contains("o3-");
-
-    if !is_reasoning {
-        if let Some(temp) = request.temperature {
-            openai_request["temperature"] = serde_json::json!(temp);
-        }
-    }
-
-    if !tools.is_empty() {
-        openai_request["tools"] = serde_json::json!(tools);
-        openai_request["tool_choice"] = serde_json::json!("auto");
-    }
-
-    openai_request
+#[command]
+pub async fn read_mcp_resource(
+    state: State<'_, Arc<AppState>>,
+    server_id: String,
+    uri: String,
+) -> Result<String, CommandError> {
+    state
+        .mcp_manager
+        .read_resource(&server_id, &uri)
+        .await
+        .map_err(|message| CommandError { message })
 }
 
-/// Convert Claude API request format to Google Gemini format
-fn convert_to_google_format(
-    request: &crate::agent::message_builder::ClaudeApiRequest,
-    _model: &str,
-    max_tokens: u32,
-    thought_signatures: &std::collections::HashMap<String, String>,
-) -> serde_json::Value {
-    use crate::agent::message_builder::ApiContent;
-
-    // Build contents array
-    let mut contents: Vec<serde_json::Value> = Vec::new();
-
-    // Convert messages to Google format
-    for msg in &request.messages {
-        // Google uses "user" and "model" instead of "user" and "assistant"
-        let role = if msg.role == "assistant" { "model" } else { &msg.role };
-
-        let parts = match &msg.content {
-            ApiContent::Text(text) => {
-                vec![serde_json::json!({"text": text})]
-            }
-            ApiContent::Blocks(blocks) => {
-                let mut parts_list = Vec::new();
-                for block in blocks {
-                    let block_type = block.get("type").and_then(|v| v.as_str()).unwrap_or("");
-
-                    match block_type {
-                        "text" => {
-                            if let Some(text) = block.get("text").and_then(|v| v.as_str()) {
-                                parts_list.push(serde_json::json!({"text": text}));
-                            }
-                        }
-                        "tool_use" => {
-                            // Convert to functionCall format with thoughtSignature if present (for Gemini 3)
-                            let tool_id = block.get("id").and_then(|v| v.as_str()).unwrap_or("");
-                            let mut fc_part = serde_json::json!({
-                                "functionCall": {
-                                    "name": block.get("name"),
-                                    "args": block.get("input")
-                                }
-                            });
-                            // Include thoughtSignature if we have it for this tool
-                            if let Some(sig) = thought_signatures.get(tool_id) {
-                                fc_part["thoughtSignature"] = serde_json::json!(sig);
-                            }
-                            parts_list.push(fc_part);
-                        }
-                        "tool_result" => {
-                            // Convert to functionResponse format with thoughtSignature (required for Gemini 3)
-                            let tool_use_id = block.get("tool_use_id").and_then(|v| v.as_str()).unwrap_or("unknown");
-                            let mut fr_part = serde_json::json!({
-                                "functionResponse": {
-                                    "name": tool_use_id,
-                                    "response": {
-                                        "content": block.get("content")
-                                    }
-                                }
-                            });
-                            // Include thoughtSignature from matching tool_use (required for Gemini 3)
-                            if let Some(sig) = thought_signatures.get(tool_use_id) {
-                                fr_part["thoughtSignature"] = serde_json::json!(sig);
-                            }
-                            parts_list.push(fr_part);
-                        }
-                        _ => {}
-                    }
-                }
-                parts_list
-            }
-        };
-
-        if !parts.is_empty() {
-            contents.push(serde_json::json!({
-                "role": role,
-                "parts": parts
-            }));
-        }
-    }
-
-    // Convert tools to Google functionDeclarations format
-    let function_declarations: Vec<serde_json::Value> = request.tools.iter().map(|tool| {
-        serde_json::json!({
-            "name": tool.name,
-            "description": tool.description,
-            "parameters": tool.input_schema
-        })
-    }).collect();
-
-    let mut google_request = serde_json::json!({
-        "contents": contents,
-        "generationConfig": {
-            "maxOutputTokens": max_tokens
-        }
-    });
-
-    // Add system instruction if present
-    if !request.system.is_empty() {
-        google_request["systemInstruction"] = serde_json::json!({
-            "parts": [{"text": request.system}]
-        });
-    }
-
-    // Add tools if present
-    if !function_declarations.is_empty() {
-        google_request["tools"] = serde_json::json!([{
-            "functionDeclarations": function_declarations
-        }]);
-    }
-
-    google_request
+#[command]
+pub async fn get_mcp_prompt(
+    state: State<'_, Arc<AppState>>,
+    server_id: String,
+    name: String,
+    arguments: HashMap<String, String>,
+) -> Result<String, CommandError> {
+    state
+        .mcp_manager
+        .get_prompt(&server_id, &name, arguments)
+        .await
+        .map_err(|message| CommandError { message })
 }
 
 // Undo Command
@@ -1693,3 +1568,27 @@ pub fn undo_last_action(
     state.backup_manager.undo_last(&conversation_id)
         .map_err(|e| CommandError { message: e })
 }
+
+// Tool approval commands
+#[command]
+pub fn approve_tool_call(
+    state: State<'_, Arc<AppState>>,
+    request_id: String,
+) -> Result<(), CommandError> {
+    state
+        .approval_manager
+        .resolve(&request_id, ApprovalDecision::Approved)
+        .map_err(|e| CommandError { message: e })
+}
+
+#[command]
+pub fn deny_tool_call(
+    state: State<'_, Arc<AppState>>,
+    request_id: String,
+    reason: Option<String>,
+) -> Result<(), CommandError> {
+    state
+        .approval_manager
+        .resolve(&request_id, ApprovalDecision::Denied { reason })
+        .map_err(|e| CommandError { message: e })
+}
@@ -1,7 +1,9 @@
+use crate::migrations;
+use crate::secrets::{self, SecretError, SecretStore};
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,9 +14,30 @@ pub enum DbError {
     Io(#[from] std::io::Error),
     #[error("Lock error")]
     Lock,
+    #[error("Secret error: {0}")]
+    Secret(#[from] SecretError),
+    #[error("Stored secrets are locked; unlock or reset them to continue")]
+    SecretsLocked,
+    #[error("Database schema {found} is newer than this app supports ({supported}); please update Kuse Cowork")]
+    NewerSchema { found: i64, supported: i64 },
+    #[error("Migration {version} ({description}) failed: {source}")]
+    Migration {
+        version: i64,
+        description: &'static str,
+        source: rusqlite::Error,
+    },
 }
 
+/// Settings whose values are stored encrypted
+const SECRET_SETTINGS: &[&str] = &["api_key", "provider_keys"];
+
+/// Known plaintext stored encrypted, to detect a key that doesn't match the stored secrets
+const SECRETS_CHECK_KEY: &str = "secrets_check";
+const SECRETS_CHECK_VALUE: &str = "kuse-cowork";
+
 use std::collections::HashMap;
+use crate::agent::{ModelPrice, TokenUsage, ToolPermission};
+use crate::providers::{FallbackProvider, ProviderEndpoint};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
@@ -42,12 +65,55 @@ pub struct Settings {
     /// Custom backup path (empty uses default .kuse/backups/)
     #[serde(default)]
     pub backup_path: String,
+    /// Permission policy per tool name (allow / ask / deny); unlisted tools are allowed
+    #[serde(default)]
+    pub tool_permissions: HashMap<String, ToolPermission>,
+    /// Confine filesystem tools to the project directory
+    #[serde(default = "default_restrict_to_project")]
+    pub restrict_to_project: bool,
+    /// Extra directories the filesystem tools may access besides the project
+    #[serde(default)]
+    pub allowed_paths: Vec<String>,
+    /// Maximum read-only tool calls run at the same time within a turn
+    #[serde(default = "default_max_parallel_tools")]
+    pub max_parallel_tools: usize,
+    /// Price overrides in USD per million tokens, keyed by model name prefix
+    #[serde(default)]
+    pub model_prices: HashMap<String, ModelPrice>,
+    /// Retries of a failed model request before giving up on a provider
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Providers tried in order when the primary one stays unavailable
+    #[serde(default)]
+    pub fallback_providers: Vec<FallbackProvider>,
+    /// Run each task's bash commands in its own Docker container
+    #[serde(default)]
+    pub sandbox_mode: bool,
+    /// Image of the sandbox container
+    #[serde(default = "default_sandbox_image")]
+    pub sandbox_image: String,
 }
 
 fn default_enable_undo() -> bool {
     true
 }
 
+fn default_restrict_to_project() -> bool {
+    true
+}
+
+fn default_max_parallel_tools() -> usize {
+    4
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_sandbox_image() -> String {
+    crate::tools::sandbox::DEFAULT_IMAGE.to_string()
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Self {
@@ -62,6 +128,15 @@ impl Default for Settings {
             openai_project: None,
             enable_undo: true,
             backup_path: String::new(),
+            tool_permissions: HashMap::new(),
+            restrict_to_project: true,
+            allowed_paths: Vec::new(),
+            max_parallel_tools: default_max_parallel_tools(),
+            model_prices: HashMap::new(),
+            max_retries: default_max_retries(),
+            fallback_providers: Vec::new(),
+            sandbox_mode: false,
+            sandbox_image: default_sandbox_image(),
         }
     }
 }
@@ -117,6 +192,23 @@ impl Settings {
         }
         false
     }
+
+    /// The configured provider, credentials and model
+    pub fn endpoint(&self) -> ProviderEndpoint {
+        ProviderEndpoint::new(Some(&self.get_provider()), &self.base_url, self.api_key.clone(), self.model.clone())
+            .with_openai_headers(self.openai_organization.clone(), self.openai_project.clone())
+    }
+
+    /// Resolve the fallback chain, taking each provider's key from `provider_keys`
+    pub fn fallback_endpoints(&self) -> Vec<ProviderEndpoint> {
+        self.fallback_providers
+            .iter()
+            .map(|fallback| {
+                let api_key = self.provider_keys.get(&fallback.provider).cloned().unwrap_or_default();
+                ProviderEndpoint::new(Some(&fallback.provider), &fallback.base_url, api_key, fallback.model.clone())
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -125,6 +217,9 @@ pub struct Conversation {
     pub title: String,
     pub created_at: i64,
     pub updated_at: i64,
+    /// Last message of the branch being shown
+    #[serde(default)]
+    pub active_leaf_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,6 +229,18 @@ pub struct Message {
     pub role: String,
     pub content: String,
     pub timestamp: i64,
+    /// The message this one follows; `None` for the first message of a branch
+    #[serde(default)]
+    pub parent_id: Option<String>,
+}
+
+/// A point where a conversation splits into alternative messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchPoint {
+    /// The message the alternatives follow; `None` when the first message was edited
+    pub parent_id: Option<String>,
+    /// The alternatives, oldest first
+    pub message_ids: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -156,6 +263,24 @@ pub struct PlanStep {
     pub status: String, // "pending", "running", "completed", "failed"
 }
 
+/// Summed token usage and estimated cost over a set of requests
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    pub requests: u64,
+    #[serde(flatten)]
+    pub usage: TokenUsage,
+    /// Estimated cost in USD; requests for models without a known price count as zero
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyUsage {
+    /// Local date as YYYY-MM-DD
+    pub date: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskMessage {
     pub id: String,
@@ -165,109 +290,211 @@ pub struct TaskMessage {
     pub timestamp: i64,
 }
 
+/// Agent history of a task saved during a run, for resuming it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskCheckpoint {
+    pub task_id: String,
+    /// Turns completed when the checkpoint was taken
+    pub turn: u32,
+    /// JSON-encoded `Vec<AgentMessage>`
+    pub messages: String,
+    pub updated_at: i64,
+}
+
+/// A message matching a history search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// "conversation" or "task"
+    pub source: String,
+    /// The conversation or task the message belongs to
+    pub parent_id: String,
+    pub title: String,
+    pub message_id: String,
+    pub role: String,
+    /// Matching excerpt, with matches wrapped in `**`
+    pub snippet: String,
+    pub timestamp: i64,
+}
+
+/// Whether the stored secrets can be read, for the unlock screen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretsStatus {
+    pub locked: bool,
+    /// Why the secrets are locked
+    pub error: Option<String>,
+    /// The key is derived from a passphrase, which can be entered to unlock them
+    pub passphrase_required: bool,
+}
+
 pub struct Database {
     pub(crate) conn: Mutex<Connection>,
+    /// Directory holding the database and the passphrase salt
+    data_dir: PathBuf,
+    /// Key of the stored secrets, or why they can't be read. The app still
+    /// starts when they can't; they are unlocked or reset from the UI.
+    secrets: RwLock<Result<SecretStore, SecretError>>,
 }
 
 impl Database {
     pub fn new() -> Result<Self, DbError> {
-        let db_path = Self::get_db_path()?;
+        let db = Self::open(&Self::get_db_path()?)?;
+
+        let store = SecretStore::open(&db.data_dir, db.secrets_check()?.is_none());
+        match store.map_err(DbError::from).and_then(|store| db.init_secrets(store)) {
+            Ok(()) => {}
+            Err(DbError::Secret(e)) => *db.secrets.write().map_err(|_| DbError::Lock)? = Err(e),
+            Err(e) => return Err(e),
+        }
+        Ok(db)
+    }
 
+    /// Open and migrate the database at `db_path`, with the secrets locked
+    pub(crate) fn open(db_path: &Path) -> Result<Self, DbError> {
         // Ensure parent directory exists
         if let Some(parent) = db_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        let conn = Connection::open(&db_path)?;
-        let db = Self {
+        let mut conn = Connection::open(db_path)?;
+        migrations::migrate(&mut conn, db_path)?;
+
+        let data_dir = db_path
+            .parent()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| DbError::Io(std::io::Error::other("Invalid database path")))?;
+        Ok(Self {
             conn: Mutex::new(conn),
-        };
-        db.init_tables()?;
-        Ok(db)
+            data_dir,
+            secrets: RwLock::new(Err(SecretError::MissingKey)),
+        })
     }
 
-    fn get_db_path() -> Result<PathBuf, DbError> {
-        let data_dir = dirs::data_dir()
-            .ok_or_else(|| DbError::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Could not find data directory",
-            )))?;
-        Ok(data_dir.join("kuse-cowork").join("kuse-cowork.db"))
+    /// The encrypted known value used to verify the key, if anything was encrypted yet
+    fn secrets_check(&self) -> Result<Option<String>, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        Ok(conn
+            .query_row("SELECT value FROM settings WHERE key = ?1", [SECRETS_CHECK_KEY], |row| row.get(0))
+            .ok())
     }
 
-    fn init_tables(&self) -> Result<(), DbError> {
+    /// Verify the key against the stored secrets and start using it, then
+    /// encrypt any settings saved before encryption was introduced
+    fn init_secrets(&self, store: SecretStore) -> Result<(), DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS settings (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            )",
-            [],
-        )?;
+        let check: Option<String> = conn
+            .query_row("SELECT value FROM settings WHERE key = ?1", [SECRETS_CHECK_KEY], |row| row.get(0))
+            .ok();
+        match check {
+            Some(check) => {
+                store.decrypt(&check)?;
+            }
+            None => {
+                conn.execute(
+                    "INSERT INTO settings (key, value) VALUES (?1, ?2)",
+                    [SECRETS_CHECK_KEY, &store.encrypt(SECRETS_CHECK_VALUE)?],
+                )?;
+            }
+        }
+        *self.secrets.write().map_err(|_| DbError::Lock)? = Ok(store);
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS conversations (
-                id TEXT PRIMARY KEY,
-                title TEXT NOT NULL,
-                created_at INTEGER NOT NULL,
-                updated_at INTEGER NOT NULL
-            )",
-            [],
-        )?;
+        for key in SECRET_SETTINGS {
+            self.encrypt_plaintext(&conn, "settings", "key", "value", Some(key))?;
+        }
+        self.encrypt_plaintext(&conn, "mcp_servers", "id", "oauth_client_secret", None)?;
+        for column in ["client_secret", "access_token", "refresh_token"] {
+            self.encrypt_plaintext(&conn, "mcp_oauth_tokens", "server_id", column, None)?;
+        }
+        Ok(())
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id TEXT PRIMARY KEY,
-                conversation_id TEXT NOT NULL,
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                timestamp INTEGER NOT NULL,
-                FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
-            )",
-            [],
-        )?;
+    pub fn secrets_status(&self) -> SecretsStatus {
+        let (locked, error) = match self.secrets.read().as_deref() {
+            Ok(Ok(_)) => (false, None),
+            Ok(Err(e)) => (true, Some(e.to_string())),
+            Err(_) => (true, Some(DbError::Lock.to_string())),
+        };
+        SecretsStatus {
+            locked,
+            error,
+            passphrase_required: SecretStore::uses_passphrase(&self.data_dir),
+        }
+    }
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_messages_conversation
-             ON messages(conversation_id)",
-            [],
-        )?;
+    /// Unlock secrets encrypted with a passphrase
+    pub fn unlock_secrets(&self, passphrase: &str) -> Result<(), DbError> {
+        let store = SecretStore::unlock(&self.data_dir, passphrase, false)?;
+        self.init_secrets(store)
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS tasks (
-                id TEXT PRIMARY KEY,
-                title TEXT NOT NULL,
-                description TEXT NOT NULL,
-                status TEXT NOT NULL DEFAULT 'planning',
-                plan TEXT,
-                current_step INTEGER NOT NULL DEFAULT 0,
-                project_path TEXT,
-                created_at INTEGER NOT NULL,
-                updated_at INTEGER NOT NULL
-            )",
-            [],
-        )?;
+    /// Delete the stored secrets (API keys, MCP OAuth secrets and tokens) and
+    /// encrypt new ones with a fresh key, derived from `passphrase` if given
+    pub fn reset_secrets(&self, passphrase: Option<&str>) -> Result<(), DbError> {
+        let store = SecretStore::reset(&self.data_dir, passphrase)?;
+        {
+            let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+            for key in SECRET_SETTINGS.iter().chain([&SECRETS_CHECK_KEY]) {
+                conn.execute("DELETE FROM settings WHERE key = ?1", [key])?;
+            }
+            conn.execute("UPDATE mcp_servers SET oauth_client_secret = NULL", [])?;
+            conn.execute("DELETE FROM mcp_oauth_tokens", [])?;
+        }
+        self.init_secrets(store)
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS task_messages (
-                id TEXT PRIMARY KEY,
-                task_id TEXT NOT NULL,
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                timestamp INTEGER NOT NULL,
-                FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE
-            )",
-            [],
-        )?;
+    /// Encrypt the values of `column` still stored in plaintext, optionally
+    /// only the row whose `id_column` equals `only`
+    pub(crate) fn encrypt_plaintext(
+        &self,
+        conn: &Connection,
+        table: &str,
+        id_column: &str,
+        column: &str,
+        only: Option<&str>,
+    ) -> Result<(), DbError> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {id}, {col} FROM {table} WHERE {col} IS NOT NULL",
+            id = id_column,
+            col = column,
+            table = table
+        ))?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (id, value) in rows {
+            if secrets::is_encrypted(&value) || only.is_some_and(|only| only != id) {
+                continue;
+            }
+            conn.execute(
+                &format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, id_column),
+                [&self.encrypt_secret(&value)?, &id],
+            )?;
+        }
+        Ok(())
+    }
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_task_messages_task
-             ON task_messages(task_id)",
-            [],
-        )?;
+    pub(crate) fn encrypt_secret(&self, value: &str) -> Result<String, DbError> {
+        match &*self.secrets.read().map_err(|_| DbError::Lock)? {
+            Ok(store) => Ok(store.encrypt(value)?),
+            Err(_) => Err(DbError::SecretsLocked),
+        }
+    }
 
-        Ok(())
+    pub(crate) fn decrypt_secret(&self, stored: &str) -> Result<String, DbError> {
+        match &*self.secrets.read().map_err(|_| DbError::Lock)? {
+            Ok(store) => Ok(store.decrypt(stored)?),
+            Err(_) => Err(DbError::SecretsLocked),
+        }
+    }
+
+    fn get_db_path() -> Result<PathBuf, DbError> {
+        let data_dir = dirs::data_dir()
+            .ok_or_else(|| DbError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Could not find data directory",
+            )))?;
+        Ok(data_dir.join("kuse-cowork").join("kuse-cowork.db"))
     }
 
     // Settings methods
@@ -283,7 +510,7 @@ impl Database {
         for row in rows {
             let (key, value) = row?;
             match key.as_str() {
-                "api_key" => settings.api_key = value,
+                "api_key" => settings.api_key = self.decrypt_secret(&value)?,
                 "model" => settings.model = value,
                 "base_url" => settings.base_url = value,
                 "max_tokens" => settings.max_tokens = value.parse().unwrap_or(4096),
@@ -291,10 +518,37 @@ impl Database {
                 "provider" => settings.provider = value,
                 "provider_keys" => {
                     // Parse JSON to HashMap
-                    if let Ok(keys) = serde_json::from_str::<HashMap<String, String>>(&value) {
+                    if let Ok(keys) = serde_json::from_str::<HashMap<String, String>>(&self.decrypt_secret(&value)?) {
                         settings.provider_keys = keys;
                     }
                 }
+                "tool_permissions" => {
+                    if let Ok(permissions) = serde_json::from_str::<HashMap<String, ToolPermission>>(&value) {
+                        settings.tool_permissions = permissions;
+                    }
+                }
+                "restrict_to_project" => settings.restrict_to_project = value.parse().unwrap_or(true),
+                "max_parallel_tools" => {
+                    settings.max_parallel_tools = value.parse().unwrap_or_else(|_| default_max_parallel_tools())
+                }
+                "allowed_paths" => {
+                    if let Ok(paths) = serde_json::from_str::<Vec<String>>(&value) {
+                        settings.allowed_paths = paths;
+                    }
+                }
+                "model_prices" => {
+                    if let Ok(prices) = serde_json::from_str::<HashMap<String, ModelPrice>>(&value) {
+                        settings.model_prices = prices;
+                    }
+                }
+                "max_retries" => settings.max_retries = value.parse().unwrap_or_else(|_| default_max_retries()),
+                "fallback_providers" => {
+                    if let Ok(fallbacks) = serde_json::from_str::<Vec<FallbackProvider>>(&value) {
+                        settings.fallback_providers = fallbacks;
+                    }
+                }
+                "sandbox_mode" => settings.sandbox_mode = value.parse().unwrap_or(false),
+                "sandbox_image" if !value.is_empty() => settings.sandbox_image = value,
                 _ => {}
             }
         }
@@ -327,15 +581,32 @@ impl Database {
         // Serialize provider_keys to JSON
         let provider_keys_json = serde_json::to_string(&settings.provider_keys)
             .unwrap_or_else(|_| "{}".to_string());
+        let tool_permissions_json = serde_json::to_string(&settings.tool_permissions)
+            .unwrap_or_else(|_| "{}".to_string());
+        let allowed_paths_json = serde_json::to_string(&settings.allowed_paths)
+            .unwrap_or_else(|_| "[]".to_string());
+        let model_prices_json = serde_json::to_string(&settings.model_prices)
+            .unwrap_or_else(|_| "{}".to_string());
+        let fallback_providers_json = serde_json::to_string(&settings.fallback_providers)
+            .unwrap_or_else(|_| "[]".to_string());
 
         let pairs = [
-            ("api_key", settings.api_key.clone()),
+            ("api_key", self.encrypt_secret(&settings.api_key)?),
             ("model", settings.model.clone()),
             ("base_url", settings.base_url.clone()),
             ("max_tokens", settings.max_tokens.to_string()),
             ("temperature", settings.temperature.to_string()),
             ("provider", provider),
-            ("provider_keys", provider_keys_json),
+            ("provider_keys", self.encrypt_secret(&provider_keys_json)?),
+            ("tool_permissions", tool_permissions_json),
+            ("restrict_to_project", settings.restrict_to_project.to_string()),
+            ("allowed_paths", allowed_paths_json),
+            ("max_parallel_tools", settings.max_parallel_tools.to_string()),
+            ("model_prices", model_prices_json),
+            ("max_retries", settings.max_retries.to_string()),
+            ("fallback_providers", fallback_providers_json),
+            ("sandbox_mode", settings.sandbox_mode.to_string()),
+            ("sandbox_image", settings.sandbox_image.clone()),
         ];
 
         for (key, value) in pairs {
@@ -353,19 +624,12 @@ impl Database {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, title, created_at, updated_at
+            "SELECT id, title, created_at, updated_at, active_leaf_id
              FROM conversations
              ORDER BY updated_at DESC"
         )?;
 
-        let rows = stmt.query_map([], |row| {
-            Ok(Conversation {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                created_at: row.get(2)?,
-                updated_at: row.get(3)?,
-            })
-        })?;
+        let rows = stmt.query_map([], conversation_from_row)?;
 
         let mut conversations = Vec::new();
         for row in rows {
@@ -389,9 +653,25 @@ impl Database {
             title: title.to_string(),
             created_at: now,
             updated_at: now,
+            active_leaf_id: None,
         })
     }
 
+    pub fn get_conversation(&self, id: &str) -> Result<Option<Conversation>, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, title, created_at, updated_at, active_leaf_id FROM conversations WHERE id = ?1"
+        )?;
+
+        let mut rows = stmt.query_map([id], conversation_from_row)?;
+
+        match rows.next() {
+            Some(conversation) => Ok(Some(conversation?)),
+            None => Ok(None),
+        }
+    }
+
     pub fn update_conversation_title(&self, id: &str, title: &str) -> Result<(), DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
         let now = chrono::Utc::now().timestamp_millis();
@@ -415,25 +695,34 @@ impl Database {
     }
 
     // Message methods
+    /// Messages on the active branch, from the first to the active leaf
     pub fn get_messages(&self, conversation_id: &str) -> Result<Vec<Message>, DbError> {
+        let leaf_id = self
+            .get_conversation(conversation_id)?
+            .and_then(|conversation| conversation.active_leaf_id);
+        match leaf_id {
+            Some(leaf_id) => self.get_branch(conversation_id, &leaf_id),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Messages from the first down to `leaf_id`, whether or not that branch is active
+    pub fn get_branch(&self, conversation_id: &str, leaf_id: &str) -> Result<Vec<Message>, DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
 
-        let mut stmt = conn.prepare(
-            "SELECT id, conversation_id, role, content, timestamp
-             FROM messages
-             WHERE conversation_id = ?1
-             ORDER BY timestamp ASC"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "WITH RECURSIVE path(id, depth) AS (
+                 SELECT id, 0 FROM messages WHERE id = ?2 AND conversation_id = ?1
+                 UNION ALL
+                 SELECT m.parent_id, path.depth + 1 FROM messages m JOIN path ON m.id = path.id
+                 WHERE m.parent_id IS NOT NULL
+             )
+             SELECT {} FROM messages JOIN path ON messages.id = path.id
+             ORDER BY path.depth DESC",
+            MESSAGE_COLUMNS
+        ))?;
 
-        let rows = stmt.query_map([conversation_id], |row| {
-            Ok(Message {
-                id: row.get(0)?,
-                conversation_id: row.get(1)?,
-                role: row.get(2)?,
-                content: row.get(3)?,
-                timestamp: row.get(4)?,
-            })
-        })?;
+        let rows = stmt.query_map([conversation_id, leaf_id], message_from_row)?;
 
         let mut messages = Vec::new();
         for row in rows {
@@ -443,26 +732,74 @@ impl Database {
         Ok(messages)
     }
 
+    /// Every message of a conversation, across all branches, oldest first
+    pub fn get_all_messages(&self, conversation_id: &str) -> Result<Vec<Message>, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM messages WHERE conversation_id = ?1 ORDER BY timestamp ASC, rowid ASC",
+            MESSAGE_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map([conversation_id], message_from_row)?;
+
+        let mut messages = Vec::new();
+        for row in rows {
+            messages.push(row?);
+        }
+
+        Ok(messages)
+    }
+
+    pub fn get_message(&self, id: &str) -> Result<Option<Message>, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM messages WHERE id = ?1", MESSAGE_COLUMNS))?;
+        let mut rows = stmt.query_map([id], message_from_row)?;
+
+        match rows.next() {
+            Some(message) => Ok(Some(message?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Append a message to the active branch
     pub fn add_message(
         &self,
         id: &str,
         conversation_id: &str,
         role: &str,
         content: &str,
+    ) -> Result<Message, DbError> {
+        let parent_id = self
+            .get_conversation(conversation_id)?
+            .and_then(|conversation| conversation.active_leaf_id);
+        self.add_message_after(id, conversation_id, role, content, parent_id.as_deref())
+    }
+
+    /// Add a message following `parent_id` and make it the active leaf. A
+    /// parent that already has a reply starts a new branch.
+    pub fn add_message_after(
+        &self,
+        id: &str,
+        conversation_id: &str,
+        role: &str,
+        content: &str,
+        parent_id: Option<&str>,
     ) -> Result<Message, DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
         let now = chrono::Utc::now().timestamp_millis();
 
         conn.execute(
-            "INSERT INTO messages (id, conversation_id, role, content, timestamp)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            [id, conversation_id, role, content, &now.to_string()],
+            "INSERT INTO messages (id, conversation_id, role, content, timestamp, parent_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![id, conversation_id, role, content, now, parent_id],
         )?;
 
-        // Update conversation's updated_at
+        // Show the new message and update conversation's updated_at
         conn.execute(
-            "UPDATE conversations SET updated_at = ?1 WHERE id = ?2",
-            [&now.to_string(), conversation_id],
+            "UPDATE conversations SET active_leaf_id = ?1, updated_at = ?2 WHERE id = ?3",
+            rusqlite::params![id, now, conversation_id],
         )?;
 
         Ok(Message {
@@ -471,9 +808,70 @@ impl Database {
             role: role.to_string(),
             content: content.to_string(),
             timestamp: now,
+            parent_id: parent_id.map(String::from),
         })
     }
 
+    /// Show the branch containing `message_id`, following its most recent
+    /// replies down to a leaf. Returns the new active path.
+    pub fn switch_branch(&self, conversation_id: &str, message_id: &str) -> Result<Vec<Message>, DbError> {
+        {
+            let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+            let mut leaf = message_id.to_string();
+            loop {
+                let child: Option<String> = conn
+                    .query_row(
+                        "SELECT id FROM messages WHERE parent_id = ?1 AND conversation_id = ?2
+                         ORDER BY timestamp DESC, rowid DESC LIMIT 1",
+                        [&leaf, conversation_id],
+                        |row| row.get(0),
+                    )
+                    .ok();
+                match child {
+                    Some(child) => leaf = child,
+                    None => break,
+                }
+            }
+
+            conn.execute(
+                "UPDATE conversations SET active_leaf_id = ?1 WHERE id = ?2",
+                [&leaf, conversation_id],
+            )?;
+        }
+
+        self.get_messages(conversation_id)
+    }
+
+    /// Places where the conversation has alternative messages
+    pub fn list_branches(&self, conversation_id: &str) -> Result<Vec<BranchPoint>, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT parent_id, id FROM messages
+             WHERE conversation_id = ?1
+             ORDER BY timestamp ASC, rowid ASC"
+        )?;
+        let rows = stmt.query_map([conversation_id], |row| {
+            Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut branches: Vec<BranchPoint> = Vec::new();
+        for row in rows {
+            let (parent_id, id) = row?;
+            match branches.iter_mut().find(|b| b.parent_id == parent_id) {
+                Some(branch) => branch.message_ids.push(id),
+                None => branches.push(BranchPoint {
+                    parent_id,
+                    message_ids: vec![id],
+                }),
+            }
+        }
+        branches.retain(|b| b.message_ids.len() > 1);
+
+        Ok(branches)
+    }
+
     #[allow(dead_code)]
     pub fn update_message_content(&self, id: &str, content: &str) -> Result<(), DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
@@ -629,10 +1027,62 @@ impl Database {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
         // Delete messages first
         conn.execute("DELETE FROM task_messages WHERE task_id = ?1", [id])?;
+        conn.execute("DELETE FROM task_checkpoints WHERE task_id = ?1", [id])?;
         conn.execute("DELETE FROM tasks WHERE id = ?1", [id])?;
         Ok(())
     }
 
+    /// Mark tasks left running by a previous session as interrupted.
+    /// Returns the number of tasks changed.
+    pub fn mark_interrupted_tasks(&self) -> Result<usize, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let changed = conn.execute(
+            "UPDATE tasks SET status = 'interrupted', updated_at = ?1 WHERE status IN ('planning', 'running')",
+            [now],
+        )?;
+
+        Ok(changed)
+    }
+
+    pub fn save_task_checkpoint(&self, task_id: &str, turn: u32, messages: &str) -> Result<(), DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        conn.execute(
+            "INSERT INTO task_checkpoints (task_id, turn, messages, updated_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(task_id) DO UPDATE SET turn = ?2, messages = ?3, updated_at = ?4",
+            rusqlite::params![task_id, turn, messages, now],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_task_checkpoint(&self, task_id: &str) -> Result<Option<TaskCheckpoint>, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+        let result = conn.query_row(
+            "SELECT task_id, turn, messages, updated_at FROM task_checkpoints WHERE task_id = ?1",
+            [task_id],
+            |row| {
+                Ok(TaskCheckpoint {
+                    task_id: row.get(0)?,
+                    turn: row.get(1)?,
+                    messages: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(checkpoint) => Ok(Some(checkpoint)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     // Task message methods
     pub fn get_task_messages(&self, task_id: &str) -> Result<Vec<TaskMessage>, DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
@@ -641,7 +1091,7 @@ impl Database {
             "SELECT id, task_id, role, content, timestamp
              FROM task_messages
              WHERE task_id = ?1
-             ORDER BY timestamp ASC"
+             ORDER BY timestamp ASC, rowid ASC"
         )?;
 
         let rows = stmt.query_map([task_id], |row| {
@@ -704,4 +1154,282 @@ impl Database {
 
         Ok(())
     }
+
+    // Import methods
+    /// Recreate an exported conversation under `id`, giving its messages
+    /// fresh IDs. Bundles from before branching are imported as one chain.
+    pub fn import_conversation(
+        &self,
+        id: &str,
+        conversation: &Conversation,
+        messages: &[Message],
+    ) -> Result<(), DbError> {
+        let mut conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        let tx = conn.transaction()?;
+
+        let new_ids: HashMap<&str, String> = messages
+            .iter()
+            .map(|m| (m.id.as_str(), uuid::Uuid::new_v4().to_string()))
+            .collect();
+        let branched = messages.iter().any(|m| m.parent_id.is_some());
+
+        let mut previous: Option<&str> = None;
+        for message in messages {
+            let parent_id = if branched {
+                message.parent_id.as_deref().and_then(|parent| new_ids.get(parent)).map(String::as_str)
+            } else {
+                previous
+            };
+            let new_id = &new_ids[message.id.as_str()];
+            tx.execute(
+                "INSERT INTO messages (id, conversation_id, role, content, timestamp, parent_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![new_id, id, message.role, message.content, message.timestamp, parent_id],
+            )?;
+            previous = Some(new_id);
+        }
+
+        let active_leaf_id = conversation
+            .active_leaf_id
+            .as_deref()
+            .and_then(|leaf| new_ids.get(leaf))
+            .map(String::as_str)
+            .or(previous);
+        tx.execute(
+            "INSERT INTO conversations (id, title, created_at, updated_at, active_leaf_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![id, conversation.title, conversation.created_at, conversation.updated_at, active_leaf_id],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Recreate an exported task under `id`, giving its messages fresh IDs.
+    /// A task exported mid-run is imported as interrupted so it can be resumed.
+    pub fn import_task(&self, id: &str, task: &Task, messages: &[TaskMessage]) -> Result<(), DbError> {
+        let mut conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        let tx = conn.transaction()?;
+        let plan_json = task.plan.as_ref().and_then(|plan| serde_json::to_string(plan).ok());
+        let status = match task.status.as_str() {
+            "planning" | "running" => "interrupted",
+            status => status,
+        };
+
+        tx.execute(
+            "INSERT INTO tasks (id, title, description, status, plan, current_step, project_path, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                id,
+                task.title,
+                task.description,
+                status,
+                plan_json,
+                task.current_step,
+                task.project_path,
+                task.created_at,
+                task.updated_at
+            ],
+        )?;
+        for message in messages {
+            tx.execute(
+                "INSERT INTO task_messages (id, task_id, role, content, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![
+                    uuid::Uuid::new_v4().to_string(),
+                    id,
+                    message.role,
+                    message.content,
+                    message.timestamp
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Usage methods
+    pub fn add_usage_record(
+        &self,
+        task_id: Option<&str>,
+        conversation_id: Option<&str>,
+        model: &str,
+        usage: &TokenUsage,
+        cost: Option<f64>,
+    ) -> Result<(), DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        conn.execute(
+            "INSERT INTO usage_records (id, task_id, conversation_id, model, input_tokens, output_tokens,
+                cache_read_tokens, cache_creation_tokens, cost, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            rusqlite::params![
+                uuid::Uuid::new_v4().to_string(),
+                task_id,
+                conversation_id,
+                model,
+                usage.input_tokens as i64,
+                usage.output_tokens as i64,
+                usage.cache_read_tokens as i64,
+                usage.cache_creation_tokens as i64,
+                cost,
+                now
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_task_usage(&self, task_id: &str) -> Result<UsageTotals, DbError> {
+        self.usage_totals("task_id = ?1", task_id)
+    }
+
+    pub fn get_conversation_usage(&self, conversation_id: &str) -> Result<UsageTotals, DbError> {
+        self.usage_totals("conversation_id = ?1", conversation_id)
+    }
+
+    fn usage_totals(&self, filter: &str, value: &str) -> Result<UsageTotals, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+        let totals = conn.query_row(
+            &format!("SELECT {} FROM usage_records WHERE {}", USAGE_SUMS, filter),
+            [value],
+            |row| usage_totals_from_row(row, 0),
+        )?;
+
+        Ok(totals)
+    }
+
+    /// Totals per local day for the last `days` days, most recent first
+    pub fn get_daily_usage(&self, days: u32) -> Result<Vec<DailyUsage>, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        let since = (chrono::Local::now() - chrono::Duration::days(days.saturating_sub(1) as i64))
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .and_then(|start| start.and_local_timezone(chrono::Local).earliest())
+            .map(|start| start.timestamp_millis())
+            .unwrap_or(0);
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT date(created_at / 1000, 'unixepoch', 'localtime') AS day, {}
+             FROM usage_records
+             WHERE created_at >= ?1
+             GROUP BY day
+             ORDER BY day DESC",
+            USAGE_SUMS
+        ))?;
+
+        let rows = stmt.query_map([since], |row| {
+            Ok(DailyUsage {
+                date: row.get(0)?,
+                totals: usage_totals_from_row(row, 1)?,
+            })
+        })?;
+
+        let mut daily = Vec::new();
+        for row in rows {
+            daily.push(row?);
+        }
+
+        Ok(daily)
+    }
+
+    // Search methods
+    /// Search conversation and task messages, best matches first
+    pub fn search_history(&self, text: &str, limit: u32) -> Result<Vec<SearchHit>, DbError> {
+        let Some(query) = fts_query(text) else {
+            return Ok(Vec::new());
+        };
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT 'conversation', m.conversation_id, c.title, m.id, m.role,
+                    snippet(messages_fts, 0, '**', '**', '…', 16), m.timestamp, bm25(messages_fts) AS rank
+             FROM messages_fts
+             JOIN messages m ON m.rowid = messages_fts.rowid
+             JOIN conversations c ON c.id = m.conversation_id
+             WHERE messages_fts MATCH ?1
+             UNION ALL
+             SELECT 'task', m.task_id, t.title, m.id, m.role,
+                    snippet(task_messages_fts, 0, '**', '**', '…', 16), m.timestamp, bm25(task_messages_fts) AS rank
+             FROM task_messages_fts
+             JOIN task_messages m ON m.rowid = task_messages_fts.rowid
+             JOIN tasks t ON t.id = m.task_id
+             WHERE task_messages_fts MATCH ?1
+             ORDER BY rank
+             LIMIT ?2",
+        )?;
+
+        let rows = stmt.query_map(rusqlite::params![query, limit], |row| {
+            Ok(SearchHit {
+                source: row.get(0)?,
+                parent_id: row.get(1)?,
+                title: row.get(2)?,
+                message_id: row.get(3)?,
+                role: row.get(4)?,
+                snippet: row.get(5)?,
+                timestamp: row.get(6)?,
+            })
+        })?;
+
+        let mut hits = Vec::new();
+        for row in rows {
+            hits.push(row?);
+        }
+
+        Ok(hits)
+    }
+}
+
+const MESSAGE_COLUMNS: &str = "messages.id, conversation_id, role, content, timestamp, parent_id";
+
+fn message_from_row(row: &rusqlite::Row) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: row.get(0)?,
+        conversation_id: row.get(1)?,
+        role: row.get(2)?,
+        content: row.get(3)?,
+        timestamp: row.get(4)?,
+        parent_id: row.get(5)?,
+    })
+}
+
+fn conversation_from_row(row: &rusqlite::Row) -> rusqlite::Result<Conversation> {
+    Ok(Conversation {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        created_at: row.get(2)?,
+        updated_at: row.get(3)?,
+        active_leaf_id: row.get(4)?,
+    })
+}
+
+/// Turn free text into an FTS5 query matching every word, so punctuation in
+/// the search box isn't read as query syntax. The last word matches as a prefix.
+fn fts_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return None;
+    }
+    Some(format!("{}*", terms.join(" ")))
+}
+
+const USAGE_SUMS: &str = "COUNT(*), COALESCE(SUM(input_tokens), 0), COALESCE(SUM(output_tokens), 0),
+    COALESCE(SUM(cache_read_tokens), 0), COALESCE(SUM(cache_creation_tokens), 0), COALESCE(SUM(cost), 0.0)";
+
+fn usage_totals_from_row(row: &rusqlite::Row, start: usize) -> rusqlite::Result<UsageTotals> {
+    Ok(UsageTotals {
+        requests: row.get::<_, i64>(start)? as u64,
+        usage: TokenUsage {
+            input_tokens: row.get::<_, i64>(start + 1)? as u64,
+            output_tokens: row.get::<_, i64>(start + 2)? as u64,
+            cache_read_tokens: row.get::<_, i64>(start + 3)? as u64,
+            cache_creation_tokens: row.get::<_, i64>(start + 4)? as u64,
+        },
+        cost: row.get(start + 5)?,
+    })
 }
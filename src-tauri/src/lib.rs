@@ -1,30 +1,37 @@
 mod agent;
-mod claude;
 mod commands;
 mod database;
-mod llm_client;
 mod mcp;
+mod migrations;
+mod providers;
+mod secrets;
 mod skills;
+#[cfg(test)]
+mod test_support;
 mod tools;
+mod transcript;
 
 use agent::backup::BackupManager;
+use agent::ApprovalManager;
 use commands::AppState;
 use mcp::MCPManager;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use tauri::Manager;
-use tokio::sync::Mutex;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // Initialize database
     let db = database::Database::new().expect("Failed to initialize database");
 
-    // Initialize MCP tables
-    db.create_mcp_tables().expect("Failed to create MCP tables");
+    // Tasks still marked running were cut off when the app last quit
+    if let Err(e) = db.mark_interrupted_tasks() {
+        eprintln!("Failed to mark interrupted tasks: {}", e);
+    }
 
     // Initialize MCP manager
-    let mcp_manager = Arc::new(MCPManager::new());
     let db_arc = Arc::new(db);
+    let mcp_manager = Arc::new(MCPManager::new().with_database(db_arc.clone()));
 
     // Initialize Backup Manager
     let backup_manager = Arc::new(BackupManager::new());
@@ -33,9 +40,10 @@ pub fn run() {
 
     let app_state = Arc::new(AppState {
         db: db_arc,
-        claude_client: Mutex::new(None),
         mcp_manager,
         backup_manager,
+        approval_manager: Arc::new(ApprovalManager::new()),
+        running_tasks: Mutex::new(HashMap::new()),
     });
 
     tauri::Builder::default()
@@ -46,6 +54,9 @@ pub fn run() {
             commands::get_platform,
             commands::get_settings,
             commands::save_settings,
+            commands::get_secrets_status,
+            commands::unlock_secrets,
+            commands::reset_secrets,
             commands::test_connection,
             commands::list_conversations,
             commands::create_conversation,
@@ -53,6 +64,8 @@ pub fn run() {
             commands::delete_conversation,
             commands::get_messages,
             commands::add_message,
+            commands::list_branches,
+            commands::switch_branch,
             commands::send_chat_message,
             commands::send_chat_with_tools,
             commands::run_agent,
@@ -61,7 +74,16 @@ pub fn run() {
             commands::create_task,
             commands::delete_task,
             commands::run_task_agent,
+            commands::resume_task_agent,
+            commands::cancel_task_agent,
             commands::get_task_messages,
+            commands::get_task_usage,
+            commands::get_conversation_usage,
+            commands::get_usage_by_day,
+            commands::search_history,
+            commands::export_conversation,
+            commands::export_task,
+            commands::import_transcript,
             commands::get_skills_list,
             commands::list_mcp_servers,
             commands::save_mcp_server,
@@ -70,8 +92,12 @@ pub fn run() {
             commands::disconnect_mcp_server,
             commands::get_mcp_server_statuses,
             commands::execute_mcp_tool,
+            commands::read_mcp_resource,
+            commands::get_mcp_prompt,
             commands::get_ollama_models,
             commands::undo_last_action,
+            commands::approve_tool_call,
+            commands::deny_tool_call,
         ])
         .setup(|app| {
             #[cfg(debug_assertions)]
@@ -103,21 +129,23 @@ pub fn run() {
             let mcp_manager = app_state.mcp_manager.clone();
             let _app_handle = app.handle().clone();
 
-            std::thread::spawn(move || {
-                let rt = tokio::runtime::Runtime::new().unwrap();
-                rt.block_on(async move {
-                    if let Ok(servers) = db.get_mcp_servers() {
-                        for server in servers {
-                            if server.enabled {
-                                if let Err(e) = mcp_manager.connect_server(&server).await {
-                                    eprintln!("Failed to auto-connect MCP server '{}': {}", server.name, e);
-                                } else {
-                                    println!("Auto-connected MCP server: {}", server.name);
-                                }
+            // Sandbox containers still around belong to runs cut off when the app last quit
+            tauri::async_runtime::spawn(tools::sandbox::remove_stale());
+
+            // Connect on the app runtime: connections keep background tasks
+            // (stdio readers and restarts) running afterwards
+            tauri::async_runtime::spawn(async move {
+                if let Ok(servers) = db.get_mcp_servers() {
+                    for server in servers {
+                        if server.enabled {
+                            if let Err(e) = mcp_manager.connect_server(&server).await {
+                                eprintln!("Failed to auto-connect MCP server '{}': {}", server.name, e);
+                            } else {
+                                println!("Auto-connected MCP server: {}", server.name);
                             }
                         }
                     }
-                });
+                }
             });
 
             Ok(())
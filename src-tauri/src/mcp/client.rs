@@ -1,18 +1,142 @@
-use super::http_client::HttpMcpClient;
+use super::discovery::{self, ServerCatalog};
+use super::http_client::{endpoint_url, HttpMcpClient};
+use super::oauth::{self, AuthRequired, OAuthSession};
+use super::stdio_client::StdioMcpClient;
 use super::types::*;
+use crate::database::Database;
+use serde_json::{json, Value};
 use std::collections::HashMap;
-use tokio::sync::RwLock;
-use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, RwLock};
+use std::sync::{Arc, Mutex as StdMutex};
 
+type McpResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Restarts attempted after a stdio server exits before giving up
+const MAX_RESTARTS: u32 = 3;
+
+/// A stdio server that ran this long is considered healthy again
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+/// A tool call fails after this long without a result or progress notification
+const TOOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Receives progress reported for a running tool call
+pub type ProgressCallback = Arc<dyn Fn(&MCPProgress) + Send + Sync>;
+
+type ProgressMap = Arc<StdMutex<HashMap<String, ProgressCallback>>>;
+
+/// A server connection. Clones share it, so callers can take one out of the
+/// client map and release the lock before awaiting the server.
+#[derive(Clone)]
+enum MCPConnection {
+    Http(Arc<HttpMcpClient>),
+    Stdio(Arc<StdioMcpClient>),
+}
+
+impl MCPConnection {
+    async fn request(&self, method: &str, params: Value) -> McpResult<Value> {
+        match self {
+            MCPConnection::Http(client) => client.request(method, params).await,
+            MCPConnection::Stdio(client) => client.request(method, params).await,
+        }
+    }
+
+    async fn call_tool(&self, tool_name: &str, arguments: Option<Value>, progress_token: Option<&str>) -> McpResult<Value> {
+        match self {
+            MCPConnection::Http(client) => client.call_tool(tool_name, arguments, progress_token).await,
+            MCPConnection::Stdio(client) => client.call_tool(tool_name, arguments, progress_token).await,
+        }
+    }
+
+    async fn shutdown(&self) {
+        if let MCPConnection::Stdio(client) = self {
+            client.shutdown().await;
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct MCPClient {
-    http_client: HttpMcpClient,
-    #[allow(dead_code)]
-    url: String,
+    connection: MCPConnection,
+    /// Capabilities the server declared when initializing
+    capabilities: Value,
 }
 
+impl MCPClient {
+    fn offers(&self, capability: &str) -> bool {
+        self.capabilities.get(capability).is_some_and(|c| !c.is_null())
+    }
+
+    /// List everything the server offers. Tools are required; resources and
+    /// prompts are optional, so failing to list them just leaves them empty.
+    async fn discover(&self, server_id: &str) -> McpResult<ServerCatalog> {
+        let tools = self
+            .connection
+            .request("tools/list", json!({}))
+            .await
+            .map_err(|e| format!("Tool discovery failed: {}", e))?;
+        let mut catalog = ServerCatalog {
+            tools: discovery::parse_tools(&tools, server_id),
+            ..Default::default()
+        };
+
+        if self.offers("resources") {
+            if let Ok(response) = self.connection.request("resources/list", json!({})).await {
+                catalog.resources = discovery::parse_resources(&response, server_id);
+            }
+            if let Ok(response) = self.connection.request("resources/templates/list", json!({})).await {
+                catalog.resource_templates = discovery::parse_resource_templates(&response, server_id);
+            }
+        }
+        if self.offers("prompts") {
+            if let Ok(response) = self.connection.request("prompts/list", json!({})).await {
+                catalog.prompts = discovery::parse_prompts(&response, server_id);
+            }
+        }
+
+        Ok(catalog)
+    }
+}
+
+/// Status of a server that just connected with the given catalog
+fn connected_status(config: &MCPServerConfig, catalog: ServerCatalog) -> MCPServerStatus {
+    MCPServerStatus {
+        id: config.id.clone(),
+        name: config.name.clone(),
+        status: ConnectionStatus::Connected,
+        tools: catalog.tools,
+        resources: catalog.resources,
+        resource_templates: catalog.resource_templates,
+        prompts: catalog.prompts,
+        last_error: None,
+    }
+}
+
+type ClientMap = Arc<RwLock<HashMap<String, MCPClient>>>;
+type StatusMap = Arc<RwLock<HashMap<String, MCPServerStatus>>>;
+
 pub struct MCPManager {
-    clients: Arc<RwLock<HashMap<String, MCPClient>>>,
-    server_status: Arc<RwLock<HashMap<String, MCPServerStatus>>>,
+    clients: ClientMap,
+    server_status: StatusMap,
+    /// Progress callbacks of running tool calls, keyed by progress token
+    progress: ProgressMap,
+    /// Where OAuth tokens are persisted
+    db: Option<Arc<Database>>,
+}
+
+/// Removes a progress callback once its tool call ends
+struct ProgressRegistration {
+    progress: ProgressMap,
+    token: String,
+}
+
+impl Drop for ProgressRegistration {
+    fn drop(&mut self) {
+        if let Ok(mut progress) = self.progress.lock() {
+            progress.remove(&self.token);
+        }
+    }
 }
 
 impl MCPManager {
@@ -20,9 +144,17 @@ impl MCPManager {
         Self {
             clients: Arc::new(RwLock::new(HashMap::new())),
             server_status: Arc::new(RwLock::new(HashMap::new())),
+            progress: Arc::new(StdMutex::new(HashMap::new())),
+            db: None,
         }
     }
 
+    /// Persist OAuth tokens in the app database
+    pub fn with_database(mut self, db: Arc<Database>) -> Self {
+        self.db = Some(db);
+        self
+    }
+
     pub async fn connect_server(&self, config: &MCPServerConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         if !config.enabled {
             return Err("Server is not enabled".into());
@@ -36,79 +168,79 @@ impl MCPManager {
                 name: config.name.clone(),
                 status: ConnectionStatus::Connecting,
                 tools: vec![],
+                resources: vec![],
+                resource_templates: vec![],
+                prompts: vec![],
                 last_error: None,
             });
         }
 
-        // Create OAuth token if needed
-        let oauth_token = if let (Some(client_id), Some(client_secret)) =
-            (&config.oauth_client_id, &config.oauth_client_secret) {
-            match self.perform_oauth_flow(client_id, client_secret, &config.server_url).await {
-                Ok(token) => Some(token),
+        // Messages the server sends on its own, handled for as long as the connection lives
+        let (notifications, notifications_rx) = mpsc::unbounded_channel();
+        tokio::spawn(handle_notifications(
+            self.clients.clone(),
+            self.server_status.clone(),
+            self.progress.clone(),
+            config.id.clone(),
+            notifications_rx,
+        ));
+
+        let client = match config.transport {
+            MCPTransport::Http => self.connect_http(config, notifications.clone()).await?,
+            MCPTransport::Stdio => match start_stdio(config, notifications.clone()).await {
+                Ok(client) => client,
                 Err(e) => {
-                    self.update_status_error(&config.id, format!("OAuth failed: {}", e)).await;
+                    self.update_status_error(&config.id, e.to_string()).await;
                     return Err(e);
                 }
-            }
-        } else {
-            None
+            },
         };
 
-        // Create HTTP MCP client
-        let mut http_client = HttpMcpClient::new(config.server_url.clone(), oauth_token);
-
-        // Initialize the connection
-        match http_client.initialize().await {
-            Ok(_) => {
-                // Initialization successful
-            }
-            Err(e) => {
-                let error_msg = format!("Connection failed: {}", e);
-                self.update_status_error(&config.id, error_msg.clone()).await;
-                return Err(error_msg.into());
-            }
-        }
-
-        // Discover tools
-        let tools = match self.discover_tools_http(&http_client, &config.id).await {
-            Ok(tools) => tools,
+        let catalog = match client.discover(&config.id).await {
+            Ok(catalog) => catalog,
             Err(e) => {
-                let error_msg = format!("Tool discovery failed: {}", e);
-                self.update_status_error(&config.id, error_msg.clone()).await;
-                return Err(error_msg.into());
+                client.connection.shutdown().await;
+                self.update_status_error(&config.id, e.to_string()).await;
+                return Err(e);
             }
         };
 
-        // Store client and update status to connected
-        let mcp_client = MCPClient {
-            http_client,
-            url: config.server_url.clone(),
-        };
+        // Restart the process if it exits while connected
+        if let MCPConnection::Stdio(stdio) = &client.connection {
+            tokio::spawn(supervise_stdio(
+                self.clients.clone(),
+                self.server_status.clone(),
+                config.clone(),
+                stdio.clone(),
+                notifications,
+            ));
+        }
 
-        {
+        // Store client, replacing any previous connection
+        let previous = {
             let mut clients = self.clients.write().await;
-            clients.insert(config.id.clone(), mcp_client);
+            clients.insert(config.id.clone(), client)
+        };
+        if let Some(previous) = previous {
+            previous.connection.shutdown().await;
         }
 
         {
             let mut status_map = self.server_status.write().await;
-            status_map.insert(config.id.clone(), MCPServerStatus {
-                id: config.id.clone(),
-                name: config.name.clone(),
-                status: ConnectionStatus::Connected,
-                tools,
-                last_error: None,
-            });
+            status_map.insert(config.id.clone(), connected_status(config, catalog));
         }
 
         Ok(())
     }
 
     pub async fn disconnect_server(&self, server_id: &str) {
-        // Remove client
-        {
+        // Remove client, stopping its process if it has one
+        let removed = {
             let mut clients = self.clients.write().await;
-            clients.remove(server_id);
+            clients.remove(server_id)
+        };
+        if let Some(client) = removed {
+            client.connection.shutdown().await;
         }
 
         // Update status to disconnected
@@ -117,64 +249,98 @@ impl MCPManager {
             if let Some(status) = status_map.get_mut(server_id) {
                 status.status = ConnectionStatus::Disconnected;
                 status.tools.clear();
+                status.resources.clear();
+                status.resource_templates.clear();
+                status.prompts.clear();
                 status.last_error = None;
             }
         }
     }
 
     pub async fn execute_tool(&self, call: &MCPToolCall) -> MCPToolResult {
-        let clients = self.clients.read().await;
-
-        match clients.get(&call.server_id) {
-            Some(client) => {
-                // Execute tool with timeout
-                match tokio::time::timeout(
-                    std::time::Duration::from_secs(60),
-                    client.http_client.call_tool(&call.tool_name, Some(call.parameters.clone()))
-                ).await {
-                    Ok(Ok(response)) => {
-                        // Parse the JSON-RPC response
-                        if let Some(error) = response.get("error") {
-                            MCPToolResult {
-                                success: false,
-                                result: serde_json::Value::Null,
-                                error: Some(format!("Tool execution error: {}", error)),
-                            }
-                        } else if let Some(result) = response.get("result") {
-                            MCPToolResult {
-                                success: true,
-                                result: result.clone(),
-                                error: None,
-                            }
-                        } else {
-                            MCPToolResult {
-                                success: false,
-                                result: serde_json::Value::Null,
-                                error: Some("Invalid response format".to_string()),
-                            }
-                        }
-                    },
-                    Ok(Err(e)) => {
-                        MCPToolResult {
-                            success: false,
-                            result: serde_json::Value::Null,
-                            error: Some(format!("Tool execution failed: {}", e)),
-                        }
-                    },
-                    Err(_) => {
-                        MCPToolResult {
-                            success: false,
-                            result: serde_json::Value::Null,
-                            error: Some("Tool execution timed out after 60 seconds".to_string()),
-                        }
+        self.execute_tool_with_progress(call, None).await
+    }
+
+    /// Execute a tool, passing progress notifications from the server to
+    /// `on_progress`. The call times out only when the server goes quiet.
+    pub async fn execute_tool_with_progress(&self, call: &MCPToolCall, on_progress: Option<ProgressCallback>) -> MCPToolResult {
+        let Some(client) = self.client(&call.server_id).await else {
+            return MCPToolResult {
+                success: false,
+                result: Value::Null,
+                error: Some(format!("Server {} not connected", call.server_id)),
+            };
+        };
+
+        // Progress notifications count as activity for the idle timeout
+        let last_activity = Arc::new(StdMutex::new(Instant::now()));
+        let registration = ProgressRegistration {
+            progress: self.progress.clone(),
+            token: uuid::Uuid::new_v4().to_string(),
+        };
+        if let Ok(mut progress) = self.progress.lock() {
+            let last_activity = last_activity.clone();
+            let callback: ProgressCallback = Arc::new(move |update: &MCPProgress| {
+                if let Ok(mut last_activity) = last_activity.lock() {
+                    *last_activity = Instant::now();
+                }
+                if let Some(on_progress) = &on_progress {
+                    on_progress(update);
+                }
+            });
+            progress.insert(registration.token.clone(), callback);
+        }
+
+        let tool_call = client.connection.call_tool(&call.tool_name, Some(call.parameters.clone()), Some(&registration.token));
+        tokio::pin!(tool_call);
+
+        let outcome = loop {
+            let idle = last_activity.lock().map(|last| last.elapsed()).unwrap_or_default();
+            if idle >= TOOL_IDLE_TIMEOUT {
+                break None;
+            }
+            tokio::select! {
+                result = &mut tool_call => break Some(result),
+                _ = tokio::time::sleep(TOOL_IDLE_TIMEOUT - idle) => {}
+            }
+        };
+
+        match outcome {
+            Some(Ok(response)) => {
+                // Parse the JSON-RPC response
+                if let Some(error) = response.get("error") {
+                    MCPToolResult {
+                        success: false,
+                        result: Value::Null,
+                        error: Some(format!("Tool execution error: {}", error)),
+                    }
+                } else if let Some(result) = response.get("result") {
+                    MCPToolResult {
+                        success: true,
+                        result: result.clone(),
+                        error: None,
+                    }
+                } else {
+                    MCPToolResult {
+                        success: false,
+                        result: Value::Null,
+                        error: Some("Invalid response format".to_string()),
                     }
                 }
             }
+            Some(Err(e)) => MCPToolResult {
+                success: false,
+                result: Value::Null,
+                error: Some(format!("Tool execution failed: {}", e)),
+            },
             None => MCPToolResult {
                 success: false,
-                result: serde_json::Value::Null,
-                error: Some(format!("Server {} not connected", call.server_id)),
-            }
+                result: Value::Null,
+                error: Some(format!(
+                    "Tool execution timed out after {} seconds without progress",
+                    TOOL_IDLE_TIMEOUT.as_secs()
+                )),
+            },
         }
     }
 
@@ -191,117 +357,366 @@ impl MCPManager {
         tools
     }
 
-    pub async fn get_server_statuses(&self) -> Vec<MCPServerStatus> {
+    /// Resources and resource templates of all connected servers
+    pub async fn get_all_resources(&self) -> (Vec<MCPResource>, Vec<MCPResourceTemplate>) {
         let status_map = self.server_status.read().await;
-        status_map.values().cloned().collect()
-    }
+        let mut resources = Vec::new();
+        let mut templates = Vec::new();
 
-    async fn update_status_error(&self, server_id: &str, error: String) {
-        let mut status_map = self.server_status.write().await;
-        if let Some(status) = status_map.get_mut(server_id) {
-            status.status = ConnectionStatus::Error;
-            status.last_error = Some(error);
+        for status in status_map.values() {
+            if matches!(status.status, ConnectionStatus::Connected) {
+                resources.extend(status.resources.clone());
+                templates.extend(status.resource_templates.clone());
+            }
         }
+
+        (resources, templates)
+    }
+
+    /// Read a resource, returning its contents as text
+    pub async fn read_resource(&self, server_id: &str, uri: &str) -> Result<String, String> {
+        let result = self.server_request(server_id, "resources/read", json!({ "uri": uri })).await?;
+        Ok(discovery::render_resource(&result))
+    }
+
+    /// Fill in a prompt template, returning the prompt text
+    pub async fn get_prompt(&self, server_id: &str, name: &str, arguments: HashMap<String, String>) -> Result<String, String> {
+        let result = self
+            .server_request(server_id, "prompts/get", json!({ "name": name, "arguments": arguments }))
+            .await?;
+        Ok(discovery::render_prompt(&result))
     }
 
-    async fn discover_tools_http(&self, client: &HttpMcpClient, server_id: &str) -> Result<Vec<MCPTool>, Box<dyn std::error::Error + Send + Sync>> {
-        let tools_response = client.list_tools().await?;
-
-        let mut mcp_tools = Vec::new();
-
-        if let Some(result) = tools_response.get("result") {
-            if let Some(tools_array) = result.get("tools") {
-                if let Some(tools) = tools_array.as_array() {
-                    for tool in tools {
-                        let name = tool.get("name")
-                            .and_then(|n| n.as_str())
-                            .unwrap_or("")
-                            .to_string();
-
-                        let description = tool.get("description")
-                            .and_then(|d| d.as_str())
-                            .unwrap_or("")
-                            .to_string();
-
-                        let input_schema = tool.get("inputSchema")
-                            .cloned()
-                            .unwrap_or(serde_json::Value::Object(serde_json::Map::new()));
-
-                        mcp_tools.push(MCPTool {
-                            server_id: server_id.to_string(),
-                            name,
-                            description,
-                            input_schema,
-                        });
+    /// Expand a `/prompt-name arg=value ...` message using the matching server
+    /// prompt. Returns `None` when the message doesn't name a known prompt.
+    /// A prompt with a single argument also takes the rest of the message as
+    /// its value, e.g. `/summarize https://example.com`.
+    pub async fn expand_prompt_command(&self, message: &str) -> Option<Result<String, String>> {
+        let command = message.trim().strip_prefix('/')?;
+        let (name, rest) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let rest = rest.trim();
+
+        let prompt = {
+            let status_map = self.server_status.read().await;
+            status_map
+                .values()
+                .filter(|status| matches!(status.status, ConnectionStatus::Connected))
+                .flat_map(|status| status.prompts.iter().map(move |prompt| (status, prompt)))
+                .find(|(status, prompt)| {
+                    prompt.name == name || format!("{}:{}", status.name, prompt.name) == name
+                })
+                .map(|(_, prompt)| prompt.clone())?
+        };
+
+        let mut arguments = HashMap::new();
+        match prompt.arguments.as_slice() {
+            [only] if !rest.is_empty() && !rest.contains('=') => {
+                arguments.insert(only.name.clone(), rest.to_string());
+            }
+            _ => {
+                for pair in rest.split_whitespace() {
+                    if let Some((key, value)) = pair.split_once('=') {
+                        arguments.insert(key.to_string(), value.to_string());
                     }
                 }
             }
         }
 
-        Ok(mcp_tools)
+        if let Some(missing) = prompt.arguments.iter().find(|a| a.required && !arguments.contains_key(&a.name)) {
+            return Some(Err(format!("Prompt '{}' requires the argument '{}'", prompt.name, missing.name)));
+        }
+
+        Some(self.get_prompt(&prompt.server_id, &prompt.name, arguments).await)
+    }
+
+    /// Send a request to a connected server, returning its result
+    async fn server_request(&self, server_id: &str, method: &str, params: Value) -> Result<Value, String> {
+        let client = self
+            .client(server_id)
+            .await
+            .ok_or_else(|| format!("Server {} not connected", server_id))?;
+
+        let response = client
+            .connection
+            .request(method, params)
+            .await
+            .map_err(|e| format!("{} failed: {}", method, e))?;
+
+        if let Some(error) = response.get("error") {
+            let message = error.get("message").and_then(|m| m.as_str()).unwrap_or("unknown error");
+            return Err(format!("{} failed: {}", method, message));
+        }
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    /// The connected client of a server, taken out of the map so the lock
+    /// isn't held while talking to the server
+    async fn client(&self, server_id: &str) -> Option<MCPClient> {
+        self.clients.read().await.get(server_id).cloned()
+    }
+
+    pub async fn get_server_statuses(&self) -> Vec<MCPServerStatus> {
+        let status_map = self.server_status.read().await;
+        status_map.values().cloned().collect()
     }
 
+    async fn update_status_error(&self, server_id: &str, error: String) {
+        set_error(&self.server_status, server_id, error).await;
+    }
 
+    async fn connect_http(
+        &self,
+        config: &MCPServerConfig,
+        notifications: mpsc::UnboundedSender<Value>,
+    ) -> McpResult<MCPClient> {
+        // Reuse tokens from an earlier authorization
+        let stored = self
+            .db
+            .as_ref()
+            .and_then(|db| db.get_mcp_authorization(&config.id).ok().flatten());
+        let auth = stored.map(|authorization| Arc::new(OAuthSession::new(authorization, self.db.clone())));
+
+        let mut http_client = HttpMcpClient::new(config.server_url.clone(), auth, notifications.clone());
+        let mut result = http_client.initialize().await;
+
+        // Sign in if the server asks for it, then try again
+        if let Some(required) = result.as_ref().err().and_then(|e| e.downcast_ref::<AuthRequired>()) {
+            let resource_metadata = required.resource_metadata.clone();
+            let auth = match self.authorize_server(config, resource_metadata.as_deref()).await {
+                Ok(auth) => auth,
+                Err(e) => {
+                    let error_msg = format!("OAuth failed: {}", e);
+                    self.update_status_error(&config.id, error_msg.clone()).await;
+                    return Err(error_msg.into());
+                }
+            };
+            http_client = HttpMcpClient::new(config.server_url.clone(), Some(auth), notifications);
+            result = http_client.initialize().await;
+        }
 
-    async fn perform_oauth_flow(&self, client_id: &str, client_secret: &str, server_url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        // Create OAuth endpoint URL
-        let mut oauth_url = if server_url.ends_with("/mcp") {
-            server_url.trim_end_matches("/mcp").to_string()
-        } else {
-            server_url.to_string()
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                let error_msg = format!("Connection failed: {}", e);
+                self.update_status_error(&config.id, error_msg.clone()).await;
+                return Err(error_msg.into());
+            }
         };
 
-        if !oauth_url.ends_with('/') {
-            oauth_url.push('/');
+        Ok(MCPClient {
+            connection: MCPConnection::Http(Arc::new(http_client)),
+            capabilities: response["result"]["capabilities"].clone(),
+        })
+    }
+
+    /// Run the OAuth flow for a server and store the resulting tokens
+    async fn authorize_server(
+        &self,
+        config: &MCPServerConfig,
+        resource_metadata: Option<&str>,
+    ) -> Result<Arc<OAuthSession>, String> {
+        let resource = endpoint_url(&config.server_url);
+        let metadata = oauth::discover(&reqwest::Client::new(), &resource, resource_metadata).await?;
+
+        let authorization = oauth::authorize(
+            &config.id,
+            &resource,
+            &metadata,
+            config.oauth_client_id.clone().filter(|id| !id.is_empty()),
+            config.oauth_client_secret.clone().filter(|secret| !secret.is_empty()),
+        )
+        .await?;
+
+        if let Some(db) = &self.db {
+            if let Err(e) = db.save_mcp_authorization(&authorization) {
+                eprintln!("Failed to save MCP authorization: {}", e);
+            }
+        }
+
+        Ok(Arc::new(OAuthSession::new(authorization, self.db.clone())))
+    }
+}
+
+/// Launch a stdio server and run the initialize handshake
+async fn start_stdio(
+    config: &MCPServerConfig,
+    notifications: mpsc::UnboundedSender<Value>,
+) -> McpResult<MCPClient> {
+    let client = Arc::new(StdioMcpClient::spawn(config, notifications)?);
+
+    match client.initialize().await {
+        Ok(response) => Ok(MCPClient {
+            capabilities: response["result"]["capabilities"].clone(),
+            connection: MCPConnection::Stdio(client),
+        }),
+        Err(e) => {
+            client.shutdown().await;
+            Err(format!("Connection failed: {}", e).into())
+        }
+    }
+}
+
+/// Watch a stdio server and restart it with backoff when it exits. Gives up
+/// after `MAX_RESTARTS` quick failures, leaving the server in the error state
+/// with its stderr as the last error. Stops once the server is disconnected
+/// or replaced.
+async fn supervise_stdio(
+    clients: ClientMap,
+    server_status: StatusMap,
+    config: MCPServerConfig,
+    mut client: Arc<StdioMcpClient>,
+    notifications: mpsc::UnboundedSender<Value>,
+) {
+    let mut restarts = 0;
+
+    loop {
+        client.closed().await;
+        let error = client.exit_error().await;
+
+        if client.uptime() >= STABLE_RUN {
+            restarts = 0;
         }
-        oauth_url.push_str("oauth/token");
-
-        // Create HTTP client for OAuth request
-        let client = reqwest::Client::new();
-
-        // Prepare OAuth request body (Client Credentials Grant)
-        let params = [
-            ("grant_type", "client_credentials"),
-            ("client_id", client_id),
-            ("client_secret", client_secret),
-        ];
-
-        // Send OAuth token request with timeout
-        let response = match tokio::time::timeout(
-            std::time::Duration::from_secs(15),
-            client
-                .post(&oauth_url)
-                .form(&params)
-                .header("Content-Type", "application/x-www-form-urlencoded")
-                .send()
-        ).await {
-            Ok(Ok(response)) => response,
-            Ok(Err(e)) => {
-                return Err(format!("OAuth request failed: {}", e).into());
+
+        // Only report on the connection that is still registered
+        if !is_current(&*clients.read().await, &config.id, &client) {
+            return;
+        }
+        set_error(&server_status, &config.id, error.clone()).await;
+
+        loop {
+            if restarts >= MAX_RESTARTS {
+                let message = format!("Gave up after {} restarts: {}", restarts, error);
+                set_error(&server_status, &config.id, message).await;
+                clients.write().await.remove(&config.id);
+                return;
             }
-            Err(_) => {
-                return Err("OAuth request timed out after 15 seconds".into());
+            restarts += 1;
+            tokio::time::sleep(Duration::from_secs(1 << restarts)).await;
+
+            // Disconnected or reconnected while waiting
+            if !is_current(&*clients.read().await, &config.id, &client) {
+                return;
+            }
+
+            let restarted = match start_stdio(&config, notifications.clone()).await {
+                Ok(restarted) => restarted,
+                Err(e) => {
+                    set_error(&server_status, &config.id, e.to_string()).await;
+                    continue;
+                }
+            };
+            let catalog = match restarted.discover(&config.id).await {
+                Ok(catalog) => catalog,
+                Err(e) => {
+                    restarted.connection.shutdown().await;
+                    set_error(&server_status, &config.id, e.to_string()).await;
+                    continue;
+                }
+            };
+            let MCPConnection::Stdio(stdio) = &restarted.connection else {
+                return;
+            };
+            let stdio = stdio.clone();
+
+            {
+                let mut clients = clients.write().await;
+                if !is_current(&clients, &config.id, &client) {
+                    drop(clients);
+                    stdio.shutdown().await;
+                    return;
+                }
+                clients.insert(config.id.clone(), restarted);
             }
-        };
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("OAuth request failed: {} - {}", status, error_text).into());
+            let mut status_map = server_status.write().await;
+            status_map.insert(config.id.clone(), connected_status(&config, catalog));
+            client = stdio;
+            break;
         }
+    }
+}
 
-        // Parse OAuth response
-        let oauth_response: serde_json::Value = response.json().await?;
+/// Route server notifications: progress goes to the running tool call that
+/// asked for it, and a changed tool, resource or prompt list triggers
+/// discovery again
+async fn handle_notifications(
+    clients: ClientMap,
+    server_status: StatusMap,
+    progress: ProgressMap,
+    server_id: String,
+    mut notifications: mpsc::UnboundedReceiver<Value>,
+) {
+    while let Some(message) = notifications.recv().await {
+        let params = &message["params"];
+        match message["method"].as_str() {
+            Some("notifications/progress") => {
+                let token = match &params["progressToken"] {
+                    Value::String(token) => token.clone(),
+                    token => token.to_string(),
+                };
+                let callback = progress.lock().ok().and_then(|progress| progress.get(&token).cloned());
+                if let Some(callback) = callback {
+                    callback(&MCPProgress {
+                        progress: params["progress"].as_f64().unwrap_or(0.0),
+                        total: params["total"].as_f64(),
+                        message: params["message"].as_str().map(String::from),
+                    });
+                }
+            }
+            Some(
+                "notifications/tools/list_changed"
+                | "notifications/resources/list_changed"
+                | "notifications/prompts/list_changed",
+            ) => {
+                // Discovery waits on the server, so don't hold up progress updates
+                tokio::spawn(refresh_catalog(clients.clone(), server_status.clone(), server_id.clone()));
+            }
+            _ => {}
+        }
+    }
+}
 
-        // Extract access token
-        if let Some(access_token) = oauth_response.get("access_token").and_then(|v| v.as_str()) {
-            Ok(format!("Bearer {}", access_token))
-        } else {
-            Err("No access_token in OAuth response".into())
+/// Discover a connected server's tools, resources and prompts again
+async fn refresh_catalog(clients: ClientMap, server_status: StatusMap, server_id: String) {
+    let Some(client) = clients.read().await.get(&server_id).cloned() else {
+        return;
+    };
+
+    let catalog = match client.discover(&server_id).await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            set_error(&server_status, &server_id, format!("Rediscovery failed: {}", e)).await;
+            return;
+        }
+    };
+
+    let mut status_map = server_status.write().await;
+    if let Some(status) = status_map.get_mut(&server_id) {
+        if matches!(status.status, ConnectionStatus::Connected) {
+            status.tools = catalog.tools;
+            status.resources = catalog.resources;
+            status.resource_templates = catalog.resource_templates;
+            status.prompts = catalog.prompts;
         }
     }
 }
 
+/// Whether `client` is still the registered connection for the server
+fn is_current(clients: &HashMap<String, MCPClient>, server_id: &str, client: &Arc<StdioMcpClient>) -> bool {
+    matches!(
+        clients.get(server_id).map(|c| &c.connection),
+        Some(MCPConnection::Stdio(current)) if Arc::ptr_eq(current, client)
+    )
+}
+
+async fn set_error(server_status: &StatusMap, server_id: &str, error: String) {
+    let mut status_map = server_status.write().await;
+    if let Some(status) = status_map.get_mut(server_id) {
+        status.status = ConnectionStatus::Error;
+        status.last_error = Some(error);
+    }
+}
+
 impl Default for MCPManager {
     fn default() -> Self {
         Self::new()
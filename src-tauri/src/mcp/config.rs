@@ -1,4 +1,5 @@
-use super::types::MCPServerConfig;
+use super::types::{MCPServerConfig, MCPTransport};
+use std::collections::HashMap;
 
 impl MCPServerConfig {
     #[allow(dead_code)]
@@ -11,7 +12,12 @@ impl MCPServerConfig {
         Self {
             id,
             name,
+            transport: MCPTransport::Http,
             server_url,
+            command: String::new(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            cwd: None,
             oauth_client_id: None,
             oauth_client_secret: None,
             enabled: false,
@@ -20,6 +26,16 @@ impl MCPServerConfig {
         }
     }
 
+    /// Configuration for a server launched as a local process
+    #[allow(dead_code)]
+    pub fn stdio(id: String, name: String, command: String, args: Vec<String>) -> Self {
+        let mut config = Self::new(id, name, String::new());
+        config.transport = MCPTransport::Stdio;
+        config.command = command;
+        config.args = args;
+        config
+    }
+
     #[allow(dead_code)]
     pub fn with_oauth(mut self, client_id: Option<String>, client_secret: Option<String>) -> Self {
         self.oauth_client_id = client_id;
@@ -0,0 +1,164 @@
+use super::types::*;
+use serde_json::Value;
+
+/// What a connected server offers
+#[derive(Debug, Default)]
+pub struct ServerCatalog {
+    pub tools: Vec<MCPTool>,
+    pub resources: Vec<MCPResource>,
+    pub resource_templates: Vec<MCPResourceTemplate>,
+    pub prompts: Vec<MCPPrompt>,
+}
+
+fn list<'a>(response: &'a Value, key: &str) -> impl Iterator<Item = &'a Value> {
+    response
+        .get("result")
+        .and_then(|r| r.get(key))
+        .and_then(|items| items.as_array())
+        .into_iter()
+        .flatten()
+}
+
+fn string(value: &Value, key: &str) -> String {
+    value.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string()
+}
+
+fn optional_string(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(|v| v.as_str()).map(String::from)
+}
+
+/// Tools listed in a `tools/list` response
+pub fn parse_tools(response: &Value, server_id: &str) -> Vec<MCPTool> {
+    list(response, "tools")
+        .map(|tool| MCPTool {
+            server_id: server_id.to_string(),
+            name: string(tool, "name"),
+            description: string(tool, "description"),
+            input_schema: tool
+                .get("inputSchema")
+                .cloned()
+                .unwrap_or(Value::Object(serde_json::Map::new())),
+            read_only: tool
+                .get("annotations")
+                .and_then(|a| a.get("readOnlyHint"))
+                .and_then(|h| h.as_bool())
+                .unwrap_or(false),
+        })
+        .collect()
+}
+
+/// Resources listed in a `resources/list` response
+pub fn parse_resources(response: &Value, server_id: &str) -> Vec<MCPResource> {
+    list(response, "resources")
+        .map(|resource| MCPResource {
+            server_id: server_id.to_string(),
+            uri: string(resource, "uri"),
+            name: string(resource, "name"),
+            description: optional_string(resource, "description"),
+            mime_type: optional_string(resource, "mimeType"),
+        })
+        .collect()
+}
+
+/// Templates listed in a `resources/templates/list` response
+pub fn parse_resource_templates(response: &Value, server_id: &str) -> Vec<MCPResourceTemplate> {
+    list(response, "resourceTemplates")
+        .map(|template| MCPResourceTemplate {
+            server_id: server_id.to_string(),
+            uri_template: string(template, "uriTemplate"),
+            name: string(template, "name"),
+            description: optional_string(template, "description"),
+            mime_type: optional_string(template, "mimeType"),
+        })
+        .collect()
+}
+
+/// Prompts listed in a `prompts/list` response
+pub fn parse_prompts(response: &Value, server_id: &str) -> Vec<MCPPrompt> {
+    list(response, "prompts")
+        .map(|prompt| MCPPrompt {
+            server_id: server_id.to_string(),
+            name: string(prompt, "name"),
+            description: optional_string(prompt, "description"),
+            arguments: prompt
+                .get("arguments")
+                .and_then(|a| a.as_array())
+                .into_iter()
+                .flatten()
+                .map(|argument| MCPPromptArgument {
+                    name: string(argument, "name"),
+                    description: optional_string(argument, "description"),
+                    required: argument.get("required").and_then(|r| r.as_bool()).unwrap_or(false),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Text of a content item (text, or an embedded text resource). Binary
+/// content is described rather than included.
+fn content_text(content: &Value) -> String {
+    if let Some(text) = content.get("text").and_then(|t| t.as_str()) {
+        return text.to_string();
+    }
+    if let Some(resource) = content.get("resource") {
+        return content_text(resource);
+    }
+
+    let mime_type = content.get("mimeType").and_then(|m| m.as_str()).unwrap_or("binary");
+    match content.get("uri").and_then(|u| u.as_str()) {
+        Some(uri) => format!("[{} content at {}]", mime_type, uri),
+        None => format!("[{} content]", mime_type),
+    }
+}
+
+/// The contents of a `resources/read` result as text
+pub fn render_resource(result: &Value) -> String {
+    result
+        .get("contents")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .map(content_text)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// The messages of a `prompts/get` result as a single prompt
+pub fn render_prompt(result: &Value) -> String {
+    result
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message.get("content"))
+        .map(|content| match content.as_array() {
+            Some(items) => items.iter().map(content_text).collect::<Vec<_>>().join("\n"),
+            None => content_text(content),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_prompts_and_render() {
+        let response = json!({ "result": { "prompts": [{
+            "name": "review",
+            "arguments": [{ "name": "file", "required": true }]
+        }] } });
+        let prompts = parse_prompts(&response, "docs");
+        assert_eq!(prompts[0].name, "review");
+        assert!(prompts[0].arguments[0].required);
+
+        let result = json!({ "messages": [
+            { "role": "user", "content": { "type": "text", "text": "Review this file" } },
+            { "role": "user", "content": { "type": "resource", "resource": { "uri": "file:///a.rs", "text": "fn main() {}" } } }
+        ] });
+        assert_eq!(render_prompt(&result), "Review this file\n\nfn main() {}");
+    }
+}
@@ -1,38 +1,140 @@
+use super::oauth::{resource_metadata_from, AuthRequired, OAuthSession};
 use serde_json::{json, Value};
+use std::sync::Arc;
 use std::time::Duration;
 use reqwest;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
-#[derive(Debug)]
-pub struct HttpMcpClient {
+type McpResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Timeout for requests that are answered immediately (everything but tool calls)
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay before reopening the server-to-client event stream after it drops
+const STREAM_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Connection details shared by requests and the server-to-client stream
+#[derive(Clone)]
+struct Session {
     client: reqwest::Client,
     base_url: String,
     session_id: Option<String>,
-    oauth_token: Option<String>,
+    auth: Option<Arc<OAuthSession>>,
+}
+
+impl Session {
+    /// Add the session ID and access token. Returns the token used, so a
+    /// rejection can be matched to it.
+    async fn authorize(&self, mut request: reqwest::RequestBuilder) -> (reqwest::RequestBuilder, Option<String>) {
+        // Add session ID if we have one
+        if let Some(ref session_id) = self.session_id {
+            request = request.header("Mcp-Session-Id", session_id);
+        }
+
+        let token = match &self.auth {
+            Some(auth) => Some(auth.access_token().await),
+            None => None,
+        };
+        if let Some(ref token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+
+        (request, token)
+    }
+
+    /// POST a message. A rejected access token is refreshed and the message
+    /// sent once more; if that fails too, authorization is required.
+    async fn post(&self, body: &Value, timeout: Option<Duration>) -> McpResult<reqwest::Response> {
+        let mut refreshed = false;
+        loop {
+            let (mut request, token) = self.authorize(self.client.post(&self.base_url)).await;
+            request = request
+                .header("Content-Type", "application/json")
+                .header("Accept", "application/json, text/event-stream")
+                .json(body);
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+
+            let response = request.send().await?;
+            if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+                return Ok(response);
+            }
+
+            if !refreshed {
+                if let (Some(auth), Some(token)) = (&self.auth, &token) {
+                    if auth.refresh(token).await.is_ok() {
+                        refreshed = true;
+                        continue;
+                    }
+                }
+            }
+            return Err(Box::new(AuthRequired {
+                resource_metadata: resource_metadata_from(&response),
+            }));
+        }
+    }
+
+    /// Handle a message the server sent on its own: notifications are
+    /// forwarded, requests are answered. Returns responses to our requests.
+    async fn dispatch(&self, message: Value, notifications: &mpsc::UnboundedSender<Value>) -> Option<Value> {
+        let method = message.get("method").and_then(|m| m.as_str());
+        let id = message.get("id").filter(|id| !id.is_null());
+
+        match (method, id) {
+            (None, Some(_)) => Some(message),
+            (Some(method), Some(id)) => {
+                let reply = if method == "ping" {
+                    json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+                } else {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": format!("Method not found: {}", method) }
+                    })
+                };
+                let _ = self.post(&reply, Some(REQUEST_TIMEOUT)).await;
+                None
+            }
+            (Some(_), None) => {
+                let _ = notifications.send(message);
+                None
+            }
+            (None, None) => None,
+        }
+    }
+}
+
+pub struct HttpMcpClient {
+    session: Session,
     message_id: std::sync::atomic::AtomicU64,
+    /// Receives notifications the server sends, on any stream
+    notifications: mpsc::UnboundedSender<Value>,
+    /// Stops the server-to-client event stream
+    stream_cancel: CancellationToken,
 }
 
 impl HttpMcpClient {
-    pub fn new(server_url: String, oauth_token: Option<String>) -> Self {
+    pub fn new(server_url: String, auth: Option<Arc<OAuthSession>>, notifications: mpsc::UnboundedSender<Value>) -> Self {
+        // No overall timeout: tool calls may stream progress for a long time
         let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
+            .connect_timeout(Duration::from_secs(30))
             .build()
             .expect("Failed to create HTTP client");
 
-        // Ensure URL ends with /mcp
-        let base_url = if server_url.ends_with("/mcp") {
-            server_url
-        } else if server_url.ends_with("/") {
-            format!("{}mcp", server_url)
-        } else {
-            format!("{}/mcp", server_url)
-        };
+        let base_url = endpoint_url(&server_url);
 
         Self {
-            client,
-            base_url,
-            session_id: None,
-            oauth_token,
+            session: Session {
+                client,
+                base_url,
+                session_id: None,
+                auth,
+            },
             message_id: std::sync::atomic::AtomicU64::new(1),
+            notifications,
+            stream_cancel: CancellationToken::new(),
         }
     }
 
@@ -40,7 +142,7 @@ impl HttpMcpClient {
         self.message_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
     }
 
-    pub async fn initialize(&mut self) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn initialize(&mut self) -> McpResult<Value> {
         let id = self.next_message_id();
 
         let request_body = json!({
@@ -58,52 +160,36 @@ impl HttpMcpClient {
             }
         });
 
-        let mut request = self.client.post(&self.base_url)
-            .header("Content-Type", "application/json")
-            .header("Accept", "application/json")
-            .json(&request_body);
-
-        // Add OAuth token if configured
-        if let Some(ref token) = self.oauth_token {
-            request = request.header("Authorization", format!("Bearer {}", token));
-        }
-
-        let response = request.send().await?;
+        let response = self.session.post(&request_body, Some(REQUEST_TIMEOUT)).await?;
 
         // Extract session ID if present
         if let Some(session_id) = response.headers().get("Mcp-Session-Id") {
-            self.session_id = Some(session_id.to_str()?.to_string());
+            self.session.session_id = Some(session_id.to_str()?.to_string());
         }
 
-        let response_body: Value = response.json().await?;
+        let response_body = self.read_response(response).await?;
 
         // Send initialized notification
         self.send_initialized().await?;
 
+        // Listen for messages the server sends outside of a request
+        tokio::spawn(listen(
+            self.session.clone(),
+            self.notifications.clone(),
+            self.stream_cancel.clone(),
+        ));
+
         Ok(response_body)
     }
 
-    async fn send_initialized(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    async fn send_initialized(&self) -> McpResult<()> {
         let request_body = json!({
             "jsonrpc": "2.0",
             "method": "notifications/initialized",
             "params": {}
         });
 
-        let mut request = self.client.post(&self.base_url)
-            .header("Content-Type", "application/json");
-
-        // Add session ID if we have one
-        if let Some(ref session_id) = self.session_id {
-            request = request.header("Mcp-Session-Id", session_id);
-        }
-
-        // Add OAuth token if configured
-        if let Some(ref token) = self.oauth_token {
-            request = request.header("Authorization", format!("Bearer {}", token));
-        }
-
-        let response = request.json(&request_body).send().await?;
+        let response = self.session.post(&request_body, Some(REQUEST_TIMEOUT)).await?;
 
         if !response.status().is_success() {
             return Err(format!("Failed to send initialized notification: {}", response.status()).into());
@@ -112,66 +198,193 @@ impl HttpMcpClient {
         Ok(())
     }
 
-    pub async fn list_tools(&self) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    /// Send a request that the server answers right away
+    pub async fn request(&self, method: &str, params: Value) -> McpResult<Value> {
         let id = self.next_message_id();
 
         let request_body = json!({
             "jsonrpc": "2.0",
             "id": id,
-            "method": "tools/list",
-            "params": {}
+            "method": method,
+            "params": params
         });
 
-        let mut request = self.client.post(&self.base_url)
-            .header("Content-Type", "application/json")
-            .header("Accept", "application/json");
+        let response = self.session.post(&request_body, Some(REQUEST_TIMEOUT)).await?;
 
-        // Add session ID if we have one
-        if let Some(ref session_id) = self.session_id {
-            request = request.header("Mcp-Session-Id", session_id);
-        }
-
-        // Add OAuth token if configured
-        if let Some(ref token) = self.oauth_token {
-            request = request.header("Authorization", format!("Bearer {}", token));
-        }
-
-        let response = request.json(&request_body).send().await?;
-        let response_body: Value = response.json().await?;
-
-        Ok(response_body)
+        self.read_response(response).await
     }
 
-    pub async fn call_tool(&self, tool_name: &str, arguments: Option<Value>) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    /// Call a tool. With a progress token, the server may send
+    /// `notifications/progress` while the tool runs.
+    pub async fn call_tool(&self, tool_name: &str, arguments: Option<Value>, progress_token: Option<&str>) -> McpResult<Value> {
         let id = self.next_message_id();
 
+        let mut params = json!({
+            "name": tool_name,
+            "arguments": arguments.unwrap_or(json!({}))
+        });
+        if let Some(token) = progress_token {
+            params["_meta"] = json!({ "progressToken": token });
+        }
+
         let request_body = json!({
             "jsonrpc": "2.0",
             "id": id,
             "method": "tools/call",
-            "params": {
-                "name": tool_name,
-                "arguments": arguments.unwrap_or(json!({}))
-            }
+            "params": params
         });
 
-        let mut request = self.client.post(&self.base_url)
-            .header("Content-Type", "application/json")
-            .header("Accept", "application/json");
+        let response = self.session.post(&request_body, None).await?;
+        self.read_response(response).await
+    }
 
-        // Add session ID if we have one
-        if let Some(ref session_id) = self.session_id {
-            request = request.header("Mcp-Session-Id", session_id);
+    /// Read the JSON-RPC response from either a JSON body or an SSE stream.
+    /// Other messages on the stream are dispatched as they arrive.
+    async fn read_response(&self, mut response: reqwest::Response) -> McpResult<Value> {
+        if !is_event_stream(&response) {
+            return Ok(response.json().await?);
         }
 
-        // Add OAuth token if configured
-        if let Some(ref token) = self.oauth_token {
-            request = request.header("Authorization", format!("Bearer {}", token));
+        let mut parser = SseParser::default();
+        while let Some(chunk) = response.chunk().await? {
+            for data in parser.feed(&chunk) {
+                let Ok(message) = serde_json::from_str::<Value>(&data) else {
+                    continue;
+                };
+                if let Some(response) = self.session.dispatch(message, &self.notifications).await {
+                    return Ok(response);
+                }
+            }
         }
 
-        let response = request.json(&request_body).send().await?;
-        let response_body: Value = response.json().await?;
+        Err("Event stream ended without a response".into())
+    }
+}
 
-        Ok(response_body)
+impl Drop for HttpMcpClient {
+    fn drop(&mut self) {
+        self.stream_cancel.cancel();
+    }
+}
+
+/// The MCP endpoint for a configured server URL, which is assumed to end in /mcp
+pub fn endpoint_url(server_url: &str) -> String {
+    if server_url.ends_with("/mcp") {
+        server_url.to_string()
+    } else if server_url.ends_with('/') {
+        format!("{}mcp", server_url)
+    } else {
+        format!("{}/mcp", server_url)
+    }
+}
+
+fn is_event_stream(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("text/event-stream"))
+        .unwrap_or(false)
+}
+
+/// Keep the GET event stream open for server-initiated messages, reconnecting
+/// when it drops. Stops if the server doesn't offer one.
+async fn listen(session: Session, notifications: mpsc::UnboundedSender<Value>, cancel: CancellationToken) {
+    while !cancel.is_cancelled() {
+        let (request, token) = session.authorize(session.client.get(&session.base_url)).await;
+        let request = request.header("Accept", "text/event-stream");
+
+        let mut response = tokio::select! {
+            response = request.send() => match response {
+                Ok(response) => response,
+                Err(_) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(STREAM_RECONNECT_DELAY) => continue,
+                        _ = cancel.cancelled() => return,
+                    }
+                }
+            },
+            _ = cancel.cancelled() => return,
+        };
+
+        // Reopen with a fresh token; give up if it can't be refreshed
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            match (&session.auth, &token) {
+                (Some(auth), Some(token)) if auth.refresh(token).await.is_ok() => continue,
+                _ => return,
+            }
+        }
+
+        // 405 means the server has no stream to offer
+        if !response.status().is_success() || !is_event_stream(&response) {
+            return;
+        }
+
+        let mut parser = SseParser::default();
+        loop {
+            let chunk = tokio::select! {
+                chunk = response.chunk() => chunk,
+                _ = cancel.cancelled() => return,
+            };
+            let Ok(Some(chunk)) = chunk else {
+                break;
+            };
+            for data in parser.feed(&chunk) {
+                if let Ok(message) = serde_json::from_str::<Value>(&data) {
+                    session.dispatch(message, &notifications).await;
+                }
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(STREAM_RECONNECT_DELAY) => {}
+            _ = cancel.cancelled() => return,
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Incremental Server-Sent Events parser yielding the data of each event
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                // A blank line ends the event
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            }
+            // event:, id:, retry: and comments carry nothing we need
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser_splits_events_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b"event: message\r\ndata: {\"a\":").is_empty());
+        let events = parser.feed(b"1}\r\n\r\n: keep-alive\n\ndata: x\ndata: y\n\n");
+        assert_eq!(events, vec!["{\"a\":1}".to_string(), "x\ny".to_string()]);
+    }
+}
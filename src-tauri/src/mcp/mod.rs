@@ -1,9 +1,12 @@
 pub mod client;
 pub mod config;
+pub mod discovery;
 pub mod http_client;
+pub mod oauth;
+pub mod stdio_client;
 pub mod storage;
 pub mod types;
 
-pub use client::MCPManager;
+pub use client::{MCPManager, ProgressCallback};
 pub use types::MCPServerConfig;
 pub use types::*;
\ No newline at end of file
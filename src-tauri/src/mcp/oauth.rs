@@ -0,0 +1,527 @@
+//! OAuth 2.1 authorization for HTTP MCP servers: metadata discovery, dynamic
+//! client registration, authorization code with PKCE through a loopback
+//! redirect, and refresh of persisted tokens.
+
+use crate::database::Database;
+use base64::Engine;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use url::Url;
+
+/// How long to wait for the user to finish signing in
+const AUTHORIZATION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Refresh tokens this long before they expire
+const EXPIRY_MARGIN_SECS: i64 = 30;
+
+/// The server rejected the request and a new authorization is needed
+#[derive(Debug)]
+pub struct AuthRequired {
+    /// Protected resource metadata URL from the `WWW-Authenticate` challenge
+    pub resource_metadata: Option<String>,
+}
+
+impl fmt::Display for AuthRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Server requires authorization")
+    }
+}
+
+impl std::error::Error for AuthRequired {}
+
+/// Read the `resource_metadata` parameter from a 401 response's challenge
+pub fn resource_metadata_from(response: &reqwest::Response) -> Option<String> {
+    let challenge = response
+        .headers()
+        .get(reqwest::header::WWW_AUTHENTICATE)?
+        .to_str()
+        .ok()?;
+    let start = challenge.find("resource_metadata=\"")? + "resource_metadata=\"".len();
+    let end = challenge[start..].find('"')? + start;
+    Some(challenge[start..end].to_string())
+}
+
+/// Tokens and the client they were issued to, persisted per server
+#[derive(Debug, Clone)]
+pub struct MCPAuthorization {
+    pub server_id: String,
+    pub token_endpoint: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Unix timestamp when the access token expires
+    pub expires_at: Option<i64>,
+}
+
+impl MCPAuthorization {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| chrono::Utc::now().timestamp() + EXPIRY_MARGIN_SECS >= expires_at)
+    }
+
+    /// Apply a token endpoint response
+    fn update(&mut self, response: &Value) -> Result<(), String> {
+        self.access_token = response["access_token"]
+            .as_str()
+            .ok_or("No access_token in token response")?
+            .to_string();
+        // Servers may rotate the refresh token or keep the old one
+        if let Some(refresh_token) = response["refresh_token"].as_str() {
+            self.refresh_token = Some(refresh_token.to_string());
+        }
+        self.expires_at = response["expires_in"]
+            .as_i64()
+            .map(|expires_in| chrono::Utc::now().timestamp() + expires_in);
+        Ok(())
+    }
+}
+
+/// Authorization in use by a connection. Refreshes the access token when it
+/// expires or is rejected, and persists the new tokens.
+pub struct OAuthSession {
+    authorization: StdMutex<MCPAuthorization>,
+    refresh_lock: tokio::sync::Mutex<()>,
+    db: Option<Arc<Database>>,
+    http: reqwest::Client,
+}
+
+impl OAuthSession {
+    pub fn new(authorization: MCPAuthorization, db: Option<Arc<Database>>) -> Self {
+        Self {
+            authorization: StdMutex::new(authorization),
+            refresh_lock: tokio::sync::Mutex::new(()),
+            db,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    fn snapshot(&self) -> Option<MCPAuthorization> {
+        self.authorization.lock().ok().map(|a| a.clone())
+    }
+
+    /// The access token to send, refreshed first if it has expired
+    pub async fn access_token(&self) -> String {
+        let Some(authorization) = self.snapshot() else {
+            return String::new();
+        };
+        if authorization.is_expired() && authorization.refresh_token.is_some() {
+            let _ = self.refresh(&authorization.access_token).await;
+            return self.snapshot().map(|a| a.access_token).unwrap_or_default();
+        }
+        authorization.access_token
+    }
+
+    /// Get a new access token after `rejected` stopped working. Does nothing
+    /// if another request already replaced it.
+    pub async fn refresh(&self, rejected: &str) -> Result<(), String> {
+        let _guard = self.refresh_lock.lock().await;
+
+        let mut authorization = self.snapshot().ok_or("Authorization state unavailable")?;
+        if authorization.access_token != rejected {
+            return Ok(());
+        }
+        let refresh_token = authorization
+            .refresh_token
+            .clone()
+            .ok_or("No refresh token available")?;
+
+        let mut form = vec![
+            ("grant_type", "refresh_token".to_string()),
+            ("refresh_token", refresh_token),
+            ("client_id", authorization.client_id.clone()),
+        ];
+        if let Some(secret) = &authorization.client_secret {
+            form.push(("client_secret", secret.clone()));
+        }
+
+        let response = token_request(&self.http, &authorization.token_endpoint, &form).await?;
+        authorization.update(&response)?;
+
+        if let Some(db) = &self.db {
+            if let Err(e) = db.save_mcp_authorization(&authorization) {
+                eprintln!("Failed to save refreshed MCP token: {}", e);
+            }
+        }
+        if let Ok(mut current) = self.authorization.lock() {
+            *current = authorization;
+        }
+        Ok(())
+    }
+}
+
+/// Endpoints of an authorization server (RFC 8414)
+#[derive(Debug, Clone)]
+pub struct AuthServerMetadata {
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub registration_endpoint: Option<String>,
+    pub grant_types_supported: Vec<String>,
+    /// Scopes the protected resource advertises
+    pub scopes: Vec<String>,
+}
+
+async fn get_json(http: &reqwest::Client, url: &str) -> Option<Value> {
+    let response = http
+        .get(url)
+        .header("Accept", "application/json")
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    response.json().await.ok()
+}
+
+/// Well-known URLs to try for `suffix`, with the path inserted after it first
+fn well_known_urls(base: &Url, suffix: &str) -> Vec<String> {
+    let origin = base.origin().ascii_serialization();
+    let path = base.path().trim_end_matches('/');
+    let mut urls = Vec::new();
+    if !path.is_empty() {
+        urls.push(format!("{}/.well-known/{}{}", origin, suffix, path));
+    }
+    urls.push(format!("{}/.well-known/{}", origin, suffix));
+    urls
+}
+
+/// Find the authorization server for an MCP server, via its protected
+/// resource metadata (RFC 9728) and the authorization server's metadata.
+/// Falls back to the default endpoints at the server's origin.
+pub async fn discover(
+    http: &reqwest::Client,
+    server_url: &str,
+    resource_metadata_url: Option<&str>,
+) -> Result<AuthServerMetadata, String> {
+    let server = Url::parse(server_url).map_err(|e| format!("Invalid server URL: {}", e))?;
+
+    let mut candidates: Vec<String> = resource_metadata_url.map(String::from).into_iter().collect();
+    candidates.extend(well_known_urls(&server, "oauth-protected-resource"));
+
+    let mut resource_metadata = None;
+    for url in &candidates {
+        if let Some(metadata) = get_json(http, url).await {
+            resource_metadata = Some(metadata);
+            break;
+        }
+    }
+
+    let issuer = resource_metadata
+        .as_ref()
+        .and_then(|m| m["authorization_servers"][0].as_str())
+        .and_then(|issuer| Url::parse(issuer).ok())
+        .unwrap_or_else(|| {
+            let mut origin = server.clone();
+            origin.set_path("");
+            origin
+        });
+    let scopes = resource_metadata
+        .as_ref()
+        .and_then(|m| m["scopes_supported"].as_array())
+        .map(|scopes| scopes.iter().filter_map(|s| s.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+
+    let mut candidates = well_known_urls(&issuer, "oauth-authorization-server");
+    candidates.extend(well_known_urls(&issuer, "openid-configuration"));
+
+    for url in &candidates {
+        let Some(metadata) = get_json(http, url).await else {
+            continue;
+        };
+        let (Some(authorization_endpoint), Some(token_endpoint)) = (
+            metadata["authorization_endpoint"].as_str(),
+            metadata["token_endpoint"].as_str(),
+        ) else {
+            continue;
+        };
+        return Ok(AuthServerMetadata {
+            authorization_endpoint: authorization_endpoint.to_string(),
+            token_endpoint: token_endpoint.to_string(),
+            registration_endpoint: metadata["registration_endpoint"].as_str().map(String::from),
+            grant_types_supported: metadata["grant_types_supported"]
+                .as_array()
+                .map(|grants| grants.iter().filter_map(|g| g.as_str().map(String::from)).collect())
+                .unwrap_or_default(),
+            scopes,
+        });
+    }
+
+    // Servers without metadata use the default paths at their origin
+    let origin = issuer.origin().ascii_serialization();
+    Ok(AuthServerMetadata {
+        authorization_endpoint: format!("{}/authorize", origin),
+        token_endpoint: format!("{}/token", origin),
+        registration_endpoint: Some(format!("{}/register", origin)),
+        grant_types_supported: Vec::new(),
+        scopes,
+    })
+}
+
+async fn token_request(
+    http: &reqwest::Client,
+    token_endpoint: &str,
+    form: &[(&str, String)],
+) -> Result<Value, String> {
+    let response = http
+        .post(token_endpoint)
+        .header("Accept", "application/json")
+        .form(form)
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .map_err(|e| format!("Token request failed: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Token request failed: {} - {}", status, body));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid token response: {}", e))
+}
+
+/// Register this app as a public client (RFC 7591)
+async fn register_client(
+    http: &reqwest::Client,
+    registration_endpoint: &str,
+    redirect_uri: &str,
+) -> Result<(String, Option<String>), String> {
+    let response = http
+        .post(registration_endpoint)
+        .json(&serde_json::json!({
+            "client_name": "Kuse Cowork",
+            "redirect_uris": [redirect_uri],
+            "grant_types": ["authorization_code", "refresh_token"],
+            "response_types": ["code"],
+            "token_endpoint_auth_method": "none"
+        }))
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .map_err(|e| format!("Client registration failed: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Client registration failed: {} - {}", status, body));
+    }
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid registration response: {}", e))?;
+
+    let client_id = body["client_id"]
+        .as_str()
+        .ok_or("No client_id in registration response")?
+        .to_string();
+    Ok((client_id, body["client_secret"].as_str().map(String::from)))
+}
+
+/// A random string of unreserved characters, suitable as a PKCE verifier
+fn random_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Obtain tokens for a server. Uses the client credentials grant when a
+/// secret is configured and the server supports it; otherwise opens the
+/// browser for the authorization code flow with PKCE.
+pub async fn authorize(
+    server_id: &str,
+    server_url: &str,
+    metadata: &AuthServerMetadata,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> Result<MCPAuthorization, String> {
+    let http = reqwest::Client::new();
+
+    if let (Some(client_id), Some(client_secret)) = (&client_id, &client_secret) {
+        if metadata.grant_types_supported.iter().any(|g| g == "client_credentials") {
+            let mut form = vec![
+                ("grant_type", "client_credentials".to_string()),
+                ("client_id", client_id.clone()),
+                ("client_secret", client_secret.clone()),
+                ("resource", server_url.to_string()),
+            ];
+            if !metadata.scopes.is_empty() {
+                form.push(("scope", metadata.scopes.join(" ")));
+            }
+            let response = token_request(&http, &metadata.token_endpoint, &form).await?;
+            let mut authorization = MCPAuthorization {
+                server_id: server_id.to_string(),
+                token_endpoint: metadata.token_endpoint.clone(),
+                client_id: client_id.clone(),
+                client_secret: Some(client_secret.clone()),
+                access_token: String::new(),
+                refresh_token: None,
+                expires_at: None,
+            };
+            authorization.update(&response)?;
+            return Ok(authorization);
+        }
+    }
+
+    // The redirect lands on a listener on a free loopback port
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("Failed to start redirect listener: {}", e))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to start redirect listener: {}", e))?
+        .port();
+    let redirect_uri = format!("http://127.0.0.1:{}/callback", port);
+
+    let (client_id, client_secret) = match client_id {
+        Some(client_id) => (client_id, client_secret),
+        None => {
+            let registration_endpoint = metadata.registration_endpoint.as_deref().ok_or(
+                "No OAuth client ID configured and the server doesn't support client registration",
+            )?;
+            register_client(&http, registration_endpoint, &redirect_uri).await?
+        }
+    };
+
+    let verifier = random_token();
+    let state = random_token();
+
+    let mut url = Url::parse(&metadata.authorization_endpoint)
+        .map_err(|e| format!("Invalid authorization endpoint: {}", e))?;
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &client_id)
+            .append_pair("redirect_uri", &redirect_uri)
+            .append_pair("code_challenge", &pkce_challenge(&verifier))
+            .append_pair("code_challenge_method", "S256")
+            .append_pair("state", &state)
+            .append_pair("resource", server_url);
+        if !metadata.scopes.is_empty() {
+            query.append_pair("scope", &metadata.scopes.join(" "));
+        }
+    }
+
+    open::that_detached(url.as_str()).map_err(|e| format!("Failed to open the browser: {}", e))?;
+
+    let code = tokio::time::timeout(AUTHORIZATION_TIMEOUT, wait_for_code(&listener, &state))
+        .await
+        .map_err(|_| "Timed out waiting for authorization in the browser".to_string())??;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code".to_string()),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", client_id.clone()),
+        ("code_verifier", verifier),
+        ("resource", server_url.to_string()),
+    ];
+    if let Some(secret) = &client_secret {
+        form.push(("client_secret", secret.clone()));
+    }
+    let response = token_request(&http, &metadata.token_endpoint, &form).await?;
+
+    let mut authorization = MCPAuthorization {
+        server_id: server_id.to_string(),
+        token_endpoint: metadata.token_endpoint.clone(),
+        client_id,
+        client_secret,
+        access_token: String::new(),
+        refresh_token: None,
+        expires_at: None,
+    };
+    authorization.update(&response)?;
+    Ok(authorization)
+}
+
+/// Accept connections on the redirect listener until the browser delivers
+/// the authorization code
+async fn wait_for_code(listener: &TcpListener, expected_state: &str) -> Result<String, String> {
+    loop {
+        let (mut stream, _) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Redirect listener failed: {}", e))?;
+
+        let mut buffer = vec![0u8; 8192];
+        let read = stream.read(&mut buffer).await.unwrap_or(0);
+        let request = String::from_utf8_lossy(&buffer[..read]);
+        let target = request
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap_or("");
+
+        // Browsers also ask for things like /favicon.ico
+        let Some(query) = target.strip_prefix("/callback?") else {
+            let _ = stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await;
+            continue;
+        };
+
+        let params: std::collections::HashMap<String, String> =
+            url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+
+        let result = if params.get("state").map(String::as_str) != Some(expected_state) {
+            Err("Authorization response had an unexpected state".to_string())
+        } else if let Some(code) = params.get("code") {
+            Ok(code.clone())
+        } else {
+            let error = params.get("error").map(String::as_str).unwrap_or("unknown_error");
+            let description = params.get("error_description").map(String::as_str).unwrap_or("");
+            Err(format!("Authorization denied: {} {}", error, description).trim().to_string())
+        };
+
+        let message = match &result {
+            Ok(_) => "Authorization complete. You can close this window and return to Kuse Cowork.",
+            Err(_) => "Authorization failed. You can close this window and return to Kuse Cowork.",
+        };
+        let body = format!("<html><body><p>{}</p></body></html>", message);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkce_challenge_matches_rfc_example() {
+        // RFC 7636, appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-1mB0mbZOBa3a5SDbiT8mwNTCiB1xkQ"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGWSjLoEqM"
+        );
+    }
+
+    #[test]
+    fn test_well_known_urls_insert_path() {
+        let url = Url::parse("https://example.com/tenant/mcp").unwrap();
+        assert_eq!(
+            well_known_urls(&url, "oauth-protected-resource"),
+            vec![
+                "https://example.com/.well-known/oauth-protected-resource/tenant/mcp".to_string(),
+                "https://example.com/.well-known/oauth-protected-resource".to_string(),
+            ]
+        );
+    }
+}
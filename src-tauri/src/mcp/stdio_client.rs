@@ -0,0 +1,316 @@
+use super::types::MCPServerConfig;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio_util::sync::CancellationToken;
+
+type McpResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Requests awaiting a response, keyed by JSON-RPC id
+type PendingRequests = Arc<StdMutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+/// Longest wait for the response to a request other than a tool call
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Stderr lines kept for error reporting
+const STDERR_LINES: usize = 20;
+
+/// MCP client for a server launched as a local process. Messages are
+/// newline-delimited JSON-RPC on the process's stdin and stdout; whatever the
+/// server writes to stderr is kept to explain failures.
+pub struct StdioMcpClient {
+    child: Mutex<Child>,
+    stdin: Arc<Mutex<ChildStdin>>,
+    pending: PendingRequests,
+    stderr: Arc<StdMutex<VecDeque<String>>>,
+    /// Cancelled once the server closes its stdout, normally because it exited
+    closed: CancellationToken,
+    started_at: Instant,
+    message_id: AtomicU64,
+}
+
+/// A request's entry in `PendingRequests`, removed when dropped
+struct PendingEntry<'a> {
+    pending: &'a PendingRequests,
+    id: u64,
+}
+
+impl Drop for PendingEntry<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+impl StdioMcpClient {
+    /// Launch the server process. Notifications it sends are forwarded to `notifications`.
+    pub fn spawn(config: &MCPServerConfig, notifications: mpsc::UnboundedSender<Value>) -> McpResult<Self> {
+        if config.command.trim().is_empty() {
+            return Err("No command configured for stdio server".into());
+        }
+
+        let mut cmd = Command::new(&config.command);
+        cmd.args(&config.args);
+        cmd.envs(&config.env);
+        if let Some(cwd) = config.cwd.as_deref().filter(|dir| !dir.is_empty()) {
+            cmd.current_dir(cwd);
+        }
+        cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+
+        // Don't open a console window for the server
+        #[cfg(windows)]
+        cmd.creation_flags(0x0800_0000);
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Failed to start '{}': {}", config.command, e))?;
+        let stdin = Arc::new(Mutex::new(child.stdin.take().ok_or("Server process has no stdin")?));
+        let stdout = child.stdout.take().ok_or("Server process has no stdout")?;
+        let stderr = child.stderr.take().ok_or("Server process has no stderr")?;
+
+        let pending = PendingRequests::default();
+        let stderr_lines = Arc::new(StdMutex::new(VecDeque::new()));
+        let closed = CancellationToken::new();
+
+        tokio::spawn(read_stdout(stdout, stdin.clone(), pending.clone(), notifications, closed.clone()));
+        tokio::spawn(read_stderr(stderr, stderr_lines.clone()));
+
+        Ok(Self {
+            child: Mutex::new(child),
+            stdin,
+            pending,
+            stderr: stderr_lines,
+            closed,
+            started_at: Instant::now(),
+            message_id: AtomicU64::new(1),
+        })
+    }
+
+    fn next_message_id(&self) -> u64 {
+        self.message_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub async fn initialize(&self) -> McpResult<Value> {
+        let response = self
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": "2024-11-05",
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": "kuse-cowork",
+                        "title": "Kuse Cowork Desktop",
+                        "version": "0.1.0"
+                    }
+                }),
+            )
+            .await?;
+
+        if let Some(error) = response.get("error") {
+            return Err(format!("Initialization rejected: {}", error).into());
+        }
+
+        self.notify("notifications/initialized", json!({})).await?;
+        Ok(response)
+    }
+
+    /// Call a tool. With a progress token, the server may send
+    /// `notifications/progress` while the tool runs.
+    pub async fn call_tool(&self, tool_name: &str, arguments: Option<Value>, progress_token: Option<&str>) -> McpResult<Value> {
+        let mut params = json!({
+            "name": tool_name,
+            "arguments": arguments.unwrap_or(json!({}))
+        });
+        if let Some(token) = progress_token {
+            params["_meta"] = json!({ "progressToken": token });
+        }
+        // Tools may run for a long time; the caller decides when to give up
+        self.send_request("tools/call", params, None).await
+    }
+
+    /// Send a request that the server answers right away
+    pub async fn request(&self, method: &str, params: Value) -> McpResult<Value> {
+        self.send_request(method, params, Some(REQUEST_TIMEOUT)).await
+    }
+
+    /// Send a request and wait for its response
+    async fn send_request(&self, method: &str, params: Value, timeout: Option<Duration>) -> McpResult<Value> {
+        if self.closed.is_cancelled() {
+            return Err(self.exit_error().await.into());
+        }
+
+        let id = self.next_message_id();
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(id, tx);
+        }
+        // Removes the entry however this ends, including when the caller
+        // drops the future while waiting
+        let _pending = PendingEntry { pending: &self.pending, id };
+
+        let message = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params
+        });
+        if let Err(e) = write_message(&self.stdin, &message).await {
+            return Err(format!("Failed to write to server: {}", e).into());
+        }
+
+        let response = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, rx).await {
+                Ok(response) => response,
+                Err(_) => {
+                    return Err(format!("Server did not answer '{}' within {} seconds", method, timeout.as_secs()).into());
+                }
+            },
+            None => rx.await,
+        };
+
+        // An error means the reader dropped the request because the server went away
+        match response {
+            Ok(response) => Ok(response),
+            Err(_) => Err(self.exit_error().await.into()),
+        }
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> McpResult<()> {
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params
+        });
+        write_message(&self.stdin, &message)
+            .await
+            .map_err(|e| format!("Failed to write to server: {}", e).into())
+    }
+
+    /// Resolves once the server process has gone away
+    pub async fn closed(&self) {
+        self.closed.cancelled().await
+    }
+
+    /// How long the process has been running
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Describe why the server stopped, including its last stderr output
+    pub async fn exit_error(&self) -> String {
+        let status = {
+            let mut child = self.child.lock().await;
+            match tokio::time::timeout(Duration::from_secs(1), child.wait()).await {
+                Ok(Ok(status)) => format!("Server process exited ({})", status),
+                _ => "Server process closed its output".to_string(),
+            }
+        };
+
+        let stderr = self.stderr_tail();
+        if stderr.is_empty() {
+            status
+        } else {
+            format!("{}: {}", status, stderr)
+        }
+    }
+
+    /// The last lines the server wrote to stderr
+    pub fn stderr_tail(&self) -> String {
+        self.stderr
+            .lock()
+            .map(|lines| lines.iter().cloned().collect::<Vec<_>>().join("\n"))
+            .unwrap_or_default()
+    }
+
+    /// Stop the server process
+    pub async fn shutdown(&self) {
+        let mut child = self.child.lock().await;
+        let _ = child.kill().await;
+    }
+}
+
+async fn write_message(stdin: &Mutex<ChildStdin>, message: &Value) -> std::io::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+
+    let mut stdin = stdin.lock().await;
+    stdin.write_all(line.as_bytes()).await?;
+    stdin.flush().await
+}
+
+/// Route responses to their pending requests and answer requests from the server
+async fn read_stdout(
+    stdout: ChildStdout,
+    stdin: Arc<Mutex<ChildStdin>>,
+    pending: PendingRequests,
+    notifications: mpsc::UnboundedSender<Value>,
+    closed: CancellationToken,
+) {
+    let mut lines = BufReader::new(stdout).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        // Anything that isn't JSON-RPC (e.g. stray log output) is ignored
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        let method = message.get("method").and_then(|m| m.as_str());
+        let id = message.get("id").filter(|id| !id.is_null());
+
+        match (method, id) {
+            (None, Some(id)) => {
+                let sender = id
+                    .as_u64()
+                    .and_then(|id| pending.lock().ok().and_then(|mut pending| pending.remove(&id)));
+                if let Some(sender) = sender {
+                    let _ = sender.send(message);
+                }
+            }
+            (Some(method), Some(id)) => {
+                let reply = if method == "ping" {
+                    json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+                } else {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": format!("Method not found: {}", method) }
+                    })
+                };
+                let _ = write_message(&stdin, &reply).await;
+            }
+            (Some(_), None) => {
+                let _ = notifications.send(message);
+            }
+            (None, None) => {}
+        }
+    }
+
+    // Dropping the senders fails every request still waiting
+    if let Ok(mut pending) = pending.lock() {
+        pending.clear();
+    }
+    closed.cancel();
+}
+
+/// Keep the last lines of stderr for error reports
+async fn read_stderr(stderr: ChildStderr, buffer: Arc<StdMutex<VecDeque<String>>>) {
+    let mut lines = BufReader::new(stderr).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if let Ok(mut buffer) = buffer.lock() {
+            if buffer.len() == STDERR_LINES {
+                buffer.pop_front();
+            }
+            buffer.push_back(line);
+        }
+    }
+}
@@ -1,44 +1,72 @@
-use super::types::MCPServerConfig;
+use super::oauth::MCPAuthorization;
+use super::types::{MCPServerConfig, MCPTransport};
 use crate::database::{Database, DbError};
-use rusqlite::params;
+use rusqlite::{params, Row};
+
+const SERVER_COLUMNS: &str = "id, name, server_url, oauth_client_id, oauth_client_secret, enabled, created_at, updated_at,
+     transport, command, args, env, cwd";
+
+fn server_from_row(row: &Row) -> rusqlite::Result<MCPServerConfig> {
+    let transport: String = row.get(8)?;
+    let args: String = row.get(10)?;
+    let env: String = row.get(11)?;
+
+    Ok(MCPServerConfig {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        server_url: row.get(2)?,
+        oauth_client_id: row.get(3)?,
+        oauth_client_secret: row.get(4)?,
+        enabled: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+        transport: MCPTransport::parse(&transport),
+        command: row.get(9)?,
+        args: serde_json::from_str(&args).unwrap_or_default(),
+        env: serde_json::from_str(&env).unwrap_or_default(),
+        cwd: row.get(12)?,
+    })
+}
 
 impl Database {
-    pub fn create_mcp_tables(&self) -> Result<(), DbError> {
-        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+    /// Encrypt an optional secret for storage
+    fn encrypt_optional(&self, value: &Option<String>) -> Result<Option<String>, DbError> {
+        value.as_deref().map(|v| self.encrypt_secret(v)).transpose()
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS mcp_servers (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                server_url TEXT NOT NULL,
-                oauth_client_id TEXT,
-                oauth_client_secret TEXT,
-                enabled BOOLEAN NOT NULL DEFAULT 0,
-                created_at TIMESTAMP NOT NULL,
-                updated_at TIMESTAMP NOT NULL
-            )",
-            [],
-        )?;
+    fn decrypt_optional(&self, stored: Option<String>) -> Result<Option<String>, DbError> {
+        stored.as_deref().map(|v| self.decrypt_secret(v)).transpose()
+    }
 
-        Ok(())
+    fn decrypt_server(&self, mut config: MCPServerConfig) -> Result<MCPServerConfig, DbError> {
+        config.oauth_client_secret = self.decrypt_optional(config.oauth_client_secret)?;
+        Ok(config)
     }
 
     pub fn save_mcp_server(&self, config: &MCPServerConfig) -> Result<(), DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        let client_secret = self.encrypt_optional(&config.oauth_client_secret)?;
 
         conn.execute(
-            "INSERT OR REPLACE INTO mcp_servers
-             (id, name, server_url, oauth_client_id, oauth_client_secret, enabled, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            &format!(
+                "INSERT OR REPLACE INTO mcp_servers ({})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                SERVER_COLUMNS
+            ),
             params![
                 config.id,
                 config.name,
                 config.server_url,
                 config.oauth_client_id,
-                config.oauth_client_secret,
+                client_secret,
                 config.enabled,
                 config.created_at,
                 config.updated_at,
+                config.transport.as_str(),
+                config.command,
+                serde_json::to_string(&config.args).unwrap_or_else(|_| "[]".to_string()),
+                serde_json::to_string(&config.env).unwrap_or_else(|_| "{}".to_string()),
+                config.cwd,
             ],
         )?;
         Ok(())
@@ -47,27 +75,16 @@ impl Database {
     pub fn get_mcp_servers(&self) -> Result<Vec<MCPServerConfig>, DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
 
-        let mut stmt = conn.prepare(
-            "SELECT id, name, server_url, oauth_client_id, oauth_client_secret, enabled, created_at, updated_at
-             FROM mcp_servers ORDER BY name"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM mcp_servers ORDER BY name",
+            SERVER_COLUMNS
+        ))?;
 
-        let server_iter = stmt.query_map([], |row| {
-            Ok(MCPServerConfig {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                server_url: row.get(2)?,
-                oauth_client_id: row.get(3)?,
-                oauth_client_secret: row.get(4)?,
-                enabled: row.get(5)?,
-                created_at: row.get(6)?,
-                updated_at: row.get(7)?,
-            })
-        })?;
+        let server_iter = stmt.query_map([], server_from_row)?;
 
         let mut servers = Vec::new();
         for server in server_iter {
-            servers.push(server?);
+            servers.push(self.decrypt_server(server?)?);
         }
         Ok(servers)
     }
@@ -75,26 +92,15 @@ impl Database {
     pub fn get_mcp_server(&self, id: &str) -> Result<Option<MCPServerConfig>, DbError> {
         let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
 
-        let mut stmt = conn.prepare(
-            "SELECT id, name, server_url, oauth_client_id, oauth_client_secret, enabled, created_at, updated_at
-             FROM mcp_servers WHERE id = ?1"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM mcp_servers WHERE id = ?1",
+            SERVER_COLUMNS
+        ))?;
 
-        let mut server_iter = stmt.query_map([id], |row| {
-            Ok(MCPServerConfig {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                server_url: row.get(2)?,
-                oauth_client_id: row.get(3)?,
-                oauth_client_secret: row.get(4)?,
-                enabled: row.get(5)?,
-                created_at: row.get(6)?,
-                updated_at: row.get(7)?,
-            })
-        })?;
+        let mut server_iter = stmt.query_map([id], server_from_row)?;
 
         match server_iter.next() {
-            Some(server) => Ok(Some(server?)),
+            Some(server) => Ok(Some(self.decrypt_server(server?)?)),
             None => Ok(None),
         }
     }
@@ -106,6 +112,10 @@ impl Database {
             "DELETE FROM mcp_servers WHERE id = ?1",
             params![id],
         )?;
+        conn.execute(
+            "DELETE FROM mcp_oauth_tokens WHERE server_id = ?1",
+            params![id],
+        )?;
         Ok(())
     }
 
@@ -118,4 +128,60 @@ impl Database {
         )?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn save_mcp_authorization(&self, authorization: &MCPAuthorization) -> Result<(), DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+        let client_secret = self.encrypt_optional(&authorization.client_secret)?;
+        let access_token = self.encrypt_secret(&authorization.access_token)?;
+        let refresh_token = self.encrypt_optional(&authorization.refresh_token)?;
+
+        conn.execute(
+            "INSERT OR REPLACE INTO mcp_oauth_tokens
+             (server_id, token_endpoint, client_id, client_secret, access_token, refresh_token, expires_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                authorization.server_id,
+                authorization.token_endpoint,
+                authorization.client_id,
+                client_secret,
+                access_token,
+                refresh_token,
+                authorization.expires_at,
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_mcp_authorization(&self, server_id: &str) -> Result<Option<MCPAuthorization>, DbError> {
+        let conn = self.conn.lock().map_err(|_| DbError::Lock)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT server_id, token_endpoint, client_id, client_secret, access_token, refresh_token, expires_at
+             FROM mcp_oauth_tokens WHERE server_id = ?1",
+        )?;
+
+        let mut rows = stmt.query_map([server_id], |row| {
+            Ok(MCPAuthorization {
+                server_id: row.get(0)?,
+                token_endpoint: row.get(1)?,
+                client_id: row.get(2)?,
+                client_secret: row.get(3)?,
+                access_token: row.get(4)?,
+                refresh_token: row.get(5)?,
+                expires_at: row.get(6)?,
+            })
+        })?;
+
+        match rows.next() {
+            Some(authorization) => {
+                let mut authorization = authorization?;
+                authorization.client_secret = self.decrypt_optional(authorization.client_secret)?;
+                authorization.access_token = self.decrypt_secret(&authorization.access_token)?;
+                authorization.refresh_token = self.decrypt_optional(authorization.refresh_token)?;
+                Ok(Some(authorization))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -1,10 +1,53 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How the client talks to an MCP server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MCPTransport {
+    /// JSON-RPC over HTTP POST to `server_url`
+    #[default]
+    Http,
+    /// JSON-RPC over the stdin/stdout of a locally launched process
+    Stdio,
+}
+
+impl MCPTransport {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MCPTransport::Http => "http",
+            MCPTransport::Stdio => "stdio",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "stdio" => MCPTransport::Stdio,
+            _ => MCPTransport::Http,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MCPServerConfig {
     pub id: String,
     pub name: String,
+    #[serde(default)]
+    pub transport: MCPTransport,
+    /// Server URL (HTTP transport)
+    #[serde(default)]
     pub server_url: String,
+    /// Program to launch (stdio transport)
+    #[serde(default)]
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Extra environment variables for the launched process
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Working directory of the launched process
+    #[serde(default)]
+    pub cwd: Option<String>,
     pub oauth_client_id: Option<String>,
     pub oauth_client_secret: Option<String>,
     pub enabled: bool,
@@ -18,6 +61,45 @@ pub struct MCPTool {
     pub name: String,
     pub description: String,
     pub input_schema: serde_json::Value,
+    /// Server hint that the tool does not modify its environment (`annotations.readOnlyHint`)
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Data a server exposes for reading (`resources/list`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MCPResource {
+    pub server_id: String,
+    pub uri: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+/// Parameterized resource URIs (`resources/templates/list`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MCPResourceTemplate {
+    pub server_id: String,
+    pub uri_template: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+/// A prompt template offered by a server (`prompts/list`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MCPPrompt {
+    pub server_id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub arguments: Vec<MCPPromptArgument>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MCPPromptArgument {
+    pub name: String,
+    pub description: Option<String>,
+    pub required: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,6 +108,12 @@ pub struct MCPServerStatus {
     pub name: String,
     pub status: ConnectionStatus,
     pub tools: Vec<MCPTool>,
+    #[serde(default)]
+    pub resources: Vec<MCPResource>,
+    #[serde(default)]
+    pub resource_templates: Vec<MCPResourceTemplate>,
+    #[serde(default)]
+    pub prompts: Vec<MCPPrompt>,
     pub last_error: Option<String>,
 }
 
@@ -49,4 +137,11 @@ pub struct MCPToolResult {
     pub success: bool,
     pub result: serde_json::Value,
     pub error: Option<String>,
-}
\ No newline at end of file
+}
+/// Progress reported by a server for a running tool call (`notifications/progress`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MCPProgress {
+    pub progress: f64,
+    pub total: Option<f64>,
+    pub message: Option<String>,
+}
@@ -0,0 +1,351 @@
+//! Versioned schema migrations, tracked in `PRAGMA user_version`.
+//!
+//! Append new migrations to `MIGRATIONS`; never change one that has shipped.
+
+use crate::database::DbError;
+use rusqlite::{Connection, Transaction};
+use std::path::Path;
+
+struct Migration {
+    description: &'static str,
+    apply: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+/// Migration `i` upgrades the schema to version `i + 1`
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "baseline schema",
+        apply: baseline,
+    },
+    Migration {
+        description: "full-text search over messages",
+        apply: message_search,
+    },
+    Migration {
+        description: "conversation branches",
+        apply: conversation_branches,
+    },
+    Migration {
+        description: "task checkpoints",
+        apply: task_checkpoints,
+    },
+];
+
+/// Schema version this build of the app writes
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Bring the database up to date, backing up the file first when an existing
+/// database is upgraded. Refuses databases written by a newer version.
+pub fn migrate(conn: &mut Connection, db_path: &Path) -> Result<(), DbError> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    if version > SCHEMA_VERSION {
+        return Err(DbError::NewerSchema {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    // Databases from before versioning have tables but version 0
+    let has_tables: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_tables {
+        let backup = db_path.with_extension(format!("db.v{}.bak", version));
+        if backup.exists() {
+            std::fs::remove_file(&backup)?;
+        }
+        conn.execute("VACUUM INTO ?1", [backup.to_string_lossy()])?;
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        (migration.apply)(&tx).map_err(|e| DbError::Migration {
+            version: index as i64 + 1,
+            description: migration.description,
+            source: e,
+        })?;
+        tx.pragma_update(None, "user_version", index as i64 + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Add a column unless the table already has it
+fn add_column_if_missing(tx: &Transaction, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let exists: bool = tx.query_row(
+        &format!("SELECT EXISTS (SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1)", table),
+        [column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        tx.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+/// The schema as it stood when versioning was introduced. Idempotent, so
+/// unversioned databases in any earlier state are brought up to it.
+fn baseline(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS conversations (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS messages (
+            id TEXT PRIMARY KEY,
+            conversation_id TEXT NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_messages_conversation
+            ON messages(conversation_id);
+
+        CREATE TABLE IF NOT EXISTS tasks (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            description TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'planning',
+            plan TEXT,
+            current_step INTEGER NOT NULL DEFAULT 0,
+            project_path TEXT,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS task_messages (
+            id TEXT PRIMARY KEY,
+            task_id TEXT NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_task_messages_task
+            ON task_messages(task_id);
+
+        CREATE TABLE IF NOT EXISTS usage_records (
+            id TEXT PRIMARY KEY,
+            task_id TEXT,
+            conversation_id TEXT,
+            model TEXT NOT NULL,
+            input_tokens INTEGER NOT NULL,
+            output_tokens INTEGER NOT NULL,
+            cache_read_tokens INTEGER NOT NULL,
+            cache_creation_tokens INTEGER NOT NULL,
+            cost REAL,
+            created_at INTEGER NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_usage_records_task
+            ON usage_records(task_id);
+
+        CREATE INDEX IF NOT EXISTS idx_usage_records_conversation
+            ON usage_records(conversation_id);
+
+        CREATE INDEX IF NOT EXISTS idx_usage_records_created
+            ON usage_records(created_at);
+
+        CREATE TABLE IF NOT EXISTS mcp_servers (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            server_url TEXT NOT NULL,
+            oauth_client_id TEXT,
+            oauth_client_secret TEXT,
+            enabled BOOLEAN NOT NULL DEFAULT 0,
+            created_at TIMESTAMP NOT NULL,
+            updated_at TIMESTAMP NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS mcp_oauth_tokens (
+            server_id TEXT PRIMARY KEY,
+            token_endpoint TEXT NOT NULL,
+            client_id TEXT NOT NULL,
+            client_secret TEXT,
+            access_token TEXT NOT NULL,
+            refresh_token TEXT,
+            expires_at INTEGER,
+            updated_at TIMESTAMP NOT NULL
+        );",
+    )?;
+
+    // Stdio transport columns, which some unversioned databases already have
+    add_column_if_missing(tx, "mcp_servers", "transport", "TEXT NOT NULL DEFAULT 'http'")?;
+    add_column_if_missing(tx, "mcp_servers", "command", "TEXT NOT NULL DEFAULT ''")?;
+    add_column_if_missing(tx, "mcp_servers", "args", "TEXT NOT NULL DEFAULT '[]'")?;
+    add_column_if_missing(tx, "mcp_servers", "env", "TEXT NOT NULL DEFAULT '{}'")?;
+    add_column_if_missing(tx, "mcp_servers", "cwd", "TEXT")?;
+
+    Ok(())
+}
+
+/// Searchable text of a stored message. Content is either plain text or
+/// JSON-encoded `AgentContent`, whose text, tool inputs and tool results are
+/// indexed; ids, signatures and type tags are not.
+const SEARCHABLE_TEXT: &str = "CASE WHEN json_valid(new.content) AND json_type(new.content) IN ('array', 'object') THEN coalesce((
+        SELECT group_concat(atom, ' ') FROM json_tree(new.content)
+        WHERE type = 'text'
+          AND coalesce(key, '') NOT IN ('type', 'id', 'tool_use_id', 'thought_signature')
+    ), '') ELSE new.content END";
+
+/// FTS5 indexes over `messages` and `task_messages`, keyed by the message
+/// rowid and kept in sync by triggers
+fn message_search(tx: &Transaction) -> rusqlite::Result<()> {
+    for table in ["messages", "task_messages"] {
+        let extract = SEARCHABLE_TEXT.replace("new.content", "m.content");
+        tx.execute_batch(&format!(
+            "CREATE VIRTUAL TABLE {table}_fts USING fts5(body, tokenize = 'unicode61 remove_diacritics 2');
+
+            CREATE TRIGGER {table}_fts_insert AFTER INSERT ON {table} BEGIN
+                INSERT INTO {table}_fts (rowid, body) VALUES (new.rowid, {text});
+            END;
+
+            CREATE TRIGGER {table}_fts_update AFTER UPDATE OF content ON {table} BEGIN
+                DELETE FROM {table}_fts WHERE rowid = old.rowid;
+                INSERT INTO {table}_fts (rowid, body) VALUES (new.rowid, {text});
+            END;
+
+            CREATE TRIGGER {table}_fts_delete AFTER DELETE ON {table} BEGIN
+                DELETE FROM {table}_fts WHERE rowid = old.rowid;
+            END;
+
+            INSERT INTO {table}_fts (rowid, body) SELECT m.rowid, {extract} FROM {table} m;",
+            table = table,
+            text = SEARCHABLE_TEXT,
+            extract = extract,
+        ))?;
+    }
+    Ok(())
+}
+
+/// Messages point at the message they follow, so a conversation becomes a
+/// tree; the conversation remembers which leaf is shown. Existing history
+/// becomes a single chain in timestamp order.
+fn conversation_branches(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE messages ADD COLUMN parent_id TEXT;
+        ALTER TABLE conversations ADD COLUMN active_leaf_id TEXT;
+
+        UPDATE messages SET parent_id = (
+            SELECT previous FROM (
+                SELECT id, LAG(id) OVER (PARTITION BY conversation_id ORDER BY timestamp, rowid) AS previous
+                FROM messages
+            ) chain
+            WHERE chain.id = messages.id
+        );
+
+        UPDATE conversations SET active_leaf_id = (
+            SELECT id FROM messages
+            WHERE messages.conversation_id = conversations.id
+            ORDER BY timestamp DESC, rowid DESC
+            LIMIT 1
+        );
+
+        CREATE INDEX idx_messages_parent ON messages(parent_id);",
+    )
+}
+
+/// The agent history of a task as of its last completed step, so an
+/// interrupted run can be resumed
+fn task_checkpoints(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE task_checkpoints (
+            task_id TEXT PRIMARY KEY,
+            turn INTEGER NOT NULL,
+            messages TEXT NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (task_id) REFERENCES tasks(id) ON DELETE CASCADE
+        );",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::path::PathBuf;
+
+    /// An unversioned database from before migrations
+    fn legacy_database(dir: &TempDir) -> (Connection, PathBuf) {
+        let db_path = dir.join("kuse-cowork.db");
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch("CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);")
+            .unwrap();
+        (conn, db_path)
+    }
+
+    #[test]
+    fn test_migrate_backs_up_legacy_database() {
+        let dir = TempDir::new("migrations");
+        let (mut conn, db_path) = legacy_database(&dir);
+
+        migrate(&mut conn, &db_path).unwrap();
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        assert!(dir.join("kuse-cowork.db.v0.bak").exists());
+
+        // Up to date: nothing to do
+        migrate(&mut conn, &db_path).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_refuses_newer_schema() {
+        let dir = TempDir::new("migrations");
+        let (mut conn, db_path) = legacy_database(&dir);
+
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        assert!(matches!(
+            migrate(&mut conn, &db_path),
+            Err(DbError::NewerSchema { .. })
+        ));
+    }
+
+    #[test]
+    fn test_search_index_follows_agent_content() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, Path::new(":memory:")).unwrap();
+
+        let blocks = r#"[{"type":"tool_use","id":"toolu_1","name":"bash","input":{"command":"docker run -v /data:/data"}}]"#;
+        conn.execute(
+            "INSERT INTO messages (id, conversation_id, role, content, timestamp) VALUES ('m1', 'c1', 'assistant', ?1, 0)",
+            [blocks],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO messages (id, conversation_id, role, content, timestamp) VALUES ('m2', 'c1', 'user', 'fix the mount', 0)",
+            [],
+        )
+        .unwrap();
+
+        let count = |query: &str| -> i64 {
+            conn.query_row("SELECT count(*) FROM messages_fts WHERE messages_fts MATCH ?1", [query], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count("docker"), 1);
+        assert_eq!(count("mount"), 1);
+        assert_eq!(count("toolu_1"), 0);
+
+        conn.execute("UPDATE messages SET content = 'podman' WHERE id = 'm1'", []).unwrap();
+        assert_eq!(count("docker"), 0);
+        conn.execute("DELETE FROM messages WHERE id = 'm2'", []).unwrap();
+        assert_eq!(count("mount"), 0);
+    }
+}
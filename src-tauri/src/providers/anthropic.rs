@@ -0,0 +1,154 @@
+use super::{content_text, parse_arguments, v1_url, Provider, ProviderEndpoint, RequestError, StreamDecoder, StreamEvent};
+use crate::agent::message_builder::ClaudeApiRequest;
+use crate::agent::{TokenUsage, ToolUse};
+use reqwest::{Client, RequestBuilder};
+use serde_json::Value;
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic Messages API. The shared request type already uses its format.
+pub struct AnthropicProvider;
+
+impl AnthropicProvider {
+    fn authorize(builder: RequestBuilder, endpoint: &ProviderEndpoint) -> RequestBuilder {
+        let builder = builder.header("anthropic-version", ANTHROPIC_VERSION);
+        if endpoint.api_key.is_empty() {
+            builder
+        } else {
+            builder.header("x-api-key", &endpoint.api_key)
+        }
+    }
+}
+
+impl Provider for AnthropicProvider {
+    fn encode(&self, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> Value {
+        let mut body = serde_json::to_value(request).unwrap_or_default();
+        let images = endpoint.supports_images();
+
+        // Gemini thought signatures are kept on tool_use blocks, which the Messages API rejects
+        if let Some(messages) = body["messages"].as_array_mut() {
+            for message in messages {
+                if let Some(blocks) = message["content"].as_array_mut() {
+                    for block in blocks.iter_mut().filter_map(|b| b.as_object_mut()) {
+                        block.remove("thought_signature");
+                        if !images && block.get("content").is_some_and(Value::is_array) {
+                            let text = content_text(block.get("content"));
+                            block.insert("content".to_string(), Value::String(text));
+                        }
+                    }
+                }
+            }
+        }
+
+        body
+    }
+
+    fn chat_request(&self, client: &Client, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> RequestBuilder {
+        let builder = client
+            .post(v1_url(&endpoint.config.base_url, "messages"))
+            .json(&self.encode(endpoint, request));
+        Self::authorize(builder, endpoint)
+    }
+
+    fn decoder(&self) -> Box<dyn StreamDecoder> {
+        Box::new(AnthropicDecoder::default())
+    }
+
+    fn models_request(&self, client: &Client, endpoint: &ProviderEndpoint) -> RequestBuilder {
+        Self::authorize(client.get(v1_url(&endpoint.config.base_url, "models")), endpoint)
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<String> {
+        body["data"]
+            .as_array()
+            .map(|models| models.iter().filter_map(|m| m["id"].as_str().map(String::from)).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+struct AnthropicDecoder {
+    usage: TokenUsage,
+    /// Tool call whose input is still streaming: (id, name, partial JSON)
+    current_tool: Option<(String, String, String)>,
+    complete: bool,
+}
+
+impl StreamDecoder for AnthropicDecoder {
+    fn decode(&mut self, data: &Value, events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        match data["type"].as_str().unwrap_or("") {
+            "message_start" => {
+                self.usage.update_anthropic(&data["message"]["usage"]);
+                events.push(StreamEvent::Usage(self.usage));
+            }
+            "message_delta" => {
+                self.usage.update_anthropic(&data["usage"]);
+                events.push(StreamEvent::Usage(self.usage));
+            }
+            "content_block_start" => {
+                let block = &data["content_block"];
+                if block["type"].as_str() == Some("tool_use") {
+                    self.current_tool = Some((
+                        block["id"].as_str().unwrap_or("").to_string(),
+                        block["name"].as_str().unwrap_or("").to_string(),
+                        String::new(),
+                    ));
+                }
+            }
+            "content_block_delta" => {
+                let delta = &data["delta"];
+                match delta["type"].as_str().unwrap_or("") {
+                    "text_delta" => {
+                        if let Some(text) = delta["text"].as_str() {
+                            events.push(StreamEvent::Text(text.to_string()));
+                        }
+                    }
+                    "input_json_delta" => {
+                        if let (Some((_, _, input)), Some(partial)) =
+                            (self.current_tool.as_mut(), delta["partial_json"].as_str())
+                        {
+                            input.push_str(partial);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            "content_block_stop" => {
+                if let Some((id, name, input)) = self.current_tool.take() {
+                    events.push(StreamEvent::ToolUse(ToolUse {
+                        id,
+                        name,
+                        input: parse_arguments(&input),
+                        thought_signature: None,
+                    }));
+                }
+            }
+            "error" => {
+                // Errors such as overloaded_error can arrive mid-stream with status 200
+                let error = &data["error"];
+                let error_type = error["type"].as_str().unwrap_or("");
+                let message = format!(
+                    "API error ({}): {}",
+                    error_type,
+                    error["message"].as_str().unwrap_or("")
+                );
+                return Err(match error_type {
+                    "overloaded_error" | "rate_limit_error" | "api_error" => RequestError::retryable(message),
+                    _ => RequestError::Fatal(message),
+                });
+            }
+            "message_stop" => self.complete = true,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        // The stream ended before message_stop, e.g. the connection dropped
+        if self.complete {
+            Ok(())
+        } else {
+            Err(RequestError::retryable("Stream ended before the response was complete"))
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+
+/// API format type
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiFormat {
+    #[default]
+    Anthropic,
+    OpenAI,
+    OpenAICompatible,
+    OpenAIResponses,  // For GPT-5 series using /v1/responses endpoint
+    Google,
+    Minimax,
+}
+
+/// Authentication type
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthType {
+    None,
+    #[default]
+    Bearer,
+    ApiKey,
+    QueryParam,
+}
+
+/// Provider configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderConfig {
+    pub id: String,
+    pub name: String,
+    pub base_url: String,
+    pub api_format: ApiFormat,
+    pub auth_type: AuthType,
+}
+
+impl ProviderConfig {
+    /// Get preset configuration by provider ID
+    pub fn from_preset(provider_id: &str) -> Self {
+        match provider_id {
+            // Official APIs
+            "anthropic" => Self {
+                id: "anthropic".to_string(),
+                name: "Anthropic".to_string(),
+                base_url: "https://api.anthropic.com".to_string(),
+                api_format: ApiFormat::Anthropic,
+                auth_type: AuthType::ApiKey,
+            },
+            "openai" => Self {
+                id: "openai".to_string(),
+                name: "OpenAI".to_string(),
+                base_url: "https://api.openai.com".to_string(),
+                api_format: ApiFormat::OpenAI,
+                auth_type: AuthType::Bearer,
+            },
+            "google" => Self {
+                id: "google".to_string(),
+                name: "Google".to_string(),
+                base_url: "https://generativelanguage.googleapis.com".to_string(),
+                api_format: ApiFormat::Google,
+                auth_type: AuthType::QueryParam,
+            },
+            "minimax" => Self {
+                id: "minimax".to_string(),
+                name: "Minimax".to_string(),
+                base_url: "https://api.minimax.chat".to_string(),
+                api_format: ApiFormat::Minimax,
+                auth_type: AuthType::Bearer,
+            },
+
+            // Local inference services
+            "ollama" => Self {
+                id: "ollama".to_string(),
+                name: "Ollama".to_string(),
+                base_url: "http://localhost:11434".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::None,
+            },
+            "lm-studio" => Self {
+                id: "lm-studio".to_string(),
+                name: "LM Studio".to_string(),
+                base_url: "http://localhost:1234".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::None,
+            },
+            "localai" => Self {
+                id: "localai".to_string(),
+                name: "LocalAI".to_string(),
+                base_url: "http://localhost:8080".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::None,
+            },
+
+            // Cloud GPU inference
+            "vllm" => Self {
+                id: "vllm".to_string(),
+                name: "vLLM".to_string(),
+                base_url: "http://localhost:8000".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::None,
+            },
+            "tgi" => Self {
+                id: "tgi".to_string(),
+                name: "TGI".to_string(),
+                base_url: "http://localhost:8080".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::None,
+            },
+            "sglang" => Self {
+                id: "sglang".to_string(),
+                name: "SGLang".to_string(),
+                base_url: "http://localhost:30000".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::None,
+            },
+
+            // API aggregation services
+            "openrouter" => Self {
+                id: "openrouter".to_string(),
+                name: "OpenRouter".to_string(),
+                base_url: "https://openrouter.ai/api/v1".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::Bearer,
+            },
+            "together" => Self {
+                id: "together".to_string(),
+                name: "Together AI".to_string(),
+                base_url: "https://api.together.xyz/v1".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::Bearer,
+            },
+            "groq" => Self {
+                id: "groq".to_string(),
+                name: "Groq".to_string(),
+                base_url: "https://api.groq.com/openai/v1".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::Bearer,
+            },
+            "deepseek" => Self {
+                id: "deepseek".to_string(),
+                name: "DeepSeek".to_string(),
+                base_url: "https://api.deepseek.com".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::Bearer,
+            },
+            "siliconflow" => Self {
+                id: "siliconflow".to_string(),
+                name: "SiliconFlow".to_string(),
+                base_url: "https://api.siliconflow.cn/v1".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::Bearer,
+            },
+
+            // Default/Custom - assume OpenAI compatible
+            _ => Self {
+                id: provider_id.to_string(),
+                name: "Custom".to_string(),
+                base_url: "http://localhost:8000".to_string(),
+                api_format: ApiFormat::OpenAICompatible,
+                auth_type: AuthType::Bearer,
+            },
+        }
+    }
+
+    /// Infer provider from model name
+    pub fn from_model(model: &str) -> Self {
+        let model_lower = model.to_lowercase();
+
+        // Check OpenRouter format first (contains slash with known prefix)
+        if model_lower.starts_with("anthropic/") || model_lower.starts_with("openai/") || model_lower.starts_with("meta-llama/") || model_lower.starts_with("deepseek/") {
+            return Self::from_preset("openrouter");
+        }
+
+        // Check Ollama format (contains colon, e.g., llama3.3:latest)
+        if model_lower.contains(":") {
+            return Self::from_preset("ollama");
+        }
+
+        // Direct provider detection by model name
+        if model_lower.contains("claude") {
+            Self::from_preset("anthropic")
+        } else if model_lower.starts_with("gpt-5") || model_lower.contains("gpt-5") {
+            // GPT-5 series uses Responses API
+            Self::from_preset_with_format("openai", ApiFormat::OpenAIResponses)
+        } else if model_lower.contains("gpt") {
+            Self::from_preset("openai")
+        } else if model_lower.contains("gemini") {
+            Self::from_preset("google")
+        } else if model_lower.contains("minimax") {
+            Self::from_preset("minimax")
+        } else {
+            // Default to Anthropic
+            Self::from_preset("anthropic")
+        }
+    }
+
+    /// Get preset configuration with custom API format override
+    fn from_preset_with_format(provider_id: &str, api_format: ApiFormat) -> Self {
+        let mut config = Self::from_preset(provider_id);
+        config.api_format = api_format;
+        config
+    }
+}
+
+/// Provider, credentials and model that model requests are sent to
+#[derive(Debug, Clone)]
+pub struct ProviderEndpoint {
+    pub config: ProviderConfig,
+    pub api_key: String,
+    pub model: String,
+    /// Optional `OpenAI-Organization` header
+    pub organization: Option<String>,
+    /// Optional `OpenAI-Project` header
+    pub project: Option<String>,
+}
+
+impl ProviderEndpoint {
+    /// Resolve the provider preset (inferred from the model when no ID is
+    /// given) and apply a custom base URL if set
+    pub fn new(provider_id: Option<&str>, base_url: &str, api_key: String, model: String) -> Self {
+        let mut config = match provider_id {
+            Some(pid) if !pid.is_empty() => ProviderConfig::from_preset(pid),
+            _ => ProviderConfig::from_model(&model),
+        };
+        if !base_url.is_empty() {
+            config.base_url = base_url.to_string();
+        }
+        Self {
+            config,
+            api_key,
+            model,
+            organization: None,
+            project: None,
+        }
+    }
+
+    /// Whether the model accepts images, judged from its name for servers
+    /// that host many kinds of models
+    pub fn supports_images(&self) -> bool {
+        let model = self.model.to_lowercase();
+        match self.config.api_format {
+            ApiFormat::Anthropic | ApiFormat::Google => true,
+            ApiFormat::OpenAI | ApiFormat::OpenAIResponses => {
+                !["gpt-3.5", "o1-mini", "o3-mini"].iter().any(|prefix| model.starts_with(prefix))
+            }
+            ApiFormat::OpenAICompatible | ApiFormat::Minimax => [
+                "vision", "-vl", "vl-", "llava", "gpt-4o", "gpt-4.1", "gpt-5", "claude", "gemini",
+                "gemma3", "gemma-3", "pixtral", "llama4", "llama-4", "minicpm-v", "moondream",
+            ]
+            .iter()
+            .any(|marker| model.contains(marker)),
+        }
+    }
+
+    pub fn with_openai_headers(mut self, organization: Option<String>, project: Option<String>) -> Self {
+        self.organization = organization.filter(|value| !value.is_empty());
+        self.project = project.filter(|value| !value.is_empty());
+        self
+    }
+}
+
+/// Provider tried when the ones before it in the chain stay unavailable
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FallbackProvider {
+    /// Provider preset ID (e.g. "openrouter"); its key comes from `provider_keys`
+    pub provider: String,
+    pub model: String,
+    /// Custom base URL; empty uses the preset's
+    #[serde(default)]
+    pub base_url: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_from_model() {
+        // Claude model
+        let config = ProviderConfig::from_model("claude-sonnet-4-5-20250929");
+        assert_eq!(config.id, "anthropic");
+        assert_eq!(config.api_format, ApiFormat::Anthropic);
+
+        // Ollama model
+        let config = ProviderConfig::from_model("llama3.3:latest");
+        assert_eq!(config.id, "ollama");
+        assert_eq!(config.api_format, ApiFormat::OpenAICompatible);
+
+        // OpenRouter model
+        let config = ProviderConfig::from_model("anthropic/claude-3.5-sonnet");
+        assert_eq!(config.id, "openrouter");
+        assert_eq!(config.api_format, ApiFormat::OpenAICompatible);
+
+        // GPT-4 model (uses Chat Completions API)
+        let config = ProviderConfig::from_model("gpt-4o");
+        assert_eq!(config.id, "openai");
+        assert_eq!(config.api_format, ApiFormat::OpenAI);
+
+        // GPT-5 model (uses Responses API)
+        let config = ProviderConfig::from_model("gpt-5");
+        assert_eq!(config.id, "openai");
+        assert_eq!(config.api_format, ApiFormat::OpenAIResponses);
+
+        // GPT-5 mini model (uses Responses API)
+        let config = ProviderConfig::from_model("gpt-5-mini");
+        assert_eq!(config.id, "openai");
+        assert_eq!(config.api_format, ApiFormat::OpenAIResponses);
+
+        // GPT-5 nano model (uses Responses API)
+        let config = ProviderConfig::from_model("gpt-5-nano");
+        assert_eq!(config.id, "openai");
+        assert_eq!(config.api_format, ApiFormat::OpenAIResponses);
+    }
+}
@@ -0,0 +1,184 @@
+use super::{content_images, content_text, stream_error, Provider, ProviderEndpoint, RequestError, StreamDecoder, StreamEvent};
+use crate::agent::message_builder::{ApiContent, ClaudeApiRequest};
+use crate::agent::{TokenUsage, ToolUse};
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Google Gemini API
+pub struct GoogleProvider;
+
+impl GoogleProvider {
+    fn authorize(builder: RequestBuilder, endpoint: &ProviderEndpoint) -> RequestBuilder {
+        builder.header("x-goog-api-key", &endpoint.api_key)
+    }
+}
+
+impl Provider for GoogleProvider {
+    fn encode(&self, _endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> Value {
+        // functionResponse parts need the name and thought signature of their call
+        let mut calls: HashMap<&str, (&Value, Option<&Value>)> = HashMap::new();
+        for msg in &request.messages {
+            if let ApiContent::Blocks(blocks) = &msg.content {
+                for block in blocks.iter().filter(|b| b["type"].as_str() == Some("tool_use")) {
+                    if let Some(id) = block["id"].as_str() {
+                        calls.insert(id, (&block["name"], block.get("thought_signature")));
+                    }
+                }
+            }
+        }
+
+        let mut contents: Vec<Value> = Vec::new();
+
+        for msg in &request.messages {
+            // Google uses "model" instead of "assistant"
+            let role = if msg.role == "assistant" { "model" } else { msg.role.as_str() };
+
+            let parts: Vec<Value> = match &msg.content {
+                ApiContent::Text(text) => vec![json!({ "text": text })],
+                ApiContent::Blocks(blocks) => blocks
+                    .iter()
+                    .flat_map(|block| match block["type"].as_str().unwrap_or("") {
+                        "text" => block["text"].as_str().map(|text| json!({ "text": text })).into_iter().collect(),
+                        "tool_use" => {
+                            let mut part = json!({
+                                "functionCall": { "name": block["name"], "args": block["input"] }
+                            });
+                            // Gemini 3 requires the signature it sent with the call
+                            if let Some(signature) = block.get("thought_signature") {
+                                part["thoughtSignature"] = signature.clone();
+                            }
+                            vec![part]
+                        }
+                        "tool_result" => {
+                            let id = block["tool_use_id"].as_str().unwrap_or("");
+                            let (name, signature) = calls.get(id).copied().unwrap_or((&Value::Null, None));
+                            let mut part = json!({
+                                "functionResponse": {
+                                    "name": name,
+                                    "response": { "content": content_text(block.get("content")) }
+                                }
+                            });
+                            if let Some(signature) = signature {
+                                part["thoughtSignature"] = signature.clone();
+                            }
+                            // Images ride along as inline data next to the response
+                            let images = content_images(block.get("content")).into_iter().map(|(media_type, data)| {
+                                json!({ "inlineData": { "mimeType": media_type, "data": data } })
+                            });
+                            std::iter::once(part).chain(images).collect()
+                        }
+                        _ => Vec::new(),
+                    })
+                    .collect(),
+            };
+
+            if !parts.is_empty() {
+                contents.push(json!({ "role": role, "parts": parts }));
+            }
+        }
+
+        // Gemini recommends keeping the default temperature
+        let mut body = json!({
+            "contents": contents,
+            "generationConfig": { "maxOutputTokens": request.max_tokens },
+        });
+
+        if !request.system.is_empty() {
+            body["systemInstruction"] = json!({ "parts": [{ "text": request.system }] });
+        }
+
+        if !request.tools.is_empty() {
+            let declarations: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.input_schema,
+                    })
+                })
+                .collect();
+            body["tools"] = json!([{ "functionDeclarations": declarations }]);
+        }
+
+        body
+    }
+
+    fn chat_request(&self, client: &Client, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> RequestBuilder {
+        let url = format!(
+            "{}/v1beta/models/{}:streamGenerateContent?alt=sse",
+            endpoint.config.base_url.trim_end_matches('/'),
+            request.model
+        );
+        Self::authorize(client.post(url), endpoint).json(&self.encode(endpoint, request))
+    }
+
+    fn decoder(&self) -> Box<dyn StreamDecoder> {
+        Box::new(GoogleDecoder)
+    }
+
+    fn models_request(&self, client: &Client, endpoint: &ProviderEndpoint) -> RequestBuilder {
+        let url = format!("{}/v1beta/models", endpoint.config.base_url.trim_end_matches('/'));
+        Self::authorize(client.get(url), endpoint)
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<String> {
+        body["models"]
+            .as_array()
+            .map(|models| {
+                models
+                    .iter()
+                    .filter_map(|m| m["name"].as_str())
+                    .map(|name| name.trim_start_matches("models/").to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+struct GoogleDecoder;
+
+impl StreamDecoder for GoogleDecoder {
+    fn decode(&mut self, data: &Value, events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        if let Some(error) = data.get("error").filter(|e| e.is_object()) {
+            return Err(stream_error(error));
+        }
+
+        // Each chunk carries the running totals; the last one wins
+        if let Some(metadata) = data.get("usageMetadata") {
+            events.push(StreamEvent::Usage(TokenUsage::from_google(metadata)));
+        }
+
+        let parts = data["candidates"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|candidate| candidate["content"]["parts"].as_array().into_iter().flatten());
+
+        for part in parts {
+            // Thought summaries are not part of the answer
+            if part["thought"].as_bool() == Some(true) {
+                continue;
+            }
+            if let Some(text) = part["text"].as_str().filter(|t| !t.is_empty()) {
+                events.push(StreamEvent::Text(text.to_string()));
+            }
+            if let Some(call) = part.get("functionCall") {
+                events.push(StreamEvent::ToolUse(ToolUse {
+                    id: format!("fc_{}", uuid::Uuid::new_v4()),
+                    name: call["name"].as_str().unwrap_or("").to_string(),
+                    input: call.get("args").cloned().unwrap_or_else(|| json!({})),
+                    thought_signature: part["thoughtSignature"].as_str().map(String::from),
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, _events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        Ok(())
+    }
+}
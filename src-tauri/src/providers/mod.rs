@@ -0,0 +1,272 @@
+//! Model providers. Each API format implements [`Provider`], which encodes the
+//! shared request type into the provider's wire format and decodes its
+//! streamed reply into [`StreamEvent`]s.
+
+mod anthropic;
+pub mod config;
+mod google;
+mod openai;
+mod openai_responses;
+pub mod retry;
+
+pub use config::{ApiFormat, FallbackProvider, ProviderEndpoint};
+pub use retry::RequestError;
+
+use crate::agent::message_builder::ClaudeApiRequest;
+use crate::agent::{TokenUsage, ToolUse};
+use reqwest::{Client, RequestBuilder, Response};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Timeout for model listing requests
+const LIST_MODELS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A piece of a streamed model reply
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// Newly generated text
+    Text(String),
+    /// A complete tool call
+    ToolUse(ToolUse),
+    /// Tokens used so far; later events replace earlier ones
+    Usage(TokenUsage),
+}
+
+/// Decodes the server-sent events of one chat stream
+pub trait StreamDecoder: Send {
+    /// Decode one `data:` payload
+    fn decode(&mut self, data: &Value, events: &mut Vec<StreamEvent>) -> Result<(), RequestError>;
+
+    /// Called once the stream has ended; fails if the reply was cut short
+    fn finish(&mut self, events: &mut Vec<StreamEvent>) -> Result<(), RequestError>;
+}
+
+/// One implementation per API format
+pub trait Provider: Send + Sync {
+    /// Encode a request into the provider's request body
+    fn encode(&self, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> Value;
+
+    /// Build the HTTP request for a streaming chat call
+    fn chat_request(&self, client: &Client, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> RequestBuilder;
+
+    fn decoder(&self) -> Box<dyn StreamDecoder>;
+
+    /// Build the HTTP request listing the models available to the endpoint
+    fn models_request(&self, client: &Client, endpoint: &ProviderEndpoint) -> RequestBuilder;
+
+    /// Read model IDs from the model listing response
+    fn parse_models(&self, body: &Value) -> Vec<String>;
+}
+
+/// The provider implementation for an API format
+pub fn provider_for(api_format: &ApiFormat) -> &'static dyn Provider {
+    match api_format {
+        ApiFormat::Anthropic => &anthropic::AnthropicProvider,
+        ApiFormat::OpenAI | ApiFormat::OpenAICompatible | ApiFormat::Minimax => &openai::OpenAIProvider,
+        ApiFormat::OpenAIResponses => &openai_responses::OpenAIResponsesProvider,
+        ApiFormat::Google => &google::GoogleProvider,
+    }
+}
+
+/// Complete model reply, accumulated from stream events
+#[derive(Debug, Default)]
+pub struct ProviderResponse {
+    pub text: String,
+    pub tool_uses: Vec<ToolUse>,
+    pub usage: TokenUsage,
+}
+
+impl ProviderResponse {
+    pub fn apply(&mut self, event: &StreamEvent) {
+        match event {
+            StreamEvent::Text(delta) => self.text.push_str(delta),
+            StreamEvent::ToolUse(tool_use) => self.tool_uses.push(tool_use.clone()),
+            StreamEvent::Usage(usage) => self.usage = *usage,
+        }
+    }
+}
+
+/// Streamed chat reply
+pub struct ResponseStream {
+    response: Response,
+    decoder: Box<dyn StreamDecoder>,
+    /// Bytes of the current, incomplete line. Kept as bytes so multi-byte
+    /// characters split across chunks are decoded intact.
+    buffer: Vec<u8>,
+    pending: VecDeque<StreamEvent>,
+    finished: bool,
+}
+
+impl ResponseStream {
+    fn new(response: Response, decoder: Box<dyn StreamDecoder>) -> Self {
+        Self {
+            response,
+            decoder,
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Next event, or None once the stream is complete
+    pub async fn next(&mut self) -> Result<Option<StreamEvent>, RequestError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.finished {
+                return Ok(None);
+            }
+
+            let mut events = Vec::new();
+            let chunk = self
+                .response
+                .chunk()
+                .await
+                .map_err(|e| RequestError::retryable(format!("Stream error: {}", e)))?;
+
+            match chunk {
+                Some(chunk) => {
+                    self.buffer.extend_from_slice(&chunk);
+                    while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+                        let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+                        self.decode_line(&line, &mut events)?;
+                    }
+                }
+                None => {
+                    let line = std::mem::take(&mut self.buffer);
+                    self.decode_line(&line, &mut events)?;
+                    self.decoder.finish(&mut events)?;
+                    self.finished = true;
+                }
+            }
+
+            self.pending.extend(events);
+        }
+    }
+
+    /// Read the rest of the stream, calling `on_event` after each event is applied
+    pub async fn collect(
+        mut self,
+        mut on_event: impl FnMut(&ProviderResponse, &StreamEvent),
+    ) -> Result<ProviderResponse, RequestError> {
+        let mut response = ProviderResponse::default();
+        while let Some(event) = self.next().await? {
+            response.apply(&event);
+            on_event(&response, &event);
+        }
+        Ok(response)
+    }
+
+    fn decode_line(&mut self, line: &[u8], events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        let line = String::from_utf8_lossy(line);
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            return Ok(());
+        };
+        let data = data.trim();
+        if data.is_empty() || data == "[DONE]" {
+            return Ok(());
+        }
+
+        match serde_json::from_str::<Value>(data) {
+            Ok(value) => self.decoder.decode(&value, events),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// Send a streaming chat request to an endpoint
+pub async fn stream_chat(
+    client: &Client,
+    endpoint: &ProviderEndpoint,
+    request: &ClaudeApiRequest,
+) -> Result<ResponseStream, RequestError> {
+    let provider = provider_for(&endpoint.config.api_format);
+    let response = provider
+        .chat_request(client, endpoint, request)
+        .send()
+        .await
+        .map_err(RequestError::from_http)?;
+
+    if !response.status().is_success() {
+        return Err(RequestError::from_response(response).await);
+    }
+
+    Ok(ResponseStream::new(response, provider.decoder()))
+}
+
+/// List the models available to an endpoint
+pub async fn list_models(client: &Client, endpoint: &ProviderEndpoint) -> Result<Vec<String>, RequestError> {
+    let provider = provider_for(&endpoint.config.api_format);
+    let response = provider
+        .models_request(client, endpoint)
+        .timeout(LIST_MODELS_TIMEOUT)
+        .send()
+        .await
+        .map_err(RequestError::from_http)?;
+
+    if !response.status().is_success() {
+        return Err(RequestError::from_response(response).await);
+    }
+
+    let body: Value = response.json().await.map_err(RequestError::from_http)?;
+    Ok(provider.parse_models(&body))
+}
+
+/// URL of an API path under `/v1`, for base URLs given with or without it
+fn v1_url(base_url: &str, path: &str) -> String {
+    let base = base_url.trim_end_matches('/');
+    if base.ends_with("/v1") {
+        format!("{}/{}", base, path)
+    } else {
+        format!("{}/v1/{}", base, path)
+    }
+}
+
+/// Parse streamed tool call arguments, falling back to an empty object
+fn parse_arguments(arguments: &str) -> Value {
+    serde_json::from_str(arguments).unwrap_or_else(|_| serde_json::json!({}))
+}
+
+/// Text of a tool result's content: a string, or the text blocks of a list
+fn content_text(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(blocks)) => blocks
+            .iter()
+            .filter(|block| block["type"].as_str() == Some("text"))
+            .filter_map(|block| block["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(value) => value.to_string(),
+        None => String::new(),
+    }
+}
+
+/// Images in a tool result's content, as (media type, base64 data)
+fn content_images(content: Option<&Value>) -> Vec<(&str, &str)> {
+    content
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|block| block["type"].as_str() == Some("image"))
+        .filter_map(|block| Some((block["source"]["media_type"].as_str()?, block["source"]["data"].as_str()?)))
+        .collect()
+}
+
+/// Error reported inside a stream that started with a success status
+fn stream_error(error: &Value) -> RequestError {
+    let message = error
+        .get("message")
+        .and_then(|v| v.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| error.to_string());
+    let message = format!("API error: {}", message);
+
+    match error.get("code").and_then(|v| v.as_u64()) {
+        Some(code) if !retry::is_retryable_status(code as u16) => RequestError::Fatal(message),
+        _ => RequestError::retryable(message),
+    }
+}
+
@@ -0,0 +1,331 @@
+use super::{
+    content_images, content_text, parse_arguments, stream_error, v1_url, ApiFormat, Provider, ProviderEndpoint, RequestError,
+    StreamDecoder, StreamEvent,
+};
+use crate::agent::message_builder::{ApiContent, ClaudeApiRequest};
+use crate::agent::{TokenUsage, ToolUse};
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// OpenAI Chat Completions API, also spoken by most OpenAI-compatible servers
+pub struct OpenAIProvider;
+
+/// Reasoning models (o1, o3, o4, gpt-5) only support the default temperature
+pub(super) fn is_reasoning_model(model: &str) -> bool {
+    let lower = model.to_lowercase();
+    let name = lower.rsplit('/').next().unwrap_or(&lower);
+    ["o1", "o3", "o4", "gpt-5"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Legacy models take `max_tokens` instead of `max_completion_tokens`
+fn is_legacy_openai_model(model: &str) -> bool {
+    let lower = model.to_lowercase();
+    lower.contains("gpt-3.5")
+        || (lower.contains("gpt-4")
+            && !lower.contains("gpt-4o")
+            && !lower.contains("gpt-4-turbo")
+            && !lower.contains("gpt-4.1"))
+}
+
+/// Add the bearer token and optional OpenAI organization/project headers
+pub(super) fn authorize(mut builder: RequestBuilder, endpoint: &ProviderEndpoint) -> RequestBuilder {
+    if !endpoint.api_key.is_empty() {
+        builder = builder.bearer_auth(&endpoint.api_key);
+    }
+    if let Some(organization) = &endpoint.organization {
+        builder = builder.header("OpenAI-Organization", organization);
+    }
+    if let Some(project) = &endpoint.project {
+        builder = builder.header("OpenAI-Project", project);
+    }
+    builder
+}
+
+pub(super) fn models_request(client: &Client, endpoint: &ProviderEndpoint) -> RequestBuilder {
+    authorize(client.get(v1_url(&endpoint.config.base_url, "models")), endpoint)
+}
+
+/// Model IDs from an OpenAI (`data[].id`) or Ollama (`models[].name`) listing
+pub(super) fn parse_models(body: &Value) -> Vec<String> {
+    if let Some(models) = body["data"].as_array() {
+        return models.iter().filter_map(|m| m["id"].as_str().map(String::from)).collect();
+    }
+    body["models"]
+        .as_array()
+        .map(|models| models.iter().filter_map(|m| m["name"].as_str().map(String::from)).collect())
+        .unwrap_or_default()
+}
+
+impl Provider for OpenAIProvider {
+    fn encode(&self, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> Value {
+        let mut messages: Vec<Value> = Vec::new();
+
+        if !request.system.is_empty() {
+            messages.push(json!({ "role": "system", "content": request.system }));
+        }
+
+        for msg in &request.messages {
+            let blocks = match &msg.content {
+                ApiContent::Text(text) => {
+                    messages.push(json!({ "role": msg.role, "content": text }));
+                    continue;
+                }
+                ApiContent::Blocks(blocks) => blocks,
+            };
+
+            let mut text_parts: Vec<&str> = Vec::new();
+            let mut tool_calls: Vec<Value> = Vec::new();
+            let mut image_parts: Vec<Value> = Vec::new();
+
+            for block in blocks {
+                match block["type"].as_str().unwrap_or("") {
+                    "text" => text_parts.extend(block["text"].as_str()),
+                    "tool_use" => tool_calls.push(json!({
+                        "id": block["id"],
+                        "type": "function",
+                        "function": {
+                            "name": block["name"],
+                            "arguments": block.get("input").unwrap_or(&json!({})).to_string(),
+                        }
+                    })),
+                    // Tool results are separate messages with the tool role
+                    "tool_result" => {
+                        messages.push(json!({
+                            "role": "tool",
+                            "tool_call_id": block["tool_use_id"],
+                            "content": content_text(block.get("content")),
+                        }));
+                        if endpoint.supports_images() {
+                            image_parts.extend(content_images(block.get("content")).into_iter().map(|(media_type, data)| {
+                                json!({
+                                    "type": "image_url",
+                                    "image_url": { "url": format!("data:{};base64,{}", media_type, data) },
+                                })
+                            }));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // Tool messages can't carry images, so they follow in a user message
+            if !image_parts.is_empty() {
+                image_parts.insert(0, json!({ "type": "text", "text": "Images returned by the tool calls above:" }));
+                messages.push(json!({ "role": "user", "content": image_parts }));
+            }
+
+            if text_parts.is_empty() && tool_calls.is_empty() {
+                continue;
+            }
+            let mut message = json!({
+                "role": msg.role,
+                "content": if text_parts.is_empty() { Value::Null } else { json!(text_parts.join("\n")) },
+            });
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = json!(tool_calls);
+            }
+            messages.push(message);
+        }
+
+        let mut body = json!({
+            "model": request.model,
+            "stream": true,
+            "messages": messages,
+        });
+
+        // max_completion_tokens is only understood by official OpenAI models;
+        // compatible servers (Ollama, vLLM, LM Studio, ...) use max_tokens
+        let official = endpoint.config.api_format == ApiFormat::OpenAI;
+        if official && !is_legacy_openai_model(&request.model) {
+            body["max_completion_tokens"] = json!(request.max_tokens);
+        } else {
+            body["max_tokens"] = json!(request.max_tokens);
+        }
+
+        if let Some(temperature) = request.temperature {
+            if !is_reasoning_model(&request.model) {
+                body["temperature"] = json!(temperature);
+            }
+        }
+
+        if !request.tools.is_empty() {
+            let tools: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.input_schema,
+                        }
+                    })
+                })
+                .collect();
+            body["tools"] = json!(tools);
+            body["tool_choice"] = json!("auto");
+        }
+
+        // Ask for token counts in the final chunk of the stream
+        if official {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+
+        body
+    }
+
+    fn chat_request(&self, client: &Client, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> RequestBuilder {
+        let url = match endpoint.config.api_format {
+            ApiFormat::Minimax => format!(
+                "{}/v1/text/chatcompletion_v2",
+                endpoint.config.base_url.trim_end_matches('/')
+            ),
+            _ => v1_url(&endpoint.config.base_url, "chat/completions"),
+        };
+        authorize(client.post(url), endpoint).json(&self.encode(endpoint, request))
+    }
+
+    fn decoder(&self) -> Box<dyn StreamDecoder> {
+        Box::new(OpenAIDecoder::default())
+    }
+
+    fn models_request(&self, client: &Client, endpoint: &ProviderEndpoint) -> RequestBuilder {
+        models_request(client, endpoint)
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<String> {
+        parse_models(body)
+    }
+}
+
+#[derive(Default)]
+struct OpenAIDecoder {
+    /// Tool calls streamed in pieces, keyed by their index: (id, name, arguments)
+    tool_calls: BTreeMap<u64, (String, String, String)>,
+    finished: bool,
+}
+
+impl StreamDecoder for OpenAIDecoder {
+    fn decode(&mut self, data: &Value, events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        if let Some(error) = data.get("error").filter(|e| e.is_object()) {
+            return Err(stream_error(error));
+        }
+
+        // Sent with the final chunk when usage is requested
+        if let Some(usage) = data.get("usage").filter(|u| u.is_object()) {
+            events.push(StreamEvent::Usage(TokenUsage::from_openai(usage)));
+        }
+
+        for choice in data["choices"].as_array().into_iter().flatten() {
+            let delta = &choice["delta"];
+
+            if let Some(content) = delta["content"].as_str().filter(|c| !c.is_empty()) {
+                events.push(StreamEvent::Text(content.to_string()));
+            }
+
+            for call in delta["tool_calls"].as_array().into_iter().flatten() {
+                let index = call["index"].as_u64().unwrap_or(0);
+                let entry = self.tool_calls.entry(index).or_default();
+                if let Some(id) = call["id"].as_str() {
+                    entry.0 = id.to_string();
+                }
+                if let Some(name) = call["function"]["name"].as_str() {
+                    entry.1 = name.to_string();
+                }
+                if let Some(arguments) = call["function"]["arguments"].as_str() {
+                    entry.2.push_str(arguments);
+                }
+            }
+
+            if choice["finish_reason"].is_string() {
+                self.finished = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        if !self.finished {
+            return Err(RequestError::retryable("Stream ended before the response was complete"));
+        }
+
+        for (id, name, arguments) in std::mem::take(&mut self.tool_calls).into_values() {
+            if name.is_empty() {
+                continue;
+            }
+            // Some compatible servers omit the call ID
+            let id = if id.is_empty() { format!("call_{}", uuid::Uuid::new_v4()) } else { id };
+            events.push(StreamEvent::ToolUse(ToolUse {
+                id,
+                name,
+                input: parse_arguments(&arguments),
+                thought_signature: None,
+            }));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::message_builder::ApiMessage;
+
+    #[test]
+    fn test_encode_tool_round_trip() {
+        let endpoint = ProviderEndpoint::new(Some("openai"), "", String::new(), "gpt-4o".to_string());
+        let request = ClaudeApiRequest {
+            model: "gpt-4o".to_string(),
+            max_tokens: 100,
+            system: "Be brief".to_string(),
+            messages: vec![
+                ApiMessage {
+                    role: "assistant".to_string(),
+                    content: ApiContent::Blocks(vec![
+                        json!({ "type": "tool_use", "id": "t1", "name": "glob", "input": { "pattern": "*.rs" } }),
+                    ]),
+                },
+                ApiMessage {
+                    role: "user".to_string(),
+                    content: ApiContent::Blocks(vec![
+                        json!({ "type": "tool_result", "tool_use_id": "t1", "content": "main.rs" }),
+                    ]),
+                },
+            ],
+            tools: Vec::new(),
+            temperature: Some(0.7),
+            stream: true,
+        };
+
+        let body = OpenAIProvider.encode(&endpoint, &request);
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[1]["tool_calls"][0]["function"]["arguments"], r#"{"pattern":"*.rs"}"#);
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(body["max_completion_tokens"], 100);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+
+    #[test]
+    fn test_decode_streamed_tool_call() {
+        let mut decoder = OpenAIDecoder::default();
+        let mut events = Vec::new();
+        let chunks = [
+            json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 0, "id": "c1", "function": { "name": "glob", "arguments": "{\"pat" } }] } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 0, "function": { "arguments": "tern\":\"*\"}" } }] } }] }),
+            json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }),
+        ];
+        for chunk in &chunks {
+            decoder.decode(chunk, &mut events).unwrap();
+        }
+        decoder.finish(&mut events).unwrap();
+
+        match events.as_slice() {
+            [StreamEvent::ToolUse(tool_use)] => assert_eq!(tool_use.input["pattern"], "*"),
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use super::openai::{authorize, is_reasoning_model, models_request, parse_models};
+use super::{
+    content_images, content_text, parse_arguments, stream_error, v1_url, Provider, ProviderEndpoint, RequestError, StreamDecoder,
+    StreamEvent,
+};
+use crate::agent::message_builder::{ApiContent, ClaudeApiRequest};
+use crate::agent::{TokenUsage, ToolUse};
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+/// OpenAI Responses API, used by the GPT-5 series
+pub struct OpenAIResponsesProvider;
+
+/// Final text of a completed response, from its `message` output items
+fn output_text(response: &Value) -> String {
+    response["output"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|item| item["type"].as_str() == Some("message"))
+        .flat_map(|item| item["content"].as_array().into_iter().flatten())
+        .filter(|part| part["type"].as_str() == Some("output_text"))
+        .filter_map(|part| part["text"].as_str())
+        .collect()
+}
+
+impl Provider for OpenAIResponsesProvider {
+    fn encode(&self, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> Value {
+        let mut input: Vec<Value> = Vec::new();
+
+        for msg in &request.messages {
+            let blocks = match &msg.content {
+                ApiContent::Text(text) => {
+                    input.push(json!({ "role": msg.role, "content": text }));
+                    continue;
+                }
+                ApiContent::Blocks(blocks) => blocks,
+            };
+
+            let text: Vec<&str> = blocks
+                .iter()
+                .filter(|block| block["type"].as_str() == Some("text"))
+                .filter_map(|block| block["text"].as_str())
+                .collect();
+            if !text.is_empty() {
+                input.push(json!({ "role": msg.role, "content": text.join("\n") }));
+            }
+
+            let mut image_parts: Vec<Value> = Vec::new();
+            for block in blocks {
+                match block["type"].as_str().unwrap_or("") {
+                    "tool_use" => input.push(json!({
+                        "type": "function_call",
+                        "call_id": block["id"],
+                        "name": block["name"],
+                        "arguments": block.get("input").unwrap_or(&json!({})).to_string(),
+                    })),
+                    "tool_result" => {
+                        input.push(json!({
+                            "type": "function_call_output",
+                            "call_id": block["tool_use_id"],
+                            "output": content_text(block.get("content")),
+                        }));
+                        if endpoint.supports_images() {
+                            image_parts.extend(content_images(block.get("content")).into_iter().map(|(media_type, data)| {
+                                json!({
+                                    "type": "input_image",
+                                    "image_url": format!("data:{};base64,{}", media_type, data),
+                                })
+                            }));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // Function call outputs are text only, so images follow as user input
+            if !image_parts.is_empty() {
+                image_parts.insert(0, json!({ "type": "input_text", "text": "Images returned by the tool calls above:" }));
+                input.push(json!({ "role": "user", "content": image_parts }));
+            }
+        }
+
+        let mut body = json!({
+            "model": request.model,
+            "input": input,
+            "max_output_tokens": request.max_tokens,
+            "stream": true,
+        });
+
+        if !request.system.is_empty() {
+            body["instructions"] = json!(request.system);
+        }
+
+        if let Some(temperature) = request.temperature {
+            if !is_reasoning_model(&request.model) {
+                body["temperature"] = json!(temperature);
+            }
+        }
+
+        if !request.tools.is_empty() {
+            let tools: Vec<Value> = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.input_schema,
+                    })
+                })
+                .collect();
+            body["tools"] = json!(tools);
+        }
+
+        body
+    }
+
+    fn chat_request(&self, client: &Client, endpoint: &ProviderEndpoint, request: &ClaudeApiRequest) -> RequestBuilder {
+        authorize(client.post(v1_url(&endpoint.config.base_url, "responses")), endpoint)
+            .json(&self.encode(endpoint, request))
+    }
+
+    fn decoder(&self) -> Box<dyn StreamDecoder> {
+        Box::new(ResponsesDecoder::default())
+    }
+
+    fn models_request(&self, client: &Client, endpoint: &ProviderEndpoint) -> RequestBuilder {
+        models_request(client, endpoint)
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<String> {
+        parse_models(body)
+    }
+}
+
+#[derive(Default)]
+struct ResponsesDecoder {
+    streamed_text: bool,
+    complete: bool,
+}
+
+impl StreamDecoder for ResponsesDecoder {
+    fn decode(&mut self, data: &Value, events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        match data["type"].as_str().unwrap_or("") {
+            "response.output_text.delta" => {
+                if let Some(delta) = data["delta"].as_str() {
+                    self.streamed_text = true;
+                    events.push(StreamEvent::Text(delta.to_string()));
+                }
+            }
+            "response.output_item.done" => {
+                let item = &data["item"];
+                if item["type"].as_str() == Some("function_call") {
+                    events.push(StreamEvent::ToolUse(ToolUse {
+                        id: item["call_id"].as_str().unwrap_or("").to_string(),
+                        name: item["name"].as_str().unwrap_or("").to_string(),
+                        input: parse_arguments(item["arguments"].as_str().unwrap_or("")),
+                        thought_signature: None,
+                    }));
+                }
+            }
+            "response.completed" | "response.incomplete" => {
+                let response = &data["response"];
+                if !self.streamed_text {
+                    let text = output_text(response);
+                    if !text.is_empty() {
+                        events.push(StreamEvent::Text(text));
+                    }
+                }
+                events.push(StreamEvent::Usage(TokenUsage::from_openai(&response["usage"])));
+                self.complete = true;
+            }
+            "response.failed" => return Err(stream_error(&data["response"]["error"])),
+            "error" => return Err(stream_error(data)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _events: &mut Vec<StreamEvent>) -> Result<(), RequestError> {
+        if self.complete {
+            Ok(())
+        } else {
+            Err(RequestError::retryable("Stream ended before the response was complete"))
+        }
+    }
+}
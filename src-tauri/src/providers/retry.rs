@@ -0,0 +1,151 @@
+use reqwest::header::HeaderMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Delay before the first retry; doubled on every further attempt
+const BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Upper bound for the exponential backoff
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Longest server-requested wait that is honoured as-is
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+/// Failure of a model request
+#[derive(Debug, Clone)]
+pub enum RequestError {
+    /// Transient failure (rate limit, overload, server or network error) worth retrying
+    Retryable {
+        message: String,
+        /// Wait requested by the server via `Retry-After`
+        retry_after: Option<Duration>,
+    },
+    /// Failure that retrying will not fix (bad request, authentication, ...)
+    Fatal(String),
+}
+
+impl RequestError {
+    pub fn retryable(message: impl Into<String>) -> Self {
+        Self::Retryable {
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    /// Classify a transport error. Connection problems and timeouts are
+    /// transient; errors building the request are not.
+    pub fn from_http(error: reqwest::Error) -> Self {
+        let message = format!("HTTP error: {}", error);
+        if error.is_builder() || error.is_redirect() {
+            Self::Fatal(message)
+        } else {
+            Self::retryable(message)
+        }
+    }
+
+    /// Build the error for a non-success HTTP response
+    pub async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = parse_retry_after(response.headers());
+        let body = response.text().await.unwrap_or_default();
+        let message = format!("API error ({}): {}", status, body);
+
+        if is_retryable_status(status) {
+            Self::Retryable { message, retry_after }
+        } else {
+            Self::Fatal(message)
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Retryable { .. })
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Retryable { message, .. } | Self::Fatal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl From<RequestError> for String {
+    fn from(error: RequestError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Rate limits, overload (Anthropic 529), timeouts and server errors
+pub fn is_retryable_status(status: u16) -> bool {
+    matches!(status, 408 | 425 | 429 | 500 | 502 | 503 | 504 | 529)
+}
+
+/// Read the server-requested wait from `retry-after-ms` or `Retry-After`
+/// (seconds or an HTTP date)
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return (ms >= 0.0).then(|| Duration::from_secs_f64(ms / 1000.0));
+    }
+
+    let value = header("retry-after")?;
+    if let Ok(seconds) = value.parse::<f64>() {
+        return (seconds >= 0.0).then(|| Duration::from_secs_f64(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Delay before retry number `attempt` (starting at 1). A server-requested
+/// wait is honoured; otherwise exponential backoff with jitter is used so
+/// parallel runs don't retry in lockstep.
+pub fn retry_delay(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    if let Some(wait) = retry_after {
+        return wait.min(MAX_RETRY_AFTER);
+    }
+
+    let exponent = attempt.saturating_sub(1).min(16);
+    let cap = BASE_DELAY.saturating_mul(1 << exponent).min(MAX_BACKOFF);
+    // Random delay in [cap / 2, cap]
+    cap / 2 + cap.mul_f64(fastrand::f64() / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert("retry-after", HeaderValue::from_static("7"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(7)));
+
+        headers.insert("retry-after-ms", HeaderValue::from_static("1500"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_millis(1500)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_retry_delay_bounds() {
+        for attempt in 1..10 {
+            let cap = BASE_DELAY.saturating_mul(1 << (attempt - 1)).min(MAX_BACKOFF);
+            let delay = retry_delay(attempt, None);
+            assert!(delay >= cap / 2 && delay <= cap);
+        }
+        assert_eq!(retry_delay(1, Some(Duration::from_secs(3600))), MAX_RETRY_AFTER);
+    }
+}
@@ -0,0 +1,365 @@
+//! Encryption at rest for API keys, OAuth secrets and tokens stored in the
+//! database. The key is derived from a passphrase (the `KUSE_COWORK_PASSPHRASE`
+//! environment variable, or one entered in the app) when one was set up, and
+//! is otherwise kept in the OS keychain, so backups of the data directory
+//! don't hold it. Where no keychain is available (e.g. Linux without a Secret
+//! Service) the key goes in a file only the user can read.
+
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+use std::path::Path;
+use thiserror::Error;
+
+/// Environment variable holding the passphrase to derive the key from
+pub const PASSPHRASE_ENV: &str = "KUSE_COWORK_PASSPHRASE";
+
+/// Salt for the passphrase, in the data directory. Its presence means the
+/// key is derived from a passphrase.
+const SALT_FILE: &str = "secrets.salt";
+
+/// Key file in the data directory, used when the keychain is unavailable.
+/// Earlier versions always used it; it is moved to the keychain when possible.
+const KEY_FILE: &str = "secrets.key";
+
+/// Keychain entry holding the key when no passphrase is used
+const KEYCHAIN_SERVICE: &str = "kuse-cowork";
+const KEYCHAIN_USER: &str = "secrets-key";
+
+/// Prefix marking an encrypted value; anything else is legacy plaintext
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid key file")]
+    InvalidKey,
+    #[error("Could not generate random bytes")]
+    Random,
+    #[error("Could not decrypt secret (wrong passphrase or key?)")]
+    Decrypt,
+    #[error("Stored secrets are encrypted with a passphrase; enter it to unlock them")]
+    PassphraseRequired,
+    #[error("The key of the stored secrets is missing from the keychain")]
+    MissingKey,
+    #[error("Keychain error: {0}")]
+    Keychain(String),
+}
+
+impl From<keyring::Error> for SecretError {
+    fn from(e: keyring::Error) -> Self {
+        SecretError::Keychain(e.to_string())
+    }
+}
+
+pub struct SecretStore {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl SecretStore {
+    fn from_key_bytes(bytes: &[u8]) -> Result<Self, SecretError> {
+        let key = UnboundKey::new(&AES_256_GCM, bytes).map_err(|_| SecretError::InvalidKey)?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Open the store for the app's data directory: derived from the
+    /// passphrase in the environment if set, otherwise from the keychain.
+    /// A new salt or key is only created when `create` is set, i.e. when
+    /// nothing has been encrypted yet.
+    pub fn open(data_dir: &Path, create: bool) -> Result<Self, SecretError> {
+        match std::env::var(PASSPHRASE_ENV) {
+            Ok(passphrase) if !passphrase.is_empty() => Self::unlock(data_dir, &passphrase, create),
+            _ if data_dir.join(SALT_FILE).exists() => Err(SecretError::PassphraseRequired),
+            _ => Self::from_keychain(data_dir, create),
+        }
+    }
+
+    /// Whether the key for the data directory is derived from a passphrase
+    pub fn uses_passphrase(data_dir: &Path) -> bool {
+        data_dir.join(SALT_FILE).exists()
+    }
+
+    /// Derive the key from a passphrase and the data directory's salt
+    pub fn unlock(data_dir: &Path, passphrase: &str, create: bool) -> Result<Self, SecretError> {
+        let salt_path = data_dir.join(SALT_FILE);
+        let salt = if salt_path.exists() {
+            std::fs::read(&salt_path)?
+        } else if create {
+            let salt = random_bytes::<SALT_LEN>(&SystemRandom::new())?;
+            write_private(&salt_path, &salt)?;
+            salt.to_vec()
+        } else {
+            return Err(SecretError::MissingKey);
+        };
+        Self::from_passphrase(passphrase, &salt)
+    }
+
+    /// Replace the key with a new one, derived from `passphrase` if given and
+    /// kept in the keychain (or key file) otherwise. Secrets encrypted with
+    /// the old key can no longer be read.
+    pub fn reset(data_dir: &Path, passphrase: Option<&str>) -> Result<Self, SecretError> {
+        for file in [SALT_FILE, KEY_FILE] {
+            match std::fs::remove_file(data_dir.join(file)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        match passphrase {
+            Some(passphrase) => {
+                // A key left in the keychain is not needed anymore
+                if let Ok(entry) = keychain_entry() {
+                    let _ = entry.delete_credential();
+                }
+                Self::unlock(data_dir, passphrase, true)
+            }
+            None => {
+                let bytes = random_bytes::<KEY_LEN>(&SystemRandom::new())?;
+                match keychain_entry().and_then(|entry| entry.set_secret(&bytes)) {
+                    Ok(()) => {}
+                    Err(e) if keychain_unavailable(&e) => write_private(&data_dir.join(KEY_FILE), &bytes)?,
+                    Err(e) => return Err(e.into()),
+                }
+                Self::from_key_bytes(&bytes)
+            }
+        }
+    }
+
+    /// Derive the key from a passphrase with PBKDF2-HMAC-SHA256
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self, SecretError> {
+        let mut key = [0u8; KEY_LEN];
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(PBKDF2_ITERATIONS).expect("iterations are non-zero"),
+            salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+        Self::from_key_bytes(&key)
+    }
+
+    /// Load the key from the OS keychain. A key file left in the data
+    /// directory by an earlier version is moved there; without a keychain
+    /// the key file is used instead.
+    fn from_keychain(data_dir: &Path, create: bool) -> Result<Self, SecretError> {
+        let key_path = data_dir.join(KEY_FILE);
+        let entry = match keychain_entry() {
+            Ok(entry) => entry,
+            Err(e) if keychain_unavailable(&e) => return Self::from_key_file(&key_path, create),
+            Err(e) => return Err(e.into()),
+        };
+        match entry.get_secret() {
+            Ok(bytes) => return Self::from_key_bytes(&bytes),
+            Err(keyring::Error::NoEntry) => {}
+            Err(e) if keychain_unavailable(&e) => return Self::from_key_file(&key_path, create),
+            Err(e) => return Err(e.into()),
+        }
+
+        let bytes = if key_path.exists() {
+            std::fs::read(&key_path)?
+        } else if create {
+            random_bytes::<KEY_LEN>(&SystemRandom::new())?.to_vec()
+        } else {
+            return Err(SecretError::MissingKey);
+        };
+
+        let store = Self::from_key_bytes(&bytes)?;
+        match entry.set_secret(&bytes) {
+            Ok(()) if key_path.exists() => std::fs::remove_file(&key_path)?,
+            Ok(()) => {}
+            Err(e) if keychain_unavailable(&e) && !key_path.exists() => write_private(&key_path, &bytes)?,
+            Err(e) if keychain_unavailable(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(store)
+    }
+
+    /// Load the key from a file in the data directory, creating it if asked
+    fn from_key_file(path: &Path, create: bool) -> Result<Self, SecretError> {
+        if path.exists() {
+            return Self::from_key_bytes(&std::fs::read(path)?);
+        }
+        if !create {
+            return Err(SecretError::MissingKey);
+        }
+        let bytes = random_bytes::<KEY_LEN>(&SystemRandom::new())?;
+        write_private(path, &bytes)?;
+        Self::from_key_bytes(&bytes)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, SecretError> {
+        let nonce_bytes = random_bytes::<NONCE_LEN>(&self.rng)?;
+        let mut sealed = plaintext.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce_bytes), Aad::empty(), &mut sealed)
+            .map_err(|_| SecretError::Random)?;
+
+        let mut payload = nonce_bytes.to_vec();
+        payload.extend_from_slice(&sealed);
+        Ok(format!(
+            "{}{}",
+            ENCRYPTED_PREFIX,
+            base64::engine::general_purpose::STANDARD.encode(payload)
+        ))
+    }
+
+    /// Decrypt a stored value. Values stored before encryption are returned as is.
+    pub fn decrypt(&self, stored: &str) -> Result<String, SecretError> {
+        let Some(encoded) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+
+        let mut payload = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|_| SecretError::Decrypt)?;
+        if payload.len() < NONCE_LEN {
+            return Err(SecretError::Decrypt);
+        }
+        let mut sealed = payload.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&payload).map_err(|_| SecretError::Decrypt)?;
+
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .map_err(|_| SecretError::Decrypt)?;
+        String::from_utf8(plaintext.to_vec()).map_err(|_| SecretError::Decrypt)
+    }
+}
+
+pub fn is_encrypted(stored: &str) -> bool {
+    stored.starts_with(ENCRYPTED_PREFIX)
+}
+
+fn keychain_entry() -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_USER)
+}
+
+/// Whether the error means there is no usable keychain on this system
+fn keychain_unavailable(e: &keyring::Error) -> bool {
+    matches!(e, keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_))
+}
+
+fn random_bytes<const N: usize>(rng: &SystemRandom) -> Result<[u8; N], SecretError> {
+    let mut bytes = [0u8; N];
+    rng.fill(&mut bytes).map_err(|_| SecretError::Random)?;
+    Ok(bytes)
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(bytes)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    // The data directory is per-user on other platforms
+    std::fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn test_store(byte: u8) -> SecretStore {
+        SecretStore::from_key_bytes(&[byte; KEY_LEN]).unwrap()
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let store = test_store(7);
+        let encrypted = store.encrypt("sk-test").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("sk-test"));
+        assert_eq!(store.decrypt(&encrypted).unwrap(), "sk-test");
+    }
+
+    #[test]
+    fn test_plaintext_passes_through_decrypt() {
+        assert_eq!(test_store(7).decrypt("legacy").unwrap(), "legacy");
+    }
+
+    #[test]
+    fn test_other_key_cannot_decrypt() {
+        let encrypted = test_store(7).encrypt("sk-test").unwrap();
+        assert!(test_store(8).decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_unlock_does_not_set_up_a_passphrase() {
+        let dir = TempDir::new("secrets");
+
+        assert!(matches!(SecretStore::unlock(dir.path(), "pass", false), Err(SecretError::MissingKey)));
+        assert!(!SecretStore::uses_passphrase(dir.path()));
+    }
+
+    #[test]
+    fn test_passphrase_reopens_store() {
+        let dir = TempDir::new("secrets");
+        let store = SecretStore::unlock(dir.path(), "pass", true).unwrap();
+        assert!(SecretStore::uses_passphrase(dir.path()));
+        let encrypted = store.encrypt("sk-test").unwrap();
+
+        let reopened = SecretStore::unlock(dir.path(), "pass", false).unwrap();
+        assert_eq!(reopened.decrypt(&encrypted).unwrap(), "sk-test");
+    }
+
+    #[test]
+    fn test_wrong_passphrase_cannot_decrypt() {
+        let dir = TempDir::new("secrets");
+        let encrypted = SecretStore::unlock(dir.path(), "pass", true).unwrap().encrypt("sk-test").unwrap();
+
+        let wrong = SecretStore::unlock(dir.path(), "other", false).unwrap();
+        assert!(matches!(wrong.decrypt(&encrypted), Err(SecretError::Decrypt)));
+    }
+
+    #[test]
+    fn test_key_file_only_created_when_asked() {
+        let dir = TempDir::new("secrets");
+        let path = dir.join(KEY_FILE);
+
+        assert!(matches!(SecretStore::from_key_file(&path, false), Err(SecretError::MissingKey)));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_key_file_reopens_store() {
+        let dir = TempDir::new("secrets");
+        let path = dir.join(KEY_FILE);
+        let encrypted = SecretStore::from_key_file(&path, true).unwrap().encrypt("sk-test").unwrap();
+
+        let reopened = SecretStore::from_key_file(&path, false).unwrap();
+        assert_eq!(reopened.decrypt(&encrypted).unwrap(), "sk-test");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_file_only_readable_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("secrets");
+        let path = dir.join(KEY_FILE);
+        SecretStore::from_key_file(&path, true).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
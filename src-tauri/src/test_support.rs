@@ -0,0 +1,48 @@
+//! Fixtures shared by the unit tests
+
+use crate::tools::paths::PathResolver;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory under the system temp dir. It is removed when dropped,
+/// so it is cleaned up even when an assertion fails.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a directory named `kuse-<name>-<uuid>`
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("kuse-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+
+    /// Write a file relative to the directory, creating parent directories
+    pub fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// A path resolver with this directory as the project
+    pub fn resolver(&self) -> PathResolver {
+        PathResolver::new(Some(&self.path.to_string_lossy()))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
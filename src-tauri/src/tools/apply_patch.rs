@@ -0,0 +1,725 @@
+//! Atomic multi-file changes from a list of edits or a unified diff.
+//!
+//! Every change is worked out in memory first; files are only written once
+//! all edits and hunks have matched, and a failed write restores the files
+//! already written.
+
+use crate::agent::ToolDefinition;
+use crate::tools::paths::PathResolver;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Number of expected lines quoted when a hunk does not match
+const MAX_QUOTED_LINES: usize = 5;
+
+pub fn definition() -> ToolDefinition {
+    ToolDefinition {
+        name: "apply_patch".to_string(),
+        description: "Apply several changes at once, across one or more files. Pass either `edits`, an ordered list of text replacements, or `patch`, a unified diff (as produced by `diff -u` or `git diff`) that can modify, create (from /dev/null) and delete (to /dev/null) files. Context is matched exactly first, then ignoring differences in whitespace. Either every change applies or none does; on failure the edit or hunk that did not match is reported.".to_string(),
+        input_schema: json!({
+            "type": "object",
+            "properties": {
+                "edits": {
+                    "type": "array",
+                    "description": "Replacements applied in order. A later edit sees the result of earlier edits to the same file. An empty old_string on a file that does not exist creates it.",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "type": "string",
+                                "description": "The path to the file to edit"
+                            },
+                            "old_string": {
+                                "type": "string",
+                                "description": "The text to find (must be unique in the file unless replace_all is set)"
+                            },
+                            "new_string": {
+                                "type": "string",
+                                "description": "The text to replace it with"
+                            },
+                            "replace_all": {
+                                "type": "boolean",
+                                "description": "If true, replace all occurrences. Default is false"
+                            }
+                        },
+                        "required": ["path", "old_string", "new_string"]
+                    }
+                },
+                "patch": {
+                    "type": "string",
+                    "description": "A unified diff with ---/+++ file headers and @@ hunks"
+                }
+            }
+        }),
+    }
+}
+
+/// The effect of a patch on one file
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    pub path: PathBuf,
+    /// Content before the patch; `None` when the patch creates the file
+    pub original: Option<String>,
+    /// Content after the patch; `None` when the patch deletes the file
+    pub updated: Option<String>,
+}
+
+impl FileChange {
+    fn label(&self) -> &'static str {
+        match (&self.original, &self.updated) {
+            (None, _) => "A",
+            (_, None) => "D",
+            _ => "M",
+        }
+    }
+}
+
+/// Work out the changes a patch makes without touching the filesystem.
+/// Files the patch leaves as they were are not included.
+pub fn plan(input: &serde_json::Value, paths: &PathResolver) -> Result<Vec<FileChange>, String> {
+    let mut workspace = Workspace::default();
+
+    match (input.get("edits"), input.get("patch").and_then(|v| v.as_str())) {
+        (Some(edits), None) => {
+            let edits = edits.as_array().ok_or("'edits' must be an array")?;
+            for (index, edit) in edits.iter().enumerate() {
+                apply_edit(&mut workspace, paths, index + 1, edit)?;
+            }
+        }
+        (None, Some(patch)) => {
+            let file_patches = parse_unified_diff(patch)?;
+            for file_patch in &file_patches {
+                apply_file_patch(&mut workspace, paths, file_patch)?;
+            }
+        }
+        (Some(_), Some(_)) => return Err("Pass either 'edits' or 'patch', not both".to_string()),
+        (None, None) => return Err("Missing 'edits' or 'patch' parameter".to_string()),
+    }
+
+    Ok(workspace
+        .files
+        .into_iter()
+        .filter(|change| change.original != change.updated)
+        .collect())
+}
+
+/// Write planned changes. If any write fails, the files already written are
+/// restored so the patch applies entirely or not at all.
+pub fn apply(changes: &[FileChange], paths: &PathResolver) -> Result<String, String> {
+    if changes.is_empty() {
+        return Ok("Patch applied, but it left every file unchanged".to_string());
+    }
+
+    let mut written: Vec<&FileChange> = Vec::new();
+    for change in changes {
+        if let Err(e) = write_content(&change.path, change.updated.as_deref()) {
+            for done in written.iter().rev() {
+                let _ = write_content(&done.path, done.original.as_deref());
+            }
+            return Err(format!(
+                "Failed to write {}: {}. No files were changed.",
+                paths.display(&change.path),
+                e
+            ));
+        }
+        written.push(change);
+    }
+
+    let mut summary = format!("Applied patch to {} file(s):", changes.len());
+    for change in changes {
+        summary.push_str(&format!("\n  {} {}", change.label(), paths.display(&change.path)));
+    }
+    Ok(summary)
+}
+
+/// Replace a file's content through a temporary sibling, or delete it for `None`
+fn write_content(path: &Path, content: Option<&str>) -> std::io::Result<()> {
+    let Some(content) = content else {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file_name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let temp = path.with_file_name(format!(".{}.kuse-patch", file_name));
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+/// In-memory state of the files touched so far
+#[derive(Default)]
+struct Workspace {
+    files: Vec<FileChange>,
+}
+
+impl Workspace {
+    fn file(&mut self, path: PathBuf, display: &str) -> Result<&mut FileChange, String> {
+        if let Some(index) = self.files.iter().position(|change| change.path == path) {
+            return Ok(&mut self.files[index]);
+        }
+
+        let original = if path.is_dir() {
+            return Err(format!("{} is a directory", display));
+        } else if path.exists() {
+            Some(fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", display, e))?)
+        } else {
+            None
+        };
+        self.files.push(FileChange {
+            path,
+            updated: original.clone(),
+            original,
+        });
+        Ok(self.files.last_mut().unwrap())
+    }
+}
+
+/// A file split into lines, remembering its line endings
+struct Text {
+    lines: Vec<String>,
+    crlf: bool,
+    trailing_newline: bool,
+}
+
+impl Text {
+    fn parse(content: &str) -> Self {
+        let body = content.strip_suffix('\n').unwrap_or(content);
+        let lines = if content.is_empty() {
+            Vec::new()
+        } else {
+            body.split('\n')
+                .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+                .collect()
+        };
+
+        Self {
+            lines,
+            crlf: content.contains("\r\n"),
+            trailing_newline: content.is_empty() || content.ends_with('\n'),
+        }
+    }
+
+    fn render(&self) -> String {
+        if self.lines.is_empty() {
+            return String::new();
+        }
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        let mut content = self.lines.join(newline);
+        if self.trailing_newline {
+            content.push_str(newline);
+        }
+        content
+    }
+}
+
+/// Line comparisons tried in turn, from exact to ignoring all surrounding whitespace
+const LINE_MATCHERS: [fn(&str, &str) -> bool; 3] = [
+    |a, b| a == b,
+    |a, b| a.trim_end() == b.trim_end(),
+    |a, b| a.trim() == b.trim(),
+];
+
+/// Positions at or after `from` where `needle` occurs, using the strictest
+/// comparison that finds any
+fn find_lines(lines: &[String], needle: &[String], from: usize) -> Vec<usize> {
+    if needle.is_empty() || needle.len() > lines.len() {
+        return Vec::new();
+    }
+
+    for matches in LINE_MATCHERS {
+        let found: Vec<usize> = (from..=lines.len() - needle.len())
+            .filter(|&start| {
+                needle
+                    .iter()
+                    .zip(&lines[start..])
+                    .all(|(expected, actual)| matches(actual, expected))
+            })
+            .collect();
+        if !found.is_empty() {
+            return found;
+        }
+    }
+    Vec::new()
+}
+
+fn to_lines(text: &str) -> Vec<String> {
+    text.lines().map(str::to_string).collect()
+}
+
+fn apply_edit(
+    workspace: &mut Workspace,
+    paths: &PathResolver,
+    number: usize,
+    edit: &serde_json::Value,
+) -> Result<(), String> {
+    let field = |name: &str| {
+        edit.get(name)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| format!("Edit {}: missing '{}'", number, name))
+    };
+    let path_str = field("path")?;
+    let old_string = field("old_string")?;
+    let new_string = field("new_string")?;
+    let replace_all = edit.get("replace_all").and_then(|v| v.as_bool()).unwrap_or(false);
+
+    let path = paths.resolve(path_str).map_err(|e| format!("Edit {}: {}", number, e))?;
+    let file = workspace.file(path, path_str)?;
+
+    let Some(content) = &file.updated else {
+        if old_string.is_empty() {
+            file.updated = Some(new_string.to_string());
+            return Ok(());
+        }
+        return Err(format!("Edit {} ({}): file not found", number, path_str));
+    };
+    if old_string.is_empty() {
+        return Err(format!("Edit {} ({}): old_string must not be empty for an existing file", number, path_str));
+    }
+
+    // Exact text first
+    let count = content.matches(old_string).count();
+    if count == 1 || (count > 1 && replace_all) {
+        file.updated = Some(content.replace(old_string, new_string));
+        return Ok(());
+    }
+    if count > 1 {
+        return Err(format!(
+            "Edit {} ({}): found {} occurrences of old_string. Set replace_all or include more context.",
+            number, path_str, count
+        ));
+    }
+
+    // Then whole lines, tolerating whitespace differences
+    let mut text = Text::parse(content);
+    let needle = to_lines(old_string);
+    let found = find_lines(&text.lines, &needle, 0);
+    if found.is_empty() {
+        return Err(format!(
+            "Edit {} ({}): could not find old_string, even ignoring whitespace",
+            number, path_str
+        ));
+    }
+    if found.len() > 1 && !replace_all {
+        return Err(format!(
+            "Edit {} ({}): old_string matches {} places when ignoring whitespace. Set replace_all or include more context.",
+            number, path_str, found.len()
+        ));
+    }
+
+    let replacement = to_lines(new_string);
+    let mut next_free = 0;
+    let mut shift = 0isize;
+    for start in found {
+        // Skip matches overlapping one already replaced
+        if start < next_free {
+            continue;
+        }
+        let at = (start as isize + shift) as usize;
+        text.lines.splice(at..at + needle.len(), replacement.iter().cloned());
+        shift += replacement.len() as isize - needle.len() as isize;
+        next_free = start + needle.len();
+    }
+    file.updated = Some(text.render());
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+#[derive(Debug)]
+struct Hunk {
+    header: String,
+    /// 1-based line where the hunk starts in the original file
+    old_start: usize,
+    lines: Vec<HunkLine>,
+    /// "\ No newline at end of file" followed the old or new side
+    old_missing_newline: bool,
+    new_missing_newline: bool,
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.clone()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct FilePatch {
+    /// `None` for /dev/null
+    old_path: Option<String>,
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+fn diff_path(header: &str) -> Option<String> {
+    // Drop timestamps after a tab, then the a/ or b/ prefix git adds
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path.strip_prefix("a/").or_else(|| path.strip_prefix("b/")).unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// Line counts of a hunk's sides, from its header
+struct HunkRange {
+    old_start: usize,
+    old_count: usize,
+    new_count: usize,
+}
+
+/// Parse "@@ -12,5 +12,6 @@" into the original start line and the line
+/// counts of both sides. An omitted count means one line.
+fn hunk_range(header: &str) -> Result<HunkRange, String> {
+    let invalid = || format!("Invalid hunk header: {}", header);
+    let parse_side = |side: &str| -> Option<(usize, usize)> {
+        match side.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((side.parse().ok()?, 1)),
+        }
+    };
+
+    let mut parts = header.strip_prefix("@@ ").ok_or_else(invalid)?.split(' ');
+    let old = parts.next().and_then(|part| part.strip_prefix('-')).and_then(parse_side);
+    let new = parts.next().and_then(|part| part.strip_prefix('+')).and_then(parse_side);
+    match (old, new) {
+        (Some((old_start, old_count)), Some((_, new_count))) => Ok(HunkRange { old_start, old_count, new_count }),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_unified_diff(patch: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = patch.lines().collect();
+    let mut file_patches: Vec<FilePatch> = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+
+        // A new file starts with ---/+++ headers
+        if let (Some(old), Some(new)) = (
+            line.strip_prefix("--- "),
+            lines.get(i + 1).and_then(|next| next.strip_prefix("+++ ")),
+        ) {
+            file_patches.push(FilePatch {
+                old_path: diff_path(old),
+                new_path: diff_path(new),
+                hunks: Vec::new(),
+            });
+            i += 2;
+            continue;
+        }
+
+        if line.starts_with("@@") {
+            let file_patch = file_patches
+                .last_mut()
+                .ok_or_else(|| format!("Hunk without ---/+++ file headers: {}", line))?;
+            let range = hunk_range(line)?;
+            let mut hunk = Hunk {
+                header: line.to_string(),
+                old_start: range.old_start,
+                lines: Vec::new(),
+                old_missing_newline: false,
+                new_missing_newline: false,
+            };
+            let (mut old_left, mut new_left) = (range.old_count, range.new_count);
+            i += 1;
+
+            while i < lines.len() {
+                let body = lines[i];
+                // The header's counts end the hunk, so blank lines between
+                // files aren't taken as context
+                if old_left == 0 && new_left == 0 && !body.starts_with('\\') {
+                    if body.starts_with('+') && !body.starts_with("+++ ")
+                        || body.starts_with('-') && !body.starts_with("--- ")
+                        || body.starts_with(' ')
+                    {
+                        return Err(format!("Hunk {} has more lines than its header says: {}", hunk.header, body));
+                    }
+                    break;
+                }
+                let starts_next_file = body.starts_with("--- ")
+                    && lines.get(i + 1).is_some_and(|next| next.starts_with("+++ "));
+                if body.starts_with("@@") || body.starts_with("diff ") || starts_next_file {
+                    break;
+                }
+
+                let line = match body.chars().next() {
+                    Some('+') => HunkLine::Add(body[1..].to_string()),
+                    Some('-') => HunkLine::Remove(body[1..].to_string()),
+                    Some(' ') => HunkLine::Context(body[1..].to_string()),
+                    Some('\\') => {
+                        match hunk.lines.last() {
+                            Some(HunkLine::Remove(_)) => hunk.old_missing_newline = true,
+                            Some(HunkLine::Add(_)) => hunk.new_missing_newline = true,
+                            _ => {
+                                hunk.old_missing_newline = true;
+                                hunk.new_missing_newline = true;
+                            }
+                        }
+                        i += 1;
+                        continue;
+                    }
+                    // Editors often strip the space from empty context lines
+                    None => HunkLine::Context(String::new()),
+                    Some(_) => return Err(format!("Invalid line in hunk {}: {}", hunk.header, body)),
+                };
+                match line {
+                    HunkLine::Context(_) => {
+                        old_left = old_left.saturating_sub(1);
+                        new_left = new_left.saturating_sub(1);
+                    }
+                    HunkLine::Remove(_) => old_left = old_left.saturating_sub(1),
+                    HunkLine::Add(_) => new_left = new_left.saturating_sub(1),
+                }
+                hunk.lines.push(line);
+                i += 1;
+            }
+
+            file_patch.hunks.push(hunk);
+            continue;
+        }
+
+        // diff --git, index and other header lines carry nothing we need
+        i += 1;
+    }
+
+    if file_patches.is_empty() {
+        return Err("Patch contains no ---/+++ file headers".to_string());
+    }
+    Ok(file_patches)
+}
+
+fn apply_file_patch(workspace: &mut Workspace, paths: &PathResolver, file_patch: &FilePatch) -> Result<(), String> {
+    let path_str = match (&file_patch.old_path, &file_patch.new_path) {
+        (Some(old), Some(new)) if old != new => {
+            return Err(format!("Renaming {} to {} is not supported; use move_file", old, new));
+        }
+        (_, Some(path)) | (Some(path), None) => path.clone(),
+        (None, None) => return Err("Patch file headers are both /dev/null".to_string()),
+    };
+    let path = paths.resolve(&path_str)?;
+    let file = workspace.file(path, &path_str)?;
+
+    let mut text = match (&file_patch.old_path, &file.updated) {
+        (None, Some(_)) => return Err(format!("Cannot create {}: the file already exists", path_str)),
+        (None, None) => Text::parse(""),
+        (Some(_), None) => return Err(format!("Cannot patch {}: file not found", path_str)),
+        (Some(_), Some(content)) => Text::parse(content),
+    };
+
+    let hunk_count = file_patch.hunks.len();
+    let mut from = 0;
+    let mut offset = 0isize;
+    for (index, hunk) in file_patch.hunks.iter().enumerate() {
+        let old = hunk.old_lines();
+        let expected_at = (hunk.old_start.saturating_sub(1) as isize + offset).max(0) as usize;
+
+        let at = if old.is_empty() {
+            // Pure insertion: nothing to match, so trust the line number
+            expected_at.clamp(from, text.lines.len())
+        } else {
+            // Of equally good matches, take the one nearest the stated line
+            find_lines(&text.lines, &old, from)
+                .into_iter()
+                .min_by_key(|&start| start.abs_diff(expected_at))
+                .ok_or_else(|| hunk_error(&path_str, index + 1, hunk_count, hunk, &old))?
+        };
+
+        let mut replacement = Vec::new();
+        let mut cursor = at;
+        for line in &hunk.lines {
+            match line {
+                // Keep the file's own context lines, which may differ in whitespace
+                HunkLine::Context(_) => {
+                    replacement.push(text.lines[cursor].clone());
+                    cursor += 1;
+                }
+                HunkLine::Remove(_) => cursor += 1,
+                HunkLine::Add(added) => replacement.push(added.clone()),
+            }
+        }
+
+        let inserted = replacement.len();
+        let reaches_end = at + old.len() == text.lines.len();
+        text.lines.splice(at..at + old.len(), replacement);
+
+        if reaches_end {
+            if hunk.new_missing_newline {
+                text.trailing_newline = false;
+            } else if hunk.old_missing_newline {
+                text.trailing_newline = true;
+            }
+        }
+
+        offset = (at + inserted) as isize - (hunk.old_start.saturating_sub(1) + old.len()) as isize;
+        from = at + inserted;
+    }
+
+    file.updated = if file_patch.new_path.is_none() {
+        None
+    } else {
+        Some(text.render())
+    };
+    Ok(())
+}
+
+fn hunk_error(path: &str, number: usize, count: usize, hunk: &Hunk, old: &[String]) -> String {
+    let mut message = format!(
+        "Hunk {} of {} in {} ({}) does not match the file, even ignoring whitespace. No files were changed. Expected to find:",
+        number, count, path, hunk.header
+    );
+    for line in old.iter().take(MAX_QUOTED_LINES) {
+        message.push_str("\n  ");
+        message.push_str(line);
+    }
+    if old.len() > MAX_QUOTED_LINES {
+        message.push_str(&format!("\n  ... ({} more lines)", old.len() - MAX_QUOTED_LINES));
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn execute(input: &serde_json::Value, paths: &PathResolver) -> Result<String, String> {
+        apply(&plan(input, paths)?, paths)
+    }
+
+    #[test]
+    fn test_unified_diff_tolerates_trailing_whitespace() {
+        let dir = TempDir::new("patch");
+        dir.write("main.rs", "fn main() {\n    let x = 1;   \n    println!(\"{}\", x);\n}\n");
+
+        let patch = "\
+--- a/main.rs
++++ b/main.rs
+@@ -1,4 +1,4 @@
+ fn main() {
+-    let x = 1;
++    let x = 2;
+     println!(\"{}\", x);
+ }
+";
+        let output = execute(&json!({ "patch": patch }), &dir.resolver()).unwrap();
+        assert!(output.contains("M main.rs"));
+        assert_eq!(
+            fs::read_to_string(dir.join("main.rs")).unwrap(),
+            "fn main() {\n    let x = 2;\n    println!(\"{}\", x);\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_creates_files() {
+        let dir = TempDir::new("patch");
+
+        let patch = "\
+--- /dev/null
++++ b/notes.txt
+@@ -0,0 +1,2 @@
++first
++second
+";
+        let output = execute(&json!({ "patch": patch }), &dir.resolver()).unwrap();
+        assert!(output.contains("A notes.txt"));
+        assert_eq!(fs::read_to_string(dir.join("notes.txt")).unwrap(), "first\nsecond\n");
+    }
+
+    #[test]
+    fn test_blank_line_between_files_ends_hunk() {
+        // Model-written diffs often put a blank line between files
+        let patch = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+ one
+-two
++TWO
+
+--- a/b.txt
++++ b/b.txt
+@@ -1 +1 @@
+-three
++THREE
+";
+        let file_patches = parse_unified_diff(patch).unwrap();
+        assert_eq!(file_patches.len(), 2);
+        assert_eq!(
+            file_patches[0].hunks[0].lines,
+            vec![
+                HunkLine::Context("one".to_string()),
+                HunkLine::Remove("two".to_string()),
+                HunkLine::Add("TWO".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_line_within_hunk_counts_is_context() {
+        let patch = "--- a/c.txt\n+++ b/c.txt\n@@ -1,3 +1,3 @@\n a\n\n-b\n+B\n";
+        let file_patches = parse_unified_diff(patch).unwrap();
+        assert_eq!(file_patches[0].hunks[0].lines[1], HunkLine::Context(String::new()));
+    }
+
+    #[test]
+    fn test_hunk_longer_than_header_is_rejected() {
+        let patch = "--- a/d.txt\n+++ b/d.txt\n@@ -1 +1 @@\n-x\n+y\n+z\n";
+        assert!(parse_unified_diff(patch).unwrap_err().contains("more lines than its header says"));
+    }
+
+    #[test]
+    fn test_failed_hunk_changes_nothing() {
+        let dir = TempDir::new("patch");
+        dir.write("a.txt", "one\ntwo\n");
+        dir.write("b.txt", "three\n");
+
+        let patch = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+ one
+-two
++TWO
+--- a/b.txt
++++ b/b.txt
+@@ -1 +1 @@
+-four
++FOUR
+";
+        let error = execute(&json!({ "patch": patch }), &dir.resolver()).unwrap_err();
+        assert!(error.contains("Hunk 1 of 1 in b.txt"));
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn test_edits_apply_in_order() {
+        let dir = TempDir::new("patch");
+        dir.write("lib.rs", "  alpha\r\nbeta\r\n");
+
+        let edits = json!({ "edits": [
+            { "path": "lib.rs", "old_string": "alpha\nbeta", "new_string": "gamma\nbeta" },
+            { "path": "lib.rs", "old_string": "beta", "new_string": "delta" },
+        ]});
+        execute(&edits, &dir.resolver()).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("lib.rs")).unwrap(), "gamma\r\ndelta\r\n");
+    }
+}
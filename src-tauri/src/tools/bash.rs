@@ -1,7 +1,26 @@
 use crate::agent::ToolDefinition;
 use serde_json::json;
-use std::process::{Command, Stdio};
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Maximum characters of output returned to the model
+const MAX_OUTPUT_CHARS: usize = 50000;
+
+/// How long to keep reading output after the shell exits, in case
+/// background jobs still hold the pipes open
+const OUTPUT_DRAIN_GRACE: Duration = Duration::from_secs(2);
+
+/// Bytes kept from each output stream; past this only the head and tail are kept
+const MAX_COLLECTED_BYTES: usize = 1024 * 1024;
+
+/// Callback receiving output as it is produced: (stream name, chunk)
+pub type OutputCallback = Arc<dyn Fn(&str, &str) + Send + Sync>;
 
 pub fn definition() -> ToolDefinition {
     ToolDefinition {
@@ -42,27 +61,8 @@ const BLOCKED_PATTERNS: &[&str] = &[
     "curl | bash",
 ];
 
-pub fn execute(
-    input: &serde_json::Value,
-    project_path: Option<&str>,
-) -> Result<String, String> {
-    let command = input
-        .get("command")
-        .and_then(|v| v.as_str())
-        .ok_or("Missing 'command' parameter")?;
-
-    let cwd = input
-        .get("cwd")
-        .and_then(|v| v.as_str())
-        .or(project_path);
-
-    let timeout_secs = input
-        .get("timeout")
-        .and_then(|v| v.as_u64())
-        .unwrap_or(60)
-        .min(300);
-
-    // Security check
+/// Reject commands containing a blocked pattern
+pub(crate) fn check_blocked(command: &str) -> Result<(), String> {
     for pattern in BLOCKED_PATTERNS {
         if command.contains(pattern) {
             return Err(format!(
@@ -71,6 +71,20 @@ pub fn execute(
             ));
         }
     }
+    Ok(())
+}
+
+pub async fn execute(
+    input: &serde_json::Value,
+    project_path: Option<&str>,
+    cancel: &CancellationToken,
+    on_output: Option<OutputCallback>,
+) -> Result<String, String> {
+    let (command, cwd, timeout_secs) = parse_input(input)?;
+    let cwd = cwd.or(project_path);
+
+    // Security check
+    check_blocked(command)?;
 
     // Build command
     let mut cmd = Command::new("sh");
@@ -80,24 +94,72 @@ pub fn execute(
         cmd.current_dir(dir);
     }
 
+    cmd.stdin(Stdio::null());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
+    cmd.kill_on_drop(true);
+
+    // Run the shell as its own process group so the whole tree can be killed
+    #[cfg(unix)]
+    cmd.process_group(0);
 
-    // Execute with timeout
-    let child = cmd.spawn()
+    let mut child = cmd.spawn()
         .map_err(|e| format!("Failed to spawn command: {}", e))?;
 
-    let output = wait_with_timeout(child, Duration::from_secs(timeout_secs))?;
+    let stdout_reader = child
+        .stdout
+        .take()
+        .map(|out| StreamReader::spawn(out, "stdout", on_output.clone()));
+    let stderr_reader = child
+        .stderr
+        .take()
+        .map(|err| StreamReader::spawn(err, "stderr", on_output.clone()));
+
+    let exit_status = tokio::select! {
+        status = child.wait() => Some(status.map_err(|e| format!("Command failed: {}", e))?),
+        _ = tokio::time::sleep(Duration::from_secs(timeout_secs)) => None,
+        _ = cancel.cancelled() => None,
+    };
+
+    if exit_status.is_none() {
+        kill_process_tree(&mut child).await;
+    }
+
+    let stdout = collect_stream(stdout_reader).await;
+    let stderr = collect_stream(stderr_reader).await;
+
+    match exit_status {
+        Some(status) => Ok(format_output(&stdout, &stderr, status.code().unwrap_or(-1) as i64)),
+        None => Err(stopped_error(&stdout, &stderr, cancel, timeout_secs)),
+    }
+}
+
+/// Command, working directory and timeout (in seconds) of a bash call
+pub(crate) fn parse_input(input: &serde_json::Value) -> Result<(&str, Option<&str>, u64), String> {
+    let command = input
+        .get("command")
+        .and_then(|v| v.as_str())
+        .ok_or("Missing 'command' parameter")?;
+
+    let cwd = input
+        .get("cwd")
+        .and_then(|v| v.as_str());
+
+    let timeout_secs = input
+        .get("timeout")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(60)
+        .min(300);
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let exit_code = output.status.code().unwrap_or(-1);
+    Ok((command, cwd, timeout_secs))
+}
 
-    // Format output
+/// Combine stdout and stderr as shown to the model
+fn combine_output(stdout: &str, stderr: &str) -> String {
     let mut result = String::new();
 
     if !stdout.is_empty() {
-        result.push_str(&stdout);
+        result.push_str(stdout);
     }
 
     if !stderr.is_empty() {
@@ -105,54 +167,261 @@ pub fn execute(
             result.push('\n');
         }
         result.push_str("[stderr]\n");
-        result.push_str(&stderr);
+        result.push_str(stderr);
     }
 
+    result
+}
+
+/// Output of a command that ran to completion
+pub(crate) fn format_output(stdout: &str, stderr: &str, exit_code: i64) -> String {
+    let mut result = combine_output(stdout, stderr);
+
     if exit_code != 0 {
         result.push_str(&format!("\n[exit code: {}]", exit_code));
     }
 
-    // Truncate if too long
-    if result.len() > 50000 {
-        result = format!(
-            "{}...\n\n[Output truncated. Total length: {} chars]",
-            &result[..50000],
-            result.len()
-        );
+    if result.is_empty() {
+        return "[Command completed with no output]".to_string();
     }
 
+    truncate_output(&result, MAX_OUTPUT_CHARS)
+}
+
+/// Error for a command stopped by its timeout or by cancellation
+pub(crate) fn stopped_error(stdout: &str, stderr: &str, cancel: &CancellationToken, timeout_secs: u64) -> String {
+    let reason = if cancel.is_cancelled() {
+        "Command cancelled".to_string()
+    } else {
+        format!("Command timed out after {} seconds", timeout_secs)
+    };
+
+    let result = combine_output(stdout, stderr);
     if result.is_empty() {
-        result = "[Command completed with no output]".to_string();
+        return reason;
     }
-
-    Ok(result)
+    format!(
+        "{}. Output before it was stopped:\n{}",
+        reason,
+        truncate_output(&result, MAX_OUTPUT_CHARS)
+    )
 }
 
-fn wait_with_timeout(
-    child: std::process::Child,
-    timeout: Duration,
-) -> Result<std::process::Output, String> {
-    use std::thread;
-    use std::sync::mpsc;
+/// Output read from a stream so far. Long output keeps its head and tail.
+#[derive(Default)]
+pub(crate) struct CollectedOutput {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    omitted: usize,
+}
 
-    let (tx, rx) = mpsc::channel();
+impl CollectedOutput {
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        let head_room = (MAX_COLLECTED_BYTES / 2).saturating_sub(self.head.len());
+        let (to_head, rest) = bytes.split_at(head_room.min(bytes.len()));
+        self.head.extend_from_slice(to_head);
 
-    let handle = thread::spawn(move || {
-        let result = child.wait_with_output();
-        let _ = tx.send(result);
-    });
+        self.tail.extend(rest);
+        let excess = self.tail.len().saturating_sub(MAX_COLLECTED_BYTES / 2);
+        if excess > 0 {
+            self.tail.drain(..excess);
+            self.omitted += excess;
+        }
+    }
 
-    match rx.recv_timeout(timeout) {
-        Ok(result) => {
-            let _ = handle.join();
-            result.map_err(|e| format!("Command failed: {}", e))
+    pub(crate) fn to_text(&self) -> String {
+        let mut bytes = self.head.clone();
+        if self.omitted > 0 {
+            bytes.extend_from_slice(format!("\n[... {} bytes omitted ...]\n", self.omitted).as_bytes());
         }
-        Err(_) => {
-            // Timeout - try to kill the process
-            Err(format!(
-                "Command timed out after {} seconds",
-                timeout.as_secs()
-            ))
+        bytes.extend(&self.tail);
+        String::from_utf8_lossy(&bytes).to_string()
+    }
+}
+
+/// A task reading one of the child's output streams into a shared buffer,
+/// so output is kept even if the reader has to be abandoned
+struct StreamReader {
+    task: JoinHandle<()>,
+    output: Arc<Mutex<CollectedOutput>>,
+}
+
+impl StreamReader {
+    fn spawn(
+        stream: impl AsyncRead + Unpin + Send + 'static,
+        name: &'static str,
+        on_output: Option<OutputCallback>,
+    ) -> Self {
+        let output = Arc::new(Mutex::new(CollectedOutput::default()));
+        let task = tokio::spawn(read_stream(stream, name, on_output, output.clone()));
+        Self { task, output }
+    }
+}
+
+/// Read a child stream line by line, forwarding each line to the callback
+async fn read_stream(
+    stream: impl AsyncRead + Unpin,
+    name: &'static str,
+    on_output: Option<OutputCallback>,
+    output: Arc<Mutex<CollectedOutput>>,
+) {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                if let Some(callback) = &on_output {
+                    callback(name, &String::from_utf8_lossy(&line));
+                }
+                output.lock().unwrap().push(&line);
+            }
         }
     }
 }
+
+/// Wait briefly for a reader to reach the end of its stream, and return what
+/// it collected. Background jobs may keep the pipe open; their output so far
+/// is returned without waiting for them.
+async fn collect_stream(reader: Option<StreamReader>) -> String {
+    let Some(mut reader) = reader else {
+        return String::new();
+    };
+
+    if tokio::time::timeout(OUTPUT_DRAIN_GRACE, &mut reader.task).await.is_err() {
+        reader.task.abort();
+    }
+
+    let output = reader.output.lock().unwrap();
+    output.to_text()
+}
+
+/// Kill the shell and every process it started
+async fn kill_process_tree(child: &mut Child) {
+    if let Some(pid) = child.id() {
+        kill_process_group(pid);
+    }
+    let _ = child.kill().await;
+}
+
+/// Kill a process group led by `pid` (a child spawned with its own group)
+#[cfg(unix)]
+pub(crate) fn kill_process_group(pid: u32) {
+    unsafe {
+        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+/// Kill a process and all of its descendants
+#[cfg(windows)]
+pub(crate) fn kill_process_group(pid: u32) {
+    let _ = std::process::Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Keep the head and tail of long output, cutting on character boundaries
+pub fn truncate_output(output: &str, max_chars: usize) -> String {
+    let total = output.chars().count();
+    if total <= max_chars {
+        return output.to_string();
+    }
+
+    let head_chars = max_chars / 2;
+    let tail_chars = max_chars - head_chars;
+
+    let head_end = output
+        .char_indices()
+        .nth(head_chars)
+        .map(|(i, _)| i)
+        .unwrap_or(output.len());
+    let tail_start = output
+        .char_indices()
+        .nth(total - tail_chars)
+        .map(|(i, _)| i)
+        .unwrap_or(output.len());
+
+    format!(
+        "{}\n\n[... {} characters omitted. Total length: {} chars ...]\n\n{}",
+        &output[..head_end],
+        total - head_chars - tail_chars,
+        total,
+        &output[tail_start..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn test_truncate_output_keeps_short_output() {
+        assert_eq!(truncate_output("hello", 10), "hello");
+    }
+
+    #[test]
+    fn test_truncate_output_keeps_head_and_tail() {
+        let output = format!("{}{}{}", "a".repeat(10), "b".repeat(100), "c".repeat(10));
+        let truncated = truncate_output(&output, 20);
+
+        assert!(truncated.starts_with(&"a".repeat(10)));
+        assert!(truncated.ends_with(&"c".repeat(10)));
+        assert!(truncated.contains("100 characters omitted"));
+    }
+
+    #[test]
+    fn test_truncate_output_multibyte_boundary() {
+        // Would panic if sliced by bytes
+        let output = "日本語".repeat(100);
+        let truncated = truncate_output(&output, 11);
+
+        assert!(truncated.starts_with("日本語日本"));
+        assert!(truncated.ends_with("語日本語"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timeout_kills_process_group() {
+        let dir = TempDir::new("bash-timeout");
+        let marker = dir.join("marker");
+        let input = json!({
+            "command": format!("(sleep 2 && touch {}) & wait", marker.display()),
+            "timeout": 1
+        });
+
+        let result = execute(&input, None, &CancellationToken::new(), None).await;
+        assert!(result.unwrap_err().contains("timed out"));
+
+        // The background job was in the killed group, so it never runs
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_collected_output_keeps_head_and_tail() {
+        let mut output = CollectedOutput::default();
+        output.push(&b"x".repeat(MAX_COLLECTED_BYTES / 2));
+        output.push(&b"-".repeat(100));
+        output.push(&b"z".repeat(MAX_COLLECTED_BYTES / 2));
+
+        let text = output.to_text();
+        assert!(text.starts_with('x'));
+        assert!(text.contains("[... 100 bytes omitted ...]"));
+        assert!(text.ends_with('z'));
+        assert!(!text.contains('-'));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_output_kept_when_background_job_holds_pipe() {
+        let input = json!({ "command": "echo hi; sleep 100 &" });
+
+        let result = execute(&input, None, &CancellationToken::new(), None).await;
+        assert_eq!(result.unwrap(), "hi\n");
+    }
+}
@@ -8,6 +8,7 @@ use bollard::models::HostConfig;
 use bollard::Docker;
 use futures::StreamExt;
 use serde_json::json;
+use tokio_util::sync::CancellationToken;
 
 /// Get Docker tool definitions
 pub fn get_docker_tools() -> Vec<ToolDefinition> {
@@ -65,20 +66,12 @@ pub fn get_docker_tools() -> Vec<ToolDefinition> {
     ]
 }
 
-/// Execute a Docker tool (sync wrapper for non-async contexts)
-pub fn execute_docker_tool(tool_use: &ToolUse, project_path: &Option<String>) -> ToolResult {
-    // Use a separate thread to avoid blocking the async runtime
-    std::thread::scope(|s| {
-        s.spawn(|| {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(async {
-                execute_docker_tool_inner(tool_use, project_path).await
-            })
-        }).join().unwrap()
-    })
-}
-
-async fn execute_docker_tool_inner(tool_use: &ToolUse, project_path: &Option<String>) -> ToolResult {
+/// Execute a Docker tool
+pub async fn execute_docker_tool(
+    tool_use: &ToolUse,
+    project_path: &Option<String>,
+    cancel: &CancellationToken,
+) -> ToolResult {
     let docker = match Docker::connect_with_local_defaults() {
         Ok(d) => d,
         Err(e) => {
@@ -90,14 +83,19 @@ async fn execute_docker_tool_inner(tool_use: &ToolUse, project_path: &Option<Str
     };
 
     match tool_use.name.as_str() {
-        "docker_run" => docker_run(&docker, tool_use, project_path).await,
+        "docker_run" => docker_run(&docker, tool_use, project_path, cancel).await,
         "docker_list" => docker_list(&docker, tool_use).await,
         "docker_images" => docker_images(&docker, tool_use).await,
         _ => ToolResult::error(tool_use.id.clone(), format!("Unknown docker tool: {}", tool_use.name)),
     }
 }
 
-async fn docker_run(docker: &Docker, tool_use: &ToolUse, project_path: &Option<String>) -> ToolResult {
+async fn docker_run(
+    docker: &Docker,
+    tool_use: &ToolUse,
+    project_path: &Option<String>,
+    cancel: &CancellationToken,
+) -> ToolResult {
     let image = tool_use.input.get("image")
         .and_then(|v| v.as_str())
         .unwrap_or("python:3.11-alpine");
@@ -183,15 +181,30 @@ async fn docker_run(docker: &Docker, tool_use: &ToolUse, project_path: &Option<S
 
     let mut log_stream = docker.logs(&container.id, Some(log_options));
 
-    while let Some(log_result) = log_stream.next().await {
+    loop {
+        let log_result = tokio::select! {
+            next = log_stream.next() => next,
+            _ = cancel.cancelled() => {
+                let _ = docker.remove_container(
+                    &container.id,
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                ).await;
+                return ToolResult::error(tool_use.id.clone(), "Container run cancelled".to_string());
+            }
+        };
+
         match log_result {
-            Ok(log) => {
+            Some(Ok(log)) => {
                 output.push_str(&log.to_string());
             }
-            Err(e) => {
+            Some(Err(e)) => {
                 output.push_str(&format!("\n[Log error: {}]", e));
                 break;
             }
+            None => break,
         }
     }
 
@@ -289,7 +302,7 @@ async fn docker_images(docker: &Docker, tool_use: &ToolUse) -> ToolResult {
     }
 }
 
-async fn pull_image_if_needed(docker: &Docker, image: &str) -> Result<(), String> {
+pub(crate) async fn pull_image_if_needed(docker: &Docker, image: &str) -> Result<(), String> {
     // Check if image exists
     if docker.inspect_image(image).await.is_ok() {
         return Ok(());
@@ -1,7 +1,7 @@
 use crate::agent::ToolDefinition;
+use crate::tools::paths::PathResolver;
 use serde_json::json;
 use std::fs;
-use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn definition() -> ToolDefinition {
@@ -23,7 +23,7 @@ pub fn definition() -> ToolDefinition {
 
 pub fn execute(
     input: &serde_json::Value,
-    project_path: Option<&str>,
+    paths: &PathResolver,
 ) -> Result<String, String> {
     let path_str = input
         .get("path")
@@ -31,7 +31,7 @@ pub fn execute(
         .ok_or("Missing 'path' parameter")?;
 
     // Resolve path
-    let path = resolve_path(path_str, project_path)?;
+    let path = paths.resolve(path_str)?;
 
     if !path.exists() {
         return Err(format!("File not found: {}", path_str));
@@ -43,7 +43,10 @@ pub fn execute(
         .unwrap()
         .as_secs();
     
-    let project_root = std::env::current_dir().map_err(|e| e.to_string())?;
+    let project_root = match paths.project_root() {
+        Some(root) => root.to_path_buf(),
+        None => std::env::current_dir().map_err(|e| e.to_string())?,
+    };
     let trash_dir = project_root
         .join(".kuse")
         .join("trash");
@@ -65,17 +68,4 @@ pub fn execute(
     }).to_string())
 }
 
-fn resolve_path(path_str: &str, project_path: Option<&str>) -> Result<std::path::PathBuf, String> {
-    let path = Path::new(path_str);
-
-    if path.is_absolute() {
-        Ok(path.to_path_buf())
-    } else if let Some(project) = project_path {
-        Ok(Path::new(project).join(path))
-    } else {
-        std::env::current_dir()
-            .map(|cwd| cwd.join(path))
-            .map_err(|e| format!("Failed to get current directory: {}", e))
-    }
-}
 
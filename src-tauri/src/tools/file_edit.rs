@@ -1,12 +1,12 @@
 use crate::agent::ToolDefinition;
+use crate::tools::paths::PathResolver;
 use serde_json::json;
 use std::fs;
-use std::path::Path;
 
 pub fn definition() -> ToolDefinition {
     ToolDefinition {
         name: "edit_file".to_string(),
-        description: "Make targeted edits to a file by replacing specific text. The old_string must match exactly (including whitespace and indentation). Use this for small, precise changes.".to_string(),
+        description: "Make targeted edits to a file by replacing specific text. The old_string must match exactly (including whitespace and indentation). Use this for small, precise changes. Fails if the file changed on disk since you last read it; read it again first.".to_string(),
         input_schema: json!({
             "type": "object",
             "properties": {
@@ -34,7 +34,7 @@ pub fn definition() -> ToolDefinition {
 
 pub fn execute(
     input: &serde_json::Value,
-    project_path: Option<&str>,
+    paths: &PathResolver,
 ) -> Result<String, String> {
     let path_str = input
         .get("path")
@@ -57,7 +57,7 @@ pub fn execute(
         .unwrap_or(false);
 
     // Resolve path
-    let path = resolve_path(path_str, project_path)?;
+    let path = paths.resolve(path_str)?;
 
     // Check if file exists
     if !path.exists() {
@@ -103,16 +103,3 @@ pub fn execute(
     ))
 }
 
-fn resolve_path(path_str: &str, project_path: Option<&str>) -> Result<std::path::PathBuf, String> {
-    let path = Path::new(path_str);
-
-    if path.is_absolute() {
-        Ok(path.to_path_buf())
-    } else if let Some(project) = project_path {
-        Ok(Path::new(project).join(path))
-    } else {
-        std::env::current_dir()
-            .map(|cwd| cwd.join(path))
-            .map_err(|e| format!("Failed to get current directory: {}", e))
-    }
-}
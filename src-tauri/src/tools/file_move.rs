@@ -1,7 +1,7 @@
 use crate::agent::ToolDefinition;
+use crate::tools::paths::PathResolver;
 use serde_json::json;
 use std::fs;
-use std::path::Path;
 
 pub fn definition() -> ToolDefinition {
     ToolDefinition {
@@ -26,7 +26,7 @@ pub fn definition() -> ToolDefinition {
 
 pub fn execute(
     input: &serde_json::Value,
-    project_path: Option<&str>,
+    paths: &PathResolver,
 ) -> Result<String, String> {
     let source_str = input
         .get("source")
@@ -39,8 +39,8 @@ pub fn execute(
         .ok_or("Missing 'destination' parameter")?;
 
     // Resolve paths
-    let source = resolve_path(source_str, project_path)?;
-    let destination = resolve_path(dest_str, project_path)?;
+    let source = paths.resolve(source_str)?;
+    let destination = paths.resolve(dest_str)?;
 
     if !source.exists() {
         return Err(format!("Source file not found: {}", source_str));
@@ -70,17 +70,4 @@ pub fn execute(
     ))
 }
 
-fn resolve_path(path_str: &str, project_path: Option<&str>) -> Result<std::path::PathBuf, String> {
-    let path = Path::new(path_str);
-
-    if path.is_absolute() {
-        Ok(path.to_path_buf())
-    } else if let Some(project) = project_path {
-        Ok(Path::new(project).join(path))
-    } else {
-        std::env::current_dir()
-            .map(|cwd| cwd.join(path))
-            .map_err(|e| format!("Failed to get current directory: {}", e))
-    }
-}
 
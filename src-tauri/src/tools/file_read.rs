@@ -1,12 +1,32 @@
-use crate::agent::ToolDefinition;
+use crate::agent::{ToolDefinition, ToolImage};
+use crate::tools::list_dir::format_size;
+use crate::tools::paths::PathResolver;
+use base64::Engine;
 use serde_json::json;
-use std::fs;
-use std::path::Path;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+/// Lines returned when no limit is given
+const DEFAULT_LIMIT: usize = 2000;
+/// Longer lines are cut off
+const MAX_LINE_BYTES: usize = 8 * 1024;
+/// Reading stops once the output grows past this
+const MAX_OUTPUT_BYTES: usize = 256 * 1024;
+/// Bytes inspected to detect the file type and encoding
+const SNIFF_BYTES: u64 = 8 * 1024;
+/// Larger images are described rather than attached
+const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+/// Bytes per hex dump row
+const HEX_ROW_BYTES: usize = 16;
+/// Hex dump rows returned when no limit is given
+const DEFAULT_HEX_ROWS: usize = 32;
+/// Upper bound on hex dump rows per call
+const MAX_HEX_ROWS: usize = 2048;
 
 pub fn definition() -> ToolDefinition {
     ToolDefinition {
         name: "read_file".to_string(),
-        description: "Read the contents of a file at the specified path. Use this to understand existing code before making changes.".to_string(),
+        description: "Read the contents of a file at the specified path. Use this to understand existing code before making changes. Text is returned with line numbers, 2000 lines at a time; use offset and limit to page through large files. UTF-16 and Latin-1 files are decoded, images (PNG, JPEG, GIF, WebP) are returned as images, and other binary files are shown as a hex dump.".to_string(),
         input_schema: json!({
             "type": "object",
             "properties": {
@@ -16,11 +36,11 @@ pub fn definition() -> ToolDefinition {
                 },
                 "offset": {
                     "type": "integer",
-                    "description": "Line number to start reading from (1-indexed, optional)"
+                    "description": "Line number to start reading from (1-indexed, optional). For binary files, the hex dump row to start from"
                 },
                 "limit": {
                     "type": "integer",
-                    "description": "Maximum number of lines to read (optional)"
+                    "description": "Maximum number of lines (or hex dump rows) to read (optional)"
                 }
             },
             "required": ["path"]
@@ -28,10 +48,41 @@ pub fn definition() -> ToolDefinition {
     }
 }
 
-pub fn execute(
+/// What read_file returns: text for every file, plus the image itself for images
+pub struct FileContent {
+    pub text: String,
+    pub images: Vec<ToolImage>,
+}
+
+impl From<String> for FileContent {
+    fn from(text: String) -> Self {
+        Self { text, images: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+        }
+    }
+}
+
+pub fn read(
     input: &serde_json::Value,
-    project_path: Option<&str>,
-) -> Result<String, String> {
+    paths: &PathResolver,
+) -> Result<FileContent, String> {
     let path_str = input
         .get("path")
         .and_then(|v| v.as_str())
@@ -48,7 +99,7 @@ pub fn execute(
         .map(|v| v as usize);
 
     // Resolve path
-    let path = resolve_path(path_str, project_path)?;
+    let path = paths.resolve(path_str)?;
 
     // Check if file exists
     if !path.exists() {
@@ -59,92 +110,509 @@ pub fn execute(
         return Err(format!("Path is not a file: {}", path_str));
     }
 
-    // Read file
-    let content = fs::read_to_string(&path)
+    let mut file = File::open(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let size = file
+        .metadata()
+        .map_err(|e| format!("Failed to read file: {}", e))?
+        .len();
+
+    // Only the head is read up front; the rest is streamed
+    let mut head = Vec::new();
+    (&mut file)
+        .take(SNIFF_BYTES)
+        .read_to_end(&mut head)
         .map_err(|e| format!("Failed to read file: {}", e))?;
 
-    // Apply offset and limit
-    let lines: Vec<&str> = content.lines().collect();
-    let start = offset.unwrap_or(1).saturating_sub(1);
-    let end = limit
-        .map(|l| (start + l).min(lines.len()))
-        .unwrap_or(lines.len());
+    if let Some(media_type) = image_type(&head) {
+        return read_image(file, media_type, size);
+    }
 
-    if start >= lines.len() {
-        return Ok(String::new());
+    let result = match detect_encoding(&head) {
+        Some((encoding, bom_len)) => {
+            file.seek(SeekFrom::Start(bom_len))
+                .and_then(|_| read_lines(file, encoding, offset, limit))
+        }
+        None => hex_dump(file, &head, size, offset, limit),
+    };
+    result
+        .map(FileContent::from)
+        .map_err(|e| format!("Failed to read file: {}", e))
+}
+
+/// Work out how to decode a file from its first bytes. `None` means binary;
+/// otherwise the encoding and the length of its byte order mark.
+fn detect_encoding(head: &[u8]) -> Option<(Encoding, u64)> {
+    if head.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return Some((Encoding::Utf8, 3));
+    }
+    if head.starts_with(&[0xFF, 0xFE]) {
+        return Some((Encoding::Utf16Le, 2));
+    }
+    if head.starts_with(&[0xFE, 0xFF]) {
+        return Some((Encoding::Utf16Be, 2));
     }
 
-    // Format with line numbers
-    let result: Vec<String> = lines[start..end]
-        .iter()
+    // UTF-16 without a BOM: mostly-ASCII text leaves every other byte zero,
+    // but never a whole unit
+    if head.len() >= 4 && !head.chunks_exact(2).any(|unit| unit == [0, 0]) {
+        let pairs = head.len() / 2;
+        let zeros = |parity: usize| head.iter().skip(parity).step_by(2).filter(|&&b| b == 0).count();
+        let (even, odd) = (zeros(0), zeros(1));
+        if odd * 10 >= pairs * 4 && even * 20 < pairs {
+            return Some((Encoding::Utf16Le, 0));
+        }
+        if even * 10 >= pairs * 4 && odd * 20 < pairs {
+            return Some((Encoding::Utf16Be, 0));
+        }
+    }
+
+    if head.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => Some((Encoding::Utf8, 0)),
+        // A character cut off by the end of the sample is still UTF-8
+        Err(e) if e.error_len().is_none() => Some((Encoding::Utf8, 0)),
+        Err(_) => Some((Encoding::Latin1, 0)),
+    }
+}
+
+/// Number a window of lines, reading no further into the file than needed
+fn read_lines(
+    file: File,
+    encoding: Encoding,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> io::Result<String> {
+    let mut reader: Box<dyn BufRead> = match encoding {
+        Encoding::Utf8 => Box::new(BufReader::new(file)),
+        _ => Box::new(BufReader::new(Decoder::new(file, encoding))),
+    };
+
+    let start = offset.unwrap_or(1).max(1);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).max(1);
+
+    let mut rows = Vec::new();
+    let mut output_bytes = 0;
+    let mut line_number = 0;
+    let mut more = false;
+    let mut line = Vec::new();
+
+    while let Some(truncated) = read_line(&mut reader, &mut line)? {
+        line_number += 1;
+        if line_number < start {
+            continue;
+        }
+        if rows.len() == limit || output_bytes > MAX_OUTPUT_BYTES {
+            more = true;
+            break;
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        let mut row = format!("{:>6}\t{}", line_number, text.trim_end_matches('\r'));
+        if truncated {
+            row.push_str(" [line truncated]");
+        }
+        output_bytes += row.len() + 1;
+        rows.push(row);
+    }
+
+    if rows.is_empty() {
+        return Ok(if line_number == 0 {
+            "[Empty file]".to_string()
+        } else {
+            format!("[Offset {} is past the end of the file ({} lines)]", start, line_number)
+        });
+    }
+
+    let mut output = rows.join("\n");
+    let mut notes = Vec::new();
+    if more {
+        let last = start + rows.len() - 1;
+        notes.push(format!("Showing lines {}-{}; use offset {} to read more", start, last, last + 1));
+    }
+    if encoding != Encoding::Utf8 {
+        notes.push(format!("Decoded from {}", encoding.name()));
+    }
+    if !notes.is_empty() {
+        output.push_str(&format!("\n\n[{}]", notes.join(". ")));
+    }
+
+    Ok(output)
+}
+
+/// Read one line into `line` without its newline, keeping at most
+/// `MAX_LINE_BYTES` of it. Returns whether the line was cut off, or `None` at
+/// the end of the file.
+fn read_line(reader: &mut dyn BufRead, line: &mut Vec<u8>) -> io::Result<Option<bool>> {
+    line.clear();
+    let mut truncated = false;
+    let mut read_any = false;
+
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return Ok(read_any.then_some(truncated));
+        }
+        read_any = true;
+
+        let newline = available.iter().position(|&b| b == b'\n');
+        let end = newline.unwrap_or(available.len());
+        let room = MAX_LINE_BYTES.saturating_sub(line.len());
+        if end > room {
+            truncated = true;
+        }
+        line.extend_from_slice(&available[..end.min(room)]);
+
+        match newline {
+            Some(i) => {
+                reader.consume(i + 1);
+                return Ok(Some(truncated));
+            }
+            None => reader.consume(end),
+        }
+    }
+}
+
+/// Transcodes UTF-16 or Latin-1 to UTF-8 as it is read
+struct Decoder<R> {
+    inner: R,
+    encoding: Encoding,
+    /// Decoded bytes not yet handed out
+    decoded: Vec<u8>,
+    position: usize,
+    /// Bytes held back until the next read: an odd trailing byte or a lone
+    /// high surrogate that may pair with the next unit
+    carry: Vec<u8>,
+}
+
+impl<R: Read> Decoder<R> {
+    fn new(inner: R, encoding: Encoding) -> Self {
+        Self {
+            inner,
+            encoding,
+            decoded: Vec::new(),
+            position: 0,
+            carry: Vec::new(),
+        }
+    }
+
+    fn decode_utf16(&mut self, bytes: &[u8], at_end: bool) {
+        let encoding = self.encoding;
+        let unit = |pair: &[u8]| match encoding {
+            Encoding::Utf16Be => u16::from_be_bytes([pair[0], pair[1]]),
+            _ => u16::from_le_bytes([pair[0], pair[1]]),
+        };
+        let mut units: Vec<u16> = bytes.chunks_exact(2).map(unit).collect();
+        let mut carry = bytes[bytes.len() - bytes.len() % 2..].to_vec();
+        if !at_end && units.last().is_some_and(|u| (0xD800..0xDC00).contains(u)) {
+            units.pop();
+            carry.splice(0..0, bytes[units.len() * 2..units.len() * 2 + 2].iter().copied());
+        }
+
+        let text: String = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        self.decoded.extend_from_slice(text.as_bytes());
+        self.carry = carry;
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.decoded.len() {
+            self.decoded.clear();
+            self.position = 0;
+
+            let mut chunk = std::mem::take(&mut self.carry);
+            let carried = chunk.len();
+            chunk.resize(carried + 8192, 0);
+            let n = self.inner.read(&mut chunk[carried..])?;
+            chunk.truncate(carried + n);
+            if chunk.is_empty() {
+                return Ok(0);
+            }
+
+            match self.encoding {
+                Encoding::Latin1 => {
+                    let text: String = chunk.iter().map(|&b| b as char).collect();
+                    self.decoded.extend_from_slice(text.as_bytes());
+                }
+                _ => {
+                    // A lone trailing byte at the end of the file can't be decoded
+                    if n == 0 && chunk.len() < 2 {
+                        return Ok(0);
+                    }
+                    self.decode_utf16(&chunk, n == 0);
+                }
+            }
+        }
+
+        let n = buf.len().min(self.decoded.len() - self.position);
+        buf[..n].copy_from_slice(&self.decoded[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Summary and a window of hex dump rows, read directly from the requested position
+fn hex_dump(
+    mut file: File,
+    head: &[u8],
+    size: u64,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> io::Result<String> {
+    let first_row = offset.unwrap_or(1).max(1) - 1;
+    let rows = limit.unwrap_or(DEFAULT_HEX_ROWS).clamp(1, MAX_HEX_ROWS);
+    let start = (first_row as u64).saturating_mul(HEX_ROW_BYTES as u64);
+
+    let mut output = format!(
+        "Binary file ({}, {}). Hex dump follows; offset and limit count rows of {} bytes.\n\n",
+        describe_binary(head),
+        format_size(size),
+        HEX_ROW_BYTES
+    );
+    if start >= size {
+        output.push_str(&format!("[Offset {} is past the end of the file]", first_row + 1));
+        return Ok(output);
+    }
+
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.take((rows * HEX_ROW_BYTES) as u64).read_to_end(&mut bytes)?;
+
+    let lines: Vec<String> = bytes
+        .chunks(HEX_ROW_BYTES)
         .enumerate()
-        .map(|(i, line)| format!("{:>6}\t{}", start + i + 1, line))
+        .map(|(i, row)| hex_row(start + (i * HEX_ROW_BYTES) as u64, row))
         .collect();
+    output.push_str(&lines.join("\n"));
+
+    let end = start + bytes.len() as u64;
+    if end < size {
+        output.push_str(&format!(
+            "\n\n[Showing bytes {}-{} of {}; use offset {} for more]",
+            start,
+            end - 1,
+            size,
+            first_row + lines.len() + 1
+        ));
+    }
 
-    Ok(result.join("\n"))
+    Ok(output)
 }
 
-fn resolve_path(path_str: &str, project_path: Option<&str>) -> Result<std::path::PathBuf, String> {
-    // Handle home directory expansion
-    let expanded_path = if let Some(stripped) = path_str.strip_prefix("~/") {
-        match dirs::home_dir() {
-            Some(home) => home.join(stripped),
-            None => return Err("Could not determine home directory".to_string()),
+/// One row in the style of `hexdump -C`
+fn hex_row(address: u64, bytes: &[u8]) -> String {
+    let mut hex = String::new();
+    for i in 0..HEX_ROW_BYTES {
+        match bytes.get(i) {
+            Some(b) => hex.push_str(&format!("{:02x} ", b)),
+            None => hex.push_str("   "),
         }
-    } else if path_str == "~" {
-        match dirs::home_dir() {
-            Some(home) => home,
-            None => return Err("Could not determine home directory".to_string()),
+        if i == HEX_ROW_BYTES / 2 - 1 {
+            hex.push(' ');
         }
+    }
+    let ascii: String = bytes
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect();
+    format!("{:08x}  {} |{}|", address, hex, ascii)
+}
+
+/// Name common binary formats by their magic bytes
+fn describe_binary(head: &[u8]) -> &'static str {
+    const FORMATS: &[(&[u8], &str)] = &[
+        (b"\x7fELF", "ELF executable"),
+        (b"MZ", "Windows executable"),
+        (b"\xcf\xfa\xed\xfe", "Mach-O binary"),
+        (b"\xca\xfe\xba\xbe", "Mach-O universal binary or Java class"),
+        (b"\0asm", "WebAssembly module"),
+        (b"%PDF", "PDF document"),
+        (b"PK\x03\x04", "ZIP archive"),
+        (b"\x1f\x8b", "gzip archive"),
+        (b"BZh", "bzip2 archive"),
+        (b"\xfd7zXZ\0", "xz archive"),
+        (b"7z\xbc\xaf\x27\x1c", "7-Zip archive"),
+        (b"\x28\xb5\x2f\xfd", "zstd archive"),
+        (b"SQLite format 3\0", "SQLite database"),
+        (b"ID3", "MP3 audio"),
+        (b"OggS", "Ogg media"),
+        (b"fLaC", "FLAC audio"),
+        (b"\x1a\x45\xdf\xa3", "Matroska/WebM video"),
+        (b"wOFF", "WOFF font"),
+        (b"wOF2", "WOFF2 font"),
+        (b"BM", "BMP image"),
+        (b"II*\0", "TIFF image"),
+        (b"MM\0*", "TIFF image"),
+    ];
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return "MP4/QuickTime media";
+    }
+    FORMATS
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+        .map(|(_, name)| *name)
+        .unwrap_or("unknown format")
+}
+
+/// MIME type of images that can be sent to a model
+fn image_type(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        Some("image/webp")
     } else {
-        std::path::PathBuf::from(path_str)
+        None
+    }
+}
+
+fn read_image(mut file: File, media_type: &'static str, size: u64) -> Result<FileContent, String> {
+    let format = media_type.trim_start_matches("image/").to_uppercase();
+
+    let description = |bytes: &[u8]| match image_dimensions(bytes, media_type) {
+        Some((width, height)) => format!("{} image, {}x{}, {}", format, width, height, format_size(size)),
+        None => format!("{} image, {}", format, format_size(size)),
     };
 
-    let path = expanded_path.as_path();
+    if size > MAX_IMAGE_BYTES {
+        let mut head = Vec::new();
+        file.seek(SeekFrom::Start(0))
+            .and_then(|_| (&mut file).take(SNIFF_BYTES).read_to_end(&mut head))
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        return Ok(FileContent::from(format!(
+            "{}. Too large to attach (limit {}).",
+            description(&head),
+            format_size(MAX_IMAGE_BYTES)
+        )));
+    }
 
-    if path.is_absolute() {
-        Ok(path.to_path_buf())
-    } else if let Some(project) = project_path {
-        Ok(Path::new(project).join(path))
-    } else {
-        // Use current directory as fallback
-        std::env::current_dir()
-            .map(|cwd| cwd.join(path))
-            .map_err(|e| format!("Failed to get current directory: {}", e))
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(0))
+        .and_then(|_| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    Ok(FileContent {
+        text: format!("{}. The image is attached.", description(&bytes)),
+        images: vec![ToolImage {
+            media_type: media_type.to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+        }],
+    })
+}
+
+/// Width and height from an image header
+fn image_dimensions(bytes: &[u8], media_type: &str) -> Option<(u32, u32)> {
+    let be16 = |i: usize| Some(u16::from_be_bytes([*bytes.get(i)?, *bytes.get(i + 1)?]) as u32);
+    let le16 = |i: usize| Some(u16::from_le_bytes([*bytes.get(i)?, *bytes.get(i + 1)?]) as u32);
+    let le24 = |i: usize| Some(le16(i)? | (*bytes.get(i + 2)? as u32) << 16);
+
+    match media_type {
+        "image/png" => {
+            let be32 = |i: usize| Some(u32::from_be_bytes(bytes.get(i..i + 4)?.try_into().ok()?));
+            Some((be32(16)?, be32(20)?))
+        }
+        "image/gif" => Some((le16(6)?, le16(8)?)),
+        "image/webp" => match bytes.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3FFF, le16(28)? & 0x3FFF)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(bytes.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        },
+        "image/jpeg" => {
+            // Walk the segments up to the start-of-frame marker
+            let mut i = 2;
+            loop {
+                if *bytes.get(i)? != 0xFF {
+                    return None;
+                }
+                let marker = *bytes.get(i + 1)?;
+                match marker {
+                    0xFF => i += 1,
+                    0x01 | 0xD0..=0xD9 => i += 2,
+                    0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                        return Some((be16(i + 7)?, be16(i + 5)?));
+                    }
+                    _ => i += 2 + be16(i + 2)? as usize,
+                }
+            }
+        }
+        _ => None,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::TempDir;
+
+    fn read_text(dir: &TempDir, input: serde_json::Value) -> String {
+        read(&input, &dir.resolver()).unwrap().text
+    }
+
+    #[test]
+    fn test_read_decodes_utf16_with_bom() {
+        let dir = TempDir::new("read");
+        let utf16: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain("héllo\r\nwörld 😀\r\n".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        dir.write("utf16.txt", utf16);
+
+        assert_eq!(
+            read_text(&dir, json!({ "path": "utf16.txt" })),
+            "     1\théllo\n     2\twörld 😀\n\n[Decoded from UTF-16LE]"
+        );
+    }
+
+    #[test]
+    fn test_read_falls_back_to_latin1() {
+        let dir = TempDir::new("read");
+        dir.write("latin1.txt", b"caf\xe9\nna\xefve\n");
+
+        assert!(read_text(&dir, json!({ "path": "latin1.txt", "offset": 2 })).starts_with("     2\tnaïve\n"));
+    }
 
     #[test]
-    fn test_resolve_path_home_expansion() {
-        // Test home directory expansion
-        let result = resolve_path("~/.kuse-cowork/test", None);
-        assert!(result.is_ok());
+    fn test_read_pages_with_offset_and_limit() {
+        let dir = TempDir::new("read");
+        let lines: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        dir.write("long.txt", lines);
 
-        let path = result.unwrap();
-        assert!(path.is_absolute());
-        assert!(path.to_string_lossy().contains(".kuse-cowork/test"));
-        assert!(!path.to_string_lossy().starts_with("~"));
+        assert_eq!(
+            read_text(&dir, json!({ "path": "long.txt", "offset": 4, "limit": 2 })),
+            "     4\tline 4\n     5\tline 5\n\n[Showing lines 4-5; use offset 6 to read more]"
+        );
     }
 
     #[test]
-    fn test_resolve_path_home_only() {
-        let result = resolve_path("~", None);
-        assert!(result.is_ok());
+    fn test_read_dumps_binary_files() {
+        let dir = TempDir::new("read");
+        dir.write("data.bin", b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0\x03\0");
 
-        let path = result.unwrap();
-        assert!(path.is_absolute());
+        let dump = read_text(&dir, json!({ "path": "data.bin" }));
+        assert!(dump.starts_with("Binary file (ELF executable, 18 B)"));
+        assert!(dump.contains("00000000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|"));
     }
 
     #[test]
-    fn test_resolve_path_absolute() {
-        let result = resolve_path("/tmp/test", None);
-        assert!(result.is_ok());
+    fn test_read_returns_images() {
+        let dir = TempDir::new("read");
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0, 3, 0, 0, 0, 2]);
+        dir.write("pixel.png", &png);
 
-        let path = result.unwrap();
-        assert_eq!(path.to_string_lossy(), "/tmp/test");
+        let image = read(&json!({ "path": "pixel.png" }), &dir.resolver()).unwrap();
+        assert!(image.text.starts_with("PNG image, 3x2"));
+        assert_eq!(image.images[0].media_type, "image/png");
     }
 }
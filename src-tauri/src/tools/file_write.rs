@@ -1,12 +1,12 @@
 use crate::agent::ToolDefinition;
+use crate::tools::paths::PathResolver;
 use serde_json::json;
 use std::fs;
-use std::path::Path;
 
 pub fn definition() -> ToolDefinition {
     ToolDefinition {
         name: "write_file".to_string(),
-        description: "Write content to a file. Creates the file if it doesn't exist, or overwrites if it does. Use for creating new files or complete rewrites.".to_string(),
+        description: "Write content to a file. Creates the file if it doesn't exist, or overwrites if it does. Use for creating new files or complete rewrites. Fails if the file changed on disk since you last read it; read it again first.".to_string(),
         input_schema: json!({
             "type": "object",
             "properties": {
@@ -26,7 +26,7 @@ pub fn definition() -> ToolDefinition {
 
 pub fn execute(
     input: &serde_json::Value,
-    project_path: Option<&str>,
+    paths: &PathResolver,
 ) -> Result<String, String> {
     let path_str = input
         .get("path")
@@ -39,7 +39,7 @@ pub fn execute(
         .ok_or("Missing 'content' parameter")?;
 
     // Resolve path
-    let path = resolve_path(path_str, project_path)?;
+    let path = paths.resolve(path_str)?;
 
     // Create parent directories if needed
     if let Some(parent) = path.parent() {
@@ -61,16 +61,3 @@ pub fn execute(
     ))
 }
 
-fn resolve_path(path_str: &str, project_path: Option<&str>) -> Result<std::path::PathBuf, String> {
-    let path = Path::new(path_str);
-
-    if path.is_absolute() {
-        Ok(path.to_path_buf())
-    } else if let Some(project) = project_path {
-        Ok(Path::new(project).join(path))
-    } else {
-        std::env::current_dir()
-            .map(|cwd| cwd.join(path))
-            .map_err(|e| format!("Failed to get current directory: {}", e))
-    }
-}
@@ -1,10 +1,12 @@
 use crate::agent::ToolDefinition;
+use crate::tools::paths::PathResolver;
+use crate::tools::walk::{self, WalkOptions};
 use serde_json::json;
 
 pub fn definition() -> ToolDefinition {
     ToolDefinition {
         name: "glob".to_string(),
-        description: "Find files matching a glob pattern. Returns a list of matching file paths. Use patterns like '**/*.rs' for recursive search or 'src/*.ts' for specific directories.".to_string(),
+        description: "Find files matching a glob pattern. Returns a list of matching file paths. Use patterns like '**/*.rs' for recursive search or 'src/*.ts' for specific directories. Files excluded by .gitignore/.ignore and hidden files are skipped unless asked.".to_string(),
         input_schema: json!({
             "type": "object",
             "properties": {
@@ -19,6 +21,18 @@ pub fn definition() -> ToolDefinition {
                 "limit": {
                     "type": "integer",
                     "description": "Maximum number of results to return (default: 100)"
+                },
+                "offset": {
+                    "type": "integer",
+                    "description": "Number of results to skip, for fetching the next page (default: 0)"
+                },
+                "hidden": {
+                    "type": "boolean",
+                    "description": "Also match hidden files and directories (default: false)"
+                },
+                "no_ignore": {
+                    "type": "boolean",
+                    "description": "Also match files excluded by .gitignore/.ignore (default: false)"
                 }
             },
             "required": ["pattern"]
@@ -28,73 +42,102 @@ pub fn definition() -> ToolDefinition {
 
 pub fn execute(
     input: &serde_json::Value,
-    project_path: Option<&str>,
+    paths: &PathResolver,
 ) -> Result<String, String> {
     let pattern = input
         .get("pattern")
         .and_then(|v| v.as_str())
         .ok_or("Missing 'pattern' parameter")?;
 
-    let base_path = input
+    let base_str = input
         .get("path")
         .and_then(|v| v.as_str())
-        .or(project_path)
         .unwrap_or(".");
 
     let limit = input
         .get("limit")
         .and_then(|v| v.as_u64())
-        .unwrap_or(100) as usize;
+        .unwrap_or(100)
+        .max(1) as usize;
+
+    let offset = input
+        .get("offset")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0) as usize;
 
-    // Construct full pattern
-    let full_pattern = if pattern.starts_with('/') || pattern.starts_with('.') {
-        pattern.to_string()
+    // Only walk the part of the tree named by the pattern's literal prefix
+    let (prefix, rest) = split_literal_prefix(pattern.trim_start_matches("./"));
+    let base_path = if prefix.starts_with('/') {
+        paths.resolve(&prefix)?
+    } else if prefix.is_empty() {
+        paths.resolve(base_str)?
     } else {
-        format!("{}/{}", base_path, pattern)
+        paths.resolve(&paths.resolve(base_str)?.join(&prefix).to_string_lossy())?
     };
 
-    // Use glob crate
-    let entries = glob::glob(&full_pattern)
-        .map_err(|e| format!("Invalid glob pattern: {}", e))?;
-
-    let mut results: Vec<String> = Vec::new();
-    let mut total_count = 0;
-
-    for entry in entries {
-        match entry {
-            Ok(path) => {
-                total_count += 1;
-                if results.len() < limit {
-                    // Make path relative to project if possible
-                    let display_path = if let Some(project) = project_path {
-                        path.strip_prefix(project)
-                            .map(|p| p.to_string_lossy().to_string())
-                            .unwrap_or_else(|_| path.to_string_lossy().to_string())
-                    } else {
-                        path.to_string_lossy().to_string()
-                    };
-                    results.push(display_path);
-                }
-            }
-            Err(_) => {
-                // Skip files we can't access
-                continue;
-            }
+    let matches: Vec<String> = if rest.is_empty() {
+        // No wildcards: the pattern names a single path
+        match paths.check(&base_path) {
+            Some(path) => vec![paths.display(&path)],
+            None => Vec::new(),
         }
+    } else if base_path.is_dir() {
+        let matcher = walk::glob_matcher(&rest)?;
+        let options = WalkOptions {
+            include_dirs: true,
+            ..WalkOptions::from_input(input)
+        };
+        walk::walk(&base_path, options, paths)
+            .into_iter()
+            .filter(|path| matcher.is_match(path.strip_prefix(&base_path).unwrap_or(path)))
+            .map(|path| paths.display(&path))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    if matches.is_empty() {
+        return Ok(format!("No files found matching pattern: {}", pattern));
     }
 
+    let total_count = matches.len();
+    let results: Vec<String> = matches.into_iter().skip(offset).take(limit).collect();
     if results.is_empty() {
-        return Ok(format!("No files found matching pattern: {}", pattern));
+        return Ok(format!("No results past offset {} ({} matches in total)", offset, total_count));
     }
 
     let mut output = results.join("\n");
 
-    if total_count > limit {
+    let shown_end = offset + results.len();
+    if offset > 0 || shown_end < total_count {
         output.push_str(&format!(
-            "\n\n[Showing {} of {} matches]",
-            limit, total_count
+            "\n\n[Showing {}-{} of {} matches",
+            offset + 1,
+            shown_end,
+            total_count
         ));
+        if shown_end < total_count {
+            output.push_str(&format!("; use offset {} for more", shown_end));
+        }
+        output.push(']');
     }
 
     Ok(output)
 }
+
+/// Split a pattern into its leading components without wildcards and the rest,
+/// e.g. "src/**/*.rs" into ("src", "**/*.rs")
+fn split_literal_prefix(pattern: &str) -> (String, String) {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let literal = components
+        .iter()
+        .take_while(|c| !c.contains(['*', '?', '[', '{']))
+        .count();
+
+    // An absolute pattern keeps its leading '/'
+    let mut prefix = components[..literal].join("/");
+    if prefix.is_empty() && pattern.starts_with('/') {
+        prefix = "/".to_string();
+    }
+    (prefix, components[literal..].join("/"))
+}
@@ -1,12 +1,15 @@
 use crate::agent::ToolDefinition;
+use crate::tools::paths::PathResolver;
+use crate::tools::walk::{self, WalkOptions};
+use globset::GlobMatcher;
 use serde_json::json;
-use std::fs;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 pub fn definition() -> ToolDefinition {
     ToolDefinition {
         name: "grep".to_string(),
-        description: "Search for a pattern in files. Returns matching lines with file paths and line numbers. Supports regex patterns.".to_string(),
+        description: "Search for a regex pattern in files. Skips binary files and anything excluded by .gitignore/.ignore (such as node_modules or target) unless asked. Output modes: 'content' (matching lines with file paths and line numbers, the default), 'files_with_matches' (paths only) or 'count' (matches per file). Results are paginated with limit and offset.".to_string(),
         input_schema: json!({
             "type": "object",
             "properties": {
@@ -20,7 +23,12 @@ pub fn definition() -> ToolDefinition {
                 },
                 "glob": {
                     "type": "string",
-                    "description": "File pattern to filter (e.g., '*.rs', '*.ts')"
+                    "description": "File pattern to filter (e.g., '*.rs', 'src/**/*.ts')"
+                },
+                "output_mode": {
+                    "type": "string",
+                    "enum": ["content", "files_with_matches", "count"],
+                    "description": "What to return (default: content)"
                 },
                 "case_insensitive": {
                     "type": "boolean",
@@ -28,11 +36,23 @@ pub fn definition() -> ToolDefinition {
                 },
                 "context": {
                     "type": "integer",
-                    "description": "Number of context lines before and after match (default: 0)"
+                    "description": "Number of context lines before and after each match in content mode (default: 0)"
                 },
                 "limit": {
                     "type": "integer",
-                    "description": "Maximum number of matches to return (default: 50)"
+                    "description": "Maximum number of results to return: matches in content mode, files otherwise (default: 50)"
+                },
+                "offset": {
+                    "type": "integer",
+                    "description": "Number of results to skip, for fetching the next page (default: 0)"
+                },
+                "hidden": {
+                    "type": "boolean",
+                    "description": "Also search hidden files and directories (default: false)"
+                },
+                "no_ignore": {
+                    "type": "boolean",
+                    "description": "Also search files excluded by .gitignore/.ignore (default: false)"
                 }
             },
             "required": ["pattern"]
@@ -40,9 +60,23 @@ pub fn definition() -> ToolDefinition {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputMode {
+    Content,
+    FilesWithMatches,
+    Count,
+}
+
+/// Matches found in one file
+struct FileMatches {
+    path: PathBuf,
+    /// 0-based numbers of matching lines
+    lines: Vec<usize>,
+}
+
 pub fn execute(
     input: &serde_json::Value,
-    project_path: Option<&str>,
+    paths: &PathResolver,
 ) -> Result<String, String> {
     let pattern = input
         .get("pattern")
@@ -52,13 +86,19 @@ pub fn execute(
     let search_path = input
         .get("path")
         .and_then(|v| v.as_str())
-        .or(project_path)
         .unwrap_or(".");
 
     let file_glob = input
         .get("glob")
         .and_then(|v| v.as_str());
 
+    let output_mode = match input.get("output_mode").and_then(|v| v.as_str()) {
+        None | Some("content") => OutputMode::Content,
+        Some("files_with_matches") => OutputMode::FilesWithMatches,
+        Some("count") => OutputMode::Count,
+        Some(other) => return Err(format!("Unknown output_mode: {}", other)),
+    };
+
     let case_insensitive = input
         .get("case_insensitive")
         .and_then(|v| v.as_bool())
@@ -72,126 +112,230 @@ pub fn execute(
     let limit = input
         .get("limit")
         .and_then(|v| v.as_u64())
-        .unwrap_or(50) as usize;
+        .unwrap_or(50)
+        .max(1) as usize;
 
-    // Build regex
-    let regex = if case_insensitive {
-        regex::RegexBuilder::new(pattern)
-            .case_insensitive(true)
-            .build()
-    } else {
-        regex::Regex::new(pattern)
-    }.map_err(|e| format!("Invalid regex pattern: {}", e))?;
+    let offset = input
+        .get("offset")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0) as usize;
 
-    let path = Path::new(search_path);
-    let mut results: Vec<String> = Vec::new();
-    let mut match_count = 0;
+    // Build regex
+    let regex = regex::RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|e| format!("Invalid regex pattern: {}", e))?;
 
-    if path.is_file() {
-        search_file(path, &regex, context, limit, &mut results, &mut match_count, project_path)?;
+    let path = paths.resolve(search_path)?;
+    let files = if path.is_file() {
+        vec![path.clone()]
     } else if path.is_dir() {
-        search_directory(path, &regex, file_glob, context, limit, &mut results, &mut match_count, project_path)?;
+        let matcher = file_glob.map(glob_matcher).transpose()?;
+        walk::walk(&path, WalkOptions::from_input(input), paths)
+            .into_iter()
+            .filter(|file| matches_glob(matcher.as_ref(), &path, file))
+            .collect()
     } else {
         return Err(format!("Path not found: {}", search_path));
+    };
+
+    let found: Vec<FileMatches> = walk::par_map(&files, |file| search_file(file, &regex))
+        .into_iter()
+        .flatten()
+        .collect();
+
+    if found.is_empty() {
+        return Ok(format!("No matches found for pattern: {}", pattern));
     }
 
+    let (results, total, unit) = match output_mode {
+        OutputMode::FilesWithMatches => {
+            let results = found.iter().map(|f| paths.display(&f.path)).skip(offset).take(limit).collect();
+            (results, found.len(), "files")
+        }
+        OutputMode::Count => {
+            let results = found
+                .iter()
+                .map(|f| format!("{}:{}", paths.display(&f.path), f.lines.len()))
+                .skip(offset)
+                .take(limit)
+                .collect();
+            (results, found.len(), "files")
+        }
+        OutputMode::Content => {
+            let total = found.iter().map(|f| f.lines.len()).sum();
+            (render_content(&found, offset, limit, context, paths), total, "matches")
+        }
+    };
+
     if results.is_empty() {
-        return Ok(format!("No matches found for pattern: {}", pattern));
+        return Ok(format!("No results past offset {} ({} {} in total)", offset, total, unit));
     }
 
     let mut output = results.join("\n");
 
-    if match_count > limit {
+    let shown_end = (offset + limit).min(total);
+    if offset > 0 || shown_end < total {
         output.push_str(&format!(
-            "\n\n[Showing {} of {} matches]",
-            limit, match_count
+            "\n\n[Showing {} {}-{} of {}",
+            unit,
+            offset + 1,
+            shown_end,
+            total
         ));
+        if shown_end < total {
+            output.push_str(&format!("; use offset {} for more", shown_end));
+        }
+        output.push(']');
     }
 
     Ok(output)
 }
 
-fn search_file(
-    path: &Path,
-    regex: &regex::Regex,
-    context: usize,
-    limit: usize,
-    results: &mut Vec<String>,
-    match_count: &mut usize,
-    project_path: Option<&str>,
-) -> Result<(), String> {
-    let content = match fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(_) => return Ok(()), // Skip binary or unreadable files
-    };
-
-    let lines: Vec<&str> = content.lines().collect();
-    let display_path = if let Some(project) = project_path {
-        path.strip_prefix(project)
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|_| path.to_string_lossy().to_string())
+/// File filter matched against the path relative to the search root. A
+/// pattern without '/' matches file names at any depth, as in ripgrep.
+fn glob_matcher(pattern: &str) -> Result<GlobMatcher, String> {
+    if pattern.contains('/') {
+        walk::glob_matcher(pattern.trim_start_matches("./"))
     } else {
-        path.to_string_lossy().to_string()
-    };
+        walk::glob_matcher(&format!("**/{}", pattern))
+    }
+}
 
-    for (i, line) in lines.iter().enumerate() {
+fn matches_glob(matcher: Option<&GlobMatcher>, root: &Path, file: &Path) -> bool {
+    match matcher {
+        Some(matcher) => matcher.is_match(file.strip_prefix(root).unwrap_or(file)),
+        None => true,
+    }
+}
+
+fn search_file(path: &Path, regex: &regex::Regex) -> Option<FileMatches> {
+    // Skip binary or unreadable files
+    let mut lines = Vec::new();
+    walk::for_each_line(path, |i, line| {
         if regex.is_match(line) {
-            *match_count += 1;
+            lines.push(i);
+        }
+        true
+    })?;
 
-            if results.len() < limit {
-                // Add context lines before
-                let start = i.saturating_sub(context);
-                for (j, line_content) in lines.iter().enumerate().take(i).skip(start) {
-                    results.push(format!("{}:{}: {}", display_path, j + 1, line_content));
-                }
+    (!lines.is_empty()).then(|| FileMatches {
+        path: path.to_path_buf(),
+        lines,
+    })
+}
+
+/// Render one page of matching lines, re-reading only the files on the page.
+/// Matches are marked with '>' and context lines with ':'; context that would
+/// repeat or overlap is merged into one block.
+fn render_content(
+    found: &[FileMatches],
+    offset: usize,
+    limit: usize,
+    context: usize,
+    paths: &PathResolver,
+) -> Vec<String> {
+    let mut results = Vec::new();
+    let mut skip = offset;
+    let mut remaining = limit;
 
-                // Add matching line
-                results.push(format!("{}:{}> {}", display_path, i + 1, line));
+    for file in found {
+        if remaining == 0 {
+            break;
+        }
+        if skip >= file.lines.len() {
+            skip -= file.lines.len();
+            continue;
+        }
 
-                // Add context lines after
-                let end = (i + context + 1).min(lines.len());
-                for (j, line_content) in lines.iter().enumerate().take(end).skip(i + 1) {
-                    results.push(format!("{}:{}: {}", display_path, j + 1, line_content));
-                }
+        let page: Vec<usize> = file.lines.iter().copied().skip(skip).take(remaining).collect();
+        skip = 0;
+        remaining -= page.len();
 
-                if context > 0 {
-                    results.push("---".to_string());
-                }
+        // Keep only the lines shown, and stop reading after the last one
+        let last = page[page.len() - 1] + context;
+        let mut lines = BTreeMap::new();
+        let read = walk::for_each_line(&file.path, |j, text| {
+            let next_match = page.partition_point(|&line| line + context < j);
+            if page.get(next_match).is_some_and(|&line| line <= j + context) {
+                lines.insert(j, text.to_string());
             }
+            j < last
+        });
+        if read.is_none() {
+            continue;
         }
-    }
+        let display_path = paths.display(&file.path);
 
-    Ok(())
-}
+        let mut printed_to = 0;
+        for &line in &page {
+            let start = line.saturating_sub(context).max(printed_to);
+            let end = (line + context + 1).max(start);
+            if context > 0 && start > printed_to && printed_to > 0 {
+                results.push("---".to_string());
+            }
 
-#[allow(clippy::too_many_arguments)]
-fn search_directory(
-    path: &Path,
-    regex: &regex::Regex,
-    file_glob: Option<&str>,
-    context: usize,
-    limit: usize,
-    results: &mut Vec<String>,
-    match_count: &mut usize,
-    project_path: Option<&str>,
-) -> Result<(), String> {
-    let glob_pattern = file_glob.unwrap_or("**/*");
-    let full_pattern = format!("{}/{}", path.to_string_lossy(), glob_pattern);
-
-    let entries = glob::glob(&full_pattern)
-        .map_err(|e| format!("Invalid glob pattern: {}", e))?;
-
-    for entry in entries {
-        if results.len() >= limit {
-            break;
+            // Lines missing from the map are past the end of the file
+            for (&j, text) in lines.range(start..end) {
+                let marker = if page.binary_search(&j).is_ok() { '>' } else { ':' };
+                results.push(format!("{}:{}{} {}", display_path, j + 1, marker, text));
+            }
+            printed_to = end;
         }
 
-        if let Ok(file_path) = entry {
-            if file_path.is_file() {
-                search_file(&file_path, regex, context, limit, results, match_count, project_path)?;
-            }
+        if context > 0 {
+            results.push("---".to_string());
         }
     }
 
-    Ok(())
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn project() -> TempDir {
+        let dir = TempDir::new("grep");
+        dir.write("src/a.rs", "todo one\nfine\ntodo two\n");
+        dir.write("src/b.rs", "todo three\n");
+        dir.write("src/c.bin", b"todo\0binary");
+        dir
+    }
+
+    #[test]
+    fn test_grep_lists_matching_text_files() {
+        let dir = project();
+        let files = execute(&json!({ "pattern": "todo", "output_mode": "files_with_matches" }), &dir.resolver()).unwrap();
+        assert_eq!(files, "src/a.rs\nsrc/b.rs");
+    }
+
+    #[test]
+    fn test_grep_counts_matches_in_globbed_files() {
+        let dir = project();
+        let count = execute(&json!({ "pattern": "todo", "output_mode": "count", "glob": "a.rs" }), &dir.resolver()).unwrap();
+        assert_eq!(count, "src/a.rs:2");
+    }
+
+    #[test]
+    fn test_grep_paginates_matches() {
+        let dir = project();
+        let page = execute(&json!({ "pattern": "todo", "limit": 2, "offset": 1 }), &dir.resolver()).unwrap();
+        assert!(page.starts_with("src/a.rs:3> todo two\nsrc/b.rs:1> todo three"));
+        assert!(page.ends_with("[Showing matches 2-3 of 3]"));
+    }
+
+    #[test]
+    fn test_grep_context_merges_overlapping_blocks() {
+        let dir = TempDir::new("grep-context");
+        dir.write("notes.txt", "a\ntodo 1\nb\ntodo 2\nc\nd\ne\ntodo 3\n");
+
+        let output = execute(&json!({ "pattern": "todo", "context": 1 }), &dir.resolver()).unwrap();
+        assert_eq!(
+            output,
+            "notes.txt:1: a\nnotes.txt:2> todo 1\nnotes.txt:3: b\nnotes.txt:4> todo 2\nnotes.txt:5: c\n---\n\
+             notes.txt:7: e\nnotes.txt:8> todo 3\n---"
+        );
+    }
 }
@@ -1,4 +1,5 @@
 use crate::agent::ToolDefinition;
+use crate::tools::paths::PathResolver;
 use serde_json::json;
 use std::fs;
 use std::path::Path;
@@ -30,12 +31,11 @@ pub fn definition() -> ToolDefinition {
 
 pub fn execute(
     input: &serde_json::Value,
-    project_path: Option<&str>,
+    paths: &PathResolver,
 ) -> Result<String, String> {
     let path_str = input
         .get("path")
         .and_then(|v| v.as_str())
-        .or(project_path)
         .unwrap_or(".");
 
     let recursive = input
@@ -48,7 +48,7 @@ pub fn execute(
         .and_then(|v| v.as_u64())
         .unwrap_or(3) as usize;
 
-    let path = resolve_path(path_str, project_path)?;
+    let path = paths.resolve(path_str)?;
 
     if !path.exists() {
         return Err(format!("Directory not found: {}", path_str));
@@ -161,7 +161,7 @@ fn list_recursive(
     Ok(())
 }
 
-fn format_size(size: u64) -> String {
+pub(crate) fn format_size(size: u64) -> String {
     if size < 1024 {
         format!("{} B", size)
     } else if size < 1024 * 1024 {
@@ -173,16 +173,3 @@ fn format_size(size: u64) -> String {
     }
 }
 
-fn resolve_path(path_str: &str, project_path: Option<&str>) -> Result<std::path::PathBuf, String> {
-    let path = Path::new(path_str);
-
-    if path.is_absolute() {
-        Ok(path.to_path_buf())
-    } else if let Some(project) = project_path {
-        Ok(Path::new(project).join(path))
-    } else {
-        std::env::current_dir()
-            .map(|cwd| cwd.join(path))
-            .map_err(|e| format!("Failed to get current directory: {}", e))
-    }
-}
@@ -0,0 +1,89 @@
+use crate::agent::ToolDefinition;
+use crate::mcp::MCPManager;
+use serde_json::json;
+
+/// Tools for reading MCP server resources, offered while a connected server has any
+pub fn get_resource_tools() -> Vec<ToolDefinition> {
+    vec![
+        ToolDefinition {
+            name: "list_mcp_resources".to_string(),
+            description: "List the resources (documents, files, data) that connected MCP servers expose, with their URIs. Also lists URI templates that can be filled in to form a resource URI.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "server_id": {
+                        "type": "string",
+                        "description": "Only list resources from this server"
+                    }
+                },
+                "required": []
+            }),
+        },
+        ToolDefinition {
+            name: "read_mcp_resource".to_string(),
+            description: "Read the contents of an MCP resource by its URI.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "server_id": {
+                        "type": "string",
+                        "description": "The server that exposes the resource"
+                    },
+                    "uri": {
+                        "type": "string",
+                        "description": "The resource URI, from list_mcp_resources or a filled-in template"
+                    }
+                },
+                "required": ["server_id", "uri"]
+            }),
+        },
+    ]
+}
+
+pub async fn execute(
+    tool_name: &str,
+    input: &serde_json::Value,
+    mcp_manager: &MCPManager,
+) -> Result<String, String> {
+    match tool_name {
+        "list_mcp_resources" => {
+            let server_id = input.get("server_id").and_then(|v| v.as_str());
+            let (resources, templates) = mcp_manager.get_all_resources().await;
+
+            let mut output = String::new();
+            for resource in resources.iter().filter(|r| server_id.is_none_or(|id| r.server_id == id)) {
+                output.push_str(&format!("[{}] {} - {}", resource.server_id, resource.uri, resource.name));
+                if let Some(description) = &resource.description {
+                    output.push_str(&format!(": {}", description));
+                }
+                output.push('\n');
+            }
+            for template in templates.iter().filter(|t| server_id.is_none_or(|id| t.server_id == id)) {
+                output.push_str(&format!("[{}] {} (template) - {}", template.server_id, template.uri_template, template.name));
+                if let Some(description) = &template.description {
+                    output.push_str(&format!(": {}", description));
+                }
+                output.push('\n');
+            }
+
+            if output.is_empty() {
+                Ok("No MCP resources available".to_string())
+            } else {
+                Ok(output)
+            }
+        }
+        "read_mcp_resource" => {
+            let server_id = input
+                .get("server_id")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing 'server_id' parameter")?;
+            let uri = input
+                .get("uri")
+                .and_then(|v| v.as_str())
+                .ok_or("Missing 'uri' parameter")?;
+
+            mcp_manager.read_resource(server_id, uri).await
+        }
+        _ => Err(format!("Unknown tool: {}", tool_name)),
+    }
+}
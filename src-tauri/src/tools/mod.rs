@@ -1,3 +1,4 @@
+pub mod apply_patch;
 pub mod bash;
 pub mod docker;
 pub mod file_delete;
@@ -8,6 +9,11 @@ pub mod file_write;
 pub mod glob;
 pub mod grep;
 pub mod list_dir;
+pub mod mcp_resources;
+pub mod paths;
+pub mod sandbox;
+pub mod shell_session;
+pub mod walk;
 
 use crate::agent::ToolDefinition;
 
@@ -17,6 +23,7 @@ pub fn get_all_tools() -> Vec<ToolDefinition> {
         file_read::definition(),
         file_write::definition(),
         file_edit::definition(),
+        apply_patch::definition(),
         bash::definition(),
         glob::definition(),
         grep::definition(),
@@ -25,6 +32,9 @@ pub fn get_all_tools() -> Vec<ToolDefinition> {
         file_delete::definition(),
     ];
 
+    // Add persistent shell session tools
+    tools.extend(shell_session::get_shell_tools());
+
     // Add Docker tools
     tools.extend(docker::get_docker_tools());
 
@@ -0,0 +1,260 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Maximum number of symlinks followed while resolving a single path
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// Resolves path arguments for the filesystem tools.
+///
+/// Paths are expanded (`~`), made absolute against the project root and
+/// canonicalized, following symlinks even when the final target does not
+/// exist yet. When confinement is enabled and a project root is set, the
+/// resolved path must stay inside the project root or one of the extra
+/// allowed roots.
+#[derive(Debug, Clone, Default)]
+pub struct PathResolver {
+    project_root: Option<PathBuf>,
+    allowed_roots: Vec<PathBuf>,
+    confine: bool,
+}
+
+impl PathResolver {
+    pub fn new(project_path: Option<&str>) -> Self {
+        let project_root = project_path
+            .filter(|p| !p.is_empty())
+            .and_then(|p| expand_home(p).ok())
+            .map(|p| canonicalize(&p).unwrap_or(p));
+
+        Self {
+            project_root,
+            allowed_roots: Vec::new(),
+            confine: false,
+        }
+    }
+
+    /// Restrict resolved paths to the project root (and extra allowed roots)
+    pub fn with_confinement(mut self, confine: bool) -> Self {
+        self.confine = confine;
+        self
+    }
+
+    /// Allow access to an additional directory tree outside the project root
+    pub fn with_allowed_root(mut self, root: impl AsRef<Path>) -> Self {
+        if let Ok(expanded) = expand_home(&root.as_ref().to_string_lossy()) {
+            let root = canonicalize(&expanded).unwrap_or(expanded);
+            self.allowed_roots.push(root);
+        }
+        self
+    }
+
+    /// Canonical project root, if any
+    pub fn project_root(&self) -> Option<&Path> {
+        self.project_root.as_deref()
+    }
+
+    /// Resolve a user-supplied path, enforcing confinement
+    pub fn resolve(&self, path_str: &str) -> Result<PathBuf, String> {
+        let expanded = expand_home(path_str)?;
+
+        let absolute = if expanded.is_absolute() {
+            expanded
+        } else if let Some(root) = &self.project_root {
+            root.join(expanded)
+        } else {
+            std::env::current_dir()
+                .map(|cwd| cwd.join(expanded))
+                .map_err(|e| format!("Failed to get current directory: {}", e))?
+        };
+
+        let resolved = canonicalize(&absolute)?;
+
+        if !self.is_allowed(&resolved) {
+            return Err(format!(
+                "Access denied: '{}' resolves to '{}', which is outside the project directory{}",
+                path_str,
+                resolved.display(),
+                self.project_root
+                    .as_ref()
+                    .map(|r| format!(" '{}'", r.display()))
+                    .unwrap_or_default()
+            ));
+        }
+
+        Ok(resolved)
+    }
+
+    /// Check a path found while walking the filesystem (e.g. a glob match).
+    /// Returns the canonical path if it is accessible under the current policy.
+    pub fn check(&self, path: &Path) -> Option<PathBuf> {
+        let resolved = canonicalize(path).ok()?;
+        self.is_allowed(&resolved).then_some(resolved)
+    }
+
+    /// Whether a canonical path is accessible under the current policy
+    pub fn is_allowed(&self, path: &Path) -> bool {
+        let root = match (&self.project_root, self.confine) {
+            (Some(root), true) => root,
+            _ => return true,
+        };
+
+        path.starts_with(root) || self.allowed_roots.iter().any(|r| path.starts_with(r))
+    }
+
+    /// Format a path for tool output, relative to the project root when possible
+    pub fn display(&self, path: &Path) -> String {
+        match &self.project_root {
+            Some(root) => path
+                .strip_prefix(root)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|_| path.to_string_lossy().to_string()),
+            None => path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+/// Expand a leading `~` to the home directory
+fn expand_home(path_str: &str) -> Result<PathBuf, String> {
+    if let Some(stripped) = path_str.strip_prefix("~/") {
+        dirs::home_dir()
+            .map(|home| home.join(stripped))
+            .ok_or_else(|| "Could not determine home directory".to_string())
+    } else if path_str == "~" {
+        dirs::home_dir().ok_or_else(|| "Could not determine home directory".to_string())
+    } else {
+        Ok(PathBuf::from(path_str))
+    }
+}
+
+/// Canonicalize a path component by component, following symlinks.
+/// Unlike `fs::canonicalize`, the trailing components do not need to exist,
+/// so paths for files about to be created resolve to where they will land.
+fn canonicalize(path: &Path) -> Result<PathBuf, String> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .map_err(|e| format!("Failed to get current directory: {}", e))?
+    };
+
+    canonicalize_with_depth(&absolute, 0)
+}
+
+fn canonicalize_with_depth(path: &Path, depth: usize) -> Result<PathBuf, String> {
+    if depth > MAX_SYMLINK_DEPTH {
+        return Err(format!("Too many levels of symbolic links: {}", path.display()));
+    }
+
+    // `resolved` is always a real (symlink-free) path, so popping it for `..` is safe
+    let mut resolved = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => resolved.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                let candidate = resolved.join(name);
+                match fs::symlink_metadata(&candidate) {
+                    Ok(meta) if meta.file_type().is_symlink() => {
+                        let target = fs::read_link(&candidate)
+                            .map_err(|e| format!("Failed to read symlink {}: {}", candidate.display(), e))?;
+                        let target = if target.is_absolute() {
+                            target
+                        } else {
+                            resolved.join(target)
+                        };
+                        resolved = canonicalize_with_depth(&target, depth + 1)?;
+                    }
+                    _ => resolved = candidate,
+                }
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    /// A project with a `src` directory, and its symlink-free path
+    fn temp_project() -> (TempDir, PathBuf) {
+        let dir = TempDir::new("paths");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        (dir, root)
+    }
+
+    #[test]
+    fn test_resolve_path_home_expansion() {
+        let result = PathResolver::new(None).resolve("~/.kuse-cowork/test");
+        assert!(result.is_ok());
+
+        let path = result.unwrap();
+        assert!(path.is_absolute());
+        assert!(path.to_string_lossy().contains(".kuse-cowork/test"));
+        assert!(!path.to_string_lossy().starts_with("~"));
+    }
+
+    #[test]
+    fn test_resolve_path_home_only() {
+        let result = PathResolver::new(None).resolve("~");
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_absolute());
+    }
+
+    #[test]
+    fn test_resolve_path_absolute() {
+        let result = PathResolver::new(None).resolve("/tmp/test");
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), canonicalize(Path::new("/tmp")).unwrap().join("test"));
+    }
+
+    #[test]
+    fn test_resolve_relative_to_project() {
+        let (_dir, root) = temp_project();
+        let resolver = PathResolver::new(Some(root.to_str().unwrap())).with_confinement(true);
+
+        assert_eq!(resolver.resolve("src/new.rs").unwrap(), root.join("src/new.rs"));
+        assert_eq!(resolver.resolve("src/../main.rs").unwrap(), root.join("main.rs"));
+    }
+
+    #[test]
+    fn test_confinement_rejects_escapes() {
+        let (_dir, root) = temp_project();
+        let resolver = PathResolver::new(Some(root.to_str().unwrap())).with_confinement(true);
+
+        assert!(resolver.resolve("../outside.txt").is_err());
+        assert!(resolver.resolve("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_paths_outside_project_resolve_without_confinement() {
+        let (_dir, root) = temp_project();
+        let resolver = PathResolver::new(Some(root.to_str().unwrap()));
+
+        assert!(resolver.resolve("../outside.txt").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_confinement_rejects_symlink_escape() {
+        let (_dir, root) = temp_project();
+        let (_outside_dir, outside) = temp_project();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+        let resolver = PathResolver::new(Some(root.to_str().unwrap())).with_confinement(true);
+        assert!(resolver.resolve("link/secret.txt").is_err());
+
+        let allowed = resolver.with_allowed_root(&outside);
+        assert_eq!(
+            allowed.resolve("link/secret.txt").unwrap(),
+            outside.join("secret.txt")
+        );
+    }
+}
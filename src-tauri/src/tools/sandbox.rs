@@ -0,0 +1,293 @@
+//! Sandbox mode: a task's bash commands run in one long-lived Docker container
+//! instead of on the host. The project is mounted at /workspace and skills at
+//! /skills. Files, installed packages, the working directory and exported
+//! variables carry over from one command to the next.
+
+use crate::tools::bash::{self, CollectedOutput, OutputCallback};
+use crate::tools::docker::pull_image_if_needed;
+use bollard::container::{
+    Config, CreateContainerOptions, ListContainersOptions, LogOutput, RemoveContainerOptions,
+    StartContainerOptions,
+};
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::models::HostConfig;
+use bollard::Docker;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Image used when settings don't name one
+pub const DEFAULT_IMAGE: &str = "python:3.11-alpine";
+
+/// Where the project is mounted in the container
+pub const WORKSPACE: &str = "/workspace";
+
+/// Label marking sandbox containers, with the task ID as its value
+const SANDBOX_LABEL: &str = "kuse-cowork.sandbox";
+
+/// Runs each command in a shell that first restores the environment and
+/// directory the previous command left, and saves them afterwards. The
+/// command comes in $KUSE_COMMAND so it needs no quoting.
+const COMMAND_WRAPPER: &str = r#"mkdir -p /tmp/.kuse-sandbox
+echo $$ > "/tmp/.kuse-sandbox/$KUSE_EXEC.pid"
+[ -f /tmp/.kuse-sandbox/env ] && . /tmp/.kuse-sandbox/env
+cd "${KUSE_CWD:-$(cat /tmp/.kuse-sandbox/cwd 2>/dev/null || echo /workspace)}" || exit 1
+__kuse_command=$KUSE_COMMAND
+unset KUSE_COMMAND KUSE_CWD KUSE_EXEC
+eval "$__kuse_command"
+__kuse_status=$?
+pwd > /tmp/.kuse-sandbox/cwd
+export -p > /tmp/.kuse-sandbox/env
+exit $__kuse_status"#;
+
+/// Kills the shell running a command and everything it started
+const KILL_SCRIPT: &str = r#"kill_tree() {
+  for child in $(cat /proc/$1/task/*/children 2>/dev/null); do kill_tree "$child"; done
+  kill -9 "$1" 2>/dev/null
+}
+pid=$(cat "/tmp/.kuse-sandbox/$1.pid" 2>/dev/null) && kill_tree "$pid""#;
+
+/// A running sandbox container, removed with `stop` when the task ends
+pub struct Sandbox {
+    docker: Docker,
+    container_id: String,
+    /// Host directory mounted at /workspace
+    project_path: Option<String>,
+}
+
+impl Sandbox {
+    /// Create and start the sandbox container for a task
+    pub async fn start(task_id: &str, image: &str, project_path: Option<&str>) -> Result<Self, String> {
+        let docker = Docker::connect_with_local_defaults()
+            .map_err(|e| format!("Failed to connect to Docker: {}. Make sure Docker Desktop is running.", e))?;
+
+        // A previous run of the task may have left its container behind
+        let name = format!("kuse-sandbox-{}", task_id);
+        let _ = docker
+            .remove_container(&name, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+            .await;
+
+        pull_image_if_needed(&docker, image).await?;
+
+        let mut binds = Vec::new();
+        if let Some(path) = project_path {
+            binds.push(format!("{}:{}", path, WORKSPACE));
+        }
+        let skills_dir = crate::skills::ensure_skills_directory();
+        binds.push(format!("{}:/skills:ro", skills_dir.display()));
+
+        let config = Config {
+            image: Some(image.to_string()),
+            // Keep the container alive; commands run through exec
+            cmd: Some(vec!["tail".to_string(), "-f".to_string(), "/dev/null".to_string()]),
+            working_dir: Some(WORKSPACE.to_string()),
+            labels: Some(HashMap::from([(SANDBOX_LABEL.to_string(), task_id.to_string())])),
+            host_config: Some(HostConfig {
+                binds: Some(binds),
+                // Reap background processes that commands leave behind
+                init: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let options = CreateContainerOptions { name: name.as_str(), platform: None };
+        let container = docker
+            .create_container(Some(options), config)
+            .await
+            .map_err(|e| format!("Failed to create sandbox container: {}", e))?;
+
+        let sandbox = Self {
+            docker,
+            container_id: container.id,
+            project_path: project_path.map(String::from),
+        };
+        if let Err(e) = sandbox
+            .docker
+            .start_container(&sandbox.container_id, None::<StartContainerOptions<String>>)
+            .await
+        {
+            sandbox.stop().await;
+            return Err(format!("Failed to start sandbox container: {}", e));
+        }
+
+        Ok(sandbox)
+    }
+
+    /// Run a bash tool call in the container. Takes the same input as
+    /// `bash::execute` and formats its output the same way.
+    pub async fn execute(
+        &self,
+        input: &serde_json::Value,
+        cancel: &CancellationToken,
+        on_output: Option<OutputCallback>,
+    ) -> Result<String, String> {
+        let (command, cwd, timeout_secs) = bash::parse_input(input)?;
+        bash::check_blocked(command)?;
+
+        let exec_name = uuid::Uuid::new_v4().simple().to_string();
+        let mut env = vec![
+            format!("KUSE_COMMAND={}", command),
+            format!("KUSE_EXEC={}", exec_name),
+        ];
+        if let Some(cwd) = cwd {
+            env.push(format!("KUSE_CWD={}", container_path(self.project_path.as_deref(), cwd)));
+        }
+
+        let exec = self
+            .docker
+            .create_exec(
+                &self.container_id,
+                CreateExecOptions {
+                    cmd: Some(vec!["sh".to_string(), "-c".to_string(), COMMAND_WRAPPER.to_string()]),
+                    env: Some(env),
+                    working_dir: Some(WORKSPACE.to_string()),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| format!("Failed to run command in sandbox: {}", e))?;
+
+        let StartExecResults::Attached { mut output, .. } = self
+            .docker
+            .start_exec(&exec.id, None)
+            .await
+            .map_err(|e| format!("Failed to run command in sandbox: {}", e))?
+        else {
+            return Err("Sandbox command started detached".to_string());
+        };
+
+        let mut stdout = CollectedOutput::default();
+        let mut stderr = CollectedOutput::default();
+        let deadline = tokio::time::sleep(Duration::from_secs(timeout_secs));
+        tokio::pin!(deadline);
+
+        let finished = loop {
+            let chunk = tokio::select! {
+                chunk = output.next() => chunk,
+                _ = &mut deadline => break false,
+                _ = cancel.cancelled() => break false,
+            };
+
+            let (stream, buffer, message) = match chunk {
+                Some(Ok(LogOutput::StdOut { message })) => ("stdout", &mut stdout, message),
+                Some(Ok(LogOutput::StdErr { message })) => ("stderr", &mut stderr, message),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    stderr.push(format!("\n[Output error: {}]", e).as_bytes());
+                    break true;
+                }
+                None => break true,
+            };
+            if let Some(callback) = &on_output {
+                callback(stream, &String::from_utf8_lossy(&message));
+            }
+            buffer.push(&message);
+        };
+
+        let (stdout, stderr) = (stdout.to_text(), stderr.to_text());
+        if !finished {
+            self.kill(&exec_name).await;
+            return Err(bash::stopped_error(&stdout, &stderr, cancel, timeout_secs));
+        }
+
+        let exit_code = self
+            .docker
+            .inspect_exec(&exec.id)
+            .await
+            .ok()
+            .and_then(|inspect| inspect.exit_code)
+            .unwrap_or(-1);
+        Ok(bash::format_output(&stdout, &stderr, exit_code))
+    }
+
+    /// Stop a command that timed out or was cancelled. Exec has no kill, so
+    /// its process tree is killed from a second exec.
+    async fn kill(&self, exec_name: &str) {
+        let options = CreateExecOptions {
+            cmd: Some(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                KILL_SCRIPT.to_string(),
+                "kill".to_string(),
+                exec_name.to_string(),
+            ]),
+            ..Default::default()
+        };
+        if let Ok(exec) = self.docker.create_exec(&self.container_id, options).await {
+            if let Ok(StartExecResults::Attached { mut output, .. }) = self.docker.start_exec(&exec.id, None).await {
+                while output.next().await.is_some() {}
+            }
+        }
+    }
+
+    /// Remove the container and everything left in it outside the mounts
+    pub async fn stop(&self) {
+        let _ = self
+            .docker
+            .remove_container(&self.container_id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+            .await;
+    }
+}
+
+/// Map a host path inside the project to its place under /workspace
+fn container_path(project_path: Option<&str>, path: &str) -> String {
+    let relative = project_path.and_then(|project| Path::new(path).strip_prefix(project).ok());
+    match relative {
+        Some(relative) if relative.as_os_str().is_empty() => WORKSPACE.to_string(),
+        Some(relative) => format!("{}/{}", WORKSPACE, relative.to_string_lossy().replace('\\', "/")),
+        None => path.to_string(),
+    }
+}
+
+/// Remove sandbox containers of runs that never got to clean up, e.g. when
+/// the app quit mid-task
+pub async fn remove_stale() {
+    let Ok(docker) = Docker::connect_with_local_defaults() else {
+        return;
+    };
+
+    let options = ListContainersOptions {
+        all: true,
+        filters: HashMap::from([("label".to_string(), vec![SANDBOX_LABEL.to_string()])]),
+        ..Default::default()
+    };
+    let Ok(containers) = docker.list_containers(Some(options)).await else {
+        return;
+    };
+
+    for id in containers.into_iter().filter_map(|c| c.id) {
+        let _ = docker
+            .remove_container(&id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_path_maps_project_root() {
+        assert_eq!(container_path(Some("/home/me/project"), "/home/me/project"), "/workspace");
+    }
+
+    #[test]
+    fn test_container_path_maps_paths_inside_project() {
+        assert_eq!(
+            container_path(Some("/home/me/project"), "/home/me/project/src/lib"),
+            "/workspace/src/lib"
+        );
+    }
+
+    #[test]
+    fn test_container_path_keeps_paths_outside_project() {
+        assert_eq!(container_path(Some("/home/me/project"), "/home/me/other"), "/home/me/other");
+        assert_eq!(container_path(Some("/home/me/project"), "/home/me/project-old"), "/home/me/project-old");
+        assert_eq!(container_path(None, "/tmp"), "/tmp");
+    }
+}
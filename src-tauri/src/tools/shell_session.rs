@@ -0,0 +1,739 @@
+use crate::agent::ToolDefinition;
+use crate::tools::bash::{check_blocked, kill_process_group, truncate_output};
+use serde_json::json;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+const DEFAULT_SESSION: &str = "default";
+
+/// Maximum characters of output returned to the model per call
+const MAX_OUTPUT_CHARS: usize = 50000;
+
+/// Unread output kept in memory per background process; older output is dropped
+const MAX_BUFFERED_BYTES: usize = 1024 * 1024;
+
+/// Maximum time `shell_poll` may wait for new output
+const MAX_POLL_WAIT_SECS: u64 = 30;
+
+pub fn get_shell_tools() -> Vec<ToolDefinition> {
+    vec![
+        ToolDefinition {
+            name: "shell_exec".to_string(),
+            description: "Run a command in a persistent named shell session. Unlike 'bash', the working directory and environment (cd, export, activated virtualenvs) are kept between calls to the same session.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": "The shell command to execute"
+                    },
+                    "session": {
+                        "type": "string",
+                        "description": "Session name (default: 'default'). A new session starts in the project directory."
+                    },
+                    "timeout": {
+                        "type": "integer",
+                        "description": "Timeout in seconds (default: 60, max: 300). The session is restarted if the command times out."
+                    }
+                },
+                "required": ["command"]
+            }),
+        },
+        ToolDefinition {
+            name: "shell_start".to_string(),
+            description: "Start a long-running background process (dev server, watcher, etc.) and return immediately. Use shell_poll to read its output, shell_stdin to send input and shell_kill to stop it.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": "The shell command to run in the background"
+                    },
+                    "name": {
+                        "type": "string",
+                        "description": "Optional ID for the process (default: generated)"
+                    },
+                    "session": {
+                        "type": "string",
+                        "description": "Start in the working directory and environment of this shell session (optional)"
+                    }
+                },
+                "required": ["command"]
+            }),
+        },
+        ToolDefinition {
+            name: "shell_poll".to_string(),
+            description: "Read new output from a background process started with shell_start, and whether it is still running.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "type": "string",
+                        "description": "The background process ID"
+                    },
+                    "wait": {
+                        "type": "integer",
+                        "description": "Seconds to wait for new output if none is available yet (default: 0, max: 30)"
+                    }
+                },
+                "required": ["id"]
+            }),
+        },
+        ToolDefinition {
+            name: "shell_stdin".to_string(),
+            description: "Send input to the stdin of a background process. Include a trailing newline to submit a line.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "type": "string",
+                        "description": "The background process ID"
+                    },
+                    "input": {
+                        "type": "string",
+                        "description": "Text to write to stdin"
+                    },
+                    "close": {
+                        "type": "boolean",
+                        "description": "Close stdin after writing (sends EOF)"
+                    }
+                },
+                "required": ["id"]
+            }),
+        },
+        ToolDefinition {
+            name: "shell_kill".to_string(),
+            description: "Stop a background process, or close a shell session, by ID or session name.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "type": "string",
+                        "description": "Background process ID or shell session name"
+                    }
+                },
+                "required": ["id"]
+            }),
+        },
+    ]
+}
+
+/// A persistent `sh` process. Commands are written to its stdin followed by
+/// a unique marker line that reports the exit code, so output can be read
+/// back per command while cwd and environment persist in the shell.
+struct ShellSession {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl ShellSession {
+    async fn spawn(cwd: Option<&str>) -> Result<Self, String> {
+        let mut cmd = Command::new("sh");
+        if let Some(dir) = cwd {
+            cmd.current_dir(dir);
+        }
+        cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::null());
+        cmd.kill_on_drop(true);
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Failed to start shell session: {}", e))?;
+        let mut stdin = child.stdin.take().ok_or("Shell session has no stdin")?;
+        let stdout = child.stdout.take().ok_or("Shell session has no stdout")?;
+
+        // Send everything (including the shell's own errors) through stdout
+        stdin
+            .write_all(b"exec 2>&1\n")
+            .await
+            .map_err(|e| format!("Failed to initialize shell session: {}", e))?;
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    /// Run a command and return its combined output and exit code.
+    /// On error (timeout, cancellation, shell exit) the session must be discarded.
+    async fn run(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<(String, i32), String> {
+        let marker = format!("__KUSE_DONE_{}__", uuid::Uuid::new_v4().simple());
+        // Braces keep cd/export in the current shell; stdin is detached so the
+        // command can't swallow the marker line
+        let script = format!(
+            "{{ {}\n}} < /dev/null\nprintf '\\n%s %s\\n' '{}' \"$?\"\n",
+            command, marker
+        );
+
+        self.stdin
+            .write_all(script.as_bytes())
+            .await
+            .map_err(|_| "Shell session has exited".to_string())?;
+        self.stdin
+            .flush()
+            .await
+            .map_err(|_| "Shell session has exited".to_string())?;
+
+        let mut output = Vec::new();
+        let outcome = tokio::select! {
+            result = read_until_marker(&mut self.stdout, &marker, &mut output) => result,
+            _ = tokio::time::sleep(timeout) => Err(format!(
+                "Command timed out after {} seconds; the session was restarted",
+                timeout.as_secs()
+            )),
+            _ = cancel.cancelled() => Err("Command cancelled".to_string()),
+        };
+
+        let mut text = String::from_utf8_lossy(&output).to_string();
+        match outcome {
+            Ok(exit_code) => {
+                // Drop the newline printed before the marker
+                if text.ends_with('\n') {
+                    text.pop();
+                }
+                Ok((text, exit_code))
+            }
+            Err(e) if text.is_empty() => Err(e),
+            Err(e) => Err(format!(
+                "{}. Output so far:\n{}",
+                e,
+                truncate_output(&text, MAX_OUTPUT_CHARS)
+            )),
+        }
+    }
+
+    fn kill(&mut self) {
+        if let Some(pid) = self.child.id() {
+            kill_process_group(pid);
+        }
+        let _ = self.child.start_kill();
+    }
+}
+
+async fn read_until_marker(
+    stdout: &mut BufReader<ChildStdout>,
+    marker: &str,
+    output: &mut Vec<u8>,
+) -> Result<i32, String> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = stdout
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| format!("Failed to read shell output: {}", e))?;
+        if read == 0 {
+            return Err("Shell session exited; it will be restarted on the next call".to_string());
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        if let Some(code) = text.trim_end().strip_prefix(marker) {
+            return Ok(code.trim().parse().unwrap_or(-1));
+        }
+        output.extend_from_slice(&line);
+    }
+}
+
+/// Output of a background process that has not been polled yet
+#[derive(Default)]
+struct OutputBuffer {
+    unread: Vec<u8>,
+    dropped: usize,
+}
+
+impl OutputBuffer {
+    fn push(&mut self, bytes: &[u8]) {
+        self.unread.extend_from_slice(bytes);
+        if self.unread.len() > MAX_BUFFERED_BYTES {
+            let excess = self.unread.len() - MAX_BUFFERED_BYTES;
+            self.unread.drain(..excess);
+            self.dropped += excess;
+        }
+    }
+
+    fn take(&mut self) -> (String, usize) {
+        let text = String::from_utf8_lossy(&self.unread).to_string();
+        self.unread.clear();
+        (text, std::mem::take(&mut self.dropped))
+    }
+}
+
+struct BackgroundProcess {
+    command: String,
+    pid: Option<u32>,
+    stdin: Mutex<Option<ChildStdin>>,
+    output: Arc<StdMutex<OutputBuffer>>,
+    /// `None` while running, then the exit code (-1 if killed by a signal)
+    exit_code: Arc<StdMutex<Option<i32>>>,
+}
+
+impl BackgroundProcess {
+    fn status(&self) -> String {
+        match *self.exit_code.lock().unwrap() {
+            None => "running".to_string(),
+            Some(code) => format!("exited with code {}", code),
+        }
+    }
+}
+
+struct SessionHandle {
+    pid: Option<u32>,
+    session: Arc<Mutex<ShellSession>>,
+}
+
+/// Named shell sessions and background processes owned by a `ToolExecutor`.
+/// Everything still running is killed when the owner is dropped.
+pub struct ShellSessions {
+    project_path: Option<String>,
+    sessions: StdMutex<HashMap<String, SessionHandle>>,
+    processes: StdMutex<HashMap<String, Arc<BackgroundProcess>>>,
+    next_process_id: AtomicUsize,
+}
+
+impl ShellSessions {
+    pub fn new(project_path: Option<String>) -> Self {
+        Self {
+            project_path,
+            sessions: StdMutex::new(HashMap::new()),
+            processes: StdMutex::new(HashMap::new()),
+            next_process_id: AtomicUsize::new(1),
+        }
+    }
+
+    pub async fn execute(
+        &self,
+        tool_name: &str,
+        input: &serde_json::Value,
+        cancel: &CancellationToken,
+    ) -> Result<String, String> {
+        match tool_name {
+            "shell_exec" => self.exec(input, cancel).await,
+            "shell_start" => self.start(input, cancel).await,
+            "shell_poll" => self.poll(input, cancel).await,
+            "shell_stdin" => self.write_stdin(input).await,
+            "shell_kill" => self.kill(input),
+            _ => Err(format!("Unknown tool: {}", tool_name)),
+        }
+    }
+
+    async fn exec(&self, input: &serde_json::Value, cancel: &CancellationToken) -> Result<String, String> {
+        let command = input
+            .get("command")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing 'command' parameter")?;
+
+        let name = input
+            .get("session")
+            .and_then(|v| v.as_str())
+            .unwrap_or(DEFAULT_SESSION);
+
+        let timeout_secs = input
+            .get("timeout")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(60)
+            .min(300);
+
+        check_blocked(command)?;
+
+        let (output, exit_code) = self
+            .run_in_session(name, command, Duration::from_secs(timeout_secs), cancel)
+            .await?;
+
+        let mut result = output;
+        if exit_code != 0 {
+            result.push_str(&format!("\n[exit code: {}]", exit_code));
+        }
+
+        if result.is_empty() {
+            return Ok("[Command completed with no output]".to_string());
+        }
+
+        Ok(truncate_output(&result, MAX_OUTPUT_CHARS))
+    }
+
+    /// Run a command in a session, starting it if needed and discarding it on failure
+    async fn run_in_session(
+        &self,
+        name: &str,
+        command: &str,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<(String, i32), String> {
+        let session = self.get_or_start_session(name).await?;
+        let mut guard = session.lock().await;
+
+        let result = guard.run(command, timeout, cancel).await;
+        if result.is_err() {
+            guard.kill();
+            let mut sessions = self.sessions.lock().unwrap();
+            if sessions
+                .get(name)
+                .is_some_and(|handle| Arc::ptr_eq(&handle.session, &session))
+            {
+                sessions.remove(name);
+            }
+        }
+        result
+    }
+
+    async fn get_or_start_session(&self, name: &str) -> Result<Arc<Mutex<ShellSession>>, String> {
+        if let Some(handle) = self.sessions.lock().unwrap().get(name) {
+            return Ok(handle.session.clone());
+        }
+
+        let session = ShellSession::spawn(self.project_path.as_deref()).await?;
+        let handle = SessionHandle {
+            pid: session.child.id(),
+            session: Arc::new(Mutex::new(session)),
+        };
+
+        // Another call may have started the same session meanwhile; keep the first
+        let mut sessions = self.sessions.lock().unwrap();
+        Ok(sessions.entry(name.to_string()).or_insert(handle).session.clone())
+    }
+
+    async fn start(&self, input: &serde_json::Value, cancel: &CancellationToken) -> Result<String, String> {
+        let command = input
+            .get("command")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing 'command' parameter")?;
+
+        check_blocked(command)?;
+
+        let id = match input.get("name").and_then(|v| v.as_str()) {
+            Some(name) => name.to_string(),
+            None => format!("proc-{}", self.next_process_id.fetch_add(1, Ordering::Relaxed)),
+        };
+
+        if let Some(existing) = self.processes.lock().unwrap().get(&id) {
+            if existing.exit_code.lock().unwrap().is_none() {
+                return Err(format!("A background process named '{}' is already running", id));
+            }
+        }
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+
+        // Inherit the session's working directory and environment when asked
+        match input.get("session").and_then(|v| v.as_str()) {
+            Some(session) => {
+                let (cwd, env) = self.session_snapshot(session, cancel).await?;
+                cmd.current_dir(cwd);
+                cmd.env_clear();
+                cmd.envs(env);
+            }
+            None => {
+                if let Some(dir) = &self.project_path {
+                    cmd.current_dir(dir);
+                }
+            }
+        }
+
+        cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Failed to start background process: {}", e))?;
+
+        let output = Arc::new(StdMutex::new(OutputBuffer::default()));
+        let exit_code = Arc::new(StdMutex::new(None));
+
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(pump_output(stdout, output.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(pump_output(stderr, output.clone()));
+        }
+
+        let process = Arc::new(BackgroundProcess {
+            command: command.to_string(),
+            pid: child.id(),
+            stdin: Mutex::new(child.stdin.take()),
+            output,
+            exit_code: exit_code.clone(),
+        });
+
+        tokio::spawn(async move {
+            let code = match child.wait().await {
+                Ok(status) => status.code().unwrap_or(-1),
+                Err(_) => -1,
+            };
+            *exit_code.lock().unwrap() = Some(code);
+        });
+
+        let pid = process.pid;
+        self.processes.lock().unwrap().insert(id.clone(), process);
+
+        Ok(format!(
+            "Started background process '{}'{}. Use shell_poll to read its output.",
+            id,
+            pid.map(|p| format!(" (pid {})", p)).unwrap_or_default()
+        ))
+    }
+
+    /// Working directory and environment of a shell session
+    async fn session_snapshot(
+        &self,
+        name: &str,
+        cancel: &CancellationToken,
+    ) -> Result<(String, Vec<(String, String)>), String> {
+        let timeout = Duration::from_secs(10);
+        let (cwd, _) = self.run_in_session(name, "pwd", timeout, cancel).await?;
+        let (env, _) = self.run_in_session(name, "env", timeout, cancel).await?;
+        Ok((cwd.trim().to_string(), parse_env(&env)))
+    }
+
+    async fn poll(&self, input: &serde_json::Value, cancel: &CancellationToken) -> Result<String, String> {
+        let process = self.get_process(input)?;
+
+        let wait_secs = input
+            .get("wait")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0)
+            .min(MAX_POLL_WAIT_SECS);
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(wait_secs);
+        while process.output.lock().unwrap().unread.is_empty()
+            && process.exit_code.lock().unwrap().is_none()
+            && tokio::time::Instant::now() < deadline
+            && !cancel.is_cancelled()
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        // Read the status first so output produced right before exit is included
+        let status = process.status();
+        let (output, dropped) = process.output.lock().unwrap().take();
+
+        let mut result = format!("[{}: {}]\n", process.command, status);
+        if dropped > 0 {
+            result.push_str(&format!("[{} bytes of older output were dropped]\n", dropped));
+        }
+        if output.is_empty() {
+            result.push_str("[no new output]");
+        } else {
+            result.push_str(&truncate_output(&output, MAX_OUTPUT_CHARS));
+        }
+
+        Ok(result)
+    }
+
+    async fn write_stdin(&self, input: &serde_json::Value) -> Result<String, String> {
+        let process = self.get_process(input)?;
+
+        let text = input.get("input").and_then(|v| v.as_str()).unwrap_or("");
+        let close = input.get("close").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        let mut stdin = process.stdin.lock().await;
+        let pipe = stdin.as_mut().ok_or("stdin of this process is closed")?;
+
+        if !text.is_empty() {
+            pipe.write_all(text.as_bytes())
+                .await
+                .map_err(|e| format!("Failed to write to stdin: {}", e))?;
+            pipe.flush()
+                .await
+                .map_err(|e| format!("Failed to write to stdin: {}", e))?;
+        }
+
+        if close {
+            *stdin = None;
+            return Ok(format!("Wrote {} bytes and closed stdin", text.len()));
+        }
+
+        Ok(format!("Wrote {} bytes to stdin", text.len()))
+    }
+
+    fn kill(&self, input: &serde_json::Value) -> Result<String, String> {
+        let id = input
+            .get("id")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing 'id' parameter")?;
+
+        if let Some(process) = self.processes.lock().unwrap().remove(id) {
+            if let Some(pid) = process.pid {
+                if process.exit_code.lock().unwrap().is_none() {
+                    kill_process_group(pid);
+                }
+            }
+            let (output, _) = process.output.lock().unwrap().take();
+            let mut result = format!("Stopped background process '{}'", id);
+            if !output.is_empty() {
+                result.push_str("\nFinal output:\n");
+                result.push_str(&truncate_output(&output, MAX_OUTPUT_CHARS));
+            }
+            return Ok(result);
+        }
+
+        if let Some(handle) = self.sessions.lock().unwrap().remove(id) {
+            if let Some(pid) = handle.pid {
+                kill_process_group(pid);
+            }
+            return Ok(format!("Closed shell session '{}'", id));
+        }
+
+        Err(self.unknown_id(id))
+    }
+
+    fn get_process(&self, input: &serde_json::Value) -> Result<Arc<BackgroundProcess>, String> {
+        let id = input
+            .get("id")
+            .and_then(|v| v.as_str())
+            .ok_or("Missing 'id' parameter")?;
+
+        self.processes
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| self.unknown_id(id))
+    }
+
+    fn unknown_id(&self, id: &str) -> String {
+        let mut known: Vec<String> = self.processes.lock().unwrap().keys().cloned().collect();
+        known.extend(self.sessions.lock().unwrap().keys().map(|s| format!("{} (session)", s)));
+
+        if known.is_empty() {
+            format!("No background process or session named '{}'", id)
+        } else {
+            known.sort();
+            format!(
+                "No background process or session named '{}'. Known: {}",
+                id,
+                known.join(", ")
+            )
+        }
+    }
+}
+
+impl Drop for ShellSessions {
+    fn drop(&mut self) {
+        if let Ok(processes) = self.processes.lock() {
+            for process in processes.values() {
+                if let (Some(pid), Ok(None)) = (process.pid, process.exit_code.lock().as_deref()) {
+                    kill_process_group(pid);
+                }
+            }
+        }
+        if let Ok(sessions) = self.sessions.lock() {
+            for pid in sessions.values().filter_map(|h| h.pid) {
+                kill_process_group(pid);
+            }
+        }
+    }
+}
+
+async fn pump_output(mut stream: impl AsyncRead + Unpin, output: Arc<StdMutex<OutputBuffer>>) {
+    let mut buf = [0u8; 8192];
+    loop {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => output.lock().unwrap().push(&buf[..n]),
+        }
+    }
+}
+
+/// Parse `env` output; lines that don't start a new variable continue the previous value
+fn parse_env(output: &str) -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+
+    for line in output.lines() {
+        let new_var = line.split_once('=').filter(|(key, _)| {
+            !key.is_empty()
+                && !key.starts_with(|c: char| c.is_ascii_digit())
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+
+        match (new_var, vars.last_mut()) {
+            (Some((key, value)), _) => vars.push((key.to_string(), value.to_string())),
+            (None, Some((_, value))) => {
+                value.push('\n');
+                value.push_str(line);
+            }
+            (None, None) => {}
+        }
+    }
+
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_multiline_values() {
+        let vars = parse_env("PATH=/usr/bin\nMULTI=first\nsecond\nEMPTY=\n");
+        assert_eq!(
+            vars,
+            vec![
+                ("PATH".to_string(), "/usr/bin".to_string()),
+                ("MULTI".to_string(), "first\nsecond".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_session_keeps_cwd_and_env() {
+        let shells = ShellSessions::new(None);
+        let cancel = CancellationToken::new();
+
+        shells
+            .execute("shell_exec", &json!({ "command": "cd /tmp && export KUSE_TEST=42" }), &cancel)
+            .await
+            .unwrap();
+        let output = shells
+            .execute("shell_exec", &json!({ "command": "pwd; echo $KUSE_TEST" }), &cancel)
+            .await
+            .unwrap();
+
+        assert!(output.contains("tmp"));
+        assert!(output.ends_with("42"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_background_process_poll_and_stdin() {
+        let shells = ShellSessions::new(None);
+        let cancel = CancellationToken::new();
+
+        shells
+            .execute("shell_start", &json!({ "command": "cat", "name": "echo" }), &cancel)
+            .await
+            .unwrap();
+        shells
+            .execute("shell_stdin", &json!({ "id": "echo", "input": "hello\n", "close": true }), &cancel)
+            .await
+            .unwrap();
+        let output = shells
+            .execute("shell_poll", &json!({ "id": "echo", "wait": 5 }), &cancel)
+            .await
+            .unwrap();
+
+        assert!(output.contains("hello"));
+    }
+}
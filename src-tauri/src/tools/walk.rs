@@ -0,0 +1,225 @@
+//! Directory walking shared by the search tools. Honours `.gitignore`,
+//! `.ignore` and git excludes, skips hidden entries unless asked, and walks
+//! on several threads.
+
+use crate::tools::paths::PathResolver;
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::{WalkBuilder, WalkState};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Bytes inspected when deciding whether a file is binary
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+/// Buffer size for reading files line by line
+const READ_BUFFER_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WalkOptions {
+    /// Include hidden files and directories (`.git` is always skipped)
+    pub hidden: bool,
+    /// Ignore `.gitignore`, `.ignore` and git exclude files
+    pub no_ignore: bool,
+    /// Yield directories as well as files
+    pub include_dirs: bool,
+}
+
+impl WalkOptions {
+    /// Read the `hidden` and `no_ignore` flags of a tool input
+    pub fn from_input(input: &serde_json::Value) -> Self {
+        let flag = |name: &str| input.get(name).and_then(|v| v.as_bool()).unwrap_or(false);
+        Self {
+            hidden: flag("hidden"),
+            no_ignore: flag("no_ignore"),
+            include_dirs: false,
+        }
+    }
+}
+
+/// Paths under `root`, sorted. Symlinks are listed when their target is a
+/// file (or directory, with `include_dirs`) the resolver allows.
+pub fn walk(root: &Path, options: WalkOptions, paths: &PathResolver) -> Vec<PathBuf> {
+    let found = Mutex::new(Vec::new());
+
+    WalkBuilder::new(root)
+        .hidden(!options.hidden)
+        .ignore(!options.no_ignore)
+        .git_ignore(!options.no_ignore)
+        .git_global(!options.no_ignore)
+        .git_exclude(!options.no_ignore)
+        .parents(!options.no_ignore)
+        // Respect .gitignore files even outside a git checkout
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build_parallel()
+        .run(|| {
+            let found = &found;
+            Box::new(move |entry| {
+                let Ok(entry) = entry else {
+                    // Unreadable entries are skipped
+                    return WalkState::Continue;
+                };
+                if entry.depth() == 0 {
+                    return WalkState::Continue;
+                }
+
+                let Some(file_type) = entry.file_type() else {
+                    return WalkState::Continue;
+                };
+                let keep = if file_type.is_symlink() {
+                    // Links may point outside the allowed roots
+                    paths.check(entry.path()).is_some_and(|target| {
+                        target.is_file() || (options.include_dirs && target.is_dir())
+                    })
+                } else {
+                    file_type.is_file() || (options.include_dirs && file_type.is_dir())
+                };
+
+                if keep {
+                    found.lock().unwrap().push(entry.into_path());
+                }
+                WalkState::Continue
+            })
+        });
+
+    let mut found = found.into_inner().unwrap();
+    found.sort();
+    found
+}
+
+/// Compile a glob where `*` stays within one path component and `**` crosses them
+pub fn glob_matcher(pattern: &str) -> Result<GlobMatcher, String> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| format!("Invalid glob pattern: {}", e))
+}
+
+/// Whether content looks binary: a NUL byte near the start, as git and ripgrep check
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0)
+}
+
+/// Call `f` with each line of a text file and its 0-based index, reading the
+/// file a buffer at a time so large files aren't held in memory. Stops early
+/// when `f` returns false. Returns `None` for binary or unreadable files.
+/// Invalid UTF-8 is replaced rather than rejected.
+pub fn for_each_line(path: &Path, mut f: impl FnMut(usize, &str) -> bool) -> Option<()> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut reader = BufReader::with_capacity(READ_BUFFER_BYTES, file);
+    if is_binary(reader.fill_buf().ok()?) {
+        return None;
+    }
+
+    let mut line = Vec::new();
+    for index in 0.. {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).ok()? == 0 {
+            break;
+        }
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        if !f(index, &String::from_utf8_lossy(text)) {
+            break;
+        }
+    }
+    Some(())
+}
+
+/// Run `f` over `items` on a few threads, keeping results in input order
+pub fn par_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(items.len().max(1));
+    let chunk_size = items.len().div_ceil(threads).max(1);
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(|| chunk.iter().map(&f).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn project() -> TempDir {
+        let dir = TempDir::new("walk");
+        dir.write(".gitignore", "node_modules/\n");
+        dir.write("node_modules/pkg/index.js", "x");
+        dir.write("src/main.rs", "fn main() {}");
+        dir.write(".env", "KEY=1");
+        dir
+    }
+
+    fn names(dir: &TempDir, options: WalkOptions) -> Vec<String> {
+        let paths = dir.resolver();
+        let root = paths.resolve(".").unwrap();
+        walk(&root, options, &paths).iter().map(|p| paths.display(p)).collect()
+    }
+
+    #[test]
+    fn test_walk_skips_ignored_and_hidden_files() {
+        let dir = project();
+        assert_eq!(names(&dir, WalkOptions::default()), vec!["src/main.rs"]);
+    }
+
+    #[test]
+    fn test_walk_includes_ignored_and_hidden_files_when_asked() {
+        let dir = project();
+        let everything = names(&dir, WalkOptions { hidden: true, no_ignore: true, include_dirs: false });
+        assert!(everything.contains(&"node_modules/pkg/index.js".to_string()));
+        assert!(everything.contains(&".env".to_string()));
+    }
+
+    #[test]
+    fn test_is_binary_looks_for_nul() {
+        assert!(is_binary(b"PNG\0\x01"));
+        assert!(!is_binary("plain text".as_bytes()));
+    }
+
+    #[test]
+    fn test_for_each_line_strips_line_endings() {
+        let dir = TempDir::new("walk-lines");
+        let path = dir.write("text.txt", "one\r\ntwo\nthree");
+
+        let mut lines = Vec::new();
+        for_each_line(&path, |i, line| {
+            lines.push((i, line.to_string()));
+            true
+        })
+        .unwrap();
+        assert_eq!(lines, vec![(0, "one".into()), (1, "two".into()), (2, "three".into())]);
+    }
+
+    #[test]
+    fn test_for_each_line_stops_when_asked() {
+        let dir = TempDir::new("walk-lines");
+        let path = dir.write("text.txt", "one\ntwo\n");
+
+        let mut seen = 0;
+        for_each_line(&path, |_, _| {
+            seen += 1;
+            false
+        })
+        .unwrap();
+        assert_eq!(seen, 1);
+    }
+
+    #[test]
+    fn test_for_each_line_skips_binary_files() {
+        let dir = TempDir::new("walk-lines");
+        let path = dir.write("data.bin", b"one\0two");
+        assert!(for_each_line(&path, |_, _| true).is_none());
+    }
+}
@@ -0,0 +1,304 @@
+//! Export of conversations and tasks as Markdown or a lossless JSON bundle,
+//! and import of bundles as new records.
+
+use crate::agent::{AgentContent, ContentBlock};
+use crate::database::{Conversation, Database, Message, Task, TaskMessage};
+use serde::{Deserialize, Serialize};
+
+/// Bundle format version, bumped on incompatible changes. Version 2 added
+/// message parents, so conversation bundles hold every branch.
+const BUNDLE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            other => Err(format!("Unknown export format: {}", other)),
+        }
+    }
+}
+
+/// Everything needed to recreate a conversation or task
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TranscriptBundle {
+    Conversation {
+        version: u32,
+        conversation: Conversation,
+        messages: Vec<Message>,
+    },
+    Task {
+        version: u32,
+        task: Task,
+        messages: Vec<TaskMessage>,
+    },
+}
+
+/// The record created by an import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedTranscript {
+    /// "conversation" or "task"
+    pub kind: String,
+    pub id: String,
+}
+
+pub fn export_conversation(db: &Database, id: &str, format: ExportFormat) -> Result<String, String> {
+    let conversation = db
+        .get_conversation(id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Conversation not found: {}", id))?;
+    match format {
+        ExportFormat::Json => to_json(&TranscriptBundle::Conversation {
+            version: BUNDLE_VERSION,
+            messages: db.get_all_messages(id).map_err(|e| e.to_string())?,
+            conversation,
+        }),
+        ExportFormat::Markdown => {
+            // The readable transcript follows the branch being shown
+            let messages = db.get_messages(id).map_err(|e| e.to_string())?;
+            let mut output = format!("# {}\n\n", conversation.title);
+            output.push_str(&format!("_Created {}_\n", format_time(conversation.created_at)));
+            for message in &messages {
+                push_message(&mut output, &message.role, &message.content, message.timestamp);
+            }
+            Ok(output)
+        }
+    }
+}
+
+pub fn export_task(db: &Database, id: &str, format: ExportFormat) -> Result<String, String> {
+    let task = db
+        .get_task(id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Task not found: {}", id))?;
+    let messages = db.get_task_messages(id).map_err(|e| e.to_string())?;
+
+    match format {
+        ExportFormat::Json => to_json(&TranscriptBundle::Task {
+            version: BUNDLE_VERSION,
+            task,
+            messages,
+        }),
+        ExportFormat::Markdown => {
+            let mut output = format!("# {}\n\n{}\n\n", task.title, task.description);
+            output.push_str(&format!("- **Status:** {}\n", task.status));
+            if let Some(path) = &task.project_path {
+                output.push_str(&format!("- **Project:** `{}`\n", path));
+            }
+            output.push_str(&format!("- **Created:** {}\n", format_time(task.created_at)));
+
+            if let Some(plan) = &task.plan {
+                output.push_str("\n## Plan\n\n");
+                for step in plan {
+                    let mark = match step.status.as_str() {
+                        "completed" => "x",
+                        "running" => "~",
+                        "failed" => "!",
+                        _ => " ",
+                    };
+                    output.push_str(&format!("- [{}] {}. {} ({})\n", mark, step.step, step.description, step.status));
+                }
+            }
+
+            output.push_str("\n## Transcript\n");
+            for message in &messages {
+                push_message(&mut output, &message.role, &message.content, message.timestamp);
+            }
+            Ok(output)
+        }
+    }
+}
+
+/// Recreate the records in a JSON bundle under fresh IDs
+pub fn import(db: &Database, bundle_json: &str) -> Result<ImportedTranscript, String> {
+    let bundle: TranscriptBundle =
+        serde_json::from_str(bundle_json).map_err(|e| format!("Invalid transcript bundle: {}", e))?;
+
+    let version = match &bundle {
+        TranscriptBundle::Conversation { version, .. } | TranscriptBundle::Task { version, .. } => *version,
+    };
+    if version > BUNDLE_VERSION {
+        return Err(format!(
+            "Transcript bundle version {} is newer than this app supports ({})",
+            version, BUNDLE_VERSION
+        ));
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let kind = match bundle {
+        TranscriptBundle::Conversation { conversation, messages, .. } => {
+            db.import_conversation(&id, &conversation, &messages)
+                .map_err(|e| e.to_string())?;
+            "conversation"
+        }
+        TranscriptBundle::Task { task, messages, .. } => {
+            db.import_task(&id, &task, &messages).map_err(|e| e.to_string())?;
+            "task"
+        }
+    };
+
+    Ok(ImportedTranscript {
+        kind: kind.to_string(),
+        id,
+    })
+}
+
+fn to_json(bundle: &TranscriptBundle) -> Result<String, String> {
+    serde_json::to_string_pretty(bundle).map_err(|e| e.to_string())
+}
+
+fn format_time(timestamp_millis: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(timestamp_millis)
+        .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// A code fence longer than any run of backticks in `text`
+fn fence_for(text: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in text.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    "`".repeat((longest + 1).max(3))
+}
+
+fn push_code(output: &mut String, language: &str, text: &str) {
+    let fence = fence_for(text);
+    output.push_str(&format!("{}{}\n{}\n{}\n\n", fence, language, text.trim_end(), fence));
+}
+
+/// Render one stored message. Content is plain text or JSON-encoded `AgentContent`.
+fn push_message(output: &mut String, role: &str, content: &str, timestamp: i64) {
+    let content = serde_json::from_str::<AgentContent>(content)
+        .unwrap_or_else(|_| AgentContent::Text(content.to_string()));
+
+    // Tool results are stored as user messages but come from the tools
+    let heading = match (&content, role) {
+        (AgentContent::ToolResults(_), _) => "Tool results",
+        (_, "user") => "User",
+        (_, "assistant") => "Assistant",
+        (_, other) => other,
+    };
+    output.push_str(&format!("\n### {} · {}\n\n", heading, format_time(timestamp)));
+
+    match content {
+        AgentContent::Text(text) => {
+            output.push_str(text.trim_end());
+            output.push_str("\n\n");
+        }
+        AgentContent::Blocks(blocks) => {
+            for block in blocks {
+                match block {
+                    ContentBlock::Text { text } => {
+                        output.push_str(text.trim_end());
+                        output.push_str("\n\n");
+                    }
+                    ContentBlock::ToolUse { name, input, .. } => {
+                        output.push_str(&format!("**Tool call:** `{}`\n\n", name));
+                        let input = serde_json::to_string_pretty(&input).unwrap_or_default();
+                        push_code(output, "json", &input);
+                    }
+                }
+            }
+        }
+        AgentContent::ToolResults(results) => {
+            for result in results {
+                let label = if result.is_error == Some(true) { "Error" } else { "Result" };
+                output.push_str(&format!("**{}** (`{}`)\n\n", label, result.tool_use_id));
+                push_code(output, "", &result.content);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::PlanStep;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn test_markdown_renders_tool_blocks() {
+        let blocks = r#"[{"type":"text","text":"Listing files"},{"type":"tool_use","id":"t1","name":"bash","input":{"command":"ls"}}]"#;
+        let results = r#"[{"type":"tool_result","tool_use_id":"t1","content":"a.txt\n```b```"}]"#;
+
+        let mut output = String::new();
+        push_message(&mut output, "assistant", blocks, 0);
+        push_message(&mut output, "user", results, 0);
+        push_message(&mut output, "user", "plain question", 0);
+
+        assert!(output.contains("### Assistant"));
+        assert!(output.contains("**Tool call:** `bash`\n\n```json\n{\n  \"command\": \"ls\"\n}\n```"));
+        assert!(output.contains("### Tool results"));
+        assert!(output.contains("````\na.txt\n```b```\n````"));
+        assert!(output.contains("### User"));
+        assert!(output.contains("plain question"));
+    }
+
+    /// A running task with a plan and messages, exported as JSON and
+    /// imported again. Returns the database and the two task IDs.
+    fn round_trip_task(dir: &TempDir) -> (Database, String, String) {
+        let db = Database::open(&dir.join("kuse-cowork.db")).unwrap();
+
+        db.create_task("t1", "Fix bug", "Fix the login bug", Some("/project")).unwrap();
+        let plan = vec![
+            PlanStep { step: 1, description: "Find it".to_string(), status: "completed".to_string() },
+            PlanStep { step: 2, description: "Fix it".to_string(), status: "running".to_string() },
+        ];
+        db.update_task_plan("t1", &plan).unwrap();
+        db.add_task_message("m1", "t1", "user", "Fix the login bug").unwrap();
+        db.add_task_message("m2", "t1", "assistant", r#"[{"type":"text","text":"On it"}]"#).unwrap();
+
+        let json = export_task(&db, "t1", ExportFormat::Json).unwrap();
+        let imported = import(&db, &json).unwrap();
+        assert_eq!(imported.kind, "task");
+        (db, "t1".to_string(), imported.id)
+    }
+
+    #[test]
+    fn test_task_json_round_trip_keeps_plan() {
+        let dir = TempDir::new("transcript");
+        let (db, original, copy) = round_trip_task(&dir);
+
+        let original = db.get_task(&original).unwrap().unwrap();
+        let copy = db.get_task(&copy).unwrap().unwrap();
+        assert_eq!(copy.title, original.title);
+        assert_eq!(copy.description, original.description);
+        assert_eq!(copy.project_path, original.project_path);
+        assert_eq!(serde_json::to_value(&copy.plan).unwrap(), serde_json::to_value(&original.plan).unwrap());
+    }
+
+    #[test]
+    fn test_task_json_round_trip_keeps_messages() {
+        let dir = TempDir::new("transcript");
+        let (db, original, copy) = round_trip_task(&dir);
+
+        let messages = |id: &str| -> Vec<(String, String, i64)> {
+            db.get_task_messages(id)
+                .unwrap()
+                .into_iter()
+                .map(|m| (m.role, m.content, m.timestamp))
+                .collect()
+        };
+        assert_eq!(messages(&copy), messages(&original));
+    }
+
+    #[test]
+    fn test_imported_running_task_is_interrupted() {
+        let dir = TempDir::new("transcript");
+        let (db, original, copy) = round_trip_task(&dir);
+
+        // Nothing runs the imported copy, so it can only be resumed
+        assert_eq!(db.get_task(&original).unwrap().unwrap().status, "running");
+        assert_eq!(db.get_task(&copy).unwrap().unwrap().status, "interrupted");
+    }
+}
@@ -1,9 +1,11 @@
 import { Component, Show, createSignal, onMount, onCleanup } from "solid-js";
 import { useSettings, loadSettings } from "./stores/settings";
 import { useI18n } from "./stores/i18n";
-import { Task, TaskMessage, AgentEvent, listTasks, createTask, deleteTask, runTaskAgent, getTask, getTaskMessages } from "./lib/tauri-api";
+import { Task, TaskMessage, AgentEvent, SecretsStatus, listTasks, createTask, deleteTask, runTaskAgent, resumeTaskAgent, cancelTaskAgent, getTask, getTaskMessages, getSecretsStatus } from "./lib/tauri-api";
 import AgentMain from "./components/AgentMain";
+import { ApprovalRequest } from "./components/ApprovalPrompt";
 import Settings from "./components/Settings";
+import SecretsUnlock from "./components/SecretsUnlock";
 import SkillsList from "./components/SkillsList";
 import MCPSettings from "./components/MCPSettings";
 import TaskSidebar from "./components/TaskSidebar";
@@ -12,16 +14,23 @@ import "./styles/global.css"; // Ensure this is imported for layout & resize sty
 
 interface ToolExecution {
   id: number;
+  toolUseId?: string;
   tool: string;
+  output?: string;
   status: "running" | "completed" | "error";
 }
 
+// Live output kept per tool call; older output is dropped from the front
+const MAX_TOOL_OUTPUT = 4000;
+
 const App: Component = () => {
   const { showSettings, toggleSettings, isLoading } = useSettings();
   const { locale } = useI18n();
 
   // UI state
   const [showSkills, setShowSkills] = createSignal(false);
+  // Set while the stored API keys can't be decrypted
+  const [lockedSecrets, setLockedSecrets] = createSignal<SecretsStatus | null>(null);
   const [showMCP, setShowMCP] = createSignal(false);
   const [isSidebarCollapsed, setIsSidebarCollapsed] = createSignal(false);
 
@@ -40,6 +49,7 @@ const App: Component = () => {
   const [isRunning, setIsRunning] = createSignal(false);
   const [toolExecutions, setToolExecutions] = createSignal<ToolExecution[]>([]);
   const [currentText, setCurrentText] = createSignal("");
+  const [pendingApprovals, setPendingApprovals] = createSignal<ApprovalRequest[]>([]);
 
   onMount(() => {
     const handleResize = () => {
@@ -50,13 +60,24 @@ const App: Component = () => {
     window.addEventListener("resize", handleResize);
     onCleanup(() => window.removeEventListener("resize", handleResize));
 
-    // Async initialization
+    // Async initialization; settings can't load until the stored keys are unlocked
     (async () => {
+      const status = await getSecretsStatus();
+      if (status.locked) {
+        setLockedSecrets(status);
+        return;
+      }
       await loadSettings();
       await refreshTasks();
     })();
   });
 
+  const handleSecretsUnlocked = async () => {
+    setLockedSecrets(null);
+    await loadSettings();
+    await refreshTasks();
+  };
+
   const toggleSkills = () => {
     setShowSkills(!showSkills());
     if (showSettings()) toggleSettings();
@@ -124,6 +145,7 @@ const App: Component = () => {
       console.error("Task error:", err);
     } finally {
       setIsRunning(false);
+      setPendingApprovals([]);
       const updated = await getTask(task.id);
       if (updated) setActiveTask(updated);
       const messages = await getTaskMessages(task.id);
@@ -178,19 +200,31 @@ const App: Component = () => {
       case "tool_start":
         setToolExecutions((prev) => [
           ...prev,
-          { id: Date.now(), tool: event.tool, status: "running" },
+          { id: Date.now(), toolUseId: event.tool_use_id, tool: event.tool, status: "running" },
         ]);
         break;
       case "tool_end":
         setToolExecutions((prev) => {
           const updated = [...prev];
-          const last = updated.findLast((t: ToolExecution) => t.tool === event.tool && t.status === "running");
+          const last = updated.findLast((t: ToolExecution) => t.toolUseId === event.tool_use_id && t.status === "running");
           if (last) {
             last.status = event.success ? "completed" : "error";
           }
           return updated;
         });
         break;
+      case "tool_output":
+        setToolExecutions((prev) =>
+          prev.map((t) =>
+            t.toolUseId === event.tool_use_id && t.status === "running"
+              ? { ...t, output: ((t.output ?? "") + event.content).slice(-MAX_TOOL_OUTPUT) }
+              : t
+          )
+        );
+        break;
+      case "approval_required":
+        setPendingApprovals((prev) => [...prev, event]);
+        break;
       case "done":
         setActiveTask((prev) => {
           if (!prev) return prev;
@@ -203,6 +237,22 @@ const App: Component = () => {
           return { ...prev, status: "failed" };
         });
         break;
+      case "cancelled":
+        setActiveTask((prev) => {
+          if (!prev) return prev;
+          return { ...prev, status: "cancelled" };
+        });
+        break;
+    }
+  };
+
+  const handleStopTask = async () => {
+    const task = activeTask();
+    if (!task || !isRunning()) return;
+    try {
+      await cancelTaskAgent(task.id);
+    } catch (err) {
+      console.error("Failed to stop task:", err);
     }
   };
 
@@ -252,6 +302,31 @@ const App: Component = () => {
       console.error("Task error:", err);
     } finally {
       setIsRunning(false);
+      setPendingApprovals([]);
+      const updated = await getTask(task.id);
+      if (updated) setActiveTask(updated);
+      const messages = await getTaskMessages(task.id);
+      setTaskMessages(messages);
+      await refreshTasks();
+    }
+  };
+
+  const handleResumeTask = async () => {
+    const task = activeTask();
+    if (!task || isRunning()) return;
+
+    setIsRunning(true);
+    setToolExecutions([]);
+    setCurrentText("");
+    setActiveTask({ ...task, status: "running" });
+
+    try {
+      await resumeTaskAgent(task.id, { maxTurns: 50, locale: locale() }, handleAgentEvent);
+    } catch (err) {
+      console.error("Task error:", err);
+    } finally {
+      setIsRunning(false);
+      setPendingApprovals([]);
       const updated = await getTask(task.id);
       if (updated) setActiveTask(updated);
       const messages = await getTaskMessages(task.id);
@@ -297,7 +372,11 @@ const App: Component = () => {
         "position": "relative"
       }}
     >
-      <Show when={!isLoading()} fallback={<LoadingScreen />}>
+      <Show when={!isLoading()} fallback={
+        <Show when={lockedSecrets()} fallback={<LoadingScreen />}>
+          {(status) => <SecretsUnlock status={status()} onUnlocked={handleSecretsUnlocked} />}
+        </Show>
+      }>
         {/* Sidebar & Mobile Backdrop */}
         <Show when={isMobile() && !isSidebarCollapsed()}>
           <div
@@ -352,6 +431,9 @@ const App: Component = () => {
               onNewConversation={handleNewConversation}
               currentText={currentText()}
               isRunning={isRunning()}
+              onStop={handleStopTask}
+              pendingApprovals={pendingApprovals()}
+              onApprovalResolved={(id) => setPendingApprovals((prev) => prev.filter((r) => r.request_id !== id))}
               activeTask={activeTask()}
               messages={taskMessages()}
               currentLocale={locale()}
@@ -370,6 +452,8 @@ const App: Component = () => {
             task={activeTask()}
             isRunning={isRunning()}
             toolExecutions={toolExecutions()}
+            onResume={handleResumeTask}
+            onStop={handleStopTask}
           />
         </aside>
       </Show>
@@ -3,10 +3,15 @@ import { useSettings } from "../stores/settings";
 import { runAgent, AgentEvent, isTauri } from "../lib/tauri-api";
 import "./Agent.css";
 
+// Live output kept per tool call; older output is dropped from the front
+const MAX_TOOL_OUTPUT = 4000;
+
 interface ToolExecution {
   id: number;
+  toolUseId?: string;
   tool: string;
   input: Record<string, unknown>;
+  output?: string;
   result?: string;
   success?: boolean;
   status: "running" | "completed" | "error";
@@ -42,6 +47,7 @@ const Agent: Component = () => {
           ...prev,
           {
             id: Date.now(),
+            toolUseId: event.tool_use_id,
             tool: event.tool,
             input: event.input,
             status: "running",
@@ -52,7 +58,7 @@ const Agent: Component = () => {
       case "tool_end":
         setToolExecutions((prev) => {
           const updated = [...prev];
-          const last = updated.findLast((t: any) => t.tool === event.tool && t.status === "running");
+          const last = updated.findLast((t: any) => t.toolUseId === event.tool_use_id && t.status === "running");
           if (last) {
             last.result = event.result;
             last.success = event.success;
@@ -62,6 +68,16 @@ const Agent: Component = () => {
         });
         scrollToBottom();
         break;
+      case "tool_output":
+        setToolExecutions((prev) =>
+          prev.map((t) =>
+            t.toolUseId === event.tool_use_id && t.status === "running"
+              ? { ...t, output: ((t.output ?? "") + event.content).slice(-MAX_TOOL_OUTPUT) }
+              : t
+          )
+        );
+        scrollToBottom();
+        break;
       case "turn_complete":
         setCurrentTurn(event.turn);
         break;
@@ -165,6 +181,12 @@ const Agent: Component = () => {
                           <div class="tool-input">
                             <span class="input-label">Input:</span> {formatInput(tool.input)}
                           </div>
+                          <Show when={tool.status === "running" && tool.output}>
+                            <div class="tool-result">
+                              <span class="result-label">Output:</span>
+                              <pre class="result-content">{tool.output}</pre>
+                            </div>
+                          </Show>
                           <Show when={tool.result}>
                             <div class="tool-result">
                               <span class="result-label">Result:</span>
@@ -1,6 +1,7 @@
 import { Component, Show, For, createSignal, onMount, onCleanup, createEffect } from "solid-js";
 import Markdown from "./Markdown";
 import Icon from "./Icon";
+import ApprovalPrompt, { ApprovalRequest } from "./ApprovalPrompt";
 import { Task, TaskMessage, openMultipleFoldersDialog, undoLastAction } from "../lib/tauri-api";
 import { useSettings } from "../stores/settings";
 import { useI18n } from "../stores/i18n";
@@ -12,6 +13,9 @@ interface AgentMainProps {
   onNewConversation: () => void;
   currentText: string;
   isRunning: boolean;
+  onStop: () => void;
+  pendingApprovals: ApprovalRequest[];
+  onApprovalResolved: (requestId: string) => void;
   activeTask: Task | null;
   messages: TaskMessage[];
   currentLocale?: string;
@@ -149,6 +153,8 @@ const AgentMain: Component<AgentMainProps> = (props) => {
                 {/* Show saved message history */}
                 {(() => {
                   const filteredMessages = props.messages.filter(m => {
+                    // Compaction records only exist to rebuild the model's context
+                    if (m.role === "compaction") return false;
                     // Hide messages that are purely tool results (user role)
                     if (m.role === "user") {
                       try {
@@ -230,6 +236,11 @@ const AgentMain: Component<AgentMainProps> = (props) => {
                     </div>
                   </div>
                 </Show>
+
+                {/* Tool calls waiting for the user's approval */}
+                <For each={props.pendingApprovals}>
+                  {(request) => <ApprovalPrompt request={request} onResolved={props.onApprovalResolved} />}
+                </For>
               </div>
             </Show>
           </div>
@@ -334,13 +345,22 @@ const AgentMain: Component<AgentMainProps> = (props) => {
                   style={{ "min-height": "50px", height: "auto" }}
                 />
 
-                <button
-                  type="submit"
-                  class={`submit-btn ${!props.isRunning && isInConversation() ? "icon-btn" : ""}`}
-                  disabled={props.isRunning || !input().trim()}
+                <Show
+                  when={props.isRunning}
+                  fallback={
+                    <button
+                      type="submit"
+                      class={`submit-btn ${isInConversation() ? "icon-btn" : ""}`}
+                      disabled={!input().trim()}
+                    >
+                      {isInConversation() ? <Icon name="send" size={18} /> : t("agent.startTask")}
+                    </button>
+                  }
                 >
-                  {props.isRunning ? t("agent.running") : isInConversation() ? <Icon name="send" size={18} /> : t("agent.startTask")}
-                </button>
+                  <button type="button" class="submit-btn stop-btn" onClick={() => props.onStop()}>
+                    {t("agent.stop")}
+                  </button>
+                </Show>
               </div>
             </form>
           </div>
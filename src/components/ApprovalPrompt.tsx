@@ -0,0 +1,65 @@
+import { Component, createSignal } from "solid-js";
+import { approveToolCall, denyToolCall } from "../lib/tauri-api";
+import { useI18n } from "../stores/i18n";
+import "./ApprovalPrompt.css";
+
+export interface ApprovalRequest {
+  request_id: string;
+  tool: string;
+  input: Record<string, unknown>;
+}
+
+interface ApprovalPromptProps {
+  request: ApprovalRequest;
+  onResolved: (requestId: string) => void;
+}
+
+// Asks the user whether a tool call with the "ask" permission may run
+const ApprovalPrompt: Component<ApprovalPromptProps> = (props) => {
+  const { t } = useI18n();
+  const [reason, setReason] = createSignal("");
+  const [busy, setBusy] = createSignal(false);
+
+  const resolve = async (approved: boolean) => {
+    setBusy(true);
+    try {
+      if (approved) {
+        await approveToolCall(props.request.request_id);
+      } else {
+        await denyToolCall(props.request.request_id, reason().trim() || undefined);
+      }
+    } catch (err) {
+      // The agent stopped waiting (cancelled or timed out)
+      console.error("Approval error:", err);
+    } finally {
+      props.onResolved(props.request.request_id);
+    }
+  };
+
+  return (
+    <div class="approval-prompt">
+      <div class="approval-title">
+        {t("approval.title").replace("{tool}", props.request.tool)}
+      </div>
+      <pre class="approval-input">{JSON.stringify(props.request.input, null, 2)}</pre>
+      <input
+        type="text"
+        class="approval-reason"
+        value={reason()}
+        onInput={(e) => setReason(e.currentTarget.value)}
+        placeholder={t("approval.reasonPlaceholder")}
+        disabled={busy()}
+      />
+      <div class="approval-actions">
+        <button type="button" class="approval-deny" onClick={() => resolve(false)} disabled={busy()}>
+          {t("approval.deny")}
+        </button>
+        <button type="button" class="approval-approve" onClick={() => resolve(true)} disabled={busy()}>
+          {t("approval.approve")}
+        </button>
+      </div>
+    </div>
+  );
+};
+
+export default ApprovalPrompt;
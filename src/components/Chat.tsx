@@ -1,7 +1,8 @@
 import { Component, For, Show, createSignal } from "solid-js";
-import { useChat } from "../stores/chat";
+import { useChat, Message } from "../stores/chat";
 import { useSettings } from "../stores/settings";
 import { sendChatMessage, sendChatWithTools, ChatEvent, isTauri } from "../lib/tauri-api";
+import ApprovalPrompt, { ApprovalRequest } from "./ApprovalPrompt";
 import "./Chat.css";
 
 interface ToolExecution {
@@ -13,6 +14,33 @@ interface ToolExecution {
   status: "running" | "completed" | "error";
 }
 
+// Step between alternative versions of a message
+const BranchNav: Component<{ messageId: string }> = (props) => {
+  const { alternativesOf, switchBranch } = useChat();
+  const alternatives = () => alternativesOf(props.messageId);
+  const index = () => alternatives().indexOf(props.messageId);
+
+  return (
+    <span class="branch-nav">
+      <button
+        type="button"
+        disabled={index() === 0}
+        onClick={() => switchBranch(alternatives()[index() - 1])}
+      >
+        ‹
+      </button>
+      {index() + 1}/{alternatives().length}
+      <button
+        type="button"
+        disabled={index() === alternatives().length - 1}
+        onClick={() => switchBranch(alternatives()[index() + 1])}
+      >
+        ›
+      </button>
+    </span>
+  );
+};
+
 const Chat: Component = () => {
   const {
     activeConversation,
@@ -22,6 +50,9 @@ const Chat: Component = () => {
     addLocalMessage,
     updateLastMessage,
     refreshConversations,
+    reloadMessages,
+    alternativesOf,
+    truncateMessages,
     isLoading,
     setIsLoading,
   } = useChat();
@@ -31,7 +62,10 @@ const Chat: Component = () => {
   const [enableTools, setEnableTools] = createSignal(true);
   const [projectPath, setProjectPath] = createSignal("");
   const [toolExecutions, setToolExecutions] = createSignal<ToolExecution[]>([]);
+  const [pendingApprovals, setPendingApprovals] = createSignal<ApprovalRequest[]>([]);
   const [showProjectInput, setShowProjectInput] = createSignal(false);
+  // User message being edited; sending replaces it on a new branch
+  const [editing, setEditing] = createSignal<Message | null>(null);
   let messagesEnd: HTMLDivElement | undefined;
 
   const scrollToBottom = () => {
@@ -82,6 +116,10 @@ const Chat: Component = () => {
         });
         scrollToBottom();
         break;
+      case "approval_required":
+        setPendingApprovals((prev) => [...prev, event]);
+        scrollToBottom();
+        break;
       case "done":
         updateLastMessage(event.final_text);
         scrollToBottom();
@@ -94,6 +132,35 @@ const Chat: Component = () => {
     const text = input().trim();
     if (!text || isLoading()) return;
 
+    const edited = editing();
+    setEditing(null);
+    setInput("");
+    if (edited) {
+      truncateMessages(messages().findIndex((m) => m.id === edited.id));
+    }
+    await send(text, edited ? { edit_message_id: edited.id } : {});
+  };
+
+  const startEdit = (msg: Message) => {
+    setEditing(msg);
+    setInput(msg.content);
+  };
+
+  const cancelEdit = () => {
+    setEditing(null);
+    setInput("");
+  };
+
+  const regenerate = async (msg: Message) => {
+    if (isLoading()) return;
+    truncateMessages(messages().findIndex((m) => m.id === msg.id));
+    await send("", { regenerate_message_id: msg.id });
+  };
+
+  const send = async (
+    text: string,
+    branch: { edit_message_id?: string; regenerate_message_id?: string }
+  ) => {
     let convId = activeConversationId();
     if (!convId) {
       const conv = await createConversation();
@@ -101,22 +168,25 @@ const Chat: Component = () => {
       convId = conv.id;
     }
 
-    setInput("");
+    const branching = branch.edit_message_id || branch.regenerate_message_id;
     setToolExecutions([]); // Reset tool executions
-    addLocalMessage("user", text);
+    if (!branch.regenerate_message_id) {
+      addLocalMessage("user", text);
+    }
     addLocalMessage("assistant", "");
     setIsLoading(true);
     scrollToBottom();
 
     try {
-      // Use enhanced chat with tools if enabled and in Tauri
-      if (enableTools() && isTauri()) {
+      // Use enhanced chat with tools if enabled and in Tauri; edits and regenerations always go through it
+      if ((enableTools() || branching) && isTauri()) {
         await sendChatWithTools(
           {
             conversation_id: convId,
             content: text,
             project_path: projectPath() || undefined,
-            enable_tools: true,
+            enable_tools: enableTools(),
+            ...branch,
           },
           handleChatEvent
         );
@@ -129,6 +199,10 @@ const Chat: Component = () => {
       }
       // Refresh conversations to get updated title
       await refreshConversations();
+      // Pick up stored IDs and branches
+      if (isTauri()) {
+        await reloadMessages();
+      }
     } catch (error) {
       console.error("Chat error:", error);
       let errorMsg = "Unknown error";
@@ -144,6 +218,7 @@ const Chat: Component = () => {
     } finally {
       setIsLoading(false);
       setToolExecutions([]); // Clear tool executions after completion
+      setPendingApprovals([]);
       scrollToBottom();
     }
   };
@@ -185,6 +260,19 @@ const Chat: Component = () => {
                       </span>
                     )}
                   </div>
+                  <Show when={isTauri() && !isLoading() && msg.content}>
+                    <div class="message-actions">
+                      <Show when={alternativesOf(msg.id).length > 1}>
+                        <BranchNav messageId={msg.id} />
+                      </Show>
+                      <Show when={msg.role === "user"}>
+                        <button type="button" onClick={() => startEdit(msg)}>Edit</button>
+                      </Show>
+                      <Show when={msg.role === "assistant"}>
+                        <button type="button" onClick={() => regenerate(msg)}>Regenerate</button>
+                      </Show>
+                    </div>
+                  </Show>
                 </div>
               )}
             </For>
@@ -206,6 +294,15 @@ const Chat: Component = () => {
                 </For>
               </div>
             </Show>
+
+            <For each={pendingApprovals()}>
+              {(request) => (
+                <ApprovalPrompt
+                  request={request}
+                  onResolved={(id) => setPendingApprovals((prev) => prev.filter((r) => r.request_id !== id))}
+                />
+              )}
+            </For>
           </Show>
           <div ref={messagesEnd} />
         </div>
@@ -246,6 +343,13 @@ const Chat: Component = () => {
           </Show>
         </div>
 
+        <Show when={editing()}>
+          <div class="editing-banner">
+            <span>Editing a message. Sending starts a new branch.</span>
+            <button type="button" onClick={cancelEdit}>Cancel</button>
+          </div>
+        </Show>
+
         <form class="input-form" onSubmit={handleSubmit}>
           <textarea
             value={input()}
@@ -2,6 +2,7 @@ import { Component, For, createSignal, onMount, createMemo } from "solid-js";
 import {
   MCPServerConfig,
   MCPServerStatus,
+  MCPTransport,
   listMCPServers,
   saveMCPServer,
   deleteMCPServer,
@@ -28,11 +29,36 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
   // Form state
   const [formData, setFormData] = createSignal({
     name: "",
+    transport: "http" as MCPTransport,
     serverUrl: "",
+    command: "",
+    args: "",
+    env: "",
+    cwd: "",
     oauthClientId: "",
     oauthClientSecret: "",
   });
 
+  // Environment variables are edited as KEY=value lines
+  const parseEnv = (text: string): Record<string, string> => {
+    const env: Record<string, string> = {};
+    for (const line of text.split("\n")) {
+      const index = line.indexOf("=");
+      if (index > 0) {
+        env[line.slice(0, index).trim()] = line.slice(index + 1);
+      }
+    }
+    return env;
+  };
+
+  const formatEnv = (env?: Record<string, string>) =>
+    Object.entries(env || {}).map(([key, value]) => `${key}=${value}`).join("\n");
+
+  const describeServer = (server: MCPServerConfig) =>
+    server.transport === "stdio"
+      ? [server.command, ...(server.args || [])].join(" ")
+      : server.server_url;
+
   const mergedData = createMemo(() => {
     const statusMap = new Map(statuses().map(s => [s.id, s]));
     return servers().map(server => ({
@@ -64,7 +90,12 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
   const resetForm = () => {
     setFormData({
       name: "",
+      transport: "http",
       serverUrl: "",
+      command: "",
+      args: "",
+      env: "",
+      cwd: "",
       oauthClientId: "",
       oauthClientSecret: "",
     });
@@ -75,7 +106,12 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
   const startEdit = (server: MCPServerConfig) => {
     setFormData({
       name: server.name,
+      transport: server.transport || "http",
       serverUrl: server.server_url || "",
+      command: server.command || "",
+      args: (server.args || []).join("\n"),
+      env: formatEnv(server.env),
+      cwd: server.cwd || "",
       oauthClientId: server.oauth_client_id || "",
       oauthClientSecret: server.oauth_client_secret || "",
     });
@@ -92,7 +128,14 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
         return;
       }
 
-      if (!data.serverUrl.trim()) {
+      const stdio = data.transport === "stdio";
+
+      if (stdio && !data.command.trim()) {
+        alert(t("mcp.alerts.commandRequired"));
+        return;
+      }
+
+      if (!stdio && !data.serverUrl.trim()) {
         alert(t("mcp.alerts.urlRequired"));
         return;
       }
@@ -100,7 +143,12 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
       const config: MCPServerConfig = {
         id: editingServer()?.id || crypto.randomUUID(),
         name: data.name,
-        server_url: data.serverUrl,
+        transport: data.transport,
+        server_url: stdio ? "" : data.serverUrl,
+        command: stdio ? data.command.trim() : "",
+        args: stdio ? data.args.split("\n").map(arg => arg.trim()).filter(Boolean) : [],
+        env: stdio ? parseEnv(data.env) : {},
+        cwd: stdio ? data.cwd.trim() || undefined : undefined,
         oauth_client_id: data.oauthClientId.trim() || undefined,
         oauth_client_secret: data.oauthClientSecret.trim() || undefined,
         enabled: editingServer()?.enabled ?? true,
@@ -188,39 +236,95 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
             </div>
 
             <div class="form-group">
-              <label>{t("mcp.addForm.url")}</label>
-              <input
-                type="url"
-                value={formData().serverUrl}
-                onInput={(e) => setFormData(prev => ({ ...prev, serverUrl: e.currentTarget.value }))}
-                placeholder={t("mcp.addForm.urlPlaceholder")}
-              />
+              <label>{t("mcp.addForm.transport")}</label>
+              <select
+                value={formData().transport}
+                onChange={(e) => setFormData(prev => ({ ...prev, transport: e.currentTarget.value as MCPTransport }))}
+              >
+                <option value="http">{t("mcp.addForm.transportHttp")}</option>
+                <option value="stdio">{t("mcp.addForm.transportStdio")}</option>
+              </select>
             </div>
 
-            <details class="advanced-settings">
-              <summary>{t("mcp.addForm.advanced")}</summary>
-              <div class="advanced-content">
+            {formData().transport === "stdio" ? (
+              <>
                 <div class="form-group">
-                  <label>{t("mcp.addForm.oauthId")}</label>
+                  <label>{t("mcp.addForm.command")}</label>
                   <input
                     type="text"
-                    value={formData().oauthClientId}
-                    onInput={(e) => setFormData(prev => ({ ...prev, oauthClientId: e.currentTarget.value }))}
-                    placeholder="your-oauth-client-id"
+                    value={formData().command}
+                    onInput={(e) => setFormData(prev => ({ ...prev, command: e.currentTarget.value }))}
+                    placeholder={t("mcp.addForm.commandPlaceholder")}
+                  />
+                </div>
+
+                <div class="form-group">
+                  <label>{t("mcp.addForm.args")}</label>
+                  <textarea
+                    rows={3}
+                    value={formData().args}
+                    onInput={(e) => setFormData(prev => ({ ...prev, args: e.currentTarget.value }))}
+                    placeholder={t("mcp.addForm.argsPlaceholder")}
                   />
                 </div>
 
                 <div class="form-group">
-                  <label>{t("mcp.addForm.oauthSecret")}</label>
+                  <label>{t("mcp.addForm.env")}</label>
+                  <textarea
+                    rows={2}
+                    value={formData().env}
+                    onInput={(e) => setFormData(prev => ({ ...prev, env: e.currentTarget.value }))}
+                    placeholder="API_KEY=..."
+                  />
+                </div>
+
+                <div class="form-group">
+                  <label>{t("mcp.addForm.cwd")}</label>
                   <input
-                    type="password"
-                    value={formData().oauthClientSecret}
-                    onInput={(e) => setFormData(prev => ({ ...prev, oauthClientSecret: e.currentTarget.value }))}
-                    placeholder="your-oauth-client-secret"
+                    type="text"
+                    value={formData().cwd}
+                    onInput={(e) => setFormData(prev => ({ ...prev, cwd: e.currentTarget.value }))}
                   />
                 </div>
+              </>
+            ) : (
+              <div class="form-group">
+                <label>{t("mcp.addForm.url")}</label>
+                <input
+                  type="url"
+                  value={formData().serverUrl}
+                  onInput={(e) => setFormData(prev => ({ ...prev, serverUrl: e.currentTarget.value }))}
+                  placeholder={t("mcp.addForm.urlPlaceholder")}
+                />
               </div>
-            </details>
+            )}
+
+            {formData().transport === "http" && (
+              <details class="advanced-settings">
+                <summary>{t("mcp.addForm.advanced")}</summary>
+                <div class="advanced-content">
+                  <div class="form-group">
+                    <label>{t("mcp.addForm.oauthId")}</label>
+                    <input
+                      type="text"
+                      value={formData().oauthClientId}
+                      onInput={(e) => setFormData(prev => ({ ...prev, oauthClientId: e.currentTarget.value }))}
+                      placeholder="your-oauth-client-id"
+                    />
+                  </div>
+
+                  <div class="form-group">
+                    <label>{t("mcp.addForm.oauthSecret")}</label>
+                    <input
+                      type="password"
+                      value={formData().oauthClientSecret}
+                      onInput={(e) => setFormData(prev => ({ ...prev, oauthClientSecret: e.currentTarget.value }))}
+                      placeholder="your-oauth-client-secret"
+                    />
+                  </div>
+                </div>
+              </details>
+            )}
 
             <div class="warning-text">
               <strong>{t("mcp.warning")}</strong> {t("mcp.warningDesc")}
@@ -251,7 +355,7 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
                     <div class="server-header">
                       <div class="server-info">
                         <h4>{server.name}</h4>
-                        <p>{server.server_url}</p>
+                        <p>{describeServer(server)}</p>
                       </div>
                       <div class="server-status">
                         <span
@@ -267,7 +371,7 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
 
                     <div class="server-details">
                       <div class="detail-row">
-                        <strong>URL:</strong> {server.server_url}
+                        <strong>{server.transport === "stdio" ? "Command:" : "URL:"}</strong> {describeServer(server)}
                       </div>
 
                       {server.oauth_client_id && (
@@ -281,6 +385,18 @@ const MCPSettings: Component<MCPSettingsProps> = (props) => {
                           <strong>Tools:</strong> {status.tools.map(t => t.name).join(", ")}
                         </div>
                       )}
+
+                      {status?.resources && status.resources.length > 0 && (
+                        <div class="detail-row">
+                          <strong>Resources:</strong> {status.resources.map(r => r.name || r.uri).join(", ")}
+                        </div>
+                      )}
+
+                      {status?.prompts && status.prompts.length > 0 && (
+                        <div class="detail-row">
+                          <strong>Prompts:</strong> {status.prompts.map(p => `/${p.name}`).join(", ")}
+                        </div>
+                      )}
                     </div>
 
                     <div class="server-actions">
@@ -0,0 +1,84 @@
+import { Component, Show, createSignal } from "solid-js";
+import { SecretsStatus, unlockSecrets, resetSecrets } from "../lib/tauri-api";
+import { useI18n } from "../stores/i18n";
+import "./SecretsUnlock.css";
+
+interface SecretsUnlockProps {
+  status: SecretsStatus;
+  onUnlocked: () => void;
+}
+
+// Shown at startup when the stored API keys and MCP secrets can't be decrypted
+const SecretsUnlock: Component<SecretsUnlockProps> = (props) => {
+  const { t } = useI18n();
+  const [passphrase, setPassphrase] = createSignal("");
+  const [newPassphrase, setNewPassphrase] = createSignal("");
+  const [error, setError] = createSignal<string | null>(null);
+  const [busy, setBusy] = createSignal(false);
+
+  const run = async (action: () => Promise<void>) => {
+    setBusy(true);
+    setError(null);
+    try {
+      await action();
+      props.onUnlocked();
+    } catch (err) {
+      setError((err as { message?: string }).message || String(err));
+    } finally {
+      setBusy(false);
+    }
+  };
+
+  const handleUnlock = (e: Event) => {
+    e.preventDefault();
+    if (!passphrase()) return;
+    run(() => unlockSecrets(passphrase()));
+  };
+
+  const handleReset = () => {
+    if (!window.confirm(t("secrets.resetConfirm"))) return;
+    run(() => resetSecrets(newPassphrase() || undefined));
+  };
+
+  return (
+    <div class="loading-screen">
+      <div class="secrets-unlock">
+        <h1>{t("secrets.title")}</h1>
+        <p>{t("secrets.description")}</p>
+        <p class="secrets-error">{error() ?? props.status.error}</p>
+
+        <Show when={props.status.passphrase_required}>
+          <form class="secrets-form" onSubmit={handleUnlock}>
+            <input
+              type="password"
+              value={passphrase()}
+              onInput={(e) => setPassphrase(e.currentTarget.value)}
+              placeholder={t("secrets.passphrase")}
+              disabled={busy()}
+            />
+            <button type="submit" disabled={busy() || !passphrase()}>
+              {t("secrets.unlock")}
+            </button>
+          </form>
+        </Show>
+
+        <div class="secrets-reset">
+          <h2>{t("secrets.resetTitle")}</h2>
+          <p>{t("secrets.resetDescription")}</p>
+          <input
+            type="password"
+            value={newPassphrase()}
+            onInput={(e) => setNewPassphrase(e.currentTarget.value)}
+            placeholder={t("secrets.newPassphrase")}
+            disabled={busy()}
+          />
+          <button type="button" class="secrets-reset-btn" onClick={handleReset} disabled={busy()}>
+            {t("secrets.reset")}
+          </button>
+        </div>
+      </div>
+    </div>
+  );
+};
+
+export default SecretsUnlock;
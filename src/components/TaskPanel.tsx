@@ -7,11 +7,15 @@ interface TaskPanelProps {
   task: Task | null;
   isRunning: boolean;
   toolExecutions: ToolExecution[];
+  onResume: () => void;
+  onStop: () => void;
 }
 
 interface ToolExecution {
   id: number;
+  toolUseId?: string;
   tool: string;
+  output?: string;
   status: "running" | "completed" | "error";
 }
 
@@ -51,6 +55,8 @@ const TaskPanel: Component<TaskPanelProps> = (props) => {
       case "running": return t("taskPanel.running");
       case "completed": return t("taskPanel.completed");
       case "failed": return t("taskPanel.failed");
+      case "interrupted": return t("taskPanel.interrupted");
+      case "cancelled": return t("taskPanel.cancelled");
       default: return status;
     }
   };
@@ -77,6 +83,15 @@ const TaskPanel: Component<TaskPanelProps> = (props) => {
 
             <div class="task-description">{task().description}</div>
 
+            <Show when={task().status === "interrupted" && !props.isRunning}>
+              <div class="task-interrupted">
+                <span>{t("taskPanel.interruptedHint")}</span>
+                <button class="resume-button" onClick={() => props.onResume()}>
+                  {t("taskPanel.resume")}
+                </button>
+              </div>
+            </Show>
+
             <Show when={task().plan && task().plan!.length > 0}>
               <div class="plan-section">
                 <div class="plan-header">{t("taskPanel.plan")}</div>
@@ -105,14 +120,19 @@ const TaskPanel: Component<TaskPanelProps> = (props) => {
                 <div class="tool-list">
                   <For each={props.toolExecutions}>
                     {(tool) => (
-                      <div class={`tool-item ${tool.status}`}>
-                        <span class="tool-name">{tool.tool}</span>
-                        <span class="tool-status-icon">
-                          {tool.status === "running" && "..."}
-                          {tool.status === "completed" && "✓"}
-                          {tool.status === "error" && "✗"}
-                        </span>
-                      </div>
+                      <>
+                        <div class={`tool-item ${tool.status}`}>
+                          <span class="tool-name">{tool.tool}</span>
+                          <span class="tool-status-icon">
+                            {tool.status === "running" && "..."}
+                            {tool.status === "completed" && "✓"}
+                            {tool.status === "error" && "✗"}
+                          </span>
+                        </div>
+                        <Show when={tool.status === "running" && tool.output}>
+                          <pre class="tool-output">{tool.output}</pre>
+                        </Show>
+                      </>
                     )}
                   </For>
                 </div>
@@ -123,6 +143,9 @@ const TaskPanel: Component<TaskPanelProps> = (props) => {
               <div class="running-indicator">
                 <span class="pulse"></span>
                 <span>{t("taskPanel.working")}</span>
+                <button class="stop-button" onClick={() => props.onStop()}>
+                  {t("taskPanel.stop")}
+                </button>
               </div>
             </Show>
           </div>
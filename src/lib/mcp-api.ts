@@ -1,9 +1,16 @@
 import { invoke } from "@tauri-apps/api/core";
 
+export type MCPTransport = "http" | "stdio";
+
 export interface MCPServerConfig {
   id: string;
   name: string;
+  transport: MCPTransport;
   server_url: string;
+  command: string;
+  args: string[];
+  env: Record<string, string>;
+  cwd?: string;
   oauth_client_id?: string;
   oauth_client_secret?: string;
   enabled: boolean;
@@ -16,6 +23,30 @@ export interface MCPTool {
   name: string;
   description: string;
   input_schema: any;
+  read_only?: boolean;
+}
+
+export interface MCPResource {
+  server_id: string;
+  uri: string;
+  name: string;
+  description?: string;
+  mime_type?: string;
+}
+
+export interface MCPResourceTemplate {
+  server_id: string;
+  uri_template: string;
+  name: string;
+  description?: string;
+  mime_type?: string;
+}
+
+export interface MCPPrompt {
+  server_id: string;
+  name: string;
+  description?: string;
+  arguments: { name: string; description?: string; required: boolean }[];
 }
 
 export interface MCPServerStatus {
@@ -23,6 +54,9 @@ export interface MCPServerStatus {
   name: string;
   status: "Connected" | "Disconnected" | "Connecting" | "Error";
   tools: MCPTool[];
+  resources: MCPResource[];
+  resource_templates: MCPResourceTemplate[];
+  prompts: MCPPrompt[];
   last_error?: string;
 }
 
@@ -64,4 +98,16 @@ export async function getMCPServerStatuses(): Promise<MCPServerStatus[]> {
 
 export async function executeMCPTool(call: MCPToolCall): Promise<MCPToolResult> {
   return invoke("execute_mcp_tool", { call });
-}
\ No newline at end of file
+}
+
+export async function readMCPResource(serverId: string, uri: string): Promise<string> {
+  return invoke("read_mcp_resource", { serverId, uri });
+}
+
+export async function getMCPPrompt(
+  serverId: string,
+  name: string,
+  args: Record<string, string>
+): Promise<string> {
+  return invoke("get_mcp_prompt", { serverId, name, arguments: args });
+}
@@ -14,6 +14,58 @@ export interface Settings {
   openai_project?: string;  // Optional OpenAI Project ID
   enable_undo: boolean;  // Enable Undo functionality
   backup_path: string;   // Custom backup path (empty uses default)
+  tool_permissions?: Record<string, ToolPermission>;  // Per-tool policy (missing means allow)
+  restrict_to_project?: boolean;  // Confine file tools to the project directory
+  allowed_paths?: string[];  // Extra directories file tools may access
+  max_parallel_tools?: number;  // Read-only tool calls run at once per turn
+  model_prices?: Record<string, ModelPrice>;  // Price overrides keyed by model name prefix
+  max_retries?: number;  // Retries of a failed model request per provider
+  fallback_providers?: FallbackProvider[];  // Tried in order when the primary provider stays down
+  sandbox_mode?: boolean;  // Run each task's bash commands in its own Docker container
+  sandbox_image?: string;  // Image of the sandbox container
+}
+
+// Keys for fallback providers come from provider_keys
+export interface FallbackProvider {
+  provider: string;
+  model: string;
+  base_url?: string;
+}
+
+export type ToolPermission = "allow" | "ask" | "deny";
+
+// Prices in USD per million tokens
+export interface ModelPrice {
+  input_per_mtok: number;
+  output_per_mtok: number;
+  cache_read_per_mtok?: number;
+  cache_write_per_mtok?: number;
+}
+
+export interface TokenUsage {
+  input_tokens: number;
+  output_tokens: number;
+  cache_read_tokens: number;
+  cache_creation_tokens: number;
+}
+
+export interface UsageTotals extends TokenUsage {
+  requests: number;
+  cost: number;  // Estimated USD
+}
+
+export interface DailyUsage extends UsageTotals {
+  date: string;  // YYYY-MM-DD (local)
+}
+
+export interface SearchHit {
+  source: "conversation" | "task";
+  parent_id: string;  // conversation or task ID
+  title: string;
+  message_id: string;
+  role: string;
+  snippet: string;  // matches wrapped in **
+  timestamp: number;
 }
 
 export interface Conversation {
@@ -21,6 +73,7 @@ export interface Conversation {
   title: string;
   created_at: number;
   updated_at: number;
+  active_leaf_id?: string | null;  // last message of the branch being shown
 }
 
 export interface Message {
@@ -29,6 +82,13 @@ export interface Message {
   role: "user" | "assistant";
   content: string;
   timestamp: number;
+  parent_id?: string | null;
+}
+
+// A point where a conversation has alternative messages
+export interface BranchPoint {
+  parent_id: string | null;
+  message_ids: string[];  // oldest first
 }
 
 interface StreamPayload {
@@ -50,11 +110,19 @@ export type AgentEvent =
   | { type: "plan"; steps: PlanStepInfo[] }
   | { type: "step_start"; step: number }
   | { type: "step_done"; step: number }
-  | { type: "tool_start"; tool: string; input: Record<string, unknown> }
-  | { type: "tool_end"; tool: string; result: string; success: boolean }
+  | { type: "tool_start"; tool_use_id: string; tool: string; input: Record<string, unknown> }
+  | { type: "tool_end"; tool_use_id: string; tool: string; result: string; success: boolean }
   | { type: "turn_complete"; turn: number }
   | { type: "done"; total_turns: number }
-  | { type: "error"; message: string };
+  | { type: "error"; message: string }
+  | { type: "tool_output"; tool_use_id: string; tool: string; stream: "stdout" | "stderr"; content: string }
+  | { type: "tool_progress"; tool_use_id: string; tool: string; progress: number; total: number | null; message: string | null }
+  | { type: "approval_required"; request_id: string; tool: string; input: Record<string, unknown> }
+  | { type: "compacted"; tokens_before: number; tokens_after: number; truncated_results: number; summary: string | null; summary_error: string | null }
+  | { type: "usage"; turn: number; model: string; usage: TokenUsage; cost: number | null }
+  | { type: "retrying"; provider: string; attempt: number; max_retries: number; delay_ms: number; error: string }
+  | { type: "failover"; from_provider: string; to_provider: string; model: string; error: string }
+  | { type: "cancelled"; turn: number };
 
 export interface PlanStepInfo {
   step: number;
@@ -66,7 +134,7 @@ export interface Task {
   id: string;
   title: string;
   description: string;
-  status: "planning" | "running" | "completed" | "failed";
+  status: "planning" | "running" | "completed" | "failed" | "cancelled" | "interrupted";
   plan: PlanStep[] | null;
   current_step: number;
   project_path: string | null;
@@ -108,12 +176,15 @@ export interface EnhancedChatRequest {
   project_path?: string;
   enable_tools: boolean;
   locale?: string;
+  edit_message_id?: string;  // send content in place of this user message, on a new branch
+  regenerate_message_id?: string;  // reply again in place of this assistant message
 }
 
 export type ChatEvent =
   | { type: "text"; content: string }
   | { type: "tool_start"; tool: string; input: Record<string, unknown> }
   | { type: "tool_end"; tool: string; result: string; success: boolean }
+  | { type: "approval_required"; request_id: string; tool: string; input: Record<string, unknown> }
   | { type: "done"; final_text: string };
 
 // Check if running in Tauri (Tauri 2.x uses __TAURI_INTERNALS__)
@@ -172,6 +243,28 @@ export async function saveSettings(settings: Settings): Promise<void> {
   return invoke("save_settings", { settings });
 }
 
+// Secret store API
+export interface SecretsStatus {
+  locked: boolean;
+  error: string | null;
+  passphrase_required: boolean;
+}
+
+export async function getSecretsStatus(): Promise<SecretsStatus> {
+  if (!isTauri()) {
+    return { locked: false, error: null, passphrase_required: false };
+  }
+  return invoke<SecretsStatus>("get_secrets_status");
+}
+
+export async function unlockSecrets(passphrase: string): Promise<void> {
+  return invoke("unlock_secrets", { passphrase });
+}
+
+export async function resetSecrets(passphrase?: string): Promise<void> {
+  return invoke("reset_secrets", { passphrase: passphrase ?? null });
+}
+
 export async function testConnection(): Promise<string> {
   console.log("testConnection called, isTauri:", isTauri());
   if (!isTauri()) {
@@ -261,6 +354,18 @@ export async function getMessages(conversationId: string): Promise<Message[]> {
   return invoke<Message[]>("get_messages", { conversationId });
 }
 
+export async function listBranches(conversationId: string): Promise<BranchPoint[]> {
+  if (!isTauri()) {
+    return [];
+  }
+  return invoke<BranchPoint[]>("list_branches", { conversationId });
+}
+
+// Show the branch containing messageId; returns its messages
+export async function switchBranch(conversationId: string, messageId: string): Promise<Message[]> {
+  return invoke<Message[]>("switch_branch", { conversationId, messageId });
+}
+
 function saveMessagesLocal(conversationId: string, messages: Message[]) {
   localStorage.setItem(
     `kuse-cowork-messages-${conversationId}`,
@@ -480,6 +585,90 @@ export async function runTaskAgent(
   }
 }
 
+// Continue an interrupted task from its last checkpoint
+export async function resumeTaskAgent(
+  taskId: string,
+  options: { maxTurns?: number; locale?: string },
+  onEvent: (event: AgentEvent) => void
+): Promise<string> {
+  if (!isTauri()) {
+    throw new Error("Task agent requires the desktop app");
+  }
+
+  let unlisten: UnlistenFn | undefined;
+
+  try {
+    unlisten = await listen<AgentEvent>("agent-event", (event) => {
+      onEvent(event.payload);
+    });
+
+    return await invoke<string>("resume_task_agent", {
+      taskId,
+      maxTurns: options.maxTurns,
+      locale: options.locale,
+    });
+  } finally {
+    if (unlisten) {
+      unlisten();
+    }
+  }
+}
+
+export async function cancelTaskAgent(taskId: string): Promise<void> {
+  if (!isTauri()) {
+    throw new Error("Task agent requires the desktop app");
+  }
+  return invoke("cancel_task_agent", { taskId });
+}
+
+// Usage commands
+export async function getTaskUsage(taskId: string): Promise<UsageTotals> {
+  return invoke<UsageTotals>("get_task_usage", { taskId });
+}
+
+export async function getConversationUsage(conversationId: string): Promise<UsageTotals> {
+  return invoke<UsageTotals>("get_conversation_usage", { conversationId });
+}
+
+export async function getUsageByDay(days?: number): Promise<DailyUsage[]> {
+  return invoke<DailyUsage[]>("get_usage_by_day", { days: days ?? null });
+}
+
+export type ExportFormat = "markdown" | "json";
+
+export interface ImportedTranscript {
+  kind: "conversation" | "task";
+  id: string;
+}
+
+export async function exportConversation(id: string, format: ExportFormat): Promise<string> {
+  return invoke<string>("export_conversation", { id, format });
+}
+
+export async function exportTask(id: string, format: ExportFormat): Promise<string> {
+  return invoke<string>("export_task", { id, format });
+}
+
+// Import a JSON bundle from exportConversation/exportTask as a new record
+export async function importTranscript(bundle: string): Promise<ImportedTranscript> {
+  return invoke<ImportedTranscript>("import_transcript", { bundle });
+}
+
+export async function searchHistory(query: string, limit?: number): Promise<SearchHit[]> {
+  if (!isTauri()) {
+    return [];
+  }
+  return invoke<SearchHit[]>("search_history", { query, limit: limit ?? null });
+}
+
+export async function approveToolCall(requestId: string): Promise<void> {
+  return invoke("approve_tool_call", { requestId });
+}
+
+export async function denyToolCall(requestId: string, reason?: string): Promise<void> {
+  return invoke("deny_tool_call", { requestId, reason: reason ?? null });
+}
+
 export async function getTaskMessages(taskId: string): Promise<TaskMessage[]> {
   if (!isTauri()) {
     // Web fallback
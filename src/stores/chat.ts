@@ -4,6 +4,9 @@ import {
   createConversation as createConversationApi,
   deleteConversation as deleteConversationApi,
   getMessages,
+  listBranches,
+  switchBranch as switchBranchApi,
+  BranchPoint,
   Conversation,
   Message,
 } from "../lib/tauri-api";
@@ -13,6 +16,7 @@ export type { Message } from "../lib/tauri-api";
 const [conversations, setConversations] = createSignal<Conversation[]>([]);
 const [activeConversationId, setActiveConversationId] = createSignal<string | null>(null);
 const [messages, setMessages] = createSignal<Message[]>([]);
+const [branches, setBranches] = createSignal<BranchPoint[]>([]);
 const [isLoading, setIsLoading] = createSignal(false);
 
 export async function loadConversations() {
@@ -28,6 +32,7 @@ export async function loadMessages(conversationId: string) {
   try {
     const msgs = await getMessages(conversationId);
     setMessages(msgs);
+    setBranches(await listBranches(conversationId));
   } catch (e) {
     console.error("Failed to load messages:", e);
   }
@@ -50,6 +55,7 @@ export function useChat() {
       setConversations((prev) => [conversation, ...prev]);
       setActiveConversationId(conversation.id);
       setMessages([]);
+      setBranches([]);
       return conversation;
     } catch (e) {
       console.error("Failed to create conversation:", e);
@@ -68,6 +74,8 @@ export function useChat() {
         } else {
           setActiveConversationId(null);
           setMessages([]);
+          setBranches([]);
+      setBranches([]);
         }
       }
     } catch (e) {
@@ -97,6 +105,25 @@ export function useChat() {
     await loadConversations();
   };
 
+  // Alternatives to a message (including itself), if it has any
+  const alternativesOf = (messageId: string) =>
+    branches().find((b) => b.message_ids.includes(messageId))?.message_ids ?? [];
+
+  const switchBranch = async (messageId: string) => {
+    const id = activeConversationId();
+    if (!id) return;
+    try {
+      setMessages(await switchBranchApi(id, messageId));
+    } catch (e) {
+      console.error("Failed to switch branch:", e);
+    }
+  };
+
+  // Drop local messages from index on, before an edit or regeneration replaces them
+  const truncateMessages = (index: number) => {
+    setMessages((prev) => prev.slice(0, index));
+  };
+
   return {
     conversations,
     activeConversation,
@@ -108,6 +135,13 @@ export function useChat() {
     addLocalMessage,
     updateLastMessage,
     refreshConversations,
+    reloadMessages: () => {
+      const id = activeConversationId();
+      return id ? loadMessages(id) : Promise.resolve();
+    },
+    alternativesOf,
+    switchBranch,
+    truncateMessages,
     isLoading,
     setIsLoading,
     loadConversations,